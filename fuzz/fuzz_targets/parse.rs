@@ -0,0 +1,29 @@
+// Arbitrary bytes through the chunked parser of aggregate_bytes and the
+// streaming one of aggregate_reader, which must return stations or an
+// error for any of them, never panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use one_billion_row_challenge::{aggregate_bytes, aggregate_reader, Options};
+
+// The first byte picks the options, and the rest is the input
+fuzz_target!(|data: &[u8]| {
+    let Some((&flags, bytes)) = data.split_first() else { return };
+    let precision = 1 + flags % 3;
+    let options = Options {
+        delimiter: if flags & 0x04 != 0 { b',' } else { b';' },
+        histograms: precision == 1 && flags & 0x08 != 0,
+        validate_utf8: flags & 0x10 != 0,
+        max_name_len: (flags & 0x20 != 0).then_some(4),
+        truncate_names: flags & 0x40 != 0,
+        precision,
+        strict: precision == 1 && flags & 0x80 != 0,
+        ..Default::default()
+    };
+
+    if let Err(e) = aggregate_bytes(bytes, &options) {
+        let _ = e.to_string();
+    }
+    let _ = aggregate_reader(bytes, &Options { max_memory: Some(1 << 20), ..options });
+});
@@ -0,0 +1,79 @@
+#ifndef BRC_H
+#define BRC_H
+
+/* Generated by cbindgen from src/capi.rs. Don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The results of a [`brc_aggregate`] call: every station, sorted by
+// name. Opaque to C
+typedef struct brc_result brc_result;
+
+// One station's results, with its temperatures in degrees
+typedef struct brc_station {
+  // The name, NUL terminated, and valid until the result is freed
+  const char *name;
+  // The name's length in bytes, not counting the NUL
+  size_t name_len;
+  double min;
+  // Rounded to the temperatures' decimals, as the challenge's
+  // output is
+  double mean;
+  double max;
+  uint64_t count;
+} brc_station;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Aggregates the measurements file at `path` (gzip and zstd compressed
+// files too) with the default options. On success, returns 0 and sets
+// `*out` to the results, which the caller frees with
+// [`brc_result_free`]. Otherwise returns the same code the `1brc`
+// command exits with (3 for I/O errors, 4 for malformed input, 1 for
+// anything else) and leaves `*out` alone; [`brc_last_error`] says what
+// went wrong.
+//
+// # Safety
+//
+// `path` must be a NUL-terminated string and `out` must be valid to
+// write a pointer to.
+int brc_aggregate(const char *path, struct brc_result **out);
+
+// The message for the last error on this thread, or NULL if there
+// hasn't been one. Valid until the thread's next failing call
+const char *brc_last_error(void);
+
+// The number of stations in `result`
+//
+// # Safety
+//
+// `result` must have come from [`brc_aggregate`] and not been freed.
+size_t brc_result_len(const struct brc_result *result);
+
+// The `index`th station in `result`, by name. An index past the end
+// gives a station with a NULL name and zero count
+//
+// # Safety
+//
+// `result` must have come from [`brc_aggregate`] and not been freed.
+struct brc_station brc_result_get(const struct brc_result *result, size_t index);
+
+// Frees `result`. NULL is ignored
+//
+// # Safety
+//
+// `result` must be NULL or have come from [`brc_aggregate`], and not
+// been freed already.
+void brc_result_free(struct brc_result *result);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BRC_H */
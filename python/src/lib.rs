@@ -0,0 +1,64 @@
+// Python bindings: the `brc` module.
+//
+// Built into a wheel with maturin (see pyproject.toml). The aggregation
+// runs with the GIL released, on the library's usual thread pool.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use one_billion_row_challenge::{aggregate_stations, Error, Options, StationMap};
+use pyo3::exceptions::{PyOSError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+/// A station's (min, mean, max, count), in degrees. The mean is rounded
+/// to the temperatures' decimals as the challenge's output is
+type Summary = (f64, f64, f64, u64);
+
+/// Aggregates the measurements file at `path` (or gzip or zstd
+/// compressed one) into a dict from station names to their
+/// (min, mean, max, count), with temperatures in degrees
+#[pyfunction]
+#[pyo3(signature = (path, *, threads = None))]
+fn aggregate(py: Python<'_>, path: PathBuf, threads: Option<usize>) -> PyResult<HashMap<String, Summary>> {
+    let options = Options { threads, ..Default::default() };
+    let data = py.detach(|| aggregate_stations(&path, &options)).map_err(to_py_err)?;
+    summaries(data)
+}
+
+fn summaries(data: StationMap) -> PyResult<HashMap<String, Summary>> {
+    data.into_iter().map(|(name, record)| {
+        let name = String::from_utf8(name.to_vec())
+            .map_err(|e| to_py_err(Error::InvalidUtf8 { station: String::from_utf8_lossy(e.as_bytes()).into_owned() }))?;
+        Ok((name, (record.min(), record.mean_rounded(), record.max(), record.count)))
+    }).collect()
+}
+
+fn to_py_err(error: Error) -> PyErr {
+    match error {
+        e @ Error::Io { .. } => PyOSError::new_err(e.to_string()),
+        e @ (Error::Parse { .. } | Error::InvalidUtf8 { .. }) => PyValueError::new_err(e.to_string()),
+        e => PyRuntimeError::new_err(e.to_string()),
+    }
+}
+
+#[pymodule]
+fn brc(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(aggregate, m)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use one_billion_row_challenge::aggregate_reader;
+
+    #[test]
+    fn summaries_in_degrees() {
+        let data = aggregate_reader(&b"Abha;12.0\nAbha;-3.5\nCairo;20.1\n"[..], &Options::default()).unwrap();
+        let stations = summaries(data).unwrap();
+        assert_eq!(stations["Abha"], (-3.5, 4.3, 12.0, 2));
+        assert_eq!(stations["Cairo"], (20.1, 20.1, 20.1, 1));
+
+        let data = aggregate_reader(&b"\xffbha;1.0\n"[..], &Options::default()).unwrap();
+        assert!(summaries(data).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+// Which CPUs the workers can run on, and pinning them there.
+
+use std::path::Path;
+
+use crate::PinCores;
+
+/// The CPUs this process may run on, or with `PinCores::Physical` just
+/// the first of each core's SMT siblings
+pub(crate) fn cpus(pin: PinCores) -> Vec<usize> {
+    let allowed = allowed_cpus();
+
+    match pin {
+        PinCores::All => allowed,
+        PinCores::Physical => allowed.iter().copied().filter(|&cpu| {
+            let siblings = Path::new("/sys/devices/system/cpu").join(format!("cpu{}/topology/thread_siblings_list", cpu));
+            // The first allowed sibling stands for the core
+            std::fs::read_to_string(siblings).ok()
+                .and_then(|list| parse_cpu_list(&list))
+                .and_then(|siblings| siblings.into_iter().find(|sibling| allowed.contains(sibling)))
+                .is_none_or(|first| first == cpu)
+        }).collect(),
+    }
+}
+
+fn allowed_cpus() -> Vec<usize> {
+    // Safe: the set is zeroed before use and outlives the call
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return (0..std::thread::available_parallelism().map_or(1, |n| n.get())).collect();
+        }
+        (0..libc::CPU_SETSIZE as usize).filter(|&cpu| libc::CPU_ISSET(cpu, &set)).collect()
+    }
+}
+
+/// Pins the calling thread to cpu. Failing to just leaves it unpinned
+pub(crate) fn pin(cpu: usize) {
+    // Safe: the set is zeroed before use and outlives the call
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            log::debug!("Could not pin a worker to CPU {}: {}", cpu, std::io::Error::last_os_error());
+        }
+    }
+}
+
+/// Parses the kernel's list format, e.g. "0-3,8-11,16"
+pub(crate) fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => cpus.extend(first.parse::<usize>().ok()?..=last.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+
+    Some(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_lists() {
+        assert_eq!(parse_cpu_list("0\n"), Some(vec![0]));
+        assert_eq!(parse_cpu_list("0-3,8-9,16\n"), Some(vec![0, 1, 2, 3, 8, 9, 16]));
+        assert_eq!(parse_cpu_list("\n"), Some(vec![]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+    }
+
+    #[test]
+    fn physical_cores_are_allowed_cpus() {
+        let all = cpus(PinCores::All);
+        let physical = cpus(PinCores::Physical);
+        assert!(!physical.is_empty());
+        assert!(physical.iter().all(|cpu| all.contains(cpu)));
+    }
+}
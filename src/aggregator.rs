@@ -0,0 +1,156 @@
+// Statistics other than Record's, kept per station by whoever uses the
+// library: anything that can start from a measurement, take more, and
+// combine with another of its kind from a different chunk.
+//
+// The main parser stays specialised to Record (its tables, merges and
+// engines all are), so aggregating with one goes through a path of its
+// own, as aggregating by time does. It reads the lines as the options
+// say, columns and quotes included.
+
+#[cfg(feature = "native")]
+use std::collections::BTreeMap;
+#[cfg(feature = "native")]
+use std::path::Path;
+
+#[cfg(feature = "native")]
+use crate::par::*;
+
+#[cfg(feature = "native")]
+use crate::error::Invalid;
+#[cfg(feature = "native")]
+use crate::parser::{self, BadLine};
+use crate::{parser::unescape, Record, StationHasher, StationKey};
+#[cfg(feature = "native")]
+use crate::{accept_name, chunk, compression, for_each_line, in_pool, name_ref, regular_file, split_fields, with_file, with_line_number, Error, Options};
+
+/// A statistic of a station's measurements, for [`aggregate_by`].
+/// Measurements are in units of `10^-decimals` degrees, so `123` is
+/// 12.3 degrees with the default precision of 1
+pub trait Aggregator: Send {
+    /// What [`finish`](Self::finish) makes of it
+    type Output;
+
+    /// Starts from a station's first measurement
+    fn new(value: i32, decimals: u8) -> Self;
+
+    /// Adds another measurement
+    fn add(&mut self, value: i32);
+
+    /// Merges in another of the same station's, from another part of
+    /// the input. Parts are combined in no particular order
+    fn combine(&mut self, other: &Self);
+
+    /// The statistic itself, once every measurement is in
+    fn finish(&self) -> Self::Output;
+}
+
+impl Aggregator for Record {
+    type Output = Record;
+
+    fn new(value: i32, decimals: u8) -> Self {
+        Record::with_decimals(value, decimals)
+    }
+
+    fn add(&mut self, value: i32) {
+        Record::add(self, value)
+    }
+
+    fn combine(&mut self, other: &Self) {
+        Record::combine(self, other)
+    }
+
+    fn finish(&self) -> Record {
+        self.clone()
+    }
+}
+
+/// Aggregators keyed by a station's raw name
+pub type AggregatorMap<A> = hashbrown::HashMap<StationKey, A, StationHasher>;
+
+/// [`aggregate_bytes_by`] for an uncompressed file, with each station's
+/// aggregator finished, by name. Names that aren't valid UTF-8 are
+/// converted lossily
+#[cfg(feature = "native")]
+pub fn aggregate_by<A: Aggregator>(path: &Path, options: &Options) -> Result<BTreeMap<String, A::Output>, Error> {
+    let data_map = with_file(path, |file| {
+        regular_file(path, file)?;
+        if compression::detect_file(file)? != compression::Compression::None {
+            return Err(format!("{}: compressed files can't be aggregated by a custom aggregator", path.display()).into());
+        }
+        // Mapping an empty file fails on some platforms
+        if file.metadata()?.len() == 0 {
+            return Ok(AggregatorMap::default());
+        }
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        aggregate_bytes_by::<A>(&mmap, options)
+    })?;
+
+    Ok(data_map.iter().map(|(name, aggregator)| (String::from_utf8_lossy(name).into_owned(), aggregator.finish())).collect())
+}
+
+/// Aggregates measurements with an `A` for every station, in parallel
+/// chunks. The options apply as they do to [`aggregate`](crate::aggregate),
+/// but for histograms and known stations, which are [`Record`]'s
+#[cfg(feature = "native")]
+pub fn aggregate_bytes_by<A: Aggregator>(bytes: &[u8], options: &Options) -> Result<AggregatorMap<A>, Error> {
+    let data_map = in_pool(options, || {
+        chunk::chunks_in(bytes, options)
+            .into_par_iter()
+            .map(|(start, end)| {
+                let mut data_map = AggregatorMap::default();
+                let mut scratch = Vec::new();
+                for_each_line(&bytes[start..end], options, |line| add_line(line, &mut data_map, &mut scratch, options))
+                    .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, bytes, start + at, 0))?;
+                Ok(data_map)
+            })
+            .try_reduce(AggregatorMap::default, |map1, map2| Ok(merge_by(map1, map2)))
+    })
+    .map_err(|e| with_line_number(e, bytes))?;
+
+    let data_map = if options.quoted { unescape_names(data_map) } else { data_map };
+    Ok(crate::normalize::normalize_names(data_map, &options.normalize_keys))
+}
+
+/// Combines the aggregators of two maps
+pub fn merge_by<A: Aggregator>(mut map1: AggregatorMap<A>, map2: AggregatorMap<A>) -> AggregatorMap<A> {
+    for (key, value) in map2 {
+        match map1.entry(key) {
+            hashbrown::hash_map::Entry::Occupied(mut entry) => entry.get_mut().combine(&value),
+            hashbrown::hash_map::Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
+    map1
+}
+
+// The names the parser left quoted, with their escaped quotes put right
+// (see parser::unescape)
+pub(crate) fn unescape_names<A: Aggregator>(data_map: AggregatorMap<A>) -> AggregatorMap<A> {
+    if !data_map.keys().any(|name| name.first() == Some(&b'"')) {
+        return data_map;
+    }
+    let (quoted, unquoted) = data_map.into_iter().partition::<AggregatorMap<A>, _>(|(name, _)| name.first() == Some(&b'"'));
+    let unescaped = quoted.into_iter().map(|(name, value)| (StationKey::from(&unescape(&name)[..]), value));
+    merge_by(unquoted, unescaped.collect())
+}
+
+#[cfg(feature = "native")]
+fn add_line<A: Aggregator>(line: &[u8], data_map: &mut AggregatorMap<A>, scratch: &mut Vec<u8>, options: &Options) -> Result<(), Invalid> {
+    let (name, temperature) = split_fields(line, options)?;
+    let value = match options.columns {
+        Some(_) => parser::parse_value(temperature, options.precision, options.strict, scratch)?,
+        None => parser::parse_temperature(temperature, options.precision, options.strict, scratch)?,
+    };
+    let Some(name) = accept_name(name, options)? else {
+        return Ok(());
+    };
+
+    match data_map.entry_ref(name_ref(name)) {
+        hashbrown::hash_map::EntryRef::Occupied(mut entry) => entry.get_mut().add(value),
+        hashbrown::hash_map::EntryRef::Vacant(entry) => {
+            entry.insert(A::new(value, options.precision));
+        }
+    }
+    Ok(())
+}
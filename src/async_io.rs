@@ -0,0 +1,167 @@
+// aggregate_reader's pipeline on tokio, for embedding in async services.
+//
+// The file is read with tokio's file I/O and cut into blocks at their
+// last \n, which go down a bounded channel so a slow parser pushes back
+// on the reader. Parsing is CPU-bound and mustn't hold up the runtime's
+// workers, so the blocks are parsed by blocking tasks, each into a map
+// of its own, and the maps are merged at the end.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinError;
+
+use crate::{aggregate_path, compression, finish_map, in_pool, max_line_len, merge_maps, new_map, parse_to_end, report_progress, stream_buffers, with_file, BadLine, Error, Options, StationMap};
+
+/// Aggregates the measurements file at `path` without blocking the
+/// async runtime: the file is read with tokio's async I/O, and parsed
+/// on its blocking thread pool by up to [`Options::threads`] tasks.
+/// Blocks are sized as for [`aggregate_reader`](crate::aggregate_reader),
+/// [`Options::max_memory`] included.
+///
+/// Only as much of the file as there was when it was opened is read.
+/// Decompression isn't async, so a compressed file is read as
+/// [`aggregate_stations`](crate::aggregate_stations) reads it, on a
+/// blocking task instead.
+pub async fn aggregate_async(path: &Path, options: &Options) -> Result<StationMap, Error> {
+    read_file(path, None, options).await.map(|data| finish_map(data, options))
+}
+
+/// [`aggregate_async`] on a runtime of its own, for `--engine async`,
+/// reading the first size bytes of the file. The map is left for the
+/// caller to finish
+pub(crate) fn aggregate(path: &Path, size: u64, options: &Options) -> Result<StationMap, Error> {
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    runtime.block_on(read_file(path, Some(size), options))
+}
+
+// aggregate_async's unfinished map, for the first size bytes of the
+// file or as many as it has once it's opened
+async fn read_file(path: &Path, size: Option<u64>, options: &Options) -> Result<StationMap, Error> {
+    let in_file = |e: std::io::Error| Error::from(e).in_file(path);
+    let mut file = tokio::fs::File::open(path).await.map_err(in_file)?;
+    let size = match size {
+        Some(size) => size,
+        None => file.metadata().await.map_err(in_file)?.len(),
+    };
+
+    let mut magic = Vec::with_capacity(4);
+    (&mut file).take(4).read_to_end(&mut magic).await.map_err(in_file)?;
+    file.rewind().await.map_err(in_file)?;
+    if compression::detect(&magic) != compression::Compression::None {
+        let (path, options) = (path.to_path_buf(), options.clone());
+        return tokio::task::spawn_blocking(move || in_pool(&options, || aggregate_path(&path, &options))).await.unwrap_or_else(resume);
+    }
+
+    let (workers, block_size) = stream_buffers(options);
+    let options = Arc::new(options.clone());
+    // Every block goes with its offset in the file, for errors
+    let (sender, receiver) = mpsc::channel::<(u64, Vec<u8>)>(workers);
+    let receiver = Arc::new(Mutex::new(receiver));
+    // Set by a worker that finds a bad line, so the reader can stop
+    let failed = Arc::new(AtomicBool::new(false));
+
+    let handles = (0..workers).map(|_| {
+        let (receiver, failed, options) = (receiver.clone(), failed.clone(), options.clone());
+        tokio::task::spawn_blocking(move || {
+            let mut data_map = new_map();
+            loop {
+                let block = receiver.lock().unwrap().blocking_recv();
+                let Some((offset, block)) = block else { return Ok(data_map) };
+                if let Err(BadLine(at, invalid)) = parse_to_end(&block, &mut data_map, &options) {
+                    failed.store(true, Ordering::Relaxed);
+                    return Err(Error::bad_line(invalid, &block, at, offset));
+                }
+            }
+        })
+    }).collect::<Vec<_>>();
+    // Once every worker has gone, so has the channel
+    drop(receiver);
+
+    let read_result = read_blocks(&mut (&mut file).take(size), sender, block_size, &failed, &options).await;
+
+    // Report the earliest bad line if there are several
+    let mut data = StationMap::default();
+    let mut error: Option<Error> = None;
+    for handle in handles {
+        match handle.await.unwrap_or_else(resume) {
+            Ok(data_map) => data = merge_maps(data, data_map),
+            Err(e) => {
+                let earlier = match (&e, &error) {
+                    (Error::Parse { offset, .. }, Some(Error::Parse { offset: first, .. })) => offset < first,
+                    _ => error.is_none(),
+                };
+                if earlier {
+                    error = Some(e);
+                }
+            }
+        }
+    }
+
+    read_result.map_err(in_file)?;
+    match error {
+        // Counting the lines before a bad one blocks too
+        Some(error) => {
+            let path = path.to_path_buf();
+            tokio::task::spawn_blocking(move || with_file(&path, |_| Err(error))).await.unwrap_or_else(resume)
+        }
+        None => Ok(data),
+    }
+}
+
+// Reads the file into blocks cut at their last \n and sends them to the
+// workers, until the end of the file or one of them fails. Hanging up
+// lets the workers finish their last blocks
+async fn read_blocks(file: &mut (impl AsyncRead + Unpin), sender: mpsc::Sender<(u64, Vec<u8>)>, block_size: usize, failed: &AtomicBool, options: &Options) -> std::io::Result<()> {
+    let mut block = Vec::with_capacity(block_size);
+    let mut offset = 0;
+
+    while !failed.load(Ordering::Relaxed) {
+        // Fill the rest of the block, after any partial line carried
+        // over (or all of it, for one very long line)
+        let filled = block.len();
+        block.resize(if filled < block_size { block_size } else { filled * 2 }, 0);
+        let read = file.read(&mut block[filled..]).await?;
+        block.truncate(filled + read);
+        report_progress(options, read);
+
+        if read == 0 {
+            if !block.is_empty() {
+                let _ = sender.send((offset, block)).await;
+            }
+            break;
+        }
+
+        if block.len() >= block_size {
+            // A line too long to be valid can be rejected without
+            // waiting for the end of it
+            if max_line_len(options).is_some_and(|max| block.len() > max) && memchr::memchr(b'\n', &block).is_none() {
+                let _ = sender.send((offset, block)).await;
+                break;
+            }
+            if let Some(last) = memchr::memrchr(b'\n', &block) {
+                let mut next = Vec::with_capacity(block_size);
+                next.extend_from_slice(&block[last + 1..]);
+                block.truncate(last + 1);
+
+                let full = std::mem::replace(&mut block, next);
+                let full_len = full.len() as u64;
+                if sender.send((offset, full)).await.is_err() {
+                    break;
+                }
+                offset += full_len;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Carries a blocking task's panic on into the awaiting one, as joining
+// a thread would
+fn resume<T>(error: JoinError) -> T {
+    std::panic::resume_unwind(error.into_panic())
+}
@@ -0,0 +1,176 @@
+// Aggregating measurements that come with a timestamp, as
+// `name;temperature;unix_ts`, by station and by the hour, day or month
+// (UTC) each was taken in. The records are the usual ones, keyed by the
+// station and the bucket's number rather than the station alone.
+
+use std::path::Path;
+
+use crate::par::*;
+
+use crate::error::Invalid;
+use crate::parser::{self, BadLine};
+use crate::{accept_name, chunk, compression, for_each_line, in_pool, new_record, regular_file, with_file, with_line_number, Error, Options, Record, StationHasher, StationKey};
+
+/// The spans of time [`aggregate_buckets`] groups measurements by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum TimeBucket {
+    Hour,
+    Day,
+    Month,
+}
+
+impl TimeBucket {
+    /// The number of the bucket a unix timestamp (in seconds) falls in,
+    /// counting from the one 1970 starts in
+    pub fn of(self, timestamp: i64) -> i64 {
+        match self {
+            TimeBucket::Hour => timestamp.div_euclid(3600),
+            TimeBucket::Day => timestamp.div_euclid(86_400),
+            TimeBucket::Month => {
+                let (year, month, _) = civil(timestamp.div_euclid(86_400));
+                (year - 1970) * 12 + month as i64 - 1
+            }
+        }
+    }
+
+    /// The bucket numbered `bucket`, as `2024-01-15T05`, `2024-01-15`
+    /// or `2024-01`
+    pub fn label(self, bucket: i64) -> String {
+        match self {
+            TimeBucket::Hour => {
+                let (year, month, day) = civil(bucket.div_euclid(24));
+                format!("{:04}-{:02}-{:02}T{:02}", year, month, day, bucket.rem_euclid(24))
+            }
+            TimeBucket::Day => {
+                let (year, month, day) = civil(bucket);
+                format!("{:04}-{:02}-{:02}", year, month, day)
+            }
+            TimeBucket::Month => format!("{:04}-{:02}", 1970 + bucket.div_euclid(12), bucket.rem_euclid(12) + 1),
+        }
+    }
+}
+
+// The year, month and day of a day counted from 1970-01-01, in the
+// proleptic Gregorian calendar (Howard Hinnant's civil_from_days)
+fn civil(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Counting months from March, so the leap day comes last
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Records keyed by a station's raw name and the number of a bucket
+/// (see [`TimeBucket::of`])
+pub type BucketMap = hashbrown::HashMap<(StationKey, i64), Record, StationHasher>;
+
+/// Aggregates `name;temperature;unix_ts` lines by station and bucket,
+/// in parallel chunks. The options' precision, strictness, name rules,
+/// station filter and [`OnError`] apply as they do to [`aggregate`](crate::aggregate)
+pub fn aggregate_buckets_bytes(bytes: &[u8], bucket: TimeBucket, options: &Options) -> Result<BucketMap, Error> {
+    in_pool(options, || {
+        chunk::chunks_in(bytes, options)
+            .into_par_iter()
+            .map(|(start, end)| {
+                aggregate_chunk(&bytes[start..end], bucket, options).map_err(|BadLine(at, invalid)| Error::bad_line(invalid, bytes, start + at, 0))
+            })
+            .try_reduce(BucketMap::default, |map1, map2| Ok(merge_buckets(map1, map2)))
+    })
+    .map_err(|e| with_line_number(e, bytes))
+}
+
+/// [`aggregate_buckets_bytes`] for an uncompressed file
+pub fn aggregate_buckets(path: &Path, bucket: TimeBucket, options: &Options) -> Result<BucketMap, Error> {
+    with_file(path, |file| {
+        regular_file(path, file)?;
+        if compression::detect_file(file)? != compression::Compression::None {
+            return Err(format!("{}: compressed files can't be aggregated by time", path.display()).into());
+        }
+        // Mapping an empty file fails on some platforms
+        if file.metadata()?.len() == 0 {
+            return Ok(BucketMap::default());
+        }
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        aggregate_buckets_bytes(&mmap, bucket, options)
+    })
+}
+
+/// Combines the records of two maps
+pub fn merge_buckets(mut map1: BucketMap, map2: BucketMap) -> BucketMap {
+    for (key, value) in map2 {
+        match map1.entry(key) {
+            hashbrown::hash_map::Entry::Occupied(mut entry) => entry.get_mut().combine(&value),
+            hashbrown::hash_map::Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
+    map1
+}
+
+fn aggregate_chunk(chunk: &[u8], bucket: TimeBucket, options: &Options) -> Result<BucketMap, BadLine> {
+    let mut data_map = BucketMap::default();
+    let mut scratch = Vec::new();
+    for_each_line(chunk, options, |line| add_line(line, bucket, &mut data_map, &mut scratch, options))?;
+    Ok(data_map)
+}
+
+fn add_line(line: &[u8], bucket: TimeBucket, data_map: &mut BucketMap, scratch: &mut Vec<u8>, options: &Options) -> Result<(), Invalid> {
+    // The timestamp comes after the last delimiter, and the temperature
+    // before it
+    let (measurement, timestamp) = parser::split_line(line, options.delimiter)?;
+    let (name, temperature) = parser::split_line(measurement, options.delimiter)?;
+    let temp = parser::parse_temperature(temperature, options.precision, options.strict, scratch)?;
+    let timestamp = parser::parse_integer::<i64>(timestamp).ok_or(Invalid::Timestamp)?;
+    let Some(name) = accept_name(name, options)? else {
+        return Ok(());
+    };
+
+    match data_map.entry((StationKey::from(name), bucket.of(timestamp))) {
+        hashbrown::hash_map::Entry::Occupied(mut entry) => entry.get_mut().add(temp),
+        hashbrown::hash_map::Entry::Vacant(entry) => {
+            entry.insert(new_record(temp, options));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OnError;
+
+    #[test]
+    fn buckets() {
+        // 2024-02-29T13:45:00Z
+        let timestamp = 1_709_214_300;
+        for (bucket, label) in [(TimeBucket::Hour, "2024-02-29T13"), (TimeBucket::Day, "2024-02-29"), (TimeBucket::Month, "2024-02")] {
+            assert_eq!(bucket.label(bucket.of(timestamp)), label);
+        }
+        assert_eq!(TimeBucket::Day.label(TimeBucket::Day.of(-1)), "1969-12-31");
+        assert_eq!(TimeBucket::Month.label(TimeBucket::Month.of(-1)), "1969-12");
+        assert_eq!(TimeBucket::Hour.label(TimeBucket::Hour.of(0)), "1970-01-01T00");
+        // The last second of a leap year's last day
+        assert_eq!(TimeBucket::Day.label(TimeBucket::Day.of(1_735_689_599)), "2024-12-31");
+    }
+
+    #[test]
+    fn aggregates_by_bucket() {
+        let bytes = b"Hamburg;12.0;1709214300\nHamburg;14.0;1709215000\nHamburg;-3.0;1709300000\nOslo;1.0;1709214300\nOslo;2.0;x\n";
+        assert!(aggregate_buckets_bytes(bytes, TimeBucket::Day, &Options::default()).is_err());
+
+        let options = Options { on_error: OnError::Skip, ..Default::default() };
+        let data = aggregate_buckets_bytes(bytes, TimeBucket::Day, &options).unwrap();
+        let day = TimeBucket::Day.of(1_709_214_300);
+        assert_eq!(data.len(), 3);
+        let hamburg = &data[&(StationKey::from(&b"Hamburg"[..]), day)];
+        assert_eq!((hamburg.min, hamburg.max, hamburg.count), (120, 140, 2));
+        assert_eq!(data[&(StationKey::from(&b"Hamburg"[..]), day + 1)].min, -30);
+    }
+}
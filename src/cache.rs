@@ -0,0 +1,89 @@
+// Keeping every file's results, so running on it again (say, to try
+// another output format) doesn't parse it again.
+//
+// Each file's entry is a `<hash of its path>.results` file in the cache
+// directory: the file and options it was made from (as a checkpoint's
+// source describes them, plus a fingerprint of the contents), a blank
+// line, and the results as a partial (see partial.rs). An entry that
+// doesn't match the file as it is now is replaced.
+//
+// The fingerprint only hashes samples of the file, so it's cheap for
+// a big one, and catches a file rewritten in place with its size and
+// modification time kept, unless nothing sampled changed.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::checkpoint::{describe, entry_name};
+use crate::{aggregate_file, cancelled, partial, report_progress, Error, Options, StationMap};
+
+// Files up to SAMPLES samples long are hashed whole
+const SAMPLE: u64 = 64 * 1024;
+const SAMPLES: u64 = 16;
+
+pub(crate) fn aggregate_cached(path: &Path, file: &File, cache: &Path, options: &Options) -> Result<StationMap, Error> {
+    let key = format!(
+        "{}fingerprint={:08x}\nvalidate_utf8={}\n\n",
+        describe(path, file, options)?, fingerprint(file)?, options.validate_utf8,
+    );
+    let entry = cache.join(entry_name(&path.canonicalize()?)).with_extension("results");
+
+    match std::fs::read(&entry) {
+        Ok(saved) => {
+            if let Some(data) = saved.strip_prefix(key.as_bytes()).and_then(partial::decode) {
+                log::debug!("{}: results from {}", path.display(), entry.display());
+                report_progress(options, file.metadata()?.len() as usize);
+                return Ok(data);
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(Error::Io { path: Some(entry), source: e }),
+    }
+
+    let data = aggregate_file(path, file, options)?;
+    // What a cancelled run got through is only part of the file's
+    if cancelled(options) {
+        return Ok(data);
+    }
+
+    // Not saving the results doesn't make them wrong
+    if let Err(e) = save(cache, &entry, key, &data) {
+        log::warn!("Could not cache the results for {} in {}: {}", path.display(), entry.display(), e);
+    }
+    Ok(data)
+}
+
+// A CRC of the file's first bytes, its last and evenly spaced samples
+// between (a fixed hash, unlike std's, so a build with another Rust still
+// finds the entry). Leaves the file at its start, for the aggregator
+fn fingerprint(file: &File) -> std::io::Result<u32> {
+    let size = file.metadata()?.len();
+    let mut hasher = crc32fast::Hasher::new();
+    let mut file = file;
+    let mut buffer = Vec::new();
+
+    let samples = if size <= SAMPLE * SAMPLES { 1 } else { SAMPLES };
+    let sample = if samples == 1 { size } else { SAMPLE };
+    for i in 0..samples {
+        let start = if samples == 1 { 0 } else { (size - SAMPLE) * i / (SAMPLES - 1) };
+        file.seek(SeekFrom::Start(start))?;
+        buffer.clear();
+        file.take(sample).read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+
+    file.rewind()?;
+    Ok(hasher.finalize())
+}
+
+// Written next to its final name and renamed into place, so another run
+// never reads half an entry
+fn save(cache: &Path, entry: &Path, key: String, data: &StationMap) -> std::io::Result<()> {
+    std::fs::create_dir_all(cache)?;
+    let temp_path = entry.with_extension(format!("{}.tmp", std::process::id()));
+
+    let mut bytes = key.into_bytes();
+    bytes.extend_from_slice(&partial::encode(data));
+    std::fs::write(&temp_path, bytes).and_then(|_| std::fs::rename(&temp_path, entry))
+}
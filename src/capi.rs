@@ -0,0 +1,182 @@
+//! A C API, for linking the aggregator into C and C++ programs.
+//!
+//! Build it as a shared or static library with
+//!
+//! ```text
+//! cargo rustc --release --lib --features capi --crate-type cdylib
+//! cargo rustc --release --lib --features capi --crate-type staticlib
+//! ```
+//!
+//! and include `include/brc.h`, which cbindgen generates from this
+//! module (`cbindgen --config cbindgen.toml --output include/brc.h`):
+//!
+//! ```c
+//! brc_result *result;
+//! if (brc_aggregate("measurements.txt", &result) != 0) {
+//!     fprintf(stderr, "%s\n", brc_last_error());
+//!     return 1;
+//! }
+//! for (size_t i = 0; i < brc_result_len(result); i++) {
+//!     brc_station station = brc_result_get(result, i);
+//!     printf("%s=%.1f/%.1f/%.1f\n", station.name, station.min, station.mean, station.max);
+//! }
+//! brc_result_free(result);
+//! ```
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::path::Path;
+
+use crate::{aggregate_stations, Error, Options, Record};
+
+/// The results of a [`brc_aggregate`] call: every station, sorted by
+/// name. Opaque to C
+#[allow(non_camel_case_types)]
+pub struct brc_result {
+    // Names with a NUL after them, so C can use them as strings (unless
+    // a name has a NUL of its own)
+    stations: Vec<(Vec<u8>, Record)>,
+}
+
+/// One station's results, with its temperatures in degrees
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct brc_station {
+    /// The name, NUL terminated, and valid until the result is freed
+    pub name: *const c_char,
+    /// The name's length in bytes, not counting the NUL
+    pub name_len: usize,
+    pub min: f64,
+    /// Rounded to the temperatures' decimals, as the challenge's
+    /// output is
+    pub mean: f64,
+    pub max: f64,
+    pub count: u64,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: &Error) -> c_int {
+    let message = CString::new(error.to_string().replace('\0', "\\0")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    error.exit_code()
+}
+
+/// Aggregates the measurements file at `path` (gzip and zstd compressed
+/// files too) with the default options. On success, returns 0 and sets
+/// `*out` to the results, which the caller frees with
+/// [`brc_result_free`]. Otherwise returns the same code the `1brc`
+/// command exits with (3 for I/O errors, 4 for malformed input, 1 for
+/// anything else) and leaves `*out` alone; [`brc_last_error`] says what
+/// went wrong.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string and `out` must be valid to
+/// write a pointer to.
+#[no_mangle]
+pub unsafe extern "C" fn brc_aggregate(path: *const c_char, out: *mut *mut brc_result) -> c_int {
+    if path.is_null() || out.is_null() {
+        return set_last_error(&"brc_aggregate: path and out can't be NULL".into());
+    }
+
+    #[cfg(unix)]
+    let path = Path::new(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(CStr::from_ptr(path).to_bytes()));
+    #[cfg(not(unix))]
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => Path::new(path),
+        Err(_) => return set_last_error(&"brc_aggregate: path isn't valid UTF-8".into()),
+    };
+
+    // A panic mustn't unwind into C
+    match std::panic::catch_unwind(|| aggregate_stations(path, &Options::default())) {
+        Ok(Ok(data)) => {
+            let mut stations: Vec<(Vec<u8>, Record)> = data.into_iter().map(|(name, record)| {
+                let mut name = name.to_vec();
+                name.push(0);
+                (name, record)
+            }).collect();
+            stations.sort_unstable_by(|(name1, _), (name2, _)| name1.cmp(name2));
+            *out = Box::into_raw(Box::new(brc_result { stations }));
+            0
+        }
+        Ok(Err(error)) => set_last_error(&error),
+        Err(_) => set_last_error(&"brc_aggregate: the aggregator panicked".into()),
+    }
+}
+
+/// The message for the last error on this thread, or NULL if there
+/// hasn't been one. Valid until the thread's next failing call
+#[no_mangle]
+pub extern "C" fn brc_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |message| message.as_ptr()))
+}
+
+/// The number of stations in `result`
+///
+/// # Safety
+///
+/// `result` must have come from [`brc_aggregate`] and not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn brc_result_len(result: *const brc_result) -> usize {
+    (*result).stations.as_slice().len()
+}
+
+/// The `index`th station in `result`, by name. An index past the end
+/// gives a station with a NULL name and zero count
+///
+/// # Safety
+///
+/// `result` must have come from [`brc_aggregate`] and not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn brc_result_get(result: *const brc_result, index: usize) -> brc_station {
+    match (*result).stations.as_slice().get(index) {
+        Some((name, record)) => brc_station {
+            name: name.as_ptr() as *const c_char,
+            name_len: name.len() - 1,
+            min: record.min(),
+            mean: record.mean_rounded(),
+            max: record.max(),
+            count: record.count,
+        },
+        None => brc_station { name: std::ptr::null(), name_len: 0, min: 0., mean: 0., max: 0., count: 0 },
+    }
+}
+
+/// Frees `result`. NULL is ignored
+///
+/// # Safety
+///
+/// `result` must be NULL or have come from [`brc_aggregate`], and not
+/// been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn brc_result_free(result: *mut brc_result) {
+    if !result.is_null() {
+        drop(Box::from_raw(result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_and_fails_from_c() {
+        unsafe {
+            let mut result = std::ptr::null_mut();
+            assert_eq!(brc_aggregate(c"tests/data/crlf.txt".as_ptr(), &mut result), 0);
+            assert_eq!(brc_result_len(result), 3);
+
+            let station = brc_result_get(result, 0);
+            assert_eq!(CStr::from_ptr(station.name), c"Bulawayo");
+            assert_eq!((station.name_len, station.min, station.max, station.count), (8, -3.5, 8.9, 2));
+            assert!(brc_result_get(result, 3).name.is_null());
+            brc_result_free(result);
+
+            assert_eq!(brc_aggregate(c"tests/data/missing.txt".as_ptr(), &mut result), 3);
+            assert!(CStr::from_ptr(brc_last_error()).to_str().unwrap().contains("missing.txt"));
+        }
+    }
+}
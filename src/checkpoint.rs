@@ -0,0 +1,138 @@
+// Saving every chunk's partial results as it finishes, so a run that's
+// killed partway can pick up where it left off.
+//
+// Each input file gets its own directory under the checkpoint directory,
+// named after a hash of its path. In it, `source` describes the file and
+// the options the partials were made with, and every finished chunk is a
+// `<start>-<end>.partial` file (see partial.rs). A later run that finds a
+// partial for one of its chunks loads it instead of parsing the chunk,
+// as long as `source` still matches: if the file or the options have
+// changed since, the old partials are thrown away.
+//
+// `source` has the file's path, size and modification time, and every
+// option that changes what a chunk's results are: the delimiter, the
+// --station filters, histograms, precision, the name limits, --on-error,
+// the columns and quoting, --assert-range, --strict and --normalize-keys.
+// Chunks are cut by --chunk-size and the thread count (see chunk.rs), and
+// a partial is named for its range, so resuming with a different -t or
+// --chunk-size only reuses the partials whose ranges happen to line up.
+
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::par::*;
+
+use crate::{cancelled, chunk, merge_maps, new_map, parse_to_end, partial, reduce_maps, report_progress, BadLine, Error, Options, StationMap};
+
+pub(crate) fn aggregate_checkpointed(path: &Path, file: &File, checkpoint: &Path, options: &Options) -> Result<StationMap, Error> {
+    let dir = prepare(path, file, checkpoint, options)?;
+
+    let mmap = unsafe { memmap2::Mmap::map(file)? };
+    let bytes = &mmap[..];
+
+    let maps = chunk::chunks_in(bytes, options).into_par_iter().try_fold(new_map, |data_map, (start, end)| {
+        if cancelled(options) {
+            return Ok(data_map);
+        }
+        let partial_path = dir.join(format!("{}-{}.partial", start, end));
+
+        let chunk_map = match load(&partial_path)? {
+            Some(chunk_map) => chunk_map,
+            None => {
+                let mut chunk_map = new_map();
+                parse_to_end(&bytes[start..end], &mut chunk_map, options)
+                    .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, bytes, start + at, 0))?;
+                save(&partial_path, &chunk_map)?;
+                chunk_map
+            }
+        };
+
+        report_progress(options, end - start);
+        Ok(merge_maps(data_map, chunk_map))
+    });
+    reduce_maps(maps, options)
+}
+
+// Finds (or makes) the directory for path's partials, emptying it if
+// they were made from a different version of the file or with different
+// options
+fn prepare(path: &Path, file: &File, checkpoint: &Path, options: &Options) -> Result<PathBuf, Error> {
+    let source = describe(path, file, options)?;
+    let dir = checkpoint.join(entry_name(&path.canonicalize()?));
+    let source_path = dir.join("source");
+
+    let in_checkpoint = |e: std::io::Error| Error::Io { path: Some(dir.clone()), source: e };
+
+    match std::fs::read_to_string(&source_path) {
+        Ok(saved) if saved == source => return Ok(dir),
+        Ok(_) => std::fs::remove_dir_all(&dir).map_err(in_checkpoint)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(in_checkpoint(e)),
+    }
+
+    std::fs::create_dir_all(&dir).map_err(in_checkpoint)?;
+    std::fs::write(&source_path, source).map_err(in_checkpoint)?;
+    Ok(dir)
+}
+
+/// What results from path depend on: the file (by path, size and
+/// modification time) and the options that change what's parsed
+pub(crate) fn describe(path: &Path, file: &File, options: &Options) -> Result<String, Error> {
+    let metadata = file.metadata()?;
+
+    Ok(format!(
+        "path={}\nsize={}\nmodified={:?}\ndelimiter={}\nstations={:?}\nhistograms={}\nprecision={}\nmax_name_len={:?}\ntruncate_names={}\non_error={:?}\ncolumns={:?}\nquoted={}\nrange={:?}\nstrict={}\nnormalize_keys={:?}\n",
+        path.canonicalize()?.display(),
+        metadata.len(),
+        metadata.modified()?,
+        options.delimiter,
+        options.stations,
+        options.histograms,
+        options.precision,
+        options.max_name_len,
+        options.truncate_names,
+        options.on_error,
+        options.columns,
+        options.quoted,
+        options.range,
+        options.strict,
+        options.normalize_keys,
+    ))
+}
+
+/// A file name for the results of the file at (canonical) path: a CRC-32
+/// of the path, which (unlike std's hashers) every build agrees on
+pub(crate) fn entry_name(path: &Path) -> String {
+    format!("{:08x}", crc32fast::hash(path.as_os_str().as_encoded_bytes()))
+}
+
+// A partial that's missing or unreadable (say, from an older version)
+// just means parsing the chunk again
+fn load(path: &Path) -> Result<Option<StationMap>, Error> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(partial::decode(&bytes)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::Io { path: Some(path.to_path_buf()), source: e }),
+    }
+}
+
+// Written next to its final name and renamed into place, so a run
+// killed partway through never leaves half a partial behind
+fn save(path: &Path, data: &StationMap) -> Result<(), Error> {
+    let temp_path = path.with_extension("tmp");
+
+    std::fs::write(&temp_path, partial::encode(data))
+        .and_then(|_| std::fs::rename(&temp_path, path))
+        .map_err(|e| Error::Io { path: Some(path.to_path_buf()), source: e })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_names_are_stable() {
+        assert_eq!(entry_name(Path::new("/data/measurements.txt")), "af42a3a7");
+    }
+}
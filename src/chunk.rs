@@ -0,0 +1,274 @@
+//! Splitting a measurements file into line-aligned chunks that can be
+//! parsed independently.
+//!
+//! Every chunk is a half-open `(start, end)` byte range. Chunks are
+//! contiguous, cover the whole file, and (apart from the first) start
+//! just after a `\n`, so no line is ever split between two chunks.
+//!
+//! Files are cut into many more chunks than there are threads, each
+//! around [`TARGET_CHUNK_SIZE`], and rayon's work stealing hands them
+//! out. That way a slow chunk (cold pages, remote memory) only holds
+//! up its own thread for a moment rather than the whole run.
+//!
+//! With [`ChunkSize::Auto`] the size is picked for the input instead:
+//! the first [`PROBE_SIZE`] bytes are read and parsed on one thread,
+//! and chunks are cut to take about [`CHUNK_TIME`] each at that rate,
+//! small enough that every thread gets [`CHUNKS_PER_THREAD`] of them
+//! (so faster cores, as P-cores next to E-cores, take more) but never
+//! below [`MIN_CHUNK_SIZE`], so a small file is a few chunks rather
+//! than a sliver for every thread.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::{new_map, parse_lines, BadLine, ChunkSource, Options};
+
+/// Roughly how many bytes each chunk covers
+pub const TARGET_CHUNK_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Bytes read and parsed to time the input with [`ChunkSize::Auto`]
+pub const PROBE_SIZE: usize = 1024 * 1024;
+/// About how long each chunk takes with [`ChunkSize::Auto`]
+pub const CHUNK_TIME: Duration = Duration::from_millis(20);
+/// How many chunks [`ChunkSize::Auto`] gives each thread at least, when
+/// the file is big enough
+pub const CHUNKS_PER_THREAD: u64 = 8;
+/// The smallest chunks [`ChunkSize::Auto`] cuts
+pub const MIN_CHUNK_SIZE: u64 = 256 * 1024;
+/// The largest chunks [`ChunkSize::Auto`] cuts
+pub const MAX_CHUNK_SIZE: u64 = 256 * 1024 * 1024;
+
+/// How big the chunks are ([`Options::chunk_size`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkSize {
+    /// About this many bytes, but at least one chunk per thread
+    Bytes(u64),
+    /// Picked from the input's size, the number of threads and how fast
+    /// the start of it is read and parsed
+    Auto,
+}
+
+impl Default for ChunkSize {
+    fn default() -> Self {
+        ChunkSize::Bytes(TARGET_CHUNK_SIZE)
+    }
+}
+
+// Enough chunks of about TARGET_CHUNK_SIZE to cover size bytes, but
+// never fewer than one per thread so small files still use them all
+pub(crate) fn chunk_count(size: u64) -> u64 {
+    size.div_ceil(TARGET_CHUNK_SIZE).max(crate::par::current_num_threads() as u64)
+}
+
+// How many chunks to cut size bytes into with options' chunk size.
+// probe reads up to the given number of bytes from the start of the
+// input, and is only called for ChunkSize::Auto
+pub(crate) fn count_for<E>(size: u64, options: &Options, probe: impl FnOnce(usize) -> Result<Vec<u8>, E>) -> Result<u64, E> {
+    let threads = crate::par::current_num_threads() as u64;
+    match options.chunk_size {
+        ChunkSize::Bytes(bytes) => Ok(size.div_ceil(bytes.max(1)).max(threads)),
+        ChunkSize::Auto => {
+            let started = Instant::now();
+            let bytes = probe(PROBE_SIZE.min(size as usize))?;
+            let rate = probe_rate(&bytes, started, options);
+            let chunk_size = auto_size(size, threads, rate);
+            log::debug!("Read and parsed the first {} bytes at {:.0} MB/s, so cutting {} byte chunks", bytes.len(), rate / 1e6, chunk_size);
+            Ok(size.div_ceil(chunk_size).max(1))
+        }
+    }
+}
+
+// Bytes a second one thread got through bytes at, counting from when
+// it started reading them
+fn probe_rate(bytes: &[u8], started: Instant, options: &Options) -> f64 {
+    // Nothing parsed here is counted or reported
+    let options = Options { progress: None, bad_lines: None, live: None, timings: None, ..options.clone() };
+    let _ = parse_lines(bytes, &mut new_map(), &options).unwrap_or_else(|BadLine(at, _)| at);
+    bytes.len() as f64 / started.elapsed().as_secs_f64().max(1e-9)
+}
+
+// The chunk size for size bytes read with threads threads at rate bytes a
+// second each
+fn auto_size(size: u64, threads: u64, rate: f64) -> u64 {
+    let by_time = (rate * CHUNK_TIME.as_secs_f64()) as u64;
+    let by_threads = size.div_ceil(threads.max(1) * CHUNKS_PER_THREAD);
+    by_time.min(by_threads).clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+}
+
+/// Splits the file at `path`, which is `size` bytes long, into chunks
+/// of about [`TARGET_CHUNK_SIZE`] (and at least one per rayon thread).
+pub fn chunk_specs(path: &Path, size: u64) -> std::io::Result<Vec<(u64, u64)>> {
+    split_source(&std::fs::File::open(path)?, size, chunk_count(size))
+}
+
+// chunk_specs with options' chunk size
+pub(crate) fn file_chunks(path: &Path, size: u64, options: &Options) -> std::io::Result<Vec<(u64, u64)>> {
+    source_chunks(&std::fs::File::open(path)?, size, options)
+}
+
+// The chunks of the first size bytes of source, with options' chunk size
+pub(crate) fn source_chunks(source: &impl ChunkSource, size: u64, options: &Options) -> std::io::Result<Vec<(u64, u64)>> {
+    let count = count_for(size, options, |len| Ok::<_, std::io::Error>(source.read_range(0, len as u64)?.as_ref().to_vec()))?;
+    split_source(source, size, count)
+}
+
+// Splits the source into count chunks, or fewer on tiny ones
+fn split_source(source: &impl ChunkSource, size: u64, chunk_count: u64) -> std::io::Result<Vec<(u64, u64)>> {
+    let chunk_count = chunk_count.max(1);
+    let chunk_size = size / chunk_count;
+    let mut chunk_specs = Vec::with_capacity(chunk_count as usize);
+    let mut chunk_start = 0;
+    let mut probe = vec![0; 4096];
+
+    for _ in 0..chunk_count {
+        // The cut doesn't necessarily fall at the end of a line, so
+        // read on from it until the next \n
+        let mut chunk_end = chunk_start + chunk_size;
+        loop {
+            let read = match source.read_at(chunk_end, &mut probe) {
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            match memchr::memchr(b'\n', &probe[..read]) {
+                Some(newline) => break chunk_end += newline as u64 + 1,
+                None if read == 0 => break,
+                None => chunk_end += read as u64,
+            }
+        }
+        let chunk_end = size.min(chunk_end);
+
+        // On tiny inputs the earlier chunks can already reach the end,
+        // which would leave the later ones empty
+        if chunk_start < chunk_end {
+            chunk_specs.push((chunk_start, chunk_end));
+        }
+        chunk_start = chunk_end;
+    }
+
+    Ok(chunk_specs)
+}
+
+/// Same split as [`chunk_specs`], but for a file that's already in
+/// memory (e.g. mapped), so the newline search happens directly on
+/// the bytes.
+pub fn chunk_specs_in(bytes: &[u8]) -> Vec<(usize, usize)> {
+    split_in(bytes, chunk_count(bytes.len() as u64) as usize)
+}
+
+// chunk_specs_in with options' chunk size
+pub(crate) fn chunks_in(bytes: &[u8], options: &Options) -> Vec<(usize, usize)> {
+    let count = count_for(bytes.len() as u64, options, |len| Ok::<_, std::convert::Infallible>(bytes[..len].to_vec()));
+    split_in(bytes, count.unwrap_or_else(|never| match never {}) as usize)
+}
+
+/// Splits `bytes` into `count` line-aligned chunks of about the same
+/// size, or fewer if there aren't enough lines to go round.
+pub fn split_in(bytes: &[u8], count: usize) -> Vec<(usize, usize)> {
+    // Chunks that would start past the end of the file (tiny files,
+    // many threads) are dropped
+    let chunk_count = count.max(1);
+    let chunk_size = bytes.len() / chunk_count;
+
+    let mut chunk_specs = Vec::with_capacity(chunk_count);
+    let mut chunk_start = 0;
+
+    while chunk_start < bytes.len() {
+        let chunk_end = (chunk_start + chunk_size).min(bytes.len());
+
+        let chunk_end = match bytes[chunk_end..].iter().position(|&c| c == b'\n') {
+            Some(offset) => chunk_end + offset + 1,
+            None => bytes.len(),
+        };
+
+        chunk_specs.push((chunk_start, chunk_end));
+        chunk_start = chunk_end;
+    }
+
+    chunk_specs
+}
+
+/// Something [`check`] found wrong with a file's chunks
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// The chunk at this index is empty, or ends before it starts
+    Empty(usize),
+    /// Bytes `from..to` aren't in any chunk
+    Gap { from: u64, to: u64 },
+    /// The chunk at this index covers bytes `from..to` again
+    Overlap { chunk: usize, from: u64, to: u64 },
+    /// The chunk at this index starts partway through a line
+    MidLine { chunk: usize, start: u64 },
+    /// The chunk at this index ends past the end of the file
+    PastEnd { chunk: usize, end: u64 },
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Problem::Empty(chunk) => write!(f, "chunk {} is empty", chunk),
+            Problem::Gap { from, to } => write!(f, "bytes {}..{} aren't in any chunk", from, to),
+            Problem::Overlap { chunk, from, to } => write!(f, "chunk {} covers bytes {}..{} again", chunk, from, to),
+            Problem::MidLine { chunk, start } => write!(f, "chunk {} starts at {}, partway through a line", chunk, start),
+            Problem::PastEnd { chunk, end } => write!(f, "chunk {} ends at {}, past the end of the file", chunk, end),
+        }
+    }
+}
+
+/// Checks that `chunks` split `bytes` the way the parsers need: in
+/// order, with no gaps or overlaps, covering exactly `0..bytes.len()`,
+/// and every chunk starting at the start of a line. Returns everything
+/// that's wrong.
+pub fn check(bytes: &[u8], chunks: &[(u64, u64)]) -> Vec<Problem> {
+    let size = bytes.len() as u64;
+    let mut problems = Vec::new();
+    // Everything before this is in a chunk
+    let mut covered = 0;
+
+    for (chunk, &(start, end)) in chunks.iter().enumerate() {
+        if start >= end {
+            problems.push(Problem::Empty(chunk));
+        }
+        if start > covered {
+            problems.push(Problem::Gap { from: covered, to: start });
+        } else if start < covered {
+            problems.push(Problem::Overlap { chunk, from: start, to: covered.min(end) });
+        }
+        if start > 0 && start <= size && bytes[start as usize - 1] != b'\n' {
+            problems.push(Problem::MidLine { chunk, start });
+        }
+        if end > size {
+            problems.push(Problem::PastEnd { chunk, end });
+        }
+        covered = covered.max(end);
+    }
+
+    if covered < size {
+        problems.push(Problem::Gap { from: covered, to: size });
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_sizes() {
+        const GB: f64 = 1e9;
+        // Cut to the time a chunk takes on a big file
+        assert_eq!(auto_size(10 << 30, 8, GB), (GB * CHUNK_TIME.as_secs_f64()) as u64);
+        // But several for each thread on a smaller one
+        assert_eq!(auto_size(64 << 20, 8, GB), (64 << 20) / 64);
+        // Never slivers, nor whole gigabytes
+        assert_eq!(auto_size(1 << 20, 8, GB), MIN_CHUNK_SIZE);
+        assert_eq!(auto_size(1 << 40, 1, 100. * GB), MAX_CHUNK_SIZE);
+
+        let bytes = (0..100_000).map(|i| format!("Station {};{}.{}\n", i % 97, i % 80 - 40, i % 10)).collect::<String>().into_bytes();
+        let options = Options { chunk_size: ChunkSize::Auto, ..Default::default() };
+        let chunks = chunks_in(&bytes, &options);
+        // A couple of megabytes is only a few chunks, however many threads
+        assert!(chunks.len() as u64 <= (bytes.len() as u64).div_ceil(MIN_CHUNK_SIZE));
+        assert_eq!(check(&bytes, &chunks.iter().map(|&(start, end)| (start as u64, end as u64)).collect::<Vec<_>>()), []);
+    }
+}
@@ -0,0 +1,90 @@
+// Ordering station names the way people look for them in a report,
+// rather than by their bytes: by their letters without case or accents
+// first (so Zürich goes between Zurich and Zwolle, rather than after
+// Zyrardów), then with their accents, and then with their case. Only
+// the Latin letters' accents are known; other scripts go by code point.
+
+use clap::ValueEnum;
+use one_billion_row_challenge::StationKey;
+
+/// The order names are printed in, and ties in --sort and --top are
+/// left in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Collation {
+    /// By their bytes, as the challenge orders them
+    #[default]
+    Bytes,
+    /// As people would look for them: ignoring case and accents, then
+    /// by them
+    Unicode,
+}
+
+/// Sorts stations by their names' keys. Those already in byte order
+/// stay in it for names that only differ in case
+pub fn sort<T>(data: &mut [(StationKey, T)]) {
+    data.sort_by_cached_key(|(name, _)| key(&String::from_utf8_lossy(name)));
+}
+
+/// What `name` sorts by. Names with the same key are the same but for
+/// case, and sort by their bytes
+pub fn key(name: &str) -> (String, String) {
+    let lower = name.chars().flat_map(char::to_lowercase).collect::<String>();
+    let mut letters = String::with_capacity(lower.len());
+    for c in lower.chars() {
+        match c {
+            'ß' => letters.push_str("ss"),
+            'æ' => letters.push_str("ae"),
+            'œ' => letters.push_str("oe"),
+            'þ' => letters.push_str("th"),
+            _ => letters.push(unaccented(c)),
+        }
+    }
+    (letters, lower)
+}
+
+// A lowercase letter without its accent
+fn unaccented(c: char) -> char {
+    const ACCENTED: [(&str, char); 19] = [
+        ("àáâãäåāăą", 'a'), ("çćĉċč", 'c'), ("ďđð", 'd'), ("èéêëēĕėęě", 'e'), ("ĝğġģ", 'g'),
+        ("ĥħ", 'h'), ("ìíîïĩīĭįı", 'i'), ("ĵ", 'j'), ("ķ", 'k'), ("ĺļľŀł", 'l'), ("ñńņňŉ", 'n'),
+        ("òóôõöøōŏő", 'o'), ("ŕŗř", 'r'), ("śŝşšș", 's'), ("ţťŧț", 't'), ("ùúûüũūŭůűų", 'u'),
+        ("ŵ", 'w'), ("ýÿŷ", 'y'), ("źżž", 'z'),
+    ];
+    if c.is_ascii() {
+        return c;
+    }
+    ACCENTED.iter().find(|(letters, _)| letters.contains(c)).map_or(c, |&(_, base)| base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&'static str]) -> Vec<&'static str> {
+        let mut names = names.to_vec();
+        names.sort();
+        names.sort_by_cached_key(|name| key(name));
+        names
+    }
+
+    #[test]
+    fn accents_after_letters() {
+        assert_eq!(sorted(&["Zyrardów", "Zürich", "Zwolle", "Zurich"]), ["Zurich", "Zürich", "Zwolle", "Zyrardów"]);
+        assert_eq!(sorted(&["Østersund", "Oslo", "Ottawa"]), ["Oslo", "Østersund", "Ottawa"]);
+        assert_eq!(sorted(&["Straßburg", "Strasbourg", "Stuttgart"]), ["Strasbourg", "Straßburg", "Stuttgart"]);
+    }
+
+    #[test]
+    fn sorts_stations() {
+        let mut data = ["Abha", "Zürich", "Zwolle", "abha", "Zurich"].map(|name| (StationKey::from(name.as_bytes()), ())).to_vec();
+        data.sort_by(|a, b| a.0.cmp(&b.0));
+        sort(&mut data);
+        let names = data.iter().map(|(name, _)| String::from_utf8_lossy(name).into_owned()).collect::<Vec<_>>();
+        assert_eq!(names, ["Abha", "abha", "Zurich", "Zürich", "Zwolle"]);
+    }
+
+    #[test]
+    fn case_last() {
+        assert_eq!(sorted(&["abha", "Accra", "Abha"]), ["Abha", "abha", "Accra"]);
+    }
+}
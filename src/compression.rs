@@ -0,0 +1,191 @@
+// Transparent decompression of gzip and zstd measurement files.
+//
+// Compressed files can't be split into chunks by byte offset, so they
+// go through the sequential aggregate_reader pipeline instead. The one
+// exception is zstd's seekable format, which is a series of
+// independent frames plus a table of their sizes: those frames are
+// decompressed and parsed in parallel, much like chunks.
+
+use std::fs::File;
+use std::io::Read;
+
+#[cfg(feature = "zstd")]
+use crate::par::*;
+
+use crate::{Error, Options, StationMap};
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use crate::read_stream;
+#[cfg(feature = "zstd")]
+use crate::{merge_maps, new_map, parse_lines, parse_to_end, BadLine};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+// Sniffs the compression from the magic bytes at the start of the
+// file, so it works whatever the file is called
+pub(crate) fn detect(bytes: &[u8]) -> Compression {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        Compression::Gzip
+    } else if bytes.starts_with(&ZSTD_MAGIC) {
+        Compression::Zstd
+    } else {
+        Compression::None
+    }
+}
+
+pub(crate) fn detect_file(file: &File) -> std::io::Result<Compression> {
+    let mut magic = [0; 4];
+    let mut read = 0;
+
+    // A short read just means a short file, which can't be compressed
+    let mut handle = file;
+    while read < magic.len() {
+        match handle.read(&mut magic[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    Ok(detect(&magic[..read]))
+}
+
+// Aggregates a compressed file. bytes is the whole (mapped) file
+#[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+pub(crate) fn aggregate_compressed(compression: Compression, bytes: &[u8], options: &Options) -> Result<StationMap, Error> {
+    match compression {
+        Compression::None => unreachable!("uncompressed files are chunked directly"),
+
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => read_stream(flate2::bufread::MultiGzDecoder::new(bytes), options),
+
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => match zstd_seek_table(bytes) {
+            Some(frames) => aggregate_zstd_seekable(bytes, &frames, options),
+            None => read_stream(zstd::stream::read::Decoder::with_buffer(bytes)?, options),
+        },
+
+        #[allow(unreachable_patterns)]
+        compression => Err(format!("{:?} input support isn't enabled in this build", compression).into()),
+    }
+}
+
+// A frame of a seekable zstd file: (offset, compressed size, decompressed size)
+#[cfg(feature = "zstd")]
+type Frame = (usize, usize, usize);
+
+// Reads the seek table at the end of a seekable zstd file, if there
+// is one. See
+// https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md
+#[cfg(feature = "zstd")]
+fn zstd_seek_table(bytes: &[u8]) -> Option<Vec<Frame>> {
+    const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+    const SKIPPABLE_MAGIC: u32 = 0x184D2A5E;
+
+    let u32_at = |offset: usize| {
+        Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().unwrap()))
+    };
+
+    // The footer is the number of frames, a descriptor byte and the magic
+    let footer = bytes.len().checked_sub(9)?;
+    if u32_at(footer + 5)? != SEEKABLE_MAGIC {
+        return None;
+    }
+
+    let frame_count = u32_at(footer)? as usize;
+    let has_checksums = bytes[footer + 4] & 0x80 != 0;
+    let entry_size = if has_checksums { 12 } else { 8 };
+
+    // The table lives in a skippable frame, whose 8 byte header gives
+    // the size of everything after it
+    let table_size = frame_count.checked_mul(entry_size)?;
+    let table = footer.checked_sub(table_size)?;
+    let header = table.checked_sub(8)?;
+    if u32_at(header)? != SKIPPABLE_MAGIC || u32_at(header + 4)? as usize != table_size + 9 {
+        return None;
+    }
+
+    let mut frames = Vec::with_capacity(frame_count);
+    let mut offset = 0;
+
+    for i in 0..frame_count {
+        let entry = table + i * entry_size;
+        let compressed = u32_at(entry)? as usize;
+        let decompressed = u32_at(entry + 4)? as usize;
+
+        frames.push((offset, compressed, decompressed));
+        offset += compressed;
+    }
+
+    // The frames have to account for everything before the table
+    (offset == header).then_some(frames)
+}
+
+#[cfg(feature = "zstd")]
+fn aggregate_zstd_seekable(bytes: &[u8], frames: &[Frame], options: &Options) -> Result<StationMap, Error> {
+    // Frames are cut at arbitrary byte offsets rather than at line
+    // breaks, so each worker parses the complete lines inside its frame
+    // and returns the partial lines at either end. Those are stitched
+    // back together in order afterwards. Frames are decompressed a
+    // round at a time so only a few are ever in memory at once
+    let round_size = current_num_threads() * 2;
+
+    // Where each frame starts in the decompressed data, for errors
+    let starts = frames.iter().scan(0, |start, &(_, _, decompressed)| {
+        let frame_start = *start;
+        *start += decompressed as u64;
+        Some(frame_start)
+    }).collect::<Vec<_>>();
+
+    let mut data = StationMap::default();
+    let mut carry = Vec::new();
+
+    for (round, round_starts) in frames.chunks(round_size).zip(starts.chunks(round_size)) {
+        let parsed = round.par_iter().zip(round_starts).map(|(&(offset, compressed, decompressed), &frame_start)| {
+            let frame = zstd::bulk::decompress(&bytes[offset..offset + compressed], decompressed)?;
+
+            let mut data_map = new_map();
+
+            let Some(first) = memchr::memchr(b'\n', &frame) else {
+                // No line ends in this frame, so all of it belongs to a
+                // line that started in an earlier one
+                return Ok((data_map, None, frame, frame_start));
+            };
+            let last = memchr::memrchr(b'\n', &frame).unwrap();
+
+            parse_lines(&frame[first + 1..=last], &mut data_map, options)
+                .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &frame, first + 1 + at, frame_start))?;
+
+            let head = frame[..=first].to_vec();
+            let tail = frame[last + 1..].to_vec();
+            Ok((data_map, Some(head), tail, frame_start))
+        }).collect::<Result<Vec<_>, Error>>()?;
+
+        for (data_map, head, tail, frame_start) in parsed {
+            data = merge_maps(data, data_map);
+
+            match head {
+                Some(head) => {
+                    // The carried partial line ran right up to this frame
+                    let carry_offset = frame_start - carry.len() as u64;
+                    carry.extend_from_slice(&head);
+                    parse_lines(&carry, &mut data, options)
+                        .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &carry, at, carry_offset))?;
+                    carry = tail;
+                }
+                None => carry.extend_from_slice(&tail),
+            }
+        }
+    }
+
+    let total = starts.last().zip(frames.last()).map_or(0, |(start, frame)| start + frame.2 as u64);
+    parse_to_end(&carry, &mut data, options)
+        .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &carry, at, total - carry.len() as u64))?;
+    Ok(data)
+}
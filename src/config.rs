@@ -0,0 +1,172 @@
+// `brc.toml` (or the file --config names): defaults for the command line.
+//
+// Top-level keys are flags by their long names, as `engine = "mmap"`,
+// `threads = 8` or `paths = ["a.txt", "b.txt"]`, and go to whichever
+// command is run that has them. A table, as `[generate]`, is for that
+// command alone. Each is turned into arguments ahead of the ones given,
+// unless the command line already has that flag or its BRC_ environment
+// variable is set, so clap checks them as it checks anything else and the
+// command line, then the environment, always win.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory};
+
+use crate::Cli;
+
+// Read from the working directory when there's no --config
+const CONFIG_FILE: &str = "brc.toml";
+
+/// The command line with the config file's defaults put in
+pub fn args(args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    let (path, required) = match config_path(&args) {
+        Some(path) => (path, true),
+        None => (PathBuf::from(CONFIG_FILE), false),
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => return Ok(args),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let config = text.parse::<toml::Table>().map_err(|e| format!("{}: {}", path.display(), e.message()))?;
+    with_defaults(args, &config).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// --config PATH or --config=PATH, looked for before clap has parsed
+// anything
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(Path::new(path).to_path_buf());
+        }
+    }
+    None
+}
+
+fn with_defaults(mut args: Vec<OsString>, config: &toml::Table) -> Result<Vec<OsString>, String> {
+    let mut cli = Cli::command();
+    cli.build();
+    // Missing arguments may well be in the file, and anything else wrong
+    // is for clap to report after
+    let Ok(matches) = cli.clone().ignore_errors(true).try_get_matches_from(&args) else {
+        return Ok(args);
+    };
+
+    let (name, matches, command, at) = match matches.subcommand() {
+        Some((name, matches)) => {
+            let at = args.iter().position(|arg| arg == name).map_or(args.len(), |at| at + 1);
+            (name, matches, cli.find_subcommand(name).unwrap(), at)
+        }
+        None => ("run", &matches, &cli, 1),
+    };
+
+    // Any command's table is checked, not only the one being run
+    for (key, table) in config.iter().filter_map(|(key, value)| Some((key, value.as_table()?))) {
+        let Some(subcommand) = cli.find_subcommand(key) else {
+            return Err(format!("there's no {} command", key));
+        };
+        if let Some(flag) = table.keys().find(|flag| !subcommand.get_arguments().any(|arg| arg.get_id() == flag.replace('-', "_").as_str())) {
+            return Err(format!("{} has no --{}", key, flag));
+        }
+    }
+    let general = config.iter().filter(|(_, value)| !value.is_table());
+    let own = config.get(name).and_then(toml::Value::as_table).into_iter().flatten();
+
+    let mut defaults = Vec::new();
+    for (key, value) in general.chain(own) {
+        let id = key.replace('-', "_");
+        // Flags for other commands are for them
+        let Some(arg) = command.get_arguments().find(|arg| arg.get_id() == id.as_str()) else {
+            continue;
+        };
+        if matches!(matches.value_source(&id), Some(ValueSource::CommandLine | ValueSource::EnvVariable)) {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(values) => values.iter().map(scalar).collect::<Result<Vec<_>, _>>(),
+            value => scalar(value).map(|value| vec![value]),
+        }.map_err(|e| format!("{}: {}", key, e))?;
+        match (arg.get_long(), arg.get_action()) {
+            (None, _) => defaults.extend(values),
+            (Some(long), ArgAction::SetTrue) => {
+                if values != ["true"] && values != ["false"] {
+                    return Err(format!("{} should be true or false", key));
+                }
+                if values == ["true"] {
+                    defaults.push(format!("--{}", long));
+                }
+            }
+            (Some(long), ArgAction::Count) => {
+                let count = values.first().and_then(|count| count.parse::<usize>().ok()).ok_or_else(|| format!("{} should be a count", key))?;
+                defaults.extend(std::iter::repeat_n(format!("--{}", long), count));
+            }
+            (Some(long), _) => {
+                for value in values {
+                    defaults.push(format!("--{}={}", long, value));
+                }
+            }
+        }
+    }
+
+    args.splice(at..at, defaults.into_iter().map(OsString::from));
+    Ok(args)
+}
+
+// A value as it would be written on the command line
+fn scalar(value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(value) => Ok(value.clone()),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err("should be a string, a number, a boolean or a list of them".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(command_line: &str, config: &str) -> Result<String, String> {
+        let args = command_line.split(' ').map(OsString::from).collect();
+        let args = with_defaults(args, &config.parse::<toml::Table>().unwrap())?;
+        Ok(args.iter().map(|arg| arg.to_str().unwrap()).collect::<Vec<_>>().join(" "))
+    }
+
+    #[test]
+    fn defaults() {
+        let config = "engine = \"mmap\"\nthreads = 4\npaths = [\"a.txt\", \"b.txt\"]\nstrict = true\n\n[validate]\nlimit = 5\n";
+        assert_eq!(apply("1brc --format json", config).unwrap(), "1brc --engine=mmap a.txt b.txt --strict --threads=4 --format json");
+        assert_eq!(apply("1brc run -t 2 c.txt", config).unwrap(), "1brc run --engine=mmap --strict -t 2 c.txt");
+        assert_eq!(apply("1brc validate -v", config).unwrap(), "1brc validate a.txt b.txt --threads=4 --limit=5 -v");
+
+        assert!(apply("1brc", "[nothing]\nrows = 1\n").is_err());
+        assert!(apply("1brc validate", "[validate]\nengine = \"mmap\"\n").is_err());
+        assert!(apply("1brc run", "[validate]\nengine = \"mmap\"\n").is_err());
+        assert!(apply("1brc", "strict = 1\n").is_err());
+
+        // The environment comes between the file and the command line
+        std::env::set_var("BRC_ENGINE", "buffered");
+        let with_env = apply("1brc -t 2 c.txt", config);
+        std::env::remove_var("BRC_ENGINE");
+        assert_eq!(with_env.unwrap(), "1brc --strict -t 2 c.txt");
+    }
+
+    #[test]
+    fn finds_the_path() {
+        let args = |line: &str| line.split(' ').map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(config_path(&args("1brc --config x.toml run")), Some(PathBuf::from("x.toml")));
+        assert_eq!(config_path(&args("1brc run --config=y.toml")), Some(PathBuf::from("y.toml")));
+        assert_eq!(config_path(&args("1brc run -- --config")), None);
+    }
+}
@@ -0,0 +1,227 @@
+// The `diff` subcommand: lines up the stations of two results files and
+// prints those whose min, mean or max are further apart than a
+// tolerance, or that only one of the files has.
+//
+// The files can be in any of --format's formats but table, which is
+// only for reading, and needn't be in the same one: the format is told
+// from the contents. Temperatures are compared as the numbers printed,
+// so results to different --precision or in different --unit differ.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use one_billion_row_challenge::{partial, Error};
+use serde::Deserialize;
+
+use crate::unit::{degrees, Unit};
+
+// Min, mean and max, in degrees, by station
+type Stations = BTreeMap<String, [f64; 3]>;
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// The results to compare against
+    a: PathBuf,
+
+    /// The results to compare
+    b: PathBuf,
+
+    /// How far apart a min, mean or max can be, in degrees, before the
+    /// station counts as different
+    #[arg(long, default_value_t = 0.)]
+    tolerance: f64,
+}
+
+pub fn diff(args: DiffArgs) -> Result<(), Error> {
+    let (a, b) = (read(&args.a)?, read(&args.b)?);
+    let (a_name, b_name) = (args.a.display(), args.b.display());
+    let values = |values: &[f64; 3]| values.map(|value| value.to_string()).join("/");
+
+    let (mut differ, mut only_a, mut only_b) = (0, 0, 0);
+    for (name, a_values) in &a {
+        match b.get(name) {
+            Some(b_values) if !within(a_values, b_values, args.tolerance) => {
+                println!("Differs: {} ({} in {}, {} in {})", name, values(a_values), a_name, values(b_values), b_name);
+                differ += 1;
+            }
+            Some(_) => {}
+            None => {
+                println!("Only in {}: {} ({})", a_name, name, values(a_values));
+                only_a += 1;
+            }
+        }
+    }
+    for (name, b_values) in b.iter().filter(|(name, _)| !a.contains_key(*name)) {
+        println!("Only in {}: {} ({})", b_name, name, values(b_values));
+        only_b += 1;
+    }
+
+    println!(
+        "{} stations in both: {} differ by more than {}, {} only in {}, {} only in {}",
+        a.len() - only_a, differ, args.tolerance, only_a, a_name, only_b, b_name,
+    );
+    match differ + only_a + only_b {
+        0 => Ok(()),
+        _ => Err(format!("{} and {} differ", a_name, b_name).into()),
+    }
+}
+
+// Whether every one of the values is near enough its counterpart. The
+// slack is for the tolerance itself not being exact in binary
+fn within(a: &[f64; 3], b: &[f64; 3], tolerance: f64) -> bool {
+    a.iter().zip(b).all(|(a, b)| (a - b).abs() <= tolerance + 1e-9)
+}
+
+fn read(path: &Path) -> Result<Stations, Error> {
+    let bytes = std::fs::read(path).map_err(|source| Error::Io { path: Some(path.to_path_buf()), source })?;
+    let unparsed = || Error::Other(format!("{}: not results in any format diff can read", path.display()));
+
+    if bytes.starts_with(b"1BRCPAR") {
+        let data = partial::decode(&bytes).ok_or_else(unparsed)?;
+        return Ok(data.iter().map(|(name, record)| {
+            let values = [Unit::C.min(record), Unit::C.mean(record), Unit::C.max(record)].map(degrees);
+            (String::from_utf8_lossy(name).into_owned(), values)
+        }).collect());
+    }
+    #[cfg(feature = "parquet")]
+    if bytes.starts_with(b"PAR1") {
+        let file = std::fs::File::open(path).map_err(|source| Error::Io { path: Some(path.to_path_buf()), source })?;
+        return Ok(crate::export::read_parquet(file)?.into_iter().collect());
+    }
+    // An IPC stream starts with a continuation marker
+    #[cfg(feature = "arrow")]
+    if bytes.starts_with(&[0xff; 4]) {
+        return Ok(crate::export::read_arrow(&bytes)?.into_iter().collect());
+    }
+
+    let text = std::str::from_utf8(&bytes).map_err(|_| unparsed())?;
+    parse(text).ok_or_else(unparsed)
+}
+
+#[derive(Deserialize)]
+struct JsonStation {
+    station: String,
+    min: f64,
+    mean: f64,
+    max: f64,
+}
+
+// Results in one of the text formats, which are told apart by how
+// they start
+fn parse(text: &str) -> Option<Stations> {
+    let trimmed = text.trim_start();
+    if trimmed.starts_with('[') {
+        let stations = serde_json::from_str::<Vec<JsonStation>>(trimmed).ok()?;
+        return Some(stations.into_iter().map(|station| (station.station, [station.min, station.mean, station.max])).collect());
+    }
+    if let Some(inner) = trimmed.strip_prefix('{') {
+        return parse_official(inner.lines().next()?.trim_end().strip_suffix('}')?);
+    }
+    // A CSV header row, with whatever the delimiter was
+    let first = text.lines().next().unwrap_or("");
+    if let Some(header) = first.strip_prefix("station") {
+        let mut rest = header.chars();
+        if let (Some(delimiter), true) = (rest.next(), rest.as_str().starts_with("min")) {
+            return parse_csv(text, delimiter);
+        }
+    }
+    parse_lines(text)
+}
+
+// {name=min/mean/max, ...}, where the names can have ", " in them but
+// the values can't, and any --with-count or --stats follow the max
+fn parse_official(mut rest: &str) -> Option<Stations> {
+    let mut stations = Stations::new();
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('=')?;
+        let (values, after) = after.split_once(", ").unwrap_or((after, ""));
+        stations.insert(name.to_string(), first_three(values.split('/'))?);
+        rest = after;
+    }
+    Some(stations)
+}
+
+// One name;min;mean;max line per station, maybe with more fields after.
+// Lines without the delimiter (a trailing "Time taken", say) are passed over
+fn parse_lines(text: &str) -> Option<Stations> {
+    let mut stations = Stations::new();
+    for line in text.lines().map(str::trim_end).filter(|line| line.contains(';')) {
+        let mut fields = line.split(';');
+        let name = fields.next()?;
+        stations.insert(name.to_string(), first_three(fields)?);
+    }
+    Some(stations)
+}
+
+// A header row and then a row per station, with the station first and
+// quoted if it has to be
+fn parse_csv(text: &str, delimiter: char) -> Option<Stations> {
+    let mut stations = Stations::new();
+    for row in text.lines().skip(1).filter(|row| !row.is_empty()) {
+        let (name, rest) = match row.strip_prefix('"') {
+            Some(quoted) => {
+                // The closing quote is the first one not doubled
+                let mut name = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next()? {
+                        (i, '"') if quoted[i + 1..].starts_with('"') => {
+                            name.push('"');
+                            chars.next();
+                        }
+                        (i, '"') => break i + 1,
+                        (_, c) => name.push(c),
+                    }
+                };
+                (name, quoted[end..].strip_prefix(delimiter)?)
+            }
+            None => {
+                let (name, rest) = row.split_once(delimiter)?;
+                (name.to_string(), rest)
+            }
+        };
+        stations.insert(name, first_three(rest.split(delimiter))?);
+    }
+    Some(stations)
+}
+
+fn first_three<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<[f64; 3]> {
+    let mut next = || fields.next()?.trim().parse::<f64>().ok();
+    Some([next()?, next()?, next()?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stations(entries: &[(&str, [f64; 3])]) -> Stations {
+        entries.iter().map(|(name, values)| (name.to_string(), *values)).collect()
+    }
+
+    #[test]
+    fn parses_every_text_format() {
+        let expected = stations(&[("Abha", [-5.2, 12.4, 30.0]), ("Washington, D.C.", [1.0, 2.0, 3.0])]);
+
+        let lines = "Abha;-5.2;12.4;30\nWashington, D.C.;1;2;3;7\n\nTime taken: 1s\n";
+        let official = "{Abha=-5.2/12.4/30.0, Washington, D.C.=1.0/2.0/3.0/7}\n";
+        let json = r#"[{"station": "Abha", "min": -5.2, "mean": 12.4, "max": 30.0, "count": 2},
+            {"station": "Washington, D.C.", "min": 1.0, "mean": 2.0, "max": 3.0, "count": 7}]"#;
+        let csv = "station,min,mean,max,count\nAbha,-5.2,12.4,30,2\n\"Washington, D.C.\",1,2,3,7\n";
+        let semicolons = "station;min;mean;max\nAbha;-5.2;12.4;30\nWashington, D.C.;1;2;3\n";
+        for text in [lines, official, json, csv, semicolons] {
+            assert_eq!(parse(text), Some(expected.clone()), "{}", text);
+        }
+
+        let quoted = "station,min,mean,max\n\"Say \"\"hi\"\", Bob\",1,2,3\n";
+        assert_eq!(parse(quoted), Some(stations(&[("Say \"hi\", Bob", [1., 2., 3.])])));
+        assert_eq!(parse("Abha;cold;12.4;30\n"), None);
+    }
+
+    #[test]
+    fn tolerates_small_differences() {
+        assert!(within(&[-5.2, 12.4, 30.], &[-5.2, 12.5, 30.], 0.1));
+        assert!(!within(&[-5.2, 12.4, 30.], &[-5.2, 12.6, 30.], 0.1));
+        assert!(!within(&[-5.2, 12.4, 30.], &[-5.2, 12.5, 30.], 0.));
+    }
+}
@@ -0,0 +1,106 @@
+// Unbuffered reads, for measuring disk-bound performance without the
+// page cache: O_DIRECT on Linux, FILE_FLAG_NO_BUFFERING on Windows.
+//
+// Both need the file offset, the length and the buffer address of every
+// read to be aligned, but chunks start and end wherever a line does. So
+// each chunk is read from the aligned offset at or before its start, in
+// aligned blocks, and the bytes outside the chunk are ignored.
+
+use std::fs::{File, OpenOptions};
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+#[cfg(windows)]
+use std::os::windows::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+
+use crate::engine::Engine;
+use crate::timings::{timed, Phase};
+use crate::{chunk, parse_carried, parse_to_end, report_progress, BadLine, Error, Options, StationMap};
+
+#[cfg(target_os = "linux")]
+const UNBUFFERED: i32 = libc::O_DIRECT;
+// FILE_FLAG_NO_BUFFERING
+#[cfg(windows)]
+const UNBUFFERED: u32 = 0x2000_0000;
+
+// Covers the logical block size of any disk we're likely to meet
+const ALIGN: usize = 4096;
+
+pub(crate) struct Direct<'a> {
+    file: File,
+    path: &'a Path,
+    size: u64,
+    read_size: usize,
+}
+
+impl<'a> Direct<'a> {
+    pub fn open(path: &'a Path, size: u64, read_size: usize) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(UNBUFFERED)
+            .open(path)
+            .map_err(|e| format!("could not open {} for unbuffered reads: {}", path.display(), e))?;
+        // Reads have to be whole aligned blocks
+        let read_size = read_size.next_multiple_of(ALIGN);
+        Ok(Direct { file, path, size, read_size })
+    }
+}
+
+impl Engine for Direct<'_> {
+    fn chunks(&self, options: &Options) -> Result<Vec<(u64, u64)>, Error> {
+        Ok(chunk::file_chunks(self.path, self.size, options)?)
+    }
+
+    fn read_chunk(&self, start: u64, end: u64, data_map: &mut StationMap, options: &Options) -> Result<(), Error> {
+        read_chunk(&self.file, start, end, self.read_size, data_map, options)
+    }
+}
+
+fn read_chunk(file: &File, start: u64, end: u64, read_size: usize, data_map: &mut StationMap, options: &Options) -> Result<(), Error> {
+    // Over-allocate so an aligned block fits somewhere inside
+    let mut allocation = vec![0u8; read_size + ALIGN];
+    let padding = allocation.as_ptr().align_offset(ALIGN);
+    let buffer = &mut allocation[padding..padding + read_size];
+
+    let mut carry = Vec::new();
+    let mut offset = start - start % ALIGN as u64;
+    let mut skip = (start - offset) as usize;
+
+    while offset < end {
+        let read = match timed(options, Phase::Io, || read_at(file, buffer, offset)) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let len = read.min((end - offset) as usize);
+        if skip < len {
+            timed(options, Phase::Parsing, || parse_carried(&buffer[skip..len], offset + skip as u64, &mut carry, data_map, options))?;
+            report_progress(options, len - skip);
+        }
+
+        // Anything short of a full block means the end of the file, and
+        // the next offset wouldn't be aligned anyway
+        if read < buffer.len() {
+            break;
+        }
+        offset += read as u64;
+        skip = 0;
+    }
+
+    timed(options, Phase::Parsing, || parse_to_end(&carry, data_map, options))
+        .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &carry, at, end - carry.len() as u64))
+}
+
+#[cfg(target_os = "linux")]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    file.read_at(buffer, offset)
+}
+
+// Moves the file's cursor too, but nothing else reads through this
+// handle's cursor
+#[cfg(windows)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    file.seek_read(buffer, offset)
+}
@@ -0,0 +1,421 @@
+// Spreading one aggregation over several machines.
+//
+// The coordinator (`--listen ADDR --workers N`) waits for N workers
+// (`--connect ADDR`) and hands out the work in one of two ways:
+//
+// - by default a worker is sent byte ranges of the coordinator's input
+//   files, one at a time as it finishes the last, so the files have to
+//   be at the same paths on every machine (e.g. a shared filesystem)
+// - with --shard, a worker aggregates its own input files and sends the
+//   results back once
+//
+// Results come back as partial maps (see the library's partial module)
+// and are merged on the coordinator, which prints them like any other
+// run. A range whose worker disconnects partway is given to another.
+// With --verify-ranges the workers also send a CRC-32 of every range
+// they read, which the coordinator checks against the range as it reads
+// it, so a file that differs between machines (a shared filesystem
+// that's behind, a copy cut short) fails the run rather than its results.
+//
+// Every message is a one-byte tag, a little-endian u64 length and that
+// many bytes of payload:
+//
+//   worker -> coordinator
+//     H  hello: "ranges" or "shard"
+//     R  result: an encoded partial map, after the range's CRC-32 (a
+//        little-endian u32) with --verify-ranges
+//     E  error: a message, which fails the whole run
+//   coordinator -> worker
+//     C  config: the options that change the results, as JSON
+//     T  task: a range to aggregate, as JSON
+//     D  done: there are no more ranges
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+
+use one_billion_row_challenge::{aggregate_range, chunk, merge_maps, partial, Columns, Error, NormalizeKey, OnError, StationFilter, StationMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{aggregate_with, in_units, input_paths, options, InputArgs};
+
+// Big enough that the round trips don't matter, small enough that the
+// ranges even out between fast and slow workers
+const TASK_SIZE: u64 = 32 * chunk::TARGET_CHUNK_SIZE;
+
+// Messages bigger than this are taken to be garbage rather than results
+const MAX_MESSAGE: u64 = 1 << 32;
+
+#[derive(Serialize, Deserialize)]
+struct Config {
+    delimiter: u8,
+    stations: Vec<String>,
+    histograms: bool,
+    max_name_len: Option<usize>,
+    truncate_names: bool,
+    precision: u8,
+    normalize_keys: Vec<NormalizeKey>,
+    on_error: OnError,
+    columns: Option<Columns>,
+    quoted: bool,
+    range: Option<(i32, i32)>,
+    strict: bool,
+    // Whether range results come with a checksum
+    checksums: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Task {
+    path: PathBuf,
+    start: u64,
+    end: u64,
+}
+
+struct Coordinator<'a> {
+    input: &'a InputArgs,
+    config: Vec<u8>,
+    checksums: bool,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    // Filled in when the first worker asks for a range
+    tasks: Option<VecDeque<Task>>,
+    // Ranges handed out that haven't come back yet
+    outstanding: usize,
+    data: StationMap,
+    error: Option<Error>,
+}
+
+// Runs the coordinator until all the workers are done, and returns the
+// combined results
+pub fn coordinate(listen: &str, workers: usize, input: &InputArgs, histograms: bool, checksums: bool) -> Result<StationMap, Error> {
+    let config = Config {
+        delimiter: input.delimiter,
+        stations: input.stations.clone(),
+        histograms,
+        max_name_len: input.max_name_len,
+        truncate_names: input.truncate_names,
+        precision: input.precision,
+        normalize_keys: input.normalize_keys.clone(),
+        on_error: input.on_error,
+        columns: input.key_col.zip(input.value_col).map(|(key, value)| Columns { key: key - 1, value: value - 1 }),
+        quoted: input.quoted,
+        range: input.assert_range.map(|range| in_units(range, input.precision)),
+        strict: input.strict,
+        checksums,
+    };
+    let coordinator = Coordinator {
+        input,
+        config: serde_json::to_vec(&config).unwrap(),
+        checksums,
+        state: Mutex::default(),
+        changed: Condvar::new(),
+    };
+
+    let listener = TcpListener::bind(listen).map_err(|e| format!("could not listen on {}: {}", listen, e))?;
+    log::info!("Waiting for {} workers on {}", workers, listen);
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let (stream, address) = listener.accept()?;
+            log::info!("Worker {} connected", address);
+
+            let coordinator = &coordinator;
+            scope.spawn(move || {
+                if let Err(e) = coordinator.handle(stream) {
+                    coordinator.fail(Error::Other(format!("worker {}: {}", address, e)));
+                }
+            });
+        }
+        Ok::<_, Error>(())
+    })?;
+
+    let state = coordinator.state.into_inner().unwrap();
+    if let Some(error) = state.error {
+        return Err(error);
+    }
+    if let Some(left) = state.tasks.filter(|tasks| !tasks.is_empty()) {
+        return Err(format!("{} ranges weren't aggregated because every worker disconnected", left.len()).into());
+    }
+
+    Ok(state.data)
+}
+
+impl Coordinator<'_> {
+    fn handle(&self, mut stream: TcpStream) -> Result<(), Error> {
+        let hello = expect(&mut stream, b'H')?;
+        send(&mut stream, b'C', &self.config)?;
+
+        if hello == b"shard" {
+            let (_, data) = receive_result(&mut stream, false)?;
+            self.add(data);
+            return Ok(());
+        }
+
+        self.make_tasks()?;
+
+        while let Some(task) = self.next_task() {
+            let result = send(&mut stream, b'T', &serde_json::to_vec(&task).unwrap())
+                .map_err(Error::from)
+                .and_then(|_| receive_result(&mut stream, self.checksums));
+
+            match result.and_then(|(checksum, data)| self.verify(&task, checksum).map(|()| data)) {
+                Ok(data) => self.finish(task, Some(data)),
+                // Someone else can have the range
+                Err(Error::Io { source, .. }) => {
+                    self.finish(task, None);
+                    log::warn!("Worker {} disconnected: {}", stream.peer_addr().map_or("?".into(), |a| a.to_string()), source);
+                    return Ok(());
+                }
+                Err(e) => {
+                    self.finish(task, None);
+                    return Err(e);
+                }
+            }
+        }
+
+        send(&mut stream, b'D', &[])?;
+        Ok(())
+    }
+
+    // Fails if the worker read the range differently from how it is here
+    fn verify(&self, task: &Task, checksum: Option<u32>) -> Result<(), Error> {
+        let Some(theirs) = checksum else { return Ok(()) };
+        let ours = checksum_range(&task.path, task.start, task.end)?;
+        if theirs != ours {
+            return Err(format!(
+                "read bytes {}..{} of {} differently from the coordinator (CRC-32 {:08x}, not {:08x}), so the file isn't the same on every machine",
+                task.start, task.end, task.path.display(), theirs, ours,
+            ).into());
+        }
+        Ok(())
+    }
+
+    fn make_tasks(&self) -> Result<(), Error> {
+        if self.state.lock().unwrap().tasks.is_some() {
+            return Ok(());
+        }
+
+        let tasks = ranges(self.input)?;
+        self.state.lock().unwrap().tasks.get_or_insert(tasks);
+        Ok(())
+    }
+
+    // Waits for a range to hand out, or returns None once there are none
+    // left (or the run has failed)
+    fn next_task(&self) -> Option<Task> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if state.error.is_some() {
+                return None;
+            }
+            if let Some(task) = state.tasks.as_mut()?.pop_front() {
+                state.outstanding += 1;
+                return Some(task);
+            }
+            // A range still out could come back for someone to redo
+            if state.outstanding == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    // Records a range coming back, with its results or for someone else
+    // to redo
+    fn finish(&self, task: Task, data: Option<StationMap>) {
+        let mut state = self.state.lock().unwrap();
+        state.outstanding -= 1;
+
+        match data {
+            Some(data) => state.data = merge_maps(std::mem::take(&mut state.data), data),
+            None => state.tasks.get_or_insert_with(VecDeque::new).push_back(task),
+        }
+
+        self.changed.notify_all();
+    }
+
+    // Merges in a shard's results
+    fn add(&self, data: StationMap) {
+        let mut state = self.state.lock().unwrap();
+        state.data = merge_maps(std::mem::take(&mut state.data), data);
+    }
+
+    fn fail(&self, error: Error) {
+        self.state.lock().unwrap().error.get_or_insert(error);
+        self.changed.notify_all();
+    }
+}
+
+// Splits every input file into line-aligned ranges of about TASK_SIZE
+fn ranges(input: &InputArgs) -> Result<VecDeque<Task>, Error> {
+    let (paths, stdin) = input_paths(input)?;
+    if stdin {
+        return Err("stdin can't be split between workers".into());
+    }
+
+    let mut tasks = VecDeque::new();
+    for path in paths {
+        let in_file = |e: std::io::Error| Error::Io { path: Some(path.clone()), source: e };
+        let file = std::fs::File::open(&path).map_err(in_file)?;
+        let mmap = unsafe { memmap2::Mmap::map(&file) }.map_err(in_file)?;
+        let path = path.canonicalize().map_err(in_file)?;
+
+        let count = (mmap.len() as u64).div_ceil(TASK_SIZE) as usize;
+        tasks.extend(chunk::split_in(&mmap, count).into_iter().map(|(start, end)| Task {
+            path: path.clone(),
+            start: start as u64,
+            end: end as u64,
+        }));
+    }
+
+    Ok(tasks)
+}
+
+// Works for the coordinator at connect until it runs out of work
+pub fn work(connect: &str, shard: bool, input: &InputArgs) -> Result<(), Error> {
+    let mut stream = TcpStream::connect(connect).map_err(|e| format!("could not connect to {}: {}", connect, e))?;
+    send(&mut stream, b'H', if shard { b"shard" } else { b"ranges" })?;
+
+    // The coordinator decides everything that changes the results
+    let config: Config = serde_json::from_slice(&expect(&mut stream, b'C')?)
+        .map_err(|e| format!("invalid config from the coordinator: {}", e))?;
+    let options = one_billion_row_challenge::Options {
+        delimiter: config.delimiter,
+        stations: (!config.stations.is_empty()).then(|| StationFilter::new(&config.stations)).transpose()?,
+        max_name_len: config.max_name_len,
+        truncate_names: config.truncate_names,
+        precision: config.precision,
+        normalize_keys: config.normalize_keys,
+        on_error: config.on_error,
+        columns: config.columns,
+        quoted: config.quoted,
+        range: config.range,
+        strict: config.strict,
+        ..options(input, config.histograms)?
+    };
+
+    if shard {
+        let data = aggregate_with(input, &options).map(|data| (None, data));
+        return send_result(&mut stream, data);
+    }
+
+    let mut ranges = 0;
+    loop {
+        match receive(&mut stream)? {
+            (b'T', task) => {
+                let task: Task = serde_json::from_slice(&task).map_err(|e| format!("invalid task from the coordinator: {}", e))?;
+                let data = aggregate_range(&task.path, task.start, task.end, &options).and_then(|data| {
+                    let checksum = config.checksums.then(|| checksum_range(&task.path, task.start, task.end)).transpose()?;
+                    Ok((checksum, data))
+                });
+                send_result(&mut stream, data)?;
+                ranges += 1;
+            }
+            (b'D', _) => break,
+            (tag, _) => return Err(format!("unexpected message {:?} from the coordinator", tag as char).into()),
+        }
+    }
+
+    log::info!("Aggregated {} ranges for {}", ranges, connect);
+    Ok(())
+}
+
+// A CRC-32 of bytes start..end of the file at path
+fn checksum_range(path: &Path, start: u64, end: u64) -> Result<u32, Error> {
+    let in_file = |source| Error::Io { path: Some(path.to_path_buf()), source };
+    let file = std::fs::File::open(path).map_err(in_file)?;
+    let mmap = unsafe { memmap2::Mmap::map(&file) }.map_err(in_file)?;
+    let bytes = mmap.get(start as usize..end as usize)
+        .ok_or_else(|| format!("{}: range {}..{} is past the end of the file", path.display(), start, end))?;
+    Ok(crc32fast::hash(bytes))
+}
+
+// Sends a worker's results (with their range's checksum, if asked for),
+// or the error that stopped it, which is then this worker's error too
+fn send_result(stream: &mut TcpStream, data: Result<(Option<u32>, StationMap), Error>) -> Result<(), Error> {
+    match data {
+        Ok((checksum, data)) => {
+            let mut payload = checksum.map_or_else(Vec::new, |checksum| checksum.to_le_bytes().to_vec());
+            payload.extend(partial::encode(&data));
+            Ok(send(stream, b'R', &payload)?)
+        }
+        Err(e) => {
+            let _ = send(stream, b'E', e.to_string().as_bytes());
+            Err(e)
+        }
+    }
+}
+
+// A worker's results, and their checksum if checksummed
+fn receive_result(stream: &mut TcpStream, checksummed: bool) -> Result<(Option<u32>, StationMap), Error> {
+    match receive(stream)? {
+        (b'R', payload) => {
+            let (checksum, data) = match checksummed {
+                true => payload.split_first_chunk::<4>().map(|(checksum, data)| (Some(u32::from_le_bytes(*checksum)), data)),
+                false => Some((None, &payload[..])),
+            }.ok_or("invalid partial results")?;
+            Ok((checksum, partial::decode(data).ok_or("invalid partial results")?))
+        }
+        (b'E', message) => Err(String::from_utf8_lossy(&message).into_owned().into()),
+        (tag, _) => Err(format!("unexpected message {:?}", tag as char).into()),
+    }
+}
+
+fn send(stream: &mut TcpStream, tag: u8, payload: &[u8]) -> std::io::Result<()> {
+    stream.write_all(&[tag])?;
+    stream.write_all(&(payload.len() as u64).to_le_bytes())?;
+    stream.write_all(payload)?;
+    stream.flush()
+}
+
+fn receive(stream: &mut TcpStream) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 9];
+    stream.read_exact(&mut header)?;
+
+    let len = u64::from_le_bytes(header[1..].try_into().unwrap());
+    if len > MAX_MESSAGE {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "message too long"));
+    }
+
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((header[0], payload))
+}
+
+fn expect(stream: &mut TcpStream, tag: u8) -> Result<Vec<u8>, Error> {
+    match receive(stream)? {
+        (received, payload) if received == tag => Ok(payload),
+        (received, _) => Err(format!("expected message {:?} but got {:?}", tag as char, received as char).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_carry_checksums() {
+        let path = std::env::temp_dir().join(format!("brc-distributed-{}.txt", std::process::id()));
+        std::fs::write(&path, "Abha;1.0\nOslo;-2.5\n").unwrap();
+        let checksum = checksum_range(&path, 9, 19).unwrap();
+        assert_eq!(checksum, crc32fast::hash(b"Oslo;-2.5\n"));
+        assert!(checksum_range(&path, 9, 20).is_err());
+        let data = aggregate_range(&path, 9, 19, &Default::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut worker = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut coordinator, _) = listener.accept().unwrap();
+        send_result(&mut worker, Ok((Some(checksum), data.clone()))).unwrap();
+        assert_eq!(receive_result(&mut coordinator, true).unwrap(), (Some(checksum), data.clone()));
+        send_result(&mut worker, Ok((None, data.clone()))).unwrap();
+        assert_eq!(receive_result(&mut coordinator, false).unwrap(), (None, data));
+    }
+}
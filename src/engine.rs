@@ -0,0 +1,201 @@
+// The ways of reading the measurements file.
+//
+// Every engine shares the chunking, the parser and the merging of the
+// per-thread maps, and differs only in how a chunk's bytes get to the
+// parser: blocks read from a ChunkSource (see source.rs), a slice of a
+// memory map, io_uring or O_DIRECT reads (see uring.rs and direct.rs).
+
+use crate::par::*;
+
+use crate::interned::Interned;
+use crate::table::Table;
+use crate::timings::{timed, Phase};
+use crate::error::Invalid;
+use crate::{cancelled, chunk, workers, max_line_len, new_map, parse_lines, parse_to_end, reduce_maps, report_progress, BadLine, ChunkSource, Error, Options, StationMap, Stations};
+
+pub(crate) trait Engine: Sync {
+    /// The line-aligned `(start, end)` byte ranges to aggregate
+    fn chunks(&self, options: &Options) -> Result<Vec<(u64, u64)>, Error>;
+
+    /// Parses the chunk from `start` to `end` into `data_map`, reporting
+    /// progress as the bytes are read
+    fn read_chunk(&self, start: u64, end: u64, data_map: &mut StationMap, options: &Options) -> Result<(), Error>;
+
+    /// Aggregates the chunks, folding them into one map per rayon job
+    /// rather than one per chunk, as there are many more chunks than
+    /// threads
+    fn aggregate_chunks(&self, chunks: Vec<(u64, u64)>, options: &Options) -> Result<StationMap, Error> {
+        let maps = chunks.into_par_iter().try_fold(new_map, |mut data_map, (start, end)| {
+            if cancelled(options) {
+                return Ok(data_map);
+            }
+            workers::chunk(options, || self.read_chunk(start, end, &mut data_map, options))?;
+            Ok(data_map)
+        });
+        reduce_maps(maps, options)
+    }
+}
+
+pub(crate) fn aggregate(engine: &dyn Engine, options: &Options) -> Result<StationMap, Error> {
+    let chunks = timed(options, Phase::Chunking, || engine.chunks(options))?;
+    log::debug!("Split into {} chunks", chunks.len());
+    for (start, end) in &chunks {
+        log::trace!("Chunk {}..{} ({} bytes)", start, end, end - start);
+    }
+
+    #[cfg(all(feature = "parallel", target_os = "linux"))]
+    if options.numa {
+        match crate::numa::nodes() {
+            Some(nodes) if nodes.len() > 1 => {
+                log::debug!("Splitting the chunks between {} NUMA nodes", nodes.len());
+                return workers::spread(options, || crate::numa::aggregate(engine, chunks, &nodes, options));
+            }
+            _ => log::debug!("There's only one NUMA node, so the chunks aren't split between nodes"),
+        }
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    if options.numa {
+        log::warn!("Built without the parallel feature, so the chunks aren't split between NUMA nodes");
+    }
+
+    workers::spread(options, || engine.aggregate_chunks(chunks, options))
+}
+
+/// Reads each chunk from the source in large blocks
+pub(crate) struct Buffered<S> {
+    pub source: S,
+    pub size: u64,
+    pub read_size: usize,
+}
+
+impl<S: ChunkSource> Engine for Buffered<S> {
+    fn chunks(&self, options: &Options) -> Result<Vec<(u64, u64)>, Error> {
+        Ok(chunk::source_chunks(&self.source, self.size, options)?)
+    }
+
+    fn read_chunk(&self, start: u64, end: u64, data_map: &mut StationMap, options: &Options) -> Result<(), Error> {
+        // Read the chunk in large blocks and parse every complete line in
+        // the block. Whatever is left after the last \n is the start of a
+        // line that continues in the next block, so move it to the front
+        // of the buffer and read in after it
+        let mut buffer = vec![0; self.read_size];
+        let mut filled = 0;
+        // Offset of the start of the buffer in the file
+        let mut buffer_offset = start;
+
+        loop {
+            // The line that's been carried over is left unparsed
+            if cancelled(options) {
+                return Ok(());
+            }
+            let room = (buffer.len() - filled).min((end - buffer_offset) as usize - filled);
+            let read = match timed(options, Phase::Io, || self.source.read_at(buffer_offset + filled as u64, &mut buffer[filled..filled + room])) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            filled += read;
+            report_progress(options, read);
+
+            let consumed = timed(options, Phase::Parsing, || parse_lines(&buffer[..filled], data_map, options))
+                .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &buffer, at, buffer_offset))?;
+            buffer.copy_within(consumed..filled, 0);
+            filled -= consumed;
+            buffer_offset += consumed as u64;
+
+            // A single line didn't fit in the buffer, so make room for
+            // it, unless it's too long to be valid anyway
+            if filled == buffer.len() {
+                if let Some(max) = max_line_len(options).filter(|&max| filled > max) {
+                    return Err(Error::bad_line(Invalid::LongLine(max), &buffer, 0, buffer_offset));
+                }
+                buffer.resize(buffer.len() * 2, 0);
+            }
+        }
+
+        // The end of the file, if it doesn't end in a newline
+        timed(options, Phase::Parsing, || parse_to_end(&buffer[..filled], data_map, options))
+            .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &buffer[..filled], at, buffer_offset))
+    }
+}
+
+/// Maps the whole file at once and lets the OS page it in as the workers
+/// touch it. Every chunk is then just a slice of the map, so there is no
+/// per-chunk file handle or read syscall, and station names aren't
+/// copied out of the map until the workers' results are merged
+pub(crate) struct Mapped {
+    mmap: memmap2::Mmap,
+    // Only unix kernels take hints
+    #[cfg_attr(not(unix), allow(dead_code))]
+    madvise: bool,
+}
+
+impl Mapped {
+    // Only the first size bytes, whatever's appended after
+    pub fn new(file: &std::fs::File, size: u64, options: &Options) -> Result<Self, Error> {
+        let mmap = unsafe { memmap2::MmapOptions::new().len(size as usize).map(file)? };
+
+        // The hints are only hints, so a kernel that refuses them is fine
+        #[cfg(unix)]
+        if options.madvise {
+            let _ = mmap.advise(memmap2::Advice::Sequential);
+        }
+        #[cfg(target_os = "linux")]
+        if options.huge_pages {
+            let _ = mmap.advise(memmap2::Advice::HugePage);
+        }
+
+        Ok(Mapped { mmap, madvise: options.madvise })
+    }
+}
+
+impl Engine for Mapped {
+    fn chunks(&self, options: &Options) -> Result<Vec<(u64, u64)>, Error> {
+        Ok(chunk::chunks_in(&self.mmap, options).into_iter().map(|(start, end)| (start as u64, end as u64)).collect())
+    }
+
+    fn read_chunk(&self, start: u64, end: u64, data_map: &mut StationMap, options: &Options) -> Result<(), Error> {
+        self.parse_chunk(start as usize, end as usize, data_map, options)
+    }
+
+    // The map outlives every job's map, so they can key their stations
+    // by the names in it and only copy them out once, to merge
+    fn aggregate_chunks(&self, chunks: Vec<(u64, u64)>, options: &Options) -> Result<StationMap, Error> {
+        if options.interned {
+            let dictionaries = chunks.into_par_iter().try_fold(Interned::new, |mut data_map, (start, end)| {
+                if cancelled(options) {
+                    return Ok(data_map);
+                }
+                workers::chunk(options, || self.parse_chunk(start as usize, end as usize, &mut data_map, options))?;
+                Ok::<_, Error>(data_map)
+            });
+            let merged = dictionaries.try_reduce(Interned::new, |a, b| Ok(timed(options, Phase::Merging, || a.merge(b))))?;
+            return Ok(merged.into_map());
+        }
+
+        let maps = chunks.into_par_iter().try_fold(Table::new, |mut data_map, (start, end)| {
+            if cancelled(options) {
+                return Ok(data_map);
+            }
+            workers::chunk(options, || self.parse_chunk(start as usize, end as usize, &mut data_map, options))?;
+            Ok(data_map)
+        });
+        reduce_maps(maps.map(|data_map| data_map.map(Table::into_map)), options)
+    }
+}
+
+impl Mapped {
+    fn parse_chunk<'a>(&'a self, start: usize, end: usize, data_map: &mut impl Stations<'a>, options: &Options) -> Result<(), Error> {
+        #[cfg(unix)]
+        if self.madvise {
+            let _ = self.mmap.advise_range(memmap2::Advice::WillNeed, start, end - start);
+        }
+
+        timed(options, Phase::Parsing, || parse_to_end(&self.mmap[start..end], data_map, options))
+            .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &self.mmap, start + at, 0))?;
+        report_progress(options, end - start);
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Error returned when aggregation fails
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the input (or writing the output) failed
+    Io {
+        /// The file involved, if known
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+    /// A line isn't a valid `name;temperature` measurement
+    Parse {
+        /// The file involved, if known
+        path: Option<PathBuf>,
+        /// 1-based line number, if known. Streamed and compressed input
+        /// only has the byte offset
+        line: Option<u64>,
+        /// Byte offset of the start of the line in the (decompressed) input
+        offset: u64,
+        /// What's wrong with the line
+        reason: String,
+        /// The offending line, lossily converted and truncated
+        text: String,
+    },
+    /// A station name isn't valid UTF-8, so it can't be printed
+    InvalidUtf8 {
+        /// The name, with the invalid bytes replaced
+        station: String,
+    },
+    /// [`Options::cancel`](crate::Options::cancel) was set before the
+    /// aggregation finished
+    Cancelled {
+        /// The stations from the chunks and blocks that were finished
+        partial: crate::StationMap,
+    },
+    /// Anything else, e.g. bad arguments or a feature that isn't built in
+    Other(String),
+}
+
+impl Error {
+    /// The process exit code for this error: 3 for I/O errors, 4 for
+    /// malformed input, 5 for names that aren't UTF-8, 130 (as for
+    /// SIGINT) for a cancelled run and 1 otherwise. 2 is left for usage
+    /// errors, which is what clap exits with
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io { .. } => 3,
+            Error::Parse { .. } => 4,
+            Error::InvalidUtf8 { .. } => 5,
+            Error::Cancelled { .. } => 130,
+            Error::Other(_) => 1,
+        }
+    }
+
+    // Attributes the error to path, unless it already names a file
+    #[cfg(feature = "native")]
+    pub(crate) fn in_file(mut self, file: &std::path::Path) -> Self {
+        if let Error::Io { path, .. } | Error::Parse { path, .. } = &mut self {
+            path.get_or_insert_with(|| file.to_path_buf());
+        }
+        self
+    }
+
+    // A parse error for the line starting at bytes[at], where bytes
+    // starts at base in the input
+    pub(crate) fn bad_line(invalid: Invalid, bytes: &[u8], at: usize, base: u64) -> Self {
+        const MAX_TEXT: usize = 100;
+
+        let line = &bytes[at..];
+        let line = &line[..memchr::memchr(b'\n', line).unwrap_or(line.len())];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        let mut text = String::from_utf8_lossy(&line[..line.len().min(MAX_TEXT)]).into_owned();
+        if line.len() > MAX_TEXT {
+            text.push_str("...");
+        }
+
+        Error::Parse {
+            path: None,
+            line: None,
+            offset: base + at as u64,
+            reason: invalid.to_string(),
+            text,
+        }
+    }
+}
+
+// Why the parser rejected a line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Invalid {
+    NoDelimiter,
+    Temperature,
+    // After the temperature, with --time-bucket
+    #[cfg_attr(not(feature = "native"), allow(dead_code))]
+    Timestamp,
+    NotUtf8,
+    EmptyName,
+    // The longest name allowed
+    LongName(usize),
+    // The fewest fields a line can have to hold Options::columns
+    Columns(usize),
+    // A quoted field with no closing quote, or more after it
+    Quote,
+    // The longest line a name of the longest allowed length could make.
+    // Only the file readers look that far ahead
+    #[cfg_attr(not(feature = "native"), allow(dead_code))]
+    LongLine(usize),
+    // Options::range, and the decimals it's in
+    OutOfRange(i32, i32, u8),
+    // Options::max_map_entries, which the line's station went past
+    TooManyStations(usize),
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Invalid::NoDelimiter => f.write_str("no delimiter"),
+            Invalid::Temperature => f.write_str("bad temperature"),
+            Invalid::Timestamp => f.write_str("bad timestamp"),
+            Invalid::NotUtf8 => f.write_str("station name isn't valid UTF-8"),
+            Invalid::EmptyName => f.write_str("station name is empty"),
+            Invalid::LongName(max) => write!(f, "station name is longer than {} bytes", max),
+            Invalid::Columns(fields) => write!(f, "line has fewer than {} fields", fields),
+            Invalid::Quote => f.write_str("unmatched quote"),
+            Invalid::LongLine(max) => write!(f, "line is longer than {} bytes", max),
+            Invalid::OutOfRange(min, max, decimals) => {
+                let scale = 10f64.powi(*decimals as i32);
+                let decimals = *decimals as usize;
+                write!(f, "temperature is outside {:.*}..{:.*}", decimals, *min as f64 / scale, decimals, *max as f64 / scale)
+            }
+            Invalid::TooManyStations(max) => write!(f, "more than {} distinct stations", max),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path: Some(path), source } => write!(f, "{}: {}", path.display(), source),
+            Error::Io { path: None, source } => write!(f, "{}", source),
+            Error::Parse { path, line, offset, reason, text } => {
+                if let Some(path) = path {
+                    write!(f, "{}: ", path.display())?;
+                }
+                match line {
+                    Some(line) => write!(f, "line {}", line)?,
+                    None => write!(f, "byte {}", offset)?,
+                }
+                write!(f, " is not a valid measurement ({}): {:?}", reason, text)
+            }
+            Error::InvalidUtf8 { station } => write!(f, "station name {:?} is not valid UTF-8", station),
+            Error::Cancelled { partial } => write!(f, "cancelled, with {} stations so far", partial.len()),
+            Error::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(source: std::io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Other(message.to_string())
+    }
+}
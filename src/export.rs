@@ -0,0 +1,158 @@
+// Columnar output formats, for loading the results straight into
+// DuckDB, Polars and the like rather than parsing text.
+
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::Float64Type;
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use one_billion_row_challenge::{Error, Record, StationKey};
+
+use crate::unit::{degrees, Unit};
+use crate::{station_name, Aggregate, AggregateValue, Stat};
+
+// One row per station: station, min, mean, max and count, then a
+// Float64 column for each of --stats
+fn record_batch(data: &[(StationKey, Record)], aggregates: &[Aggregate], stats: &[Stat], unit: Unit) -> Result<RecordBatch, Error> {
+    let stations = data.iter().map(|(key, _)| station_name(key)).collect::<Result<Vec<_>, Error>>()?;
+    let float_column = |value: &dyn Fn(&Record) -> f64| -> ArrayRef {
+        Arc::new(data.iter().map(|(_, record)| value(record)).collect::<Float64Array>())
+    };
+
+    let mut fields = vec![Field::new("station", DataType::Utf8, false)];
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(StringArray::from(stations))];
+    for &aggregate in aggregates {
+        match aggregate {
+            Aggregate::Count => {
+                fields.push(Field::new(aggregate.name(), DataType::UInt64, false));
+                columns.push(Arc::new(data.iter().map(|(_, record)| record.count).collect::<UInt64Array>()));
+            }
+            _ => {
+                fields.push(Field::new(aggregate.name(), DataType::Float64, false));
+                columns.push(float_column(&|record| match aggregate.value(record, unit) {
+                    AggregateValue::Temperature(temperature) => degrees(temperature),
+                    AggregateValue::Count(_) => unreachable!("the count has a column of its own"),
+                }));
+            }
+        }
+    }
+
+    for stat in stats {
+        fields.push(Field::new(stat.name(), DataType::Float64, false));
+        columns.push(float_column(&|record| stat.value(record, unit)));
+    }
+
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(|e| Error::Other(e.to_string()))
+}
+
+#[cfg(feature = "arrow")]
+pub fn format_arrow(data: &[(StationKey, Record)], aggregates: &[Aggregate], stats: &[Stat], unit: Unit) -> Result<Vec<u8>, Error> {
+    let batch = record_batch(data, aggregates, stats, unit)?;
+    let arrow_error = |e: arrow_schema::ArrowError| Error::Other(format!("could not write arrow: {}", e));
+
+    let mut output = Vec::new();
+    let mut writer = arrow_ipc::writer::StreamWriter::try_new(&mut output, &batch.schema()).map_err(arrow_error)?;
+    writer.write(&batch).map_err(arrow_error)?;
+    writer.finish().map_err(arrow_error)?;
+
+    Ok(output)
+}
+
+#[cfg(feature = "parquet")]
+pub fn format_parquet(data: &[(StationKey, Record)], aggregates: &[Aggregate], stats: &[Stat], unit: Unit) -> Result<Vec<u8>, Error> {
+    let batch = record_batch(data, aggregates, stats, unit)?;
+    let parquet_error = |e: parquet::errors::ParquetError| Error::Other(format!("could not write parquet: {}", e));
+
+    let mut output = Vec::new();
+    let mut writer = parquet::arrow::ArrowWriter::try_new(&mut output, batch.schema(), None).map_err(parquet_error)?;
+    writer.write(&batch).map_err(parquet_error)?;
+    writer.close().map_err(parquet_error)?;
+
+    Ok(output)
+}
+
+// The station, min, mean and max columns of batches written as above,
+// for the diff subcommand
+fn read_batches(batches: impl Iterator<Item = Result<RecordBatch, ArrowError>>) -> Result<Vec<(String, [f64; 3])>, Error> {
+    let mut stations = Vec::new();
+    for batch in batches {
+        let batch = batch.map_err(|e| Error::Other(format!("could not read results: {}", e)))?;
+        let column = |name: &str| batch.column_by_name(name).ok_or_else(|| Error::Other(format!("results without a {} column", name)));
+        let float_column = |name: &str| column(name)?.as_primitive_opt::<Float64Type>().ok_or_else(|| Error::Other(format!("results with a {} column that isn't Float64", name)));
+
+        let names = column("station")?.as_string_opt::<i32>().ok_or_else(|| Error::Other("results with a station column that isn't Utf8".into()))?;
+        let (min, mean, max) = (float_column("min")?, float_column("mean")?, float_column("max")?);
+        for row in 0..batch.num_rows() {
+            stations.push((names.value(row).to_string(), [min.value(row), mean.value(row), max.value(row)]));
+        }
+    }
+    Ok(stations)
+}
+
+#[cfg(feature = "arrow")]
+pub fn read_arrow(bytes: &[u8]) -> Result<Vec<(String, [f64; 3])>, Error> {
+    let reader = arrow_ipc::reader::StreamReader::try_new(bytes, None)
+        .map_err(|e| Error::Other(format!("could not read arrow: {}", e)))?;
+    read_batches(reader)
+}
+
+#[cfg(feature = "parquet")]
+pub fn read_parquet(file: std::fs::File) -> Result<Vec<(String, [f64; 3])>, Error> {
+    let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(file)
+        .and_then(|builder| builder.build())
+        .map_err(|e| Error::Other(format!("could not read parquet: {}", e)))?;
+    read_batches(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::types::UInt64Type;
+    #[cfg(feature = "parquet")]
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    const SUMMARY: [Aggregate; 4] = [Aggregate::Min, Aggregate::Mean, Aggregate::Max, Aggregate::Count];
+
+    fn data() -> Vec<(StationKey, Record)> {
+        let mut abha = Record::new(-52);
+        abha.add(300);
+        vec![(StationKey::from(&b"Abha"[..]), abha), (StationKey::from(&b"Z\xc3\xbcrich"[..]), Record::new(5))]
+    }
+
+    fn check(batch: &RecordBatch) {
+        let names = batch.schema().fields().iter().map(|f| f.name().clone()).collect::<Vec<_>>();
+        assert_eq!(names, ["station", "min", "mean", "max", "count", "stddev"]);
+
+        let stations = batch.column(0).as_string::<i32>();
+        assert_eq!((stations.value(0), stations.value(1)), ("Abha", "Zürich"));
+        assert_eq!(batch.column(1).as_primitive::<Float64Type>().value(0), -5.2);
+        assert_eq!(batch.column(2).as_primitive::<Float64Type>().value(0), 12.4);
+        assert_eq!(batch.column(4).as_primitive::<UInt64Type>().values(), &[2, 1]);
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn arrow_round_trip() {
+        let output = format_arrow(&data(), &SUMMARY, &[Stat::StdDev], Unit::C).unwrap();
+        let mut reader = arrow_ipc::reader::StreamReader::try_new(output.as_slice(), None).unwrap();
+
+        check(&reader.next().unwrap().unwrap());
+        assert!(reader.next().is_none());
+        assert_eq!(read_arrow(&output).unwrap(), [("Abha".to_string(), [-5.2, 12.4, 30.]), ("Zürich".to_string(), [0.5; 3])]);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_round_trip() {
+        let path = std::env::temp_dir().join(format!("1brc-export-{}.parquet", std::process::id()));
+        std::fs::write(&path, format_parquet(&data(), &SUMMARY, &[Stat::StdDev], Unit::C).unwrap()).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let batch = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap().next().unwrap().unwrap();
+        let read = read_parquet(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read[0], ("Abha".to_string(), [-5.2, 12.4, 30.]));
+
+        check(&batch);
+    }
+}
@@ -0,0 +1,121 @@
+//! Restricting aggregation to some of the stations.
+
+use regex::bytes::RegexSet;
+
+use crate::Error;
+
+/// A set of station name patterns. Each pattern is one of
+///
+/// - a regex between slashes, e.g. `/^San /`, matched anywhere in the
+///   name unless anchored
+/// - a glob using `*`, `?` and `[...]`, e.g. `St*`, matched against the
+///   whole name
+/// - anything else, matched exactly
+///
+/// A station is kept if it matches any of them.
+#[derive(Clone, Debug)]
+pub struct StationFilter {
+    exact: Vec<Vec<u8>>,
+    patterns: Option<RegexSet>,
+}
+
+impl StationFilter {
+    /// Compiles the patterns
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self, Error> {
+        let mut exact = Vec::new();
+        let mut regexes = Vec::new();
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+
+            if let Some(regex) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+                regexes.push(regex.to_string());
+            } else if pattern.contains(['*', '?', '[']) {
+                regexes.push(glob_to_regex(pattern));
+            } else {
+                exact.push(pattern.as_bytes().to_vec());
+            }
+        }
+
+        let patterns = if regexes.is_empty() {
+            None
+        } else {
+            Some(RegexSet::new(&regexes).map_err(|e| Error::Other(format!("invalid station pattern: {}", e)))?)
+        };
+
+        Ok(Self { exact, patterns })
+    }
+
+    /// Whether the station called `name` is kept
+    pub fn matches(&self, name: &[u8]) -> bool {
+        self.exact.iter().any(|exact| exact == name)
+            || self.patterns.as_ref().is_some_and(|patterns| patterns.is_match(name))
+    }
+
+    // True if every pattern is an exact name, so matches() is cheap
+    // enough to call for every line rather than once per station
+    pub(crate) fn exact_only(&self) -> bool {
+        self.patterns.is_none()
+    }
+}
+
+// Translates a glob into an anchored regex
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                // Classes carry over as they are, apart from glob's [!...]
+                regex.push('[');
+                if chars.next_if_eq(&'!').is_some() {
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let filter = StationFilter::new(&["Hamburg", "St*", "[!A-Z]?", "/^Pal.*g$/"]).unwrap();
+
+        assert!(filter.matches(b"Hamburg"));
+        assert!(!filter.matches(b"Hamburg2"));
+        assert!(filter.matches(b"St. John's"));
+        assert!(filter.matches(b"xy"));
+        assert!(!filter.matches(b"Xy"));
+        assert!(filter.matches(b"Palembang"));
+        assert!(!filter.matches(b"Abha"));
+        assert!(!filter.exact_only());
+    }
+
+    #[test]
+    fn glob_metacharacters_are_escaped() {
+        let filter = StationFilter::new(&["St. *"]).unwrap();
+
+        assert!(filter.matches(b"St. John's"));
+        assert!(!filter.matches(b"Stx John's"));
+    }
+}
@@ -0,0 +1,109 @@
+// Aggregating a file that is still being written to.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::par::*;
+
+use crate::{chunk, compression, finish_map, in_pool, merge_maps, new_map, parse_lines, parse_to_end, BadLine, Error, Options, StationMap};
+
+/// Keeps a running aggregate of a measurements file that grows over
+/// time, e.g. a log other processes append to.
+///
+/// Only complete lines are ever parsed: a last line without its `\n`
+/// yet is left until a later [`poll`](Follower::poll) finds the rest of
+/// it. If the file shrinks, it's taken to have been truncated or
+/// replaced, and is aggregated again from the start.
+pub struct Follower {
+    path: PathBuf,
+    file: File,
+    options: Options,
+    data: StationMap,
+    // Everything before this has been parsed, and it's the start of a line
+    offset: u64,
+}
+
+impl Follower {
+    /// Aggregates the complete lines already in the (uncompressed) file
+    /// at `path`
+    pub fn new(path: &Path, options: &Options) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|e| Error::from(e).in_file(path))?;
+        if compression::detect_file(&file).map_err(|e| Error::from(e).in_file(path))? != compression::Compression::None {
+            return Err(format!("{}: compressed files can't be followed", path.display()).into());
+        }
+
+        let mut follower = Follower {
+            path: path.to_path_buf(),
+            file,
+            options: Options { progress: None, ..options.clone() },
+            data: new_map(),
+            offset: 0,
+        };
+        follower.read_existing().map_err(|e| e.in_file(path))?;
+
+        Ok(follower)
+    }
+
+    /// Parses any complete lines appended since the last call, returning
+    /// whether there were any
+    pub fn poll(&mut self) -> Result<bool, Error> {
+        self.poll_inner().map_err(|e| e.in_file(&self.path))
+    }
+
+    /// The stations so far
+    pub fn stations(&self) -> StationMap {
+        finish_map(self.data.clone(), &self.options)
+    }
+
+    /// How far into the file has been parsed
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn poll_inner(&mut self) -> Result<bool, Error> {
+        let len = self.file.metadata()?.len();
+
+        if len < self.offset {
+            self.data = new_map();
+            self.offset = 0;
+            self.read_existing()?;
+            return Ok(true);
+        }
+        if len == self.offset {
+            return Ok(false);
+        }
+
+        // Appends between polls are small next to the file, so they're
+        // parsed on this thread
+        let mut bytes = Vec::with_capacity((len - self.offset) as usize);
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        (&self.file).take(len - self.offset).read_to_end(&mut bytes)?;
+
+        let consumed = parse_lines(&bytes, &mut self.data, &self.options)
+            .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &bytes, at, self.offset))?;
+        self.offset += consumed as u64;
+
+        Ok(consumed > 0)
+    }
+
+    // Aggregates the file from the start up to its last complete line
+    fn read_existing(&mut self) -> Result<(), Error> {
+        let mmap = unsafe { memmap2::Mmap::map(&self.file)? };
+        let end = memchr::memrchr(b'\n', &mmap).map_or(0, |i| i + 1);
+        let bytes = &mmap[..end];
+        let options = &self.options;
+
+        let data = in_pool(options, || {
+            chunk::chunks_in(bytes, options).into_par_iter().try_fold(new_map, |mut data_map, (start, end)| {
+                parse_to_end(&bytes[start..end], &mut data_map, options)
+                    .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, bytes, start + at, 0))?;
+                Ok(data_map)
+            }).try_reduce(StationMap::default, |map1, map2| Ok(merge_maps(map1, map2)))
+        })?;
+
+        self.data = data;
+        self.offset = end as u64;
+        Ok(())
+    }
+}
@@ -1,458 +1,951 @@
-use rand_distr::{Distribution, Normal};
+use rand_distr::{Distribution, Normal, Zipf};
 
 use rand::prelude::SliceRandom;
 use rayon::prelude::*;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 
 
-use rand::rngs::StdRng;
+use clap::Parser;
 use rand::SeedableRng;
-use std::io::Write;
-
-const STATIONS: [(&str, f64); 413] = [
-    ("Abha", 18.0),
-    ("Abidjan", 26.0),
-    ("Abéché", 29.4),
-    ("Accra", 26.4),
-    ("Addis Ababa", 16.0),
-    ("Adelaide", 17.3),
-    ("Aden", 29.1),
-    ("Ahvaz", 25.4),
-    ("Albuquerque", 14.0),
-    ("Alexandra", 11.0),
-    ("Alexandria", 20.0),
-    ("Algiers", 18.2),
-    ("Alice Springs", 21.0),
-    ("Almaty", 10.0),
-    ("Amsterdam", 10.2),
-    ("Anadyr", -6.9),
-    ("Anchorage", 2.8),
-    ("Andorra la Vella", 9.8),
-    ("Ankara", 12.0),
-    ("Antananarivo", 17.9),
-    ("Antsiranana", 25.2),
-    ("Arkhangelsk", 1.3),
-    ("Ashgabat", 17.1),
-    ("Asmara", 15.6),
-    ("Assab", 30.5),
-    ("Astana", 3.5),
-    ("Athens", 19.2),
-    ("Atlanta", 17.0),
-    ("Auckland", 15.2),
-    ("Austin", 20.7),
-    ("Baghdad", 22.77),
-    ("Baguio", 19.5),
-    ("Baku", 15.1),
-    ("Baltimore", 13.1),
-    ("Bamako", 27.8),
-    ("Bangkok", 28.6),
-    ("Bangui", 26.0),
-    ("Banjul", 26.0),
-    ("Barcelona", 18.2),
-    ("Bata", 25.1),
-    ("Batumi", 14.0),
-    ("Beijing", 12.9),
-    ("Beirut", 20.9),
-    ("Belgrade", 12.5),
-    ("Belize City", 26.7),
-    ("Benghazi", 19.9),
-    ("Bergen", 7.7),
-    ("Berlin", 10.3),
-    ("Bilbao", 14.7),
-    ("Birao", 26.5),
-    ("Bishkek", 11.3),
-    ("Bissau", 27.0),
-    ("Blantyre", 22.2),
-    ("Bloemfontein", 15.6),
-    ("Boise", 11.4),
-    ("Bordeaux", 14.2),
-    ("Bosaso", 30.0),
-    ("Boston", 10.9),
-    ("Bouaké", 26.0),
-    ("Bratislava", 10.5),
-    ("Brazzaville", 25.0),
-    ("Bridgetown", 27.0),
-    ("Brisbane", 21.4),
-    ("Brussels", 10.5),
-    ("Bucharest", 10.8),
-    ("Budapest", 11.3),
-    ("Bujumbura", 23.8),
-    ("Bulawayo", 18.9),
-    ("Burnie", 13.1),
-    ("Busan", 15.0),
-    ("Cabo San Lucas", 23.9),
-    ("Cairns", 25.0),
-    ("Cairo", 21.4),
-    ("Calgary", 4.4),
-    ("Canberra", 13.1),
-    ("Cape Town", 16.2),
-    ("Changsha", 17.4),
-    ("Charlotte", 16.1),
-    ("Chiang Mai", 25.8),
-    ("Chicago", 9.8),
-    ("Chihuahua", 18.6),
-    ("Chișinău", 10.2),
-    ("Chittagong", 25.9),
-    ("Chongqing", 18.6),
-    ("Christchurch", 12.2),
-    ("City of San Marino", 11.8),
-    ("Colombo", 27.4),
-    ("Columbus", 11.7),
-    ("Conakry", 26.4),
-    ("Copenhagen", 9.1),
-    ("Cotonou", 27.2),
-    ("Cracow", 9.3),
-    ("Da Lat", 17.9),
-    ("Da Nang", 25.8),
-    ("Dakar", 24.0),
-    ("Dallas", 19.0),
-    ("Damascus", 17.0),
-    ("Dampier", 26.4),
-    ("Dar es Salaam", 25.8),
-    ("Darwin", 27.6),
-    ("Denpasar", 23.7),
-    ("Denver", 10.4),
-    ("Detroit", 10.0),
-    ("Dhaka", 25.9),
-    ("Dikson", -11.1),
-    ("Dili", 26.6),
-    ("Djibouti", 29.9),
-    ("Dodoma", 22.7),
-    ("Dolisie", 24.0),
-    ("Douala", 26.7),
-    ("Dubai", 26.9),
-    ("Dublin", 9.8),
-    ("Dunedin", 11.1),
-    ("Durban", 20.6),
-    ("Dushanbe", 14.7),
-    ("Edinburgh", 9.3),
-    ("Edmonton", 4.2),
-    ("El Paso", 18.1),
-    ("Entebbe", 21.0),
-    ("Erbil", 19.5),
-    ("Erzurum", 5.1),
-    ("Fairbanks", -2.3),
-    ("Fianarantsoa", 17.9),
-    ("Flores,  Petén", 26.4),
-    ("Frankfurt", 10.6),
-    ("Fresno", 17.9),
-    ("Fukuoka", 17.0),
-    ("Gabès", 19.5),
-    ("Gaborone", 21.0),
-    ("Gagnoa", 26.0),
-    ("Gangtok", 15.2),
-    ("Garissa", 29.3),
-    ("Garoua", 28.3),
-    ("George Town", 27.9),
-    ("Ghanzi", 21.4),
-    ("Gjoa Haven", -14.4),
-    ("Guadalajara", 20.9),
-    ("Guangzhou", 22.4),
-    ("Guatemala City", 20.4),
-    ("Halifax", 7.5),
-    ("Hamburg", 9.7),
-    ("Hamilton", 13.8),
-    ("Hanga Roa", 20.5),
-    ("Hanoi", 23.6),
-    ("Harare", 18.4),
-    ("Harbin", 5.0),
-    ("Hargeisa", 21.7),
-    ("Hat Yai", 27.0),
-    ("Havana", 25.2),
-    ("Helsinki", 5.9),
-    ("Heraklion", 18.9),
-    ("Hiroshima", 16.3),
-    ("Ho Chi Minh City", 27.4),
-    ("Hobart", 12.7),
-    ("Hong Kong", 23.3),
-    ("Honiara", 26.5),
-    ("Honolulu", 25.4),
-    ("Houston", 20.8),
-    ("Ifrane", 11.4),
-    ("Indianapolis", 11.8),
-    ("Iqaluit", -9.3),
-    ("Irkutsk", 1.0),
-    ("Istanbul", 13.9),
-    ("İzmir", 17.9),
-    ("Jacksonville", 20.3),
-    ("Jakarta", 26.7),
-    ("Jayapura", 27.0),
-    ("Jerusalem", 18.3),
-    ("Johannesburg", 15.5),
-    ("Jos", 22.8),
-    ("Juba", 27.8),
-    ("Kabul", 12.1),
-    ("Kampala", 20.0),
-    ("Kandi", 27.7),
-    ("Kankan", 26.5),
-    ("Kano", 26.4),
-    ("Kansas City", 12.5),
-    ("Karachi", 26.0),
-    ("Karonga", 24.4),
-    ("Kathmandu", 18.3),
-    ("Khartoum", 29.9),
-    ("Kingston", 27.4),
-    ("Kinshasa", 25.3),
-    ("Kolkata", 26.7),
-    ("Kuala Lumpur", 27.3),
-    ("Kumasi", 26.0),
-    ("Kunming", 15.7),
-    ("Kuopio", 3.4),
-    ("Kuwait City", 25.7),
-    ("Kyiv", 8.4),
-    ("Kyoto", 15.8),
-    ("La Ceiba", 26.2),
-    ("La Paz", 23.7),
-    ("Lagos", 26.8),
-    ("Lahore", 24.3),
-    ("Lake Havasu City", 23.7),
-    ("Lake Tekapo", 8.7),
-    ("Las Palmas de Gran Canaria", 21.2),
-    ("Las Vegas", 20.3),
-    ("Launceston", 13.1),
-    ("Lhasa", 7.6),
-    ("Libreville", 25.9),
-    ("Lisbon", 17.5),
-    ("Livingstone", 21.8),
-    ("Ljubljana", 10.9),
-    ("Lodwar", 29.3),
-    ("Lomé", 26.9),
-    ("London", 11.3),
-    ("Los Angeles", 18.6),
-    ("Louisville", 13.9),
-    ("Luanda", 25.8),
-    ("Lubumbashi", 20.8),
-    ("Lusaka", 19.9),
-    ("Luxembourg City", 9.3),
-    ("Lviv", 7.8),
-    ("Lyon", 12.5),
-    ("Madrid", 15.0),
-    ("Mahajanga", 26.3),
-    ("Makassar", 26.7),
-    ("Makurdi", 26.0),
-    ("Malabo", 26.3),
-    ("Malé", 28.0),
-    ("Managua", 27.3),
-    ("Manama", 26.5),
-    ("Mandalay", 28.0),
-    ("Mango", 28.1),
-    ("Manila", 28.4),
-    ("Maputo", 22.8),
-    ("Marrakesh", 19.6),
-    ("Marseille", 15.8),
-    ("Maun", 22.4),
-    ("Medan", 26.5),
-    ("Mek'ele", 22.7),
-    ("Melbourne", 15.1),
-    ("Memphis", 17.2),
-    ("Mexicali", 23.1),
-    ("Mexico City", 17.5),
-    ("Miami", 24.9),
-    ("Milan", 13.0),
-    ("Milwaukee", 8.9),
-    ("Minneapolis", 7.8),
-    ("Minsk", 6.7),
-    ("Mogadishu", 27.1),
-    ("Mombasa", 26.3),
-    ("Monaco", 16.4),
-    ("Moncton", 6.1),
-    ("Monterrey", 22.3),
-    ("Montreal", 6.8),
-    ("Moscow", 5.8),
-    ("Mumbai", 27.1),
-    ("Murmansk", 0.6),
-    ("Muscat", 28.0),
-    ("Mzuzu", 17.7),
-    ("N'Djamena", 28.3),
-    ("Naha", 23.1),
-    ("Nairobi", 17.8),
-    ("Nakhon Ratchasima", 27.3),
-    ("Napier", 14.6),
-    ("Napoli", 15.9),
-    ("Nashville", 15.4),
-    ("Nassau", 24.6),
-    ("Ndola", 20.3),
-    ("New Delhi", 25.0),
-    ("New Orleans", 20.7),
-    ("New York City", 12.9),
-    ("Ngaoundéré", 22.0),
-    ("Niamey", 29.3),
-    ("Nicosia", 19.7),
-    ("Niigata", 13.9),
-    ("Nouadhibou", 21.3),
-    ("Nouakchott", 25.7),
-    ("Novosibirsk", 1.7),
-    ("Nuuk", -1.4),
-    ("Odesa", 10.7),
-    ("Odienné", 26.0),
-    ("Oklahoma City", 15.9),
-    ("Omaha", 10.6),
-    ("Oranjestad", 28.1),
-    ("Oslo", 5.7),
-    ("Ottawa", 6.6),
-    ("Ouagadougou", 28.3),
-    ("Ouahigouya", 28.6),
-    ("Ouarzazate", 18.9),
-    ("Oulu", 2.7),
-    ("Palembang", 27.3),
-    ("Palermo", 18.5),
-    ("Palm Springs", 24.5),
-    ("Palmerston North", 13.2),
-    ("Panama City", 28.0),
-    ("Parakou", 26.8),
-    ("Paris", 12.3),
-    ("Perth", 18.7),
-    ("Petropavlovsk-Kamchatsky", 1.9),
-    ("Philadelphia", 13.2),
-    ("Phnom Penh", 28.3),
-    ("Phoenix", 23.9),
-    ("Pittsburgh", 10.8),
-    ("Podgorica", 15.3),
-    ("Pointe-Noire", 26.1),
-    ("Pontianak", 27.7),
-    ("Port Moresby", 26.9),
-    ("Port Sudan", 28.4),
-    ("Port Vila", 24.3),
-    ("Port-Gentil", 26.0),
-    ("Portland (OR)", 12.4),
-    ("Porto", 15.7),
-    ("Prague", 8.4),
-    ("Praia", 24.4),
-    ("Pretoria", 18.2),
-    ("Pyongyang", 10.8),
-    ("Rabat", 17.2),
-    ("Rangpur", 24.4),
-    ("Reggane", 28.3),
-    ("Reykjavík", 4.3),
-    ("Riga", 6.2),
-    ("Riyadh", 26.0),
-    ("Rome", 15.2),
-    ("Roseau", 26.2),
-    ("Rostov-on-Don", 9.9),
-    ("Sacramento", 16.3),
-    ("Saint Petersburg", 5.8),
-    ("Saint-Pierre", 5.7),
-    ("Salt Lake City", 11.6),
-    ("San Antonio", 20.8),
-    ("San Diego", 17.8),
-    ("San Francisco", 14.6),
-    ("San Jose", 16.4),
-    ("San José", 22.6),
-    ("San Juan", 27.2),
-    ("San Salvador", 23.1),
-    ("Sana'a", 20.0),
-    ("Santo Domingo", 25.9),
-    ("Sapporo", 8.9),
-    ("Sarajevo", 10.1),
-    ("Saskatoon", 3.3),
-    ("Seattle", 11.3),
-    ("Ségou", 28.0),
-    ("Seoul", 12.5),
-    ("Seville", 19.2),
-    ("Shanghai", 16.7),
-    ("Singapore", 27.0),
-    ("Skopje", 12.4),
-    ("Sochi", 14.2),
-    ("Sofia", 10.6),
-    ("Sokoto", 28.0),
-    ("Split", 16.1),
-    ("St. John's", 5.0),
-    ("St. Louis", 13.9),
-    ("Stockholm", 6.6),
-    ("Surabaya", 27.1),
-    ("Suva", 25.6),
-    ("Suwałki", 7.2),
-    ("Sydney", 17.7),
-    ("Tabora", 23.0),
-    ("Tabriz", 12.6),
-    ("Taipei", 23.0),
-    ("Tallinn", 6.4),
-    ("Tamale", 27.9),
-    ("Tamanrasset", 21.7),
-    ("Tampa", 22.9),
-    ("Tashkent", 14.8),
-    ("Tauranga", 14.8),
-    ("Tbilisi", 12.9),
-    ("Tegucigalpa", 21.7),
-    ("Tehran", 17.0),
-    ("Tel Aviv", 20.0),
-    ("Thessaloniki", 16.0),
-    ("Thiès", 24.0),
-    ("Tijuana", 17.8),
-    ("Timbuktu", 28.0),
-    ("Tirana", 15.2),
-    ("Toamasina", 23.4),
-    ("Tokyo", 15.4),
-    ("Toliara", 24.1),
-    ("Toluca", 12.4),
-    ("Toronto", 9.4),
-    ("Tripoli", 20.0),
-    ("Tromsø", 2.9),
-    ("Tucson", 20.9),
-    ("Tunis", 18.4),
-    ("Ulaanbaatar", -0.4),
-    ("Upington", 20.4),
-    ("Ürümqi", 7.4),
-    ("Vaduz", 10.1),
-    ("Valencia", 18.3),
-    ("Valletta", 18.8),
-    ("Vancouver", 10.4),
-    ("Veracruz", 25.4),
-    ("Vienna", 10.4),
-    ("Vientiane", 25.9),
-    ("Villahermosa", 27.1),
-    ("Vilnius", 6.0),
-    ("Virginia Beach", 15.8),
-    ("Vladivostok", 4.9),
-    ("Warsaw", 8.5),
-    ("Washington, D.C.", 14.6),
-    ("Wau", 27.8),
-    ("Wellington", 12.9),
-    ("Whitehorse", -0.1),
-    ("Wichita", 13.9),
-    ("Willemstad", 28.0),
-    ("Winnipeg", 3.0),
-    ("Wrocław", 9.6),
-    ("Xi'an", 14.1),
-    ("Yakutsk", -8.8),
-    ("Yangon", 27.5),
-    ("Yaoundé", 23.8),
-    ("Yellowknife", -4.3),
-    ("Yerevan", 12.4),
-    ("Yinchuan", 9.0),
-    ("Zagreb", 10.7),
-    ("Zanzibar City", 26.0),
-    ("Zürich", 9.3),
+use rand_chacha::ChaCha8Rng;
+use std::io::{IoSlice, IsTerminal, Seek, Write};
+use std::time::Instant;
+
+use indicatif::{HumanBytes, HumanCount, HumanDuration, ProgressBar, ProgressState, ProgressStyle};
+
+// Shared with the aggregator's --known-stations, which doesn't need the
+// rest of the generator
+#[path = "stations.rs"]
+pub(crate) mod stations;
+use stations::{read_stations, STATIONS, STD_DEV};
+
+const DEFAULT_ROWS: usize = 1_000_000_000;
+
+const BATCH_SIZE: usize = 1_000_000;
+
+#[derive(clap::Args)]
+pub struct GenerateArgs {
+    /// Seed for the random number generator. The same seed always
+    /// produces a byte-identical file; a random seed is used (and
+    /// printed) if this isn't given
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Number of rows to generate. Underscores are allowed as
+    /// separators, e.g. 10_000_000
+    #[arg(long, default_value_t = DEFAULT_ROWS, value_parser = parse_rows)]
+    pub rows: usize,
+
+    /// File of stations to generate rows for instead of the built-in
+    /// table. One `name;mean` (or just `name`) per line, lines
+    /// starting with # are ignored, so the official
+    /// weather_stations.csv can be given as it is
+    #[arg(long, alias = "station-table")]
+    pub stations: Option<PathBuf>,
+
+    /// Number of distinct stations, e.g. 10_000 for the challenge's
+    /// 10K-key variant. Fewer than the built-in table's 413 are picked
+    /// from it at random; beyond that, made-up names are added, as long
+    /// as the built-in ones and with some of their means
+    #[arg(long, value_parser = parse_rows, conflicts_with = "stations")]
+    pub stations_count: Option<usize>,
+
+    /// Compress the file as it's generated, into measurements.txt.gz or
+    /// measurements.txt.zst. Every batch of rows is compressed on its
+    /// own (as a gzip member, or a frame of a seekable zstd file), so
+    /// the aggregator can decompress them in parallel
+    #[arg(long, value_enum)]
+    pub compress: Option<Compress>,
+
+    /// Write the rows to this file instead of measurements.txt, or to
+    /// stdout for -
+    #[arg(long, short = 'o')]
+    pub output: Option<PathBuf>,
+
+    /// Draw rows the way the official CreateMeasurements does: one
+    /// java.util.Random stream for the whole file, a station from
+    /// nextInt, then its temperature from nextGaussian, rounded by
+    /// Math.round. The official generator uses an unseeded
+    /// ThreadLocalRandom, so what's reproduced is a run of it with
+    /// `new Random(seed)` instead. One thread draws every row, which
+    /// makes this slower
+    #[arg(long, conflicts_with_all = ["stations", "stations_count", "distribution"])]
+    pub official: bool,
+
+    /// How often each station comes up: uniform, or zipf(S) (zipf alone
+    /// being zipf(1)), where the kth commonest station comes up 1/k^S
+    /// as often as the commonest. Which stations are common is random,
+    /// by the seed
+    #[arg(long, default_value = "uniform", value_parser = parse_distribution)]
+    pub distribution: KeyDistribution,
+
+    /// Instead of random rows, write a small corpus of edge cases for
+    /// testing parsers and rounding to the --output directory (by
+    /// default edge-cases): measurements.txt, the same rows in
+    /// no-trailing-newline.txt, and expected.txt with the results in
+    /// the official format
+    #[arg(long, conflicts_with_all = ["seed", "rows", "stations", "stations_count", "compress", "official", "distribution", "resume"])]
+    pub edge_cases: bool,
+
+    /// Carry on with the file a run with the same options and --seed
+    /// was stopped in the middle of. It's cut back to its last whole
+    /// batch of rows and the rest drawn from there, so it ends up the
+    /// same as if the run hadn't stopped. Uncompressed files only
+    #[arg(long, requires = "seed", conflicts_with = "compress")]
+    pub resume: bool,
+
+    /// Split the rows between N files, measurements_000.txt and on (or
+    /// --output's name, numbered the same way), each row going to the
+    /// next file in turn. Aggregated together, the files give the same
+    /// results as the one file of the same --seed would
+    #[arg(long, value_name = "N", conflicts_with_all = ["edge_cases", "resume"])]
+    pub shards: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyDistribution {
+    Uniform,
+    Zipf(f64),
+}
+
+fn parse_distribution(distribution: &str) -> Result<KeyDistribution, String> {
+    let exponent = match distribution.trim() {
+        "uniform" => return Ok(KeyDistribution::Uniform),
+        "zipf" => "1",
+        other => other.strip_prefix("zipf(").and_then(|rest| rest.strip_suffix(')'))
+            .ok_or_else(|| format!("expected uniform, zipf or zipf(S), not {:?}", distribution))?,
+    };
+
+    match exponent.trim().parse::<f64>() {
+        Ok(s) if s.is_finite() && s >= 0.0 => Ok(KeyDistribution::Zipf(s)),
+        _ => Err(format!("invalid Zipf exponent {:?}", exponent)),
+    }
+}
+
+// java.util.Random, which is specified exactly so that every JVM draws
+// the same values from the same seed
+#[derive(Clone)]
+struct JavaRandom {
+    seed: u64,
+    next_gaussian: Option<f64>,
+}
+
+impl JavaRandom {
+    const MULTIPLIER: u64 = 0x5DEECE66D;
+    const MASK: u64 = (1 << 48) - 1;
+
+    fn new(seed: u64) -> JavaRandom {
+        JavaRandom { seed: (seed ^ Self::MULTIPLIER) & Self::MASK, next_gaussian: None }
+    }
+
+    fn next(&mut self, bits: u32) -> i32 {
+        self.seed = self.seed.wrapping_mul(Self::MULTIPLIER).wrapping_add(0xB) & Self::MASK;
+        (self.seed >> (48 - bits)) as i32
+    }
+
+    fn next_int(&mut self, bound: i32) -> i32 {
+        let r = self.next(31);
+        let m = bound - 1;
+        if bound & m == 0 {
+            return ((bound as i64 * r as i64) >> 31) as i32;
+        }
+        // Rejects the values that would make the low results likelier
+        let mut u = r;
+        loop {
+            let r = u % bound;
+            if u.wrapping_sub(r).wrapping_add(m) >= 0 {
+                return r;
+            }
+            u = self.next(31);
+        }
+    }
+
+    fn next_double(&mut self) -> f64 {
+        (((self.next(26) as i64) << 27) + self.next(27) as i64) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    // The polar method, which makes two values at a time
+    fn next_gaussian(&mut self) -> f64 {
+        if let Some(next) = self.next_gaussian.take() {
+            return next;
+        }
+        loop {
+            let v1 = 2.0 * self.next_double() - 1.0;
+            let v2 = 2.0 * self.next_double() - 1.0;
+            let s = v1 * v1 + v2 * v2;
+            if s < 1.0 && s != 0.0 {
+                let multiplier = (-2.0 * strict_log(s) / s).sqrt();
+                self.next_gaussian = Some(v2 * multiplier);
+                return v1 * multiplier;
+            }
+        }
+    }
+}
+
+// StrictMath.log, which is fdlibm's (and can differ from the platform's
+// in the last bit), for the s in (0, 1) that nextGaussian takes the
+// log of
+fn strict_log(x: f64) -> f64 {
+    const LN2_HI: f64 = f64::from_bits(0x3fe62e42_fee00000);
+    const LN2_LO: f64 = f64::from_bits(0x3dea39ef_35793c76);
+    const TWO54: f64 = (1u64 << 54) as f64;
+    const LG: [f64; 7] = [
+        f64::from_bits(0x3fe55555_55555593),
+        f64::from_bits(0x3fd99999_9997fa04),
+        f64::from_bits(0x3fd24924_94229359),
+        f64::from_bits(0x3fcc71c5_1d8e78af),
+        f64::from_bits(0x3fc74664_96cb03de),
+        f64::from_bits(0x3fc39a09_d078c69f),
+        f64::from_bits(0x3fc2f112_df3e5244),
+    ];
+    let high = |x: f64| (x.to_bits() >> 32) as i32;
+
+    let mut x = x;
+    let mut k = 0;
+    let mut hx = high(x);
+    if hx < 0x0010_0000 {
+        // Subnormal, so scale it up
+        k -= 54;
+        x *= TWO54;
+        hx = high(x);
+    }
+    k += (hx >> 20) - 1023;
+    hx &= 0x000f_ffff;
+    // Normalise x into [sqrt(2)/2, sqrt(2))
+    let i = (hx + 0x95f64) & 0x10_0000;
+    x = f64::from_bits(((hx | (i ^ 0x3ff0_0000)) as u64) << 32 | (x.to_bits() & 0xffff_ffff));
+    k += i >> 20;
+    let f = x - 1.0;
+    let dk = k as f64;
+
+    if (0x000f_ffff & (2 + hx)) < 3 {
+        // |f| < 2^-20
+        if f == 0.0 {
+            return dk * LN2_HI + dk * LN2_LO;
+        }
+        let r = f * f * (0.5 - (1.0 / 3.0) * f);
+        return if k == 0 { f - r } else { dk * LN2_HI - ((r - dk * LN2_LO) - f) };
+    }
+
+    let s = f / (2.0 + f);
+    let z = s * s;
+    let w = z * z;
+    let t1 = w * (LG[1] + w * (LG[3] + w * LG[5]));
+    let t2 = z * (LG[0] + w * (LG[2] + w * (LG[4] + w * LG[6])));
+    let r = t2 + t1;
+    if ((hx - 0x6147a) | (0x6b851 - hx)) > 0 {
+        let hfsq = 0.5 * f * f;
+        if k == 0 { f - (hfsq - s * (hfsq + r)) } else { dk * LN2_HI - ((hfsq - (s * (hfsq + r) + dk * LN2_LO)) - f) }
+    } else if k == 0 {
+        f - s * (f - r)
+    } else {
+        dk * LN2_HI - ((s * (f - r) - dk * LN2_LO) - f)
+    }
+}
+
+// Math.round: to the nearest integer, with halves rounded up
+fn java_round(x: f64) -> f64 {
+    let floor = x.floor();
+    if x - floor >= 0.5 { floor + 1.0 } else { floor }
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Compress {
+    Gzip,
+    Zstd,
+}
+
+impl Compress {
+    fn extension(self) -> &'static str {
+        match self {
+            Compress::Gzip => "gz",
+            Compress::Zstd => "zst",
+        }
+    }
+
+    // Batches are compressed independently, so the file is the
+    // compressed batches one after another (gzip's multi-member files
+    // decompress to all the members in order)
+    #[cfg_attr(not(all(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+    fn compress(self, buffer: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compress::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(buffer)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Compress::Zstd => zstd::bulk::compress(buffer, ZSTD_LEVEL),
+            #[allow(unreachable_patterns)]
+            compress => Err(std::io::Error::other(format!("{:?} output support isn't enabled in this build", compress))),
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+// The seek table that ends a seekable zstd file, one entry (compressed
+// size, decompressed size) per frame. See
+// https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md
+fn zstd_seek_table(frames: &[(u32, u32)]) -> Vec<u8> {
+    const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+    const SKIPPABLE_MAGIC: u32 = 0x184D2A5E;
+
+    let mut table = Vec::with_capacity(17 + frames.len() * 8);
+    table.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+    table.extend_from_slice(&(frames.len() as u32 * 8 + 9).to_le_bytes());
+    for (compressed, decompressed) in frames {
+        table.extend_from_slice(&compressed.to_le_bytes());
+        table.extend_from_slice(&decompressed.to_le_bytes());
+    }
+    // The footer: the number of frames, a descriptor (no checksums) and the magic
+    table.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+    table.push(0);
+    table.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+    table
+}
+
+fn parse_rows(rows: &str) -> Result<usize, std::num::ParseIntError> {
+    rows.replace('_', "").parse()
+}
+
+// Lets this file double as the standalone `generate` binary
+#[derive(Parser)]
+#[command(about = "Generate a measurements file")]
+struct Cli {
+    #[command(flatten)]
+    args: GenerateArgs,
+}
+
+// Only used when this file is built as the `generate` binary
+#[allow(dead_code)]
+pub fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    generate(&Cli::parse().args)
+}
+
+// Syllables for made-up station names, the plain vowels repeated so
+// accented ones only come up now and then
+const ONSETS: [&str; 30] = [
+    "b", "c", "d", "f", "g", "h", "j", "k", "l", "m", "n", "p", "r", "s", "t",
+    "v", "w", "z", "br", "ch", "dr", "gr", "kh", "kr", "pr", "sh", "st", "th", "tr", "",
 ];
+const VOWELS: [&str; 20] = [
+    "a", "e", "i", "o", "u", "a", "e", "i", "o", "a", "e", "o", "ai", "ou", "ia", "ua", "é", "ö", "ü", "á",
+];
+const CODAS: [&str; 12] = ["", "", "", "", "n", "r", "s", "l", "m", "k", "t", "ng"];
 
-const STATIONS_TO_GENERATE: usize = 1_000_000_000;
+// count distinct stations for --stations-count, drawn from their own
+// stream of the seed so they don't depend on how many rows there are
+fn pick_stations(count: usize, seed: u64) -> Vec<(String, f64)> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    // Past any stream a batch could use
+    rng.set_stream(u64::MAX);
 
-pub fn main() -> Result<(), Box<dyn Error>> {
-    // Generate it in batches of 1,000,000, and write to a file
-    // Do this all in parallel
-    let file = std::fs::File::create("measurements.txt")?;
-    let file = std::sync::Mutex::new(file);
+    let mut stations = STATIONS.iter().map(|(name, mean)| (name.to_string(), *mean)).collect::<Vec<_>>();
+    if count <= stations.len() {
+        stations.shuffle(&mut rng);
+        stations.truncate(count);
+        stations.sort_by(|a, b| a.0.cmp(&b.0));
+        return stations;
+    }
 
-    let stations = STATIONS.to_vec();
+    let mut names = stations.iter().map(|(name, _)| name.clone()).collect::<std::collections::HashSet<_>>();
+    while stations.len() < count {
+        let (real, mean) = STATIONS.choose(&mut rng).unwrap();
+        let mut name = made_up_name(real.chars().count(), &mut rng);
+        // Short names run out, so they get numbered
+        if !names.insert(name.clone()) {
+            let base = name;
+            name = (2..).map(|n| format!("{} {}", base, n)).find(|name| !names.contains(name)).unwrap();
+            names.insert(name.clone());
+        }
+        stations.push((name, *mean));
+    }
 
-    let batches = (STATIONS_TO_GENERATE as f64 / 1_000_000.0).ceil() as usize;
+    stations
+}
 
-    (0..batches).into_par_iter().for_each(|i| {
-        let mut rng = StdRng::seed_from_u64(i as u64);
+// A capitalised name of len characters, sometimes of several words
+fn made_up_name(len: usize, rng: &mut ChaCha8Rng) -> String {
+    use rand::Rng;
 
-        let mut buffer = String::new();
+    let mut name = String::new();
+    let mut word_len = 0;
+    let mut capital = true;
 
-        for _ in 0..1_000_000 {
-            let (name, temp) = stations.choose(&mut rng).unwrap();
-            let temp = Normal::new(*temp, 5.0).unwrap().sample(&mut rng);
-            buffer.push_str(&format!("{};{:.1}\n", name, temp));
+    while name.chars().count() < len {
+        if word_len >= 4 && rng.gen_bool(0.15) {
+            name.push(' ');
+            word_len = 0;
+            capital = true;
+        }
+        let syllable = [ONSETS.choose(rng), VOWELS.choose(rng), CODAS.choose(rng)].map(|part| *part.unwrap()).concat();
+        for c in syllable.chars() {
+            if capital {
+                name.extend(c.to_uppercase());
+                capital = false;
+            } else {
+                name.push(c);
+            }
+            word_len += 1;
         }
+    }
 
-        let mut file = file.lock().unwrap();
-        file.write_all(buffer.as_bytes()).unwrap();
+    name.chars().take(len).collect::<String>().trim_end().to_string()
+}
 
-        println!("Batch #{} done", i + 1);
-    });
+// Names of one to 100 bytes, multibyte ones, names that only differ in
+// case or accents, temperatures at both ends of the range and at zero of
+// either sign, and means that round half up. Listed with the stations
+// mixed up, as they would be in a real file
+fn edge_case_rows() -> Vec<(String, &'static str)> {
+    // 100 bytes each, of 1, 2 and 4 byte characters
+    let long = "Taumatawhakatangihangakoauauotamateaturipukakapikimaungahoronukupokaiwhenuakitanatahu South Beach Rd";
+    let accented = "é".repeat(50);
+    let emoji = "🌡".repeat(25);
+
+    [
+        ("A", "0.0"),
+        ("Extremes", "-99.9"),
+        ("Zürich", "9.8"),
+        ("Half up", "0.1"),
+        (long, "12.3"),
+        ("Z", "-0.0"),
+        ("Cold", "-99.9"),
+        ("東京", "15.4"),
+        ("Half up negative", "-0.1"),
+        ("Negative zero mean", "-0.1"),
+        ("A", "-0.0"),
+        ("Hot", "99.9"),
+        ("Extremes", "99.9"),
+        ("Zurich", "8.0"),
+        (&accented, "-45.6"),
+        ("Half up", "0.2"),
+        ("a", "3.3"),
+        ("Αθήνα", "19.2"),
+        ("Single digits", "1.5"),
+        ("Half up negative", "-0.2"),
+        ("Cold", "-99.9"),
+        (&emoji, "7.7"),
+        ("Negative zero mean", "0.0"),
+        ("İzmir", "17.9"),
+        ("Hot", "99.9"),
+        ("Single digits", "-1.5"),
+        ("Addis Ababa", "16.0"),
+        ("São Paulo", "-2.3"),
+        (long, "-12.3"),
+        ("Hot", "99.9"),
+    ].into_iter().map(|(name, temp)| (name.to_string(), temp)).collect()
+}
+
+// The results of rows in the official format, worked out here in
+// integers rather than by the aggregator, so they can check it
+fn edge_case_results(rows: &[(String, &str)]) -> String {
+    let mut stations = std::collections::BTreeMap::<&str, (i64, i64, i64, i64)>::new();
+    for (name, temp) in rows {
+        let tenths = temp.replace('.', "").parse::<i64>().unwrap();
+        let (min, max, total, count) = stations.entry(name.as_str()).or_insert((i64::MAX, i64::MIN, 0, 0));
+        *min = (*min).min(tenths);
+        *max = (*max).max(tenths);
+        *total += tenths;
+        *count += 1;
+    }
+
+    let fixed = |tenths: i64| format!("{}{}.{}", if tenths < 0 { "-" } else { "" }, tenths.abs() / 10, tenths.abs() % 10);
+    // BTreeMap orders str by their bytes, as the challenge does
+    let stations = stations.iter().map(|(name, &(min, max, total, count))| {
+        // Half up, so -0.05 is 0.0
+        let mean = (2 * total + count).div_euclid(2 * count);
+        format!("{}={}/{}/{}", name, fixed(min), fixed(mean), fixed(max))
+    }).collect::<Vec<_>>();
+
+    format!("{{{}}}\n", stations.join(", "))
+}
+
+fn write_edge_cases(dir: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let rows = edge_case_rows();
+    let mut contents = rows.iter().map(|(name, temp)| format!("{};{}\n", name, temp)).collect::<String>();
+
+    let write = |name: &str, contents: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).map_err(|e| std::io::Error::new(e.kind(), format!("could not write {}: {}", path.display(), e)))
+    };
+    std::fs::create_dir_all(dir).map_err(|e| std::io::Error::new(e.kind(), format!("could not create {}: {}", dir.display(), e)))?;
+    write("measurements.txt", &contents)?;
+    write("expected.txt", &edge_case_results(&rows))?;
+    contents.pop();
+    write("no-trailing-newline.txt", &contents)?;
 
+    log::info!("Wrote {} edge cases to {}", rows.len(), dir.display());
     Ok(())
-}
\ No newline at end of file
+}
+
+// A batch of rows, from a station index and rounded temperature each,
+// written over whatever buffer had in it, with which stations it used
+fn write_rows(mut buffer: Vec<u8>, rows: usize, stations: &[(&str, Normal<f64>)], mut row: impl FnMut() -> (usize, f64)) -> (Vec<u8>, usize, Vec<bool>) {
+    // Rows average well under 32 bytes, so this rarely reallocates
+    buffer.clear();
+    buffer.reserve(rows * 32);
+    let mut seen = vec![false; stations.len()];
+
+    for _ in 0..rows {
+        let (station, temp) = row();
+        seen[station] = true;
+        buffer.extend_from_slice(stations[station].0.as_bytes());
+        buffer.push(b';');
+        push_tenths(&mut buffer, (temp * 10.0).round() as i32);
+        buffer.push(b'\n');
+    }
+
+    (buffer, rows, seen)
+}
+
+// A temperature in tenths as {:.1} prints it, without the float
+// formatting that would otherwise be most of the time spent on a row
+fn push_tenths(buffer: &mut Vec<u8>, tenths: i32) {
+    if tenths < 0 {
+        buffer.push(b'-');
+    }
+    let tenths = tenths.unsigned_abs();
+    // Filled from the end: the tenth, the point, then the whole degrees
+    let mut digits = [0; 11];
+    digits[10] = b'0' + (tenths % 10) as u8;
+    digits[9] = b'.';
+    let (mut at, mut whole) = (9, tenths / 10);
+    loop {
+        at -= 1;
+        digits[at] = b'0' + (whole % 10) as u8;
+        whole /= 10;
+        if whole == 0 {
+            break;
+        }
+    }
+    buffer.extend_from_slice(&digits[at..]);
+}
+
+// Writes all of bufs, with as few calls as file takes them in
+fn write_all_vectored(file: &mut dyn Write, bufs: &[&[u8]]) -> std::io::Result<()> {
+    let mut slices = bufs.iter().map(|buf| IoSlice::new(buf)).collect::<Vec<_>>();
+    let mut slices = &mut slices[..];
+    // Drops any empty ones at the start
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match file.write_vectored(slices) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// The file for shard k of shards, numbered before the extensions, so
+// measurements.txt.gz has measurements_000.txt.gz first
+fn shard_path(path: &Path, k: usize, shards: usize) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (stem, extensions) = name.split_at(name.find('.').unwrap_or(name.len()));
+    let width = (shards - 1).to_string().len().max(3);
+    path.with_file_name(format!("{}_{:0width$}{}", stem, k, extensions))
+}
+
+// The rows in buffer, the first of which is row first of the file,
+// dealt out between shards by their row number
+fn deal_rows(buffer: &[u8], first: usize, shards: usize, mut empty: impl FnMut() -> Vec<u8>) -> Vec<Vec<u8>> {
+    let mut dealt = (0..shards).map(|_| {
+        let mut shard = empty();
+        shard.clear();
+        shard.reserve(buffer.len() / shards + 64);
+        shard
+    }).collect::<Vec<_>>();
+    let mut start = 0;
+    for (row, newline) in (first..).zip(memchr::memchr_iter(b'\n', buffer)) {
+        dealt[row % shards].extend_from_slice(&buffer[start..=newline]);
+        start = newline + 1;
+    }
+    dealt
+}
+
+// Where to carry on with the file at path: its number of whole batches,
+// which it's cut back to the end of. first_row is what its first line
+// has to be for it to have been generated with the same options
+fn resume(path: &Path, rows: usize, first_row: &[u8]) -> Result<(std::fs::File, usize), Box<dyn Error + Send + Sync>> {
+    let in_file = |e: std::io::Error| std::io::Error::new(e.kind(), format!("could not resume {}: {}", path.display(), e));
+    let mut file = match std::fs::OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            log::info!("There's no {} yet, so starting it", path.display());
+            return Ok((std::fs::File::create(path).map_err(in_file)?, 0));
+        }
+        Err(e) => return Err(in_file(e).into()),
+    };
+
+    // Mapping an empty file fails on some platforms
+    let mmap = match file.metadata().map_err(in_file)?.len() {
+        0 => None,
+        _ => Some(unsafe { memmap2::Mmap::map(&file) }.map_err(in_file)?),
+    };
+    let bytes = mmap.as_deref().unwrap_or_default();
+    if !bytes.is_empty() && !bytes.starts_with(first_row) {
+        return Err(format!("{} wasn't generated with these options and --seed", path.display()).into());
+    }
+    let (mut lines, mut batches, mut end) = (0, 0, 0);
+    for newline in memchr::memchr_iter(b'\n', bytes) {
+        lines += 1;
+        if lines % BATCH_SIZE == 0 {
+            (batches, end) = (lines / BATCH_SIZE, newline + 1);
+        }
+    }
+    if lines > rows {
+        return Err(format!("{} has more than --rows rows already", path.display()).into());
+    }
+    drop(mmap);
+
+    file.set_len(end as u64).map_err(in_file)?;
+    file.seek(std::io::SeekFrom::End(0)).map_err(in_file)?;
+    log::info!("Resuming {} after {} rows", path.display(), HumanCount((batches * BATCH_SIZE) as u64));
+    Ok((file, batches))
+}
+
+// A batch of rows as written to the files (compressed, with
+// --compress), one part for each shard
+struct Batch {
+    bytes: Vec<Vec<u8>>,
+    rows: usize,
+    // Before compression
+    rows_len: Vec<usize>,
+    // By station index
+    seen: Vec<bool>,
+}
+
+pub fn generate(args: &GenerateArgs) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if args.edge_cases {
+        return write_edge_cases(args.output.as_deref().unwrap_or("edge-cases".as_ref()));
+    }
+
+    let seed = args.seed.unwrap_or_else(rand::random);
+    log::info!("Seed: {}", seed);
+
+    let stations = match (&args.stations, args.stations_count) {
+        (Some(path), _) => read_stations(path)?,
+        (None, Some(0)) => return Err("--stations-count has to be at least 1".into()),
+        (None, Some(count)) => pick_stations(count, seed),
+        (None, None) => STATIONS.iter().map(|(name, mean)| (name.to_string(), *mean)).collect(),
+    };
+
+    // Each station's temperatures follow a Gaussian centred on its
+    // real mean temperature, like the official generator's table
+    let stations = stations.iter()
+        .map(|(name, mean)| (name.as_str(), Normal::new(*mean, STD_DEV).unwrap()))
+        .collect::<Vec<_>>();
+
+    // Generate it in batches of BATCH_SIZE rows, and write to a file.
+    // Each round, every thread generates one contiguous batch of rows
+    // into its own buffer. The finished round is handed to a writer
+    // thread, which writes the batches out in order while the next
+    // round is generated, so the file only depends on the seed
+    let path = match (&args.output, args.compress) {
+        (Some(path), _) => path.clone(),
+        (None, Some(compress)) => PathBuf::from(format!("measurements.txt.{}", compress.extension())),
+        (None, None) => PathBuf::from("measurements.txt"),
+    };
+    // Fail before generating anything if compression isn't built in
+    if let Some(compress) = args.compress {
+        compress.compress(&[])?;
+    }
+    let stdout = path.as_os_str() == "-";
+    if stdout && args.compress.is_some() && std::io::stdout().is_terminal() {
+        return Err("refusing to write compressed rows to a terminal, use -o or redirect stdout".into());
+    }
+    if stdout && args.resume {
+        return Err("--resume needs the file to carry on with, not stdout".into());
+    }
+    let shards = args.shards.unwrap_or(1);
+    match args.shards {
+        Some(0) => return Err("--shards has to be at least 1".into()),
+        Some(_) if stdout => return Err("--shards needs files to write, not stdout".into()),
+        _ => {}
+    }
+
+    let batches = args.rows.div_ceil(BATCH_SIZE);
+    let round_size = rayon::current_num_threads();
+    // choose() picks from these rather than the stations themselves so
+    // the batches can note which stations they used
+    let indices = (0..stations.len()).collect::<Vec<_>>();
+    // The stations in order of how common they are, and how common
+    let zipf = match args.distribution {
+        KeyDistribution::Uniform => None,
+        KeyDistribution::Zipf(s) => {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            rng.set_stream(u64::MAX - 1);
+            let mut ranks = indices.clone();
+            ranks.shuffle(&mut rng);
+            Some((ranks, Zipf::new(stations.len() as u64, s)?))
+        }
+    };
+
+    // A row as CreateMeasurements draws it, from the one stream it
+    // carries on with from each batch to the next
+    let official_row = |java: &mut JavaRandom| {
+        let station = java.next_int(stations.len() as i32) as usize;
+        let temp = stations[station].1.mean() + STD_DEV * java.next_gaussian();
+        (station, java_round(temp * 10.0) / 10.0)
+    };
+    let random_batch = |i: usize, rows: usize, buffer: Vec<u8>| {
+        // Every batch gets its own ChaCha stream of the same seed,
+        // so batches don't depend on which thread generated them.
+        // ChaCha8Rng (unlike StdRng) is guaranteed to produce the
+        // same values across platforms and versions
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        rng.set_stream(i as u64);
+
+        write_rows(buffer, rows, &stations, || {
+            let station = match &zipf {
+                // Ranks from 1
+                Some((ranks, zipf)) => ranks[zipf.sample(&mut rng) as usize - 1],
+                None => *indices.choose(&mut rng).unwrap(),
+            };
+            // Round half up to one decimal like Java's Math.round,
+            // which is what the official generator uses
+            (station, (stations[station].1.sample(&mut rng) * 10.0 + 0.5).floor() / 10.0)
+        })
+    };
+
+    let paths = match args.shards {
+        Some(shards) => (0..shards).map(|k| shard_path(&path, k, shards)).collect(),
+        None => vec![path.clone()],
+    };
+    let create = |path: &Path| -> std::io::Result<Box<dyn Write + Send>> {
+        let file = std::fs::File::create(path).map_err(|e| std::io::Error::new(e.kind(), format!("could not create {}: {}", path.display(), e)))?;
+        Ok(Box::new(file))
+    };
+
+    let mut java = JavaRandom::new(seed);
+    let (mut files, first_batch): (Vec<Box<dyn Write + Send>>, usize) = if stdout {
+        (vec![Box::new(std::io::stdout())], 0)
+    } else if args.resume {
+        let first_row = match args.official {
+            true => write_rows(Vec::new(), 1, &stations, || official_row(&mut java.clone())).0,
+            false => random_batch(0, 1, Vec::new()).0,
+        };
+        let (file, first_batch) = resume(&path, args.rows, &first_row)?;
+        // The official stream has to be drawn from up to where it left off
+        if args.official {
+            for _ in 0..first_batch * BATCH_SIZE {
+                official_row(&mut java);
+            }
+        }
+        (vec![Box::new(file)], first_batch)
+    } else {
+        (paths.iter().map(|path| create(path)).collect::<std::io::Result<_>>()?, 0)
+    };
+    let skipped_rows = (first_batch * BATCH_SIZE).min(args.rows);
+
+    // Hidden when stderr isn't a terminal
+    let bar = ProgressBar::new(args.rows as u64).with_style(
+        ProgressStyle::with_template("{bar:40} {human_pos}/{human_len} rows ({rows_per_sec}, ETA {eta})").unwrap()
+            .with_key("rows_per_sec", |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                write!(w, "{} rows/s", HumanCount(state.per_sec() as u64)).unwrap()
+            }),
+    );
+    bar.set_position(skipped_rows as u64);
+    let started = Instant::now();
+
+    // Buffers the writer is done with, for the next rounds' rows
+    let free = std::sync::Mutex::new(Vec::<Vec<u8>>::new());
+    let buffer = || free.lock().unwrap().pop().unwrap_or_default();
+
+    let result = std::thread::scope(|scope| {
+        // Only one finished round can be waiting on the writer at a
+        // time, which bounds memory use to about two rounds of batches
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<(usize, Vec<Batch>)>(1);
+
+        let compress = args.compress;
+        let bar = &bar;
+        let station_count = stations.len();
+        let free = &free;
+        let writer = scope.spawn(move || -> std::io::Result<(u64, Vec<bool>)> {
+            let mut written = 0;
+            let mut seen = vec![false; station_count];
+            // The sizes of each file's zstd frames, for its seek table
+            let mut frames = vec![Vec::new(); files.len()];
+
+            for (round_start, batches) in receiver {
+                // The whole round in one go for each file, then its
+                // buffers go back to be filled again
+                for (k, file) in files.iter_mut().enumerate() {
+                    write_all_vectored(file, &batches.iter().map(|batch| &batch.bytes[k][..]).collect::<Vec<_>>())?;
+                }
+                for (i, batch) in (round_start..).zip(batches) {
+                    for (k, (bytes, rows_len)) in batch.bytes.into_iter().zip(batch.rows_len).enumerate() {
+                        written += bytes.len() as u64;
+                        frames[k].push((bytes.len() as u32, rows_len as u32));
+                        if compress.is_none() {
+                            free.lock().unwrap().push(bytes);
+                        }
+                    }
+                    seen.iter_mut().zip(batch.seen).for_each(|(seen, used)| *seen |= used);
+                    bar.inc(batch.rows as u64);
+                    log::debug!("Batch #{} done", i + 1);
+                }
+            }
+
+            for (file, frames) in files.iter_mut().zip(&frames) {
+                if let Some(Compress::Zstd) = compress {
+                    let table = zstd_seek_table(frames);
+                    file.write_all(&table)?;
+                    written += table.len() as u64;
+                }
+                file.flush()?;
+            }
+            Ok((written, seen))
+        });
+
+        for round_start in (first_batch..batches).step_by(round_size) {
+            let round = round_start..batches.min(round_start + round_size);
+            // Only the last batch can be short
+            let batch_rows = |i: usize| BATCH_SIZE.min(args.rows - i * BATCH_SIZE);
+
+            let rows = if args.official {
+                round.map(|i| write_rows(buffer(), batch_rows(i), &stations, || official_row(&mut java))).collect::<Vec<_>>()
+            } else {
+                round.into_par_iter().map(|i| random_batch(i, batch_rows(i), buffer())).collect::<Vec<_>>()
+            };
+
+            let batches = rows.into_par_iter().enumerate().map(|(j, (buffer, rows, seen))| {
+                let parts = match shards {
+                    1 => vec![buffer],
+                    _ => {
+                        let dealt = deal_rows(&buffer, (round_start + j) * BATCH_SIZE, shards, || free.lock().unwrap().pop().unwrap_or_default());
+                        free.lock().unwrap().push(buffer);
+                        dealt
+                    }
+                };
+                let rows_len = parts.iter().map(Vec::len).collect();
+                let bytes = match args.compress {
+                    Some(compress) => parts.into_iter().map(|part| {
+                        let compressed = compress.compress(&part)?;
+                        free.lock().unwrap().push(part);
+                        Ok(compressed)
+                    }).collect::<std::io::Result<_>>()?,
+                    None => parts,
+                };
+                Ok(Batch { bytes, rows, rows_len, seen })
+            }).collect::<std::io::Result<Vec<_>>>();
+
+            // A batch that failed to compress fails the whole file
+            let batches = match batches {
+                Ok(batches) => batches,
+                Err(e) => {
+                    drop(sender);
+                    writer.join().unwrap()?;
+                    return Err(e);
+                }
+            };
+
+            // The writer only hangs up if it hit an error, which
+            // join() below returns
+            if sender.send((round_start, batches)).is_err() {
+                break;
+            }
+        }
+
+        drop(sender);
+        writer.join().unwrap()
+    });
+    bar.finish_and_clear();
+
+    match result {
+        Ok((written, seen)) => {
+            log::info!(
+                "Wrote {} ({} rows of {} stations) to {} in {}",
+                HumanBytes(written),
+                HumanCount((args.rows - skipped_rows) as u64),
+                HumanCount(seen.iter().filter(|&&seen| seen).count() as u64),
+                match paths.len() {
+                    _ if stdout => "stdout".into(),
+                    1 => paths[0].display().to_string(),
+                    n => format!("{} files, {} to {}", n, paths[0].display(), paths[n - 1].display()),
+                },
+                HumanDuration(started.elapsed()),
+            );
+            Ok(())
+        }
+        // Whatever was reading stdout has seen all it wanted
+        Err(e) if stdout && e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_distributions() {
+        assert_eq!(parse_distribution("uniform"), Ok(KeyDistribution::Uniform));
+        assert_eq!(parse_distribution("zipf"), Ok(KeyDistribution::Zipf(1.0)));
+        assert_eq!(parse_distribution("zipf(1.5)"), Ok(KeyDistribution::Zipf(1.5)));
+        assert!(parse_distribution("zipf(-1)").is_err());
+        assert!(parse_distribution("normal").is_err());
+    }
+
+    #[test]
+    fn picks_distinct_stations() {
+        assert_eq!(pick_stations(10, 1).len(), 10);
+
+        let stations = pick_stations(10_000, 1);
+        let names = stations.iter().map(|(name, _)| name.as_str()).collect::<std::collections::HashSet<_>>();
+        assert_eq!(names.len(), 10_000);
+        assert!(names.iter().all(|name| !name.is_empty() && name.len() <= 100 && !name.contains([';', '\n'])));
+        assert_eq!(pick_stations(10_000, 1), stations);
+    }
+
+    #[test]
+    fn tenths_as_formatted() {
+        for tenths in [-999, -100, -10, -9, -1, 0, 1, 9, 10, 99, 100, 9999, i32::MAX, i32::MIN + 1] {
+            let mut buffer = Vec::new();
+            push_tenths(&mut buffer, tenths);
+            assert_eq!(String::from_utf8(buffer).unwrap(), format!("{:.1}", tenths as f64 / 10.0));
+        }
+    }
+
+    #[test]
+    fn shards_in_turn() {
+        assert_eq!(shard_path(Path::new("data/measurements.txt.gz"), 2, 4), Path::new("data/measurements_002.txt.gz"));
+        assert_eq!(shard_path(Path::new("rows"), 7, 1000), Path::new("rows_007"));
+        assert_eq!(shard_path(Path::new("rows.txt"), 7, 1001), Path::new("rows_0007.txt"));
+
+        let dealt = deal_rows(b"a;1.0\nb;2.0\nc;3.0\nd;4.0\ne;5.0\n", 4, 3, Vec::new);
+        assert_eq!(dealt, [&b"c;3.0\n"[..], b"a;1.0\nd;4.0\n", b"b;2.0\ne;5.0\n"]);
+    }
+
+    #[test]
+    fn resumes_after_whole_batches() {
+        let path = std::env::temp_dir().join(format!("1brc-resume-{}.txt", std::process::id()));
+        std::fs::write(&path, "a;1.0\n".repeat(BATCH_SIZE + 5) + "a;1").unwrap();
+
+        assert!(resume(&path, BATCH_SIZE * 2, b"b;").is_err());
+        assert!(resume(&path, BATCH_SIZE, b"a;").is_err());
+        let (_, batches) = resume(&path, BATCH_SIZE * 2, b"a;").unwrap();
+        assert_eq!(batches, 1);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 6 * BATCH_SIZE as u64);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn draws_what_java_does() {
+        // new Random(42).nextInt(), .nextGaussian() and .nextInt(10)
+        assert_eq!(JavaRandom::new(42).next(32), -1170105035);
+        assert_eq!(JavaRandom::new(42).next_gaussian(), 1.1419053154730547);
+        assert_eq!(JavaRandom::new(42).next_int(10), 0);
+        assert_eq!(java_round(-0.5), 0.0);
+        for x in [1e-300, 0.1, 0.5, 0.7, 0.999999, 1.0 - f64::EPSILON] {
+            assert!((strict_log(x) - x.ln()).abs() <= x.ln().abs() * 2.0 * f64::EPSILON, "{}", x);
+        }
+        assert_eq!(java_round(2.5), 3.0);
+    }
+}
@@ -0,0 +1,289 @@
+// An experimental engine that parses chunks on the GPU.
+//
+// The file is mapped as for the mmap engine, and each chunk in turn is
+// uploaded to a wgpu compute shader (gpu.wgsl) that finds the lines and
+// reduces them into a table of stations. Only the table comes back, and
+// only the names are read back out of the map, on the CPU, to merge the
+// chunks' tables.
+//
+// The shader only parses the challenge's own format, and stations are
+// told apart by 64 bits of hash rather than by name. A chunk it can't
+// do (a line it doesn't parse, more stations than slots, or too big to
+// bind) goes to the CPU parser instead, which also gives any error its
+// line number, as does every chunk with options the shader knows nothing
+// about.
+
+use std::sync::mpsc;
+
+use crate::engine::Engine;
+use crate::timings::{timed, Phase};
+use crate::{cancelled, chunk, merge_maps, new_map, parse_to_end, report_progress, BadLine, Error, Options, Record, StationKey, StationMap, Total};
+
+const SHADER: &str = include_str!("gpu.wgsl");
+
+// Enough for the challenge's 10,000 stations at under two thirds full
+const SLOTS: usize = 16 * 1024;
+// Words per slot (see gpu.wgsl)
+const SLOT: usize = 10;
+const TABLE_BYTES: u64 = (SLOTS * SLOT * 4) as u64;
+
+const WORKGROUP_SIZE: u64 = 64;
+// The most workgroups a dispatch can have in one dimension
+const MAX_WORKGROUPS: u64 = 65535;
+// Shortest segment of the chunk an invocation parses
+const MIN_SEGMENT: u64 = 256;
+
+pub(crate) struct Gpu {
+    mmap: memmap2::Mmap,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+// The buffers for a chunk of up to data's size, reused for every chunk
+struct Buffers {
+    params: wgpu::Buffer,
+    data: wgpu::Buffer,
+    table: wgpu::Buffer,
+    flags: wgpu::Buffer,
+    // The table then the flags, copied back to be read
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Gpu {
+    pub fn new(file: &std::fs::File, size: u64) -> Result<Self, Error> {
+        let mmap = unsafe { memmap2::MmapOptions::new().len(size as usize).map(file)? };
+
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle_from_env());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        })).map_err(|e| Error::Other(format!("No GPU for the gpu engine: {}", e)))?;
+        log::debug!("Parsing on {} ({:?})", adapter.get_info().name, adapter.get_info().backend);
+
+        // As big a chunk as the adapter can take
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("brc"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        })).map_err(|e| Error::Other(format!("Could not open the GPU: {}", e)))?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("brc"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("brc"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Ok(Gpu { mmap, device, queue, pipeline })
+    }
+
+    fn buffers(&self, largest: u64) -> Buffers {
+        let buffer = |label, size, usage| self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage,
+            mapped_at_creation: false,
+        });
+        use wgpu::BufferUsages as Usage;
+
+        let params = buffer("params", 16, Usage::UNIFORM | Usage::COPY_DST);
+        let data = buffer("data", largest.next_multiple_of(4).max(4), Usage::STORAGE | Usage::COPY_DST);
+        let table = buffer("table", TABLE_BYTES, Usage::STORAGE | Usage::COPY_SRC | Usage::COPY_DST);
+        let flags = buffer("flags", 4, Usage::STORAGE | Usage::COPY_SRC | Usage::COPY_DST);
+        let readback = buffer("readback", TABLE_BYTES + 4, Usage::MAP_READ | Usage::COPY_DST);
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("brc"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[&params, &data, &table, &flags].iter().enumerate().map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            }).collect::<Vec<_>>(),
+        });
+
+        Buffers { params, data, table, flags, readback, bind_group }
+    }
+
+    // The stations in bytes, or None if the shader couldn't parse them
+    fn reduce(&self, buffers: &Buffers, bytes: &[u8], options: &Options) -> Result<Option<StationMap>, Error> {
+        let len = bytes.len() as u64;
+        let segment = len.div_ceil(WORKGROUP_SIZE * MAX_WORKGROUPS).max(MIN_SEGMENT);
+        let invocations = len.div_ceil(segment);
+
+        let params = [len as u32, options.delimiter as u32, SLOTS as u32 - 1, segment as u32];
+        self.queue.write_buffer(&buffers.params, 0, &params.map(u32::to_le_bytes).concat());
+        // Writes have to be whole words, so the last few bytes are padded
+        let whole = bytes.len() / 4 * 4;
+        self.queue.write_buffer(&buffers.data, 0, &bytes[..whole]);
+        if whole < bytes.len() {
+            let mut tail = [0; 4];
+            tail[..bytes.len() - whole].copy_from_slice(&bytes[whole..]);
+            self.queue.write_buffer(&buffers.data, whole as u64, &tail);
+        }
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        encoder.clear_buffer(&buffers.table, 0, None);
+        encoder.clear_buffer(&buffers.flags, 0, None);
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &buffers.bind_group, &[]);
+            pass.dispatch_workgroups(invocations.div_ceil(WORKGROUP_SIZE) as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&buffers.table, 0, &buffers.readback, 0, TABLE_BYTES);
+        encoder.copy_buffer_to_buffer(&buffers.flags, 0, &buffers.readback, TABLE_BYTES, 4);
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        buffers.readback.map_async(wgpu::MapMode::Read, .., move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::wait_indefinitely()).map_err(|e| Error::Other(format!("The GPU failed: {}", e)))?;
+        receiver.recv().map_err(|e| Error::Other(e.to_string()))?
+            .map_err(|e| Error::Other(format!("Could not read back from the GPU: {}", e)))?;
+
+        let words: Vec<u32> = buffers.readback.get_mapped_range(..)
+            .map_err(|e| Error::Other(format!("Could not read back from the GPU: {}", e)))?
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        buffers.readback.unmap();
+
+        let (table, flags) = words.split_at(SLOTS * SLOT);
+        if flags[0] != 0 {
+            log::debug!("The shader couldn't parse a chunk (flags {:#x}), so parsing it on the CPU", flags[0]);
+            return Ok(None);
+        }
+        Ok(Some(decode(table, bytes, options.delimiter)))
+    }
+}
+
+// The stations in a table the shader filled in from bytes
+fn decode(table: &[u32], bytes: &[u8], delimiter: u8) -> StationMap {
+    let mut data_map = new_map();
+
+    for slot in table.chunks_exact(SLOT).filter(|slot| slot[5] != 0) {
+        let line = &bytes[slot[2] as usize..];
+        let line = &line[..memchr::memchr(b'\n', line).unwrap_or(line.len())];
+        let name = &line[..memchr::memrchr(delimiter, line).unwrap_or(0)];
+
+        let wide = |low: u32, high: u32| (high as u64) << 32 | low as u64;
+        let count = slot[5] as u64;
+        data_map.insert(StationKey::from(name), Record {
+            min: 1000 - slot[3] as i32,
+            max: slot[4] as i32 - 1000,
+            total: wide(slot[6], slot[7]) as Total - 1000 * count as Total,
+            count,
+            sum_squares: wide(slot[8], slot[9]) as Total,
+            histogram: None,
+            decimals: 1,
+        });
+    }
+
+    data_map
+}
+
+// Whether the shader can parse with these options, which it only knows
+// the delimiter of
+fn supported(options: &Options) -> bool {
+    options.stations.is_none()
+        && !options.histograms
+        && !options.validate_utf8
+        && !options.strict
+        && options.max_name_len.is_none()
+        && options.precision == 1
+}
+
+impl Engine for Gpu {
+    fn chunks(&self, options: &Options) -> Result<Vec<(u64, u64)>, Error> {
+        Ok(chunk::chunks_in(&self.mmap, options).into_iter().map(|(start, end)| (start as u64, end as u64)).collect())
+    }
+
+    fn read_chunk(&self, start: u64, end: u64, data_map: &mut StationMap, options: &Options) -> Result<(), Error> {
+        let chunk_map = self.aggregate_chunks(vec![(start, end)], options)?;
+        *data_map = merge_maps(std::mem::take(data_map), chunk_map);
+        Ok(())
+    }
+
+    // There's one device, so the chunks go to it one at a time
+    fn aggregate_chunks(&self, chunks: Vec<(u64, u64)>, options: &Options) -> Result<StationMap, Error> {
+        let supported = supported(options);
+        if !supported {
+            log::warn!("The gpu engine can't parse with these options, so parsing on the CPU");
+        }
+
+        let max_binding = self.device.limits().max_storage_buffer_binding_size;
+        let largest = chunks.iter().map(|(start, end)| end - start).filter(|&len| len <= max_binding).max();
+        let buffers = largest.filter(|_| supported).map(|largest| self.buffers(largest));
+
+        let mut data_map = new_map();
+        for (start, end) in chunks {
+            if cancelled(options) {
+                break;
+            }
+            let bytes = &self.mmap[start as usize..end as usize];
+            let reduced = match &buffers {
+                Some(buffers) if !bytes.is_empty() && bytes.len() as u64 <= max_binding => {
+                    timed(options, Phase::Parsing, || self.reduce(buffers, bytes, options))?
+                }
+                _ => None,
+            };
+
+            match reduced {
+                Some(chunk_map) => data_map = timed(options, Phase::Merging, || merge_maps(data_map, chunk_map)),
+                None => timed(options, Phase::Parsing, || parse_to_end(bytes, &mut data_map, options))
+                    .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &self.mmap, start as usize + at, 0))?,
+            }
+            report_progress(options, bytes.len());
+        }
+
+        Ok(data_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shader_is_valid() {
+        use wgpu::naga;
+
+        let module = naga::front::wgsl::parse_str(SHADER).unwrap();
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::empty())
+            .validate(&module)
+            .unwrap();
+    }
+
+    #[test]
+    fn decodes_tables() {
+        let bytes = b"Abha;5.0\nSt. John's;-1.5\r\nAbha;-2.5";
+        let mut table = vec![0; 3 * SLOT];
+        table[..SLOT].copy_from_slice(&[1, 1, 0, 1000 + 25, 1000 + 50, 2, 2000 + 25, 0, 625 + 2500, 0]);
+        table[2 * SLOT..].copy_from_slice(&[2, 2, 9, 1000 + 15, 1000 - 15, 1, 1000 - 15, 0, 225, 0]);
+
+        let mut expected = new_map();
+        assert!(parse_to_end(bytes, &mut expected, &Options::default()).is_ok());
+        assert_eq!(decode(&table, bytes, b';'), expected);
+    }
+
+    #[test]
+    fn same_results_as_the_cpu() {
+        let file = std::fs::File::open("tests/data/crlf.txt").unwrap();
+        // Most machines running the tests have no GPU
+        let size = file.metadata().unwrap().len();
+        let Ok(gpu) = Gpu::new(&file, size) else { return };
+
+        let options = Options::default();
+        let expected = crate::engine::aggregate(&crate::engine::Mapped::new(&file, size, &options).unwrap(), &options).unwrap();
+        assert_eq!(crate::engine::aggregate(&gpu, &options).unwrap(), expected);
+    }
+}
@@ -0,0 +1,170 @@
+// Scans a chunk for lines and reduces them per station, for gpu.rs.
+//
+// Every invocation owns a segment of the chunk and parses the lines
+// that start in it, reading on past its end to finish the last one.
+// Stations go in an open addressing table keyed by two 32-bit hashes of
+// the name, and each slot's fields are updated with atomics, so any
+// number of invocations can add to the same station at once.
+
+struct Params {
+    len: u32,
+    delimiter: u32,
+    // Number of slots, less one
+    mask: u32,
+    segment: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+// The chunk's bytes, four to a word, little end first
+@group(0) @binding(1) var<storage, read> data: array<u32>;
+// SLOT words per slot: the two hashes, the offset of a line with the
+// name, 1000 - min, max + 1000, the count, and the sum of max + 1000
+// and of the squares as two words each, low word first. The biases keep
+// every field positive, so a zeroed table is an empty one
+@group(0) @binding(2) var<storage, read_write> table: array<atomic<u32>>;
+@group(0) @binding(3) var<storage, read_write> flags: atomic<u32>;
+
+const SLOT: u32 = 10u;
+// A line the shader can't parse, which may or may not be valid
+const UNPARSED: u32 = 1u;
+// More stations than slots
+const FULL: u32 = 2u;
+
+fn byte_at(i: u32) -> u32 {
+    return (data[i >> 2u] >> ((i & 3u) * 8u)) & 0xffu;
+}
+
+fn digit_at(i: u32) -> u32 {
+    // Anything below '0' wraps round to more than 9
+    return byte_at(i) - 48u;
+}
+
+// Waits out spurious failures, so a slot only looks taken if it is
+fn claim(at: u32, value: u32) -> u32 {
+    loop {
+        let result = atomicCompareExchangeWeak(&table[at], 0u, value);
+        if (result.exchanged) {
+            return 0u;
+        }
+        if (result.old_value != 0u) {
+            return result.old_value;
+        }
+    }
+    return 0u;
+}
+
+// The first word of the slot for the name hashed to h1 and h2, which is
+// claimed for it if it's new, or -1 if the table is full
+fn find_slot(h1: u32, h2: u32, line: u32) -> i32 {
+    for (var probe = 0u; probe <= params.mask; probe++) {
+        let base = ((h1 + probe) & params.mask) * SLOT;
+        let key = claim(base, h1);
+        if (key != 0u && key != h1) {
+            continue;
+        }
+        let check = claim(base + 1u, h2);
+        if (check == 0u) {
+            atomicStore(&table[base + 2u], line);
+            return i32(base);
+        }
+        if (check == h2) {
+            return i32(base);
+        }
+    }
+    return -1;
+}
+
+fn add_wide(at: u32, value: u32) {
+    let old = atomicAdd(&table[at], value);
+    if (old + value < old) {
+        atomicAdd(&table[at + 1u], 1u);
+    }
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let start = id.x * params.segment;
+    if (start >= params.len) {
+        return;
+    }
+    let end = min(start + params.segment, params.len);
+
+    // Skip the rest of a line that started in the segment before
+    var p = start;
+    while (p > 0u && p < end && byte_at(p - 1u) != 10u) {
+        p++;
+    }
+
+    while (p < end) {
+        // The line's \n (or the end of the chunk) and its last delimiter
+        var q = p;
+        var split = 0xffffffffu;
+        while (q < params.len) {
+            let c = byte_at(q);
+            if (c == 10u) {
+                break;
+            }
+            if (c == params.delimiter) {
+                split = q;
+            }
+            q++;
+        }
+
+        var line_end = q;
+        if (line_end > p && byte_at(line_end - 1u) == 13u) {
+            line_end--;
+        }
+        if (split == 0xffffffffu || split >= line_end) {
+            atomicOr(&flags, UNPARSED);
+            return;
+        }
+
+        // Only the challenge's shapes: d.d, dd.d, -d.d and -dd.d
+        let negative = byte_at(split + 1u) == 45u;
+        let digits = split + 1u + u32(negative);
+        let len = line_end - digits;
+        var tens = 0u;
+        if (len == 4u) {
+            tens = digit_at(digits);
+        }
+        let units = digit_at(line_end - 3u);
+        let tenths = digit_at(line_end - 1u);
+        if ((len != 3u && len != 4u) || tens > 9u || units > 9u || tenths > 9u || byte_at(line_end - 2u) != 46u) {
+            atomicOr(&flags, UNPARSED);
+            return;
+        }
+        let magnitude = i32(tens * 100u + units * 10u + tenths);
+        let temp = select(magnitude, -magnitude, negative);
+
+        // FNV-1a, and a multiplicative hash finished with murmur3's mix.
+        // Neither can be 0, which marks an empty slot
+        var h1 = 2166136261u;
+        var h2 = 0u;
+        for (var i = p; i < split; i++) {
+            let c = byte_at(i);
+            h1 = (h1 ^ c) * 16777619u;
+            h2 = (h2 + c) * 2654435761u;
+        }
+        h2 ^= h2 >> 16u;
+        h2 *= 0x85ebca6bu;
+        h2 ^= h2 >> 13u;
+        h2 *= 0xc2b2ae35u;
+        h2 ^= h2 >> 16u;
+        h1 = max(h1, 1u);
+        h2 = max(h2, 1u);
+
+        let slot = find_slot(h1, h2, p);
+        if (slot < 0) {
+            atomicOr(&flags, FULL);
+            return;
+        }
+        let base = u32(slot);
+        atomicMax(&table[base + 3u], u32(1000 - temp));
+        atomicMax(&table[base + 4u], u32(temp + 1000));
+        atomicAdd(&table[base + 5u], 1u);
+        add_wide(base + 6u, u32(temp + 1000));
+        add_wide(base + 8u, u32(temp * temp));
+
+        p = q + 1u;
+    }
+}
@@ -0,0 +1,129 @@
+// `--group-by`: rolling stations up into coarser groups, such as every
+// station whose name starts with the same word, by combining their
+// records.
+
+use one_billion_row_challenge::{Error, Record, StationKey};
+use regex::Regex;
+
+use crate::metadata::Metadata;
+
+/// How stations are put together
+#[derive(Clone, Debug)]
+pub enum GroupBy {
+    /// By the first this many characters of their names
+    Prefix(usize),
+    /// By the first word of their names
+    Word,
+    /// By the first capture group of a pattern (or the whole match, if
+    /// it has none). Names it doesn't match aren't in any group
+    Regex(Regex),
+    /// By their value in a column of the `--metadata` file. Stations
+    /// without one aren't in any group
+    Column(String),
+}
+
+/// Parses `prefix:N`, `word`, `regex:PATTERN` or the name of a
+/// metadata column
+pub fn parse(by: &str) -> Result<GroupBy, String> {
+    if by == "word" {
+        return Ok(GroupBy::Word);
+    }
+    if let Some(n) = by.strip_prefix("prefix:") {
+        return n.parse().ok().filter(|&n| n > 0).map(GroupBy::Prefix).ok_or_else(|| format!("invalid prefix length {:?}", n));
+    }
+    if let Some(pattern) = by.strip_prefix("regex:") {
+        return Regex::new(pattern).map(GroupBy::Regex).map_err(|e| e.to_string());
+    }
+    // The columns aren't known until the metadata's read
+    match by {
+        "" => Err("invalid grouping \"\" (expected prefix:N, word, regex:PATTERN or a --metadata column)".to_string()),
+        column => Ok(GroupBy::Column(column.to_string())),
+    }
+}
+
+impl GroupBy {
+    /// The group a station is in, if any. column is the metadata's
+    /// column for GroupBy::Column
+    fn group(&self, name: &str, metadata: Option<(&Metadata, usize)>) -> Option<String> {
+        match self {
+            GroupBy::Prefix(n) => Some(name.chars().take(*n).collect()),
+            GroupBy::Word => name.split_whitespace().next().map(str::to_string),
+            GroupBy::Regex(regex) => regex.captures(name).and_then(|captures| captures.get(1).or(captures.get(0))).map(|group| group.as_str().to_string()),
+            GroupBy::Column(_) => metadata.and_then(|(metadata, column)| metadata.get(name.as_bytes(), column)).map(str::to_string),
+        }
+    }
+}
+
+/// Every group's combined record, in order of name. Alongside the
+/// stations, groups are named with a `*` after them so the two can't
+/// be mixed up
+pub fn rollup(data: Vec<(StationKey, Record)>, by: &GroupBy, with_stations: bool, metadata: Option<&Metadata>) -> Result<Vec<(StationKey, Record)>, Error> {
+    let column = match (by, metadata) {
+        (GroupBy::Column(name), None) => return Err(format!("--group-by {} needs --metadata, with a {} column", name, name).into()),
+        (GroupBy::Column(name), Some(metadata)) => {
+            let column = metadata.column(name).ok_or_else(|| format!("--group-by {}: the metadata's columns are {}", name, metadata.columns.join(", ")))?;
+            Some((metadata, column))
+        }
+        _ => None,
+    };
+
+    let mut groups = std::collections::BTreeMap::<String, Record>::new();
+    for (key, record) in &data {
+        let Some(group) = by.group(&String::from_utf8_lossy(key), column) else {
+            continue;
+        };
+        match groups.get_mut(&group) {
+            Some(all) => all.combine(record),
+            None => {
+                groups.insert(group, record.clone());
+            }
+        }
+    }
+
+    let groups = groups.into_iter().map(|(group, record)| {
+        let name = if with_stations { group + "*" } else { group };
+        (StationKey::from(name.as_bytes()), record)
+    });
+    if !with_stations {
+        return Ok(groups.collect());
+    }
+    let mut data = data.into_iter().chain(groups).collect::<Vec<_>>();
+    data.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stations(names: &[(&str, i32)]) -> Vec<(StationKey, Record)> {
+        names.iter().map(|(name, temp)| (StationKey::from(name.as_bytes()), Record::new(*temp))).collect()
+    }
+
+    fn names(data: &[(StationKey, Record)]) -> Vec<(String, i32, i32)> {
+        data.iter().map(|(key, record)| (String::from_utf8_lossy(key).into_owned(), record.min, record.max)).collect()
+    }
+
+    #[test]
+    fn groups() {
+        let data = stations(&[("New Delhi", 300), ("New York", 100), ("Newcastle", 50), ("Oslo", -20)]);
+        assert_eq!(names(&rollup(data.clone(), &GroupBy::Word, false, None).unwrap()), [
+            ("New".to_string(), 100, 300), ("Newcastle".to_string(), 50, 50), ("Oslo".to_string(), -20, -20),
+        ]);
+        assert_eq!(names(&rollup(data.clone(), &GroupBy::Prefix(3), false, None).unwrap()), [("New".to_string(), 50, 300), ("Osl".to_string(), -20, -20)]);
+
+        let regex = parse("regex:^(New) ").unwrap();
+        assert_eq!(names(&rollup(data.clone(), &regex, true, None).unwrap()).iter().map(|(name, ..)| name.as_str()).collect::<Vec<_>>(), [
+            "New Delhi", "New York", "New*", "Newcastle", "Oslo",
+        ]);
+    }
+
+    #[test]
+    fn parses() {
+        assert!(matches!(parse("prefix:2"), Ok(GroupBy::Prefix(2))));
+        assert!(parse("prefix:0").is_err());
+        assert!(parse("regex:(").is_err());
+        assert!(matches!(parse("country"), Ok(GroupBy::Column(column)) if column == "country"));
+        assert!(rollup(Vec::new(), &GroupBy::Column("country".to_string()), false, None).is_err());
+    }
+}
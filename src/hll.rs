@@ -0,0 +1,143 @@
+// Counting the distinct station names in a file without keeping them:
+// a HyperLogLog sketch, which is 16 KiB however many there are, to
+// within about 1%. Each chunk is sketched on its own and the sketches
+// merged, which loses nothing.
+
+use std::hash::Hasher;
+use std::path::Path;
+
+use crate::par::*;
+
+use crate::{chunk, compression, in_pool, parser, regular_file, with_file, Error, Options};
+
+// 2^PRECISION registers, for a standard error of 1.04 / sqrt(2^14), or
+// 0.81%
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// A HyperLogLog sketch of a set of station names
+#[derive(Clone, Debug)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog { registers: vec![0; REGISTERS] }
+    }
+}
+
+impl HyperLogLog {
+    /// The standard error of [`estimate`](Self::estimate), relative to
+    /// the true count
+    pub const ERROR: f64 = 1.04 / (1 << (PRECISION / 2)) as f64;
+
+    pub fn insert(&mut self, name: &[u8]) {
+        let mut hasher = std::hash::DefaultHasher::new();
+        hasher.write(name);
+        let hash = hasher.finish();
+
+        // The first bits pick the register, which keeps the longest run
+        // of zeros (plus one) the rest start with
+        let register = (hash >> (64 - PRECISION)) as usize;
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    /// Adds every name in other
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, &rank) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(rank);
+        }
+    }
+
+    /// About how many distinct names were inserted
+    pub fn estimate(&self) -> f64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1. + 1.079 / m);
+        let sum = self.registers.iter().map(|&rank| 2f64.powi(-(rank as i32))).sum::<f64>();
+        let raw = alpha * m * m / sum;
+
+        // Few names leave many registers empty, which counts them better
+        let empty = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if raw <= 2.5 * m && empty > 0 {
+            m * (m / empty as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+/// Sketches the station names in bytes, in parallel chunks. Lines with
+/// no delimiter are passed over
+pub fn sketch_bytes(bytes: &[u8], options: &Options) -> Result<HyperLogLog, Error> {
+    in_pool(options, || {
+        Ok(chunk::chunks_in(bytes, options)
+            .into_par_iter()
+            .map(|(start, end)| sketch_chunk(&bytes[start..end], options.delimiter))
+            .reduce(HyperLogLog::default, |mut a, b| {
+                a.merge(&b);
+                a
+            }))
+    })
+}
+
+/// [`sketch_bytes`] for an uncompressed file
+pub fn sketch_file(path: &Path, options: &Options) -> Result<HyperLogLog, Error> {
+    with_file(path, |file| {
+        regular_file(path, file)?;
+        if compression::detect_file(file)? != compression::Compression::None {
+            return Err(format!("{}: compressed files can't be sketched", path.display()).into());
+        }
+        // Mapping an empty file fails on some platforms
+        if file.metadata()?.len() == 0 {
+            return Ok(HyperLogLog::default());
+        }
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        sketch_bytes(&mmap, options)
+    })
+}
+
+fn sketch_chunk(chunk: &[u8], delimiter: u8) -> HyperLogLog {
+    let mut sketch = HyperLogLog::default();
+    let mut lines = parser::Lines::new(chunk, delimiter);
+    for line in lines.by_ref().flatten() {
+        sketch.insert(line.name);
+    }
+    if let Ok((name, _)) = parser::split_line(&chunk[lines.consumed()..], delimiter) {
+        sketch.insert(name);
+    }
+    sketch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates() {
+        for count in [0, 1, 100, 10_000, 200_000] {
+            let mut sketch = HyperLogLog::default();
+            // Each name twice, which doesn't count
+            for _ in 0..2 {
+                for i in 0..count {
+                    sketch.insert(format!("Station {}", i).as_bytes());
+                }
+            }
+            let error = (sketch.estimate() - count as f64).abs() / (count as f64).max(1.);
+            assert!(error < 4. * HyperLogLog::ERROR, "{} estimated as {}", count, sketch.estimate());
+        }
+    }
+
+    #[test]
+    fn merges() {
+        let (mut a, mut b, mut both) = (HyperLogLog::default(), HyperLogLog::default(), HyperLogLog::default());
+        for i in 0..50_000 {
+            let name = format!("Station {}", i);
+            if i % 2 == 0 { a.insert(name.as_bytes()) } else { b.insert(name.as_bytes()) }
+            both.insert(name.as_bytes());
+        }
+        a.merge(&b);
+        assert_eq!(a.estimate(), both.estimate());
+    }
+}
@@ -0,0 +1,283 @@
+// The `index` and `query-indexed` subcommands, for a file that's asked
+// about again and again. `index` aggregates it once, a block at a time,
+// and keeps every block's results in FILE.1brcidx beside it along with
+// the blocks each station is in. `query-indexed` answers from those:
+// with --stations it merges only the blocks they're in, and otherwise
+// (e.g. for --top) every block, which is still far less than the file.
+// Lines appended to the file since it was indexed are parsed as usual.
+//
+// The index is "1BRCIDX1", the header's length (a little-endian u64)
+// and the header as JSON, then the blocks' results as partials (see
+// partial.rs), where the header says. A file whose indexed bytes no
+// longer start and end as they did has been rewritten, so its index is
+// refused rather than trusted.
+
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use indicatif::HumanBytes;
+use one_billion_row_challenge::{aggregate_range, chunk, merge_maps, partial, Error, Options, StationFilter, StationMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{format_results, parse_delimiter, parse_size, sort_stations, write_output, OutputArgs, Stat};
+
+const MAGIC: &[u8; 8] = b"1BRCIDX1";
+
+// How much of the start and of the end of the indexed bytes is hashed
+const SAMPLE: usize = 64 * 1024;
+
+#[derive(Args)]
+pub struct IndexArgs {
+    /// The measurements file, uncompressed
+    path: PathBuf,
+
+    /// About how much of the file each block covers. Smaller blocks
+    /// make a bigger index, but let queries for stations that are only
+    /// in parts of the file skip more of it
+    #[arg(long, value_name = "BYTES", default_value = "64M", value_parser = parse_size)]
+    block_size: usize,
+
+    /// Character between the station name and the temperature
+    #[arg(long, default_value = ";", value_parser = parse_delimiter)]
+    delimiter: u8,
+
+    /// Number of decimals the temperatures are given to
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=3))]
+    precision: u8,
+
+    /// Keep every station's histogram in the index as well, which
+    /// `query-indexed --histogram` and percentiles need
+    #[arg(long)]
+    histograms: bool,
+}
+
+#[derive(Args)]
+pub struct QueryIndexedArgs {
+    /// The measurements file, indexed with `index`
+    path: PathBuf,
+
+    /// Only these stations: exact names, globs or /regexes/, as for
+    /// `run --stations`
+    #[arg(long, value_delimiter = ',')]
+    stations: Vec<String>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Write the results to this file instead of stdout
+    #[arg(long = "output", short = 'o', env = "BRC_OUTPUT")]
+    output_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    // How much of the file is indexed, which ends with a whole line
+    size: u64,
+    // Hashes of up to SAMPLE bytes from its start and its end
+    head: u64,
+    tail: u64,
+    delimiter: u8,
+    precision: u8,
+    histograms: bool,
+    blocks: Vec<Block>,
+    // The blocks each station is in, by name (converted lossily)
+    stations: BTreeMap<String, Vec<usize>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Block {
+    // The bytes of the file it covers
+    start: u64,
+    end: u64,
+    // Where its results are, after the header
+    at: u64,
+    len: u64,
+}
+
+pub fn index(args: IndexArgs) -> Result<(), Error> {
+    let options = Options { delimiter: args.delimiter, precision: args.precision, histograms: args.histograms, ..Default::default() };
+    let (index, header) = build(&args.path, args.block_size.max(1), &options)?;
+    let index_path = index_path(&args.path);
+    crate::write_atomically(&index_path, &index).map_err(|source| Error::Io { path: Some(index_path.clone()), source })?;
+    log::info!(
+        "Indexed {} of {} in {} blocks, {} stations, into {} ({})",
+        HumanBytes(header.size), args.path.display(), header.blocks.len(), header.stations.len(), index_path.display(), HumanBytes(index.len() as u64),
+    );
+    Ok(())
+}
+
+pub fn query_indexed(mut args: QueryIndexedArgs) -> Result<(), Error> {
+    args.output.color = args.output.color.resolve(args.output_path.is_none());
+    if args.output.format.is_binary() && args.output_path.is_none() && std::io::stdout().is_terminal() {
+        return Err("refusing to write binary results to a terminal, use -o or redirect stdout".into());
+    }
+
+    let histograms = args.output.histogram.is_some() || args.output.stats.iter().any(Stat::needs_histogram);
+    let data = lookup(&args.path, &args.stations, histograms)?;
+    write_output(&format_results(sort_stations(data), &args.output)?, args.output_path.as_deref(), false)?;
+    Ok(())
+}
+
+// FILE.1brcidx, for FILE
+fn index_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1brcidx");
+    PathBuf::from(name)
+}
+
+// FNV-1a
+fn fingerprint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// The index of path's whole lines, and its header
+fn build(path: &Path, block_size: usize, options: &Options) -> Result<(Vec<u8>, Header), Error> {
+    let bytes = std::fs::read(path).map_err(|source| Error::Io { path: Some(path.to_path_buf()), source })?;
+    // Lines appended later then start a line of their own
+    let size = memchr::memrchr(b'\n', &bytes).map_or(0, |last| last + 1);
+    let indexed = &bytes[..size];
+
+    let mut blocks = Vec::new();
+    let mut stations = BTreeMap::<String, Vec<usize>>::new();
+    let mut summaries = Vec::new();
+    for (i, (start, end)) in chunk::split_in(indexed, size.div_ceil(block_size)).into_iter().enumerate() {
+        let data = aggregate_range(path, start as u64, end as u64, options)?;
+        for name in data.keys() {
+            stations.entry(String::from_utf8_lossy(name).into_owned()).or_default().push(i);
+        }
+        let summary = partial::encode(&data);
+        blocks.push(Block { start: start as u64, end: end as u64, at: summaries.len() as u64, len: summary.len() as u64 });
+        summaries.extend(summary);
+    }
+
+    let header = Header {
+        size: size as u64,
+        head: fingerprint(&indexed[..size.min(SAMPLE)]),
+        tail: fingerprint(&indexed[size.saturating_sub(SAMPLE)..]),
+        delimiter: options.delimiter,
+        precision: options.precision,
+        histograms: options.histograms,
+        blocks,
+        stations,
+    };
+    let json = serde_json::to_vec(&header).expect("the header serializes");
+    let mut index = Vec::with_capacity(MAGIC.len() + 8 + json.len() + summaries.len());
+    index.extend(MAGIC);
+    index.extend((json.len() as u64).to_le_bytes());
+    index.extend(json);
+    index.extend(summaries);
+    Ok((index, header))
+}
+
+// The results for the stations (all of them if there are none) from
+// path's index, and from whatever's been appended to path since
+fn lookup(path: &Path, stations: &[String], histograms: bool) -> Result<StationMap, Error> {
+    let index_path = index_path(path);
+    let index = std::fs::read(&index_path).map_err(|source| match source.kind() {
+        std::io::ErrorKind::NotFound => Error::Other(format!("{} has no index, make one with `1brc index {}`", path.display(), path.display())),
+        _ => Error::Io { path: Some(index_path.clone()), source },
+    })?;
+    let corrupt = || Error::Other(format!("{}: not an index, or a damaged one", index_path.display()));
+    let (header, summaries) = parse(&index).ok_or_else(corrupt)?;
+    if histograms && !header.histograms {
+        return Err(format!("{} was indexed without histograms, which --histogram and percentiles need (index it with --histograms)", path.display()).into());
+    }
+
+    let bytes = std::fs::read(path).map_err(|source| Error::Io { path: Some(path.to_path_buf()), source })?;
+    let size = header.size as usize;
+    let unchanged = bytes.get(..size).is_some_and(|indexed| {
+        fingerprint(&indexed[..size.min(SAMPLE)]) == header.head && fingerprint(&indexed[size.saturating_sub(SAMPLE)..]) == header.tail
+    });
+    if !unchanged {
+        return Err(format!("{} has changed since it was indexed, run `1brc index` on it again", path.display()).into());
+    }
+
+    let filter = match stations.is_empty() {
+        true => None,
+        false => Some(StationFilter::new(stations)?),
+    };
+    let mut blocks = match &filter {
+        None => (0..header.blocks.len()).collect(),
+        Some(filter) => header.stations.iter()
+            .filter(|(name, _)| filter.matches(name.as_bytes()))
+            .flat_map(|(_, blocks)| blocks.iter().copied())
+            .collect::<Vec<_>>(),
+    };
+    blocks.sort_unstable();
+    blocks.dedup();
+
+    let mut data = StationMap::default();
+    for &i in &blocks {
+        let block = header.blocks.get(i).ok_or_else(corrupt)?;
+        let summary = summaries.get(block.at as usize..(block.at + block.len) as usize).and_then(partial::decode).ok_or_else(corrupt)?;
+        data = merge_maps(data, summary);
+    }
+    if let Some(filter) = &filter {
+        data.retain(|name, _| filter.matches(name));
+    }
+
+    let appended = bytes.len() - size;
+    drop(bytes);
+    if appended > 0 {
+        let options = Options {
+            delimiter: header.delimiter,
+            precision: header.precision,
+            histograms: header.histograms,
+            stations: filter,
+            ..Default::default()
+        };
+        data = merge_maps(data, aggregate_range(path, header.size, header.size + appended as u64, &options)?);
+    }
+    log::info!("Read {} of {} blocks from the index, and {} appended since", blocks.len(), header.blocks.len(), HumanBytes(appended as u64));
+    Ok(data)
+}
+
+// The header and the blocks' results after it
+fn parse(index: &[u8]) -> Option<(Header, &[u8])> {
+    let rest = index.strip_prefix(MAGIC)?;
+    let (len, rest) = rest.split_at_checked(8)?;
+    let (json, summaries) = rest.split_at_checked(u64::from_le_bytes(len.try_into().ok()?) as usize)?;
+    Some((serde_json::from_slice(json).ok()?, summaries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_blocks_and_appended_lines() {
+        let dir = std::env::temp_dir().join(format!("brc-index-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("measurements.txt");
+        // Alpha only in the first half, so a query for it skips the rest
+        let mut rows = (0..2000).map(|i| format!("Alpha;{}.{}\n", i % 40 - 20, i % 10)).collect::<String>();
+        rows.extend((0..2000).map(|i| format!("Beta;{}.{}\n", i % 30, i % 10)));
+        std::fs::write(&path, &rows).unwrap();
+
+        let options = Options::default();
+        let (index, header) = build(&path, 4096, &options).unwrap();
+        std::fs::write(index_path(&path), index).unwrap();
+        assert!(header.stations["Alpha"].len() < header.blocks.len() / 2 + 2);
+        let whole = one_billion_row_challenge::aggregate_stations(&path, &options).unwrap();
+        assert_eq!(lookup(&path, &[], false).unwrap(), whole);
+        let alpha = lookup(&path, &["Alpha".into()], false).unwrap();
+        assert_eq!(alpha.len(), 1);
+        assert_eq!(alpha[&b"Alpha"[..]], whole[&b"Alpha"[..]]);
+        assert!(lookup(&path, &[], true).is_err());
+
+        // Lines appended since are parsed, even without a newline
+        rows.push_str("Alpha;99.0\nGamma;1.5");
+        std::fs::write(&path, &rows).unwrap();
+        let whole = one_billion_row_challenge::aggregate_stations(&path, &options).unwrap();
+        assert_eq!(lookup(&path, &[], false).unwrap(), whole);
+        assert_eq!(lookup(&path, &["Alpha".into()], false).unwrap()[&b"Alpha"[..]].max, 990);
+
+        // But not a file that's been rewritten
+        std::fs::write(&path, rows.replacen("Alpha", "Omega", 1)).unwrap();
+        assert!(lookup(&path, &[], false).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+// Station names as map keys, kept inline when they're short.
+
+use std::borrow::Borrow;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+use serde::de::{SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// Names up to this long fit in the key itself, which is then 32 bytes
+const INLINE: usize = 30;
+
+/// A station name that's stored in place when it's up to 30 bytes, as
+/// nearly every real one is, and on the heap otherwise.
+///
+/// Comparing a short name against one being looked up then never
+/// follows a pointer, and adding a new station doesn't allocate. It
+/// hashes and compares like the `[u8]` it derefs to, so the map can be
+/// looked up with plain byte slices.
+#[derive(Clone)]
+pub enum InlineKey {
+    #[doc(hidden)]
+    Inline { len: u8, bytes: [u8; INLINE] },
+    #[doc(hidden)]
+    Heap(Box<[u8]>),
+}
+
+impl From<&[u8]> for InlineKey {
+    #[inline]
+    fn from(name: &[u8]) -> Self {
+        if name.len() <= INLINE {
+            let mut bytes = [0; INLINE];
+            bytes[..name.len()].copy_from_slice(name);
+            InlineKey::Inline { len: name.len() as u8, bytes }
+        } else {
+            InlineKey::Heap(name.into())
+        }
+    }
+}
+
+impl From<Vec<u8>> for InlineKey {
+    fn from(name: Vec<u8>) -> Self {
+        if name.len() <= INLINE {
+            name.as_slice().into()
+        } else {
+            InlineKey::Heap(name.into_boxed_slice())
+        }
+    }
+}
+
+impl Deref for InlineKey {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        match self {
+            InlineKey::Inline { len, bytes } => &bytes[..*len as usize],
+            InlineKey::Heap(bytes) => bytes,
+        }
+    }
+}
+
+impl InlineKey {
+    // The bytes kept outside the key itself
+    pub(crate) fn heap_len(&self) -> usize {
+        match self {
+            InlineKey::Inline { .. } => 0,
+            InlineKey::Heap(bytes) => bytes.len(),
+        }
+    }
+}
+
+impl Borrow<[u8]> for InlineKey {
+    #[inline]
+    fn borrow(&self) -> &[u8] {
+        self
+    }
+}
+
+// A borrowed name as the map looks it up, which hashbrown turns into an
+// InlineKey (through ToOwned) when the station is new
+#[derive(PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub(crate) struct NameRef([u8]);
+
+impl NameRef {
+    #[inline]
+    pub fn new(name: &[u8]) -> &NameRef {
+        // Safe: NameRef is a transparent wrapper around [u8]
+        unsafe { &*(name as *const [u8] as *const NameRef) }
+    }
+}
+
+impl ToOwned for NameRef {
+    type Owned = InlineKey;
+
+    #[inline]
+    fn to_owned(&self) -> InlineKey {
+        self.0.into()
+    }
+}
+
+impl Borrow<NameRef> for InlineKey {
+    #[inline]
+    fn borrow(&self) -> &NameRef {
+        NameRef::new(self)
+    }
+}
+
+impl Hash for InlineKey {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl PartialEq for InlineKey {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for InlineKey {}
+
+impl PartialOrd for InlineKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InlineKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl std::fmt::Debug for InlineKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// Serialized as a string when the name is valid UTF-8, as nearly every
+// one is, so a StationMap can be a JSON object, and as bytes otherwise
+impl Serialize for InlineKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(self) {
+            Ok(name) => serializer.serialize_str(name),
+            Err(_) => serializer.serialize_bytes(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for InlineKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NameVisitor;
+
+        impl<'de> Visitor<'de> for NameVisitor {
+            type Value = InlineKey;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a station name")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, name: &str) -> Result<InlineKey, E> {
+                Ok(name.as_bytes().into())
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, name: &[u8]) -> Result<InlineKey, E> {
+                Ok(name.into())
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<InlineKey, A::Error> {
+                let mut name = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    name.push(byte);
+                }
+                Ok(name.into())
+            }
+        }
+
+        deserializer.deserialize_bytes(NameVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_and_long() {
+        assert_eq!(std::mem::size_of::<InlineKey>(), 32);
+
+        for name in [&b""[..], b"Abha", &[b'x'; INLINE], &[b'y'; INLINE + 1]] {
+            let key = InlineKey::from(name);
+            assert_eq!(&*key, name);
+            assert_eq!(InlineKey::from(name.to_vec()), key);
+            assert_eq!(matches!(key, InlineKey::Inline { .. }), name.len() <= INLINE);
+        }
+    }
+
+    #[test]
+    fn looked_up_by_slice() {
+        let mut map = hashbrown::HashMap::<InlineKey, u32, crate::StationHasher>::default();
+        *map.entry_ref(NameRef::new(b"Abha")).or_insert(0) += 1;
+        *map.entry_ref(NameRef::new(b"Abha")).or_insert(0) += 1;
+        assert_eq!(map[&b"Abha"[..]], 2);
+    }
+}
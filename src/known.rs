@@ -0,0 +1,186 @@
+// A perfect hash over a station list that's known before the run.
+//
+// Built with hash and displace: the names are hashed once, split into
+// small buckets by the hash's high bits, and each bucket in turn (the
+// biggest first) is given the displacement that lands all its names in
+// free slots. Looking a name up is then one hash, one displacement and
+// one slot, with a single comparison against the name in it, and never
+// any probing.
+
+/// A set of station names with a perfect hash, so the parser can keep
+/// their records in a flat array rather than a hash map.
+///
+/// Names outside the set still work, through the map as usual. Pass
+/// one in [`Options::known_stations`](crate::Options::known_stations).
+#[derive(Debug)]
+pub struct KnownStations {
+    names: Vec<Vec<u8>>,
+    seed: u64,
+    displacements: Vec<u64>,
+    // Index into names, or EMPTY
+    slots: Vec<u32>,
+    slot_bits: u32,
+}
+
+const EMPTY: u32 = u32::MAX;
+
+// Tries per bucket before giving up on the seed and table size
+const MAX_TRIES: u64 = 1 << 16;
+
+impl KnownStations {
+    /// Builds the hash for `names`. Duplicates are ignored
+    pub fn new<I: IntoIterator<Item = N>, N: AsRef<[u8]>>(names: I) -> Self {
+        let mut names: Vec<Vec<u8>> = names.into_iter().map(|name| name.as_ref().to_vec()).collect();
+        names.sort_unstable();
+        names.dedup();
+
+        // Half full, which leaves every bucket plenty of room
+        let mut slot_bits = (names.len() * 2).next_power_of_two().max(2).trailing_zeros();
+        let mut seed = 0;
+
+        loop {
+            if let Some(known) = Self::build(&names, seed, slot_bits) {
+                return known;
+            }
+            seed += 1;
+            slot_bits += 1;
+        }
+    }
+
+    // The hash for names with this seed and table size, if every bucket
+    // found a displacement
+    fn build(names: &[Vec<u8>], seed: u64, slot_bits: u32) -> Option<Self> {
+        let hashes: Vec<u64> = names.iter().map(|name| hash(name, seed)).collect();
+        let bucket_count = (names.len() / 4).next_power_of_two();
+
+        let mut buckets = vec![Vec::new(); bucket_count];
+        for (i, &hash) in hashes.iter().enumerate() {
+            buckets[bucket(hash, bucket_count)].push(i);
+        }
+        let mut order: Vec<usize> = (0..bucket_count).collect();
+        order.sort_unstable_by_key(|&bucket| std::cmp::Reverse(buckets[bucket].len()));
+
+        let mut slots = vec![EMPTY; 1 << slot_bits];
+        let mut displacements = vec![0; bucket_count];
+        let mut taken = Vec::new();
+
+        for bucket in order {
+            let fits = (0..MAX_TRIES).find(|&displacement| {
+                taken.clear();
+                buckets[bucket].iter().all(|&i| {
+                    let slot = slot(hashes[i], displacement, slot_bits);
+                    let free = slots[slot] == EMPTY && !taken.contains(&slot);
+                    taken.push(slot);
+                    free
+                })
+            })?;
+
+            displacements[bucket] = fits;
+            for &i in &buckets[bucket] {
+                slots[slot(hashes[i], fits, slot_bits)] = i as u32;
+            }
+        }
+
+        Some(KnownStations { names: names.to_vec(), seed, displacements, slots, slot_bits })
+    }
+
+    /// The names, in the order of their indices
+    pub fn names(&self) -> &[Vec<u8>] {
+        &self.names
+    }
+
+    /// The index of `name` in [`names`](KnownStations::names), if it's
+    /// one of them
+    #[inline]
+    pub fn index(&self, name: &[u8]) -> Option<usize> {
+        let hash = hash(name, self.seed);
+        let displacement = self.displacements[bucket(hash, self.displacements.len())];
+        let i = self.slots[slot(hash, displacement, self.slot_bits)];
+        (i != EMPTY && self.names[i as usize] == name).then_some(i as usize)
+    }
+}
+
+// Only has to tell the known names apart (with a seed that works for
+// them), so it's cheap, but it reads every byte: most names are read
+// as a first and last word that overlap
+#[inline]
+fn hash(name: &[u8], seed: u64) -> u64 {
+    let len = name.len();
+    let (first, last) = if len >= 8 {
+        let mut first = read_u64(&name[..8]);
+        for offset in (8..len - 8).step_by(8) {
+            first = (first ^ read_u64(&name[offset..offset + 8])).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        }
+        (first, read_u64(&name[len - 8..]))
+    } else if len >= 4 {
+        (read_u32(&name[..4]), read_u32(&name[len - 4..]))
+    } else {
+        let short = name.iter().fold(0, |word, &byte| word << 8 | byte as u64);
+        (short, 0)
+    };
+
+    let hash = (seed ^ len as u64 ^ first).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let hash = (hash ^ hash >> 29 ^ last).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^ hash >> 32
+}
+
+#[inline]
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+#[inline]
+fn read_u32(bytes: &[u8]) -> u64 {
+    u32::from_le_bytes(bytes.try_into().unwrap()) as u64
+}
+
+#[inline]
+fn bucket(hash: u64, bucket_count: usize) -> usize {
+    (hash >> 32) as usize & (bucket_count - 1)
+}
+
+#[inline]
+fn slot(hash: u64, displacement: u64, slot_bits: u32) -> usize {
+    ((hash ^ displacement.wrapping_mul(0xc2b2_ae3d_27d4_eb4f)).wrapping_mul(0x1656_67b1_9e37_79f9) >> (64 - slot_bits)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_name_has_its_own_index() {
+        let names: Vec<String> = (0..1000).map(|i| format!("Station {}", i)).collect();
+        let known = KnownStations::new(&names);
+        assert_eq!(known.names().len(), 1000);
+
+        let mut seen = vec![false; 1000];
+        for name in &names {
+            let index = known.index(name.as_bytes()).unwrap();
+            assert_eq!(known.names()[index], name.as_bytes());
+            assert!(!std::mem::replace(&mut seen[index], true));
+        }
+
+        for name in ["", "Station", "Station 1000", "station 1"] {
+            assert_eq!(known.index(name.as_bytes()), None);
+        }
+    }
+
+    #[test]
+    fn long_names_differing_in_the_middle() {
+        let names: Vec<String> = (0..100).map(|i| format!("Long name {:03} of a station", i)).collect();
+        let known = KnownStations::new(&names);
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(known.names()[known.index(name.as_bytes()).unwrap()], names[i].as_bytes());
+        }
+    }
+
+    #[test]
+    fn tiny_sets() {
+        assert_eq!(KnownStations::new(Vec::<&str>::new()).index(b"Abha"), None);
+
+        let known = KnownStations::new(["Abha", "Abha"]);
+        assert_eq!(known.names().len(), 1);
+        assert_eq!(known.index(b"Abha"), Some(0));
+    }
+}
@@ -0,0 +1,1710 @@
+//! A fast aggregator for the One Billion Row Challenge.
+//!
+//! The input is a text file of `name;temperature` lines, with every
+//! temperature given to one decimal place:
+//!
+//! ```text
+//! Hamburg;12.0
+//! Bulawayo;8.9
+//! Palembang;38.8
+//! ```
+//!
+//! [`aggregate`] splits the file into line-aligned chunks (see
+//! [`chunk`]), parses the chunks in parallel on the rayon thread pool,
+//! and merges the per-chunk results into one [`Record`] per station.
+//!
+//! ```no_run
+//! # #[cfg(feature = "native")] {
+//! use one_billion_row_challenge::{aggregate, Options};
+//!
+//! let stations = aggregate("measurements.txt".as_ref(), &Options::default()).unwrap();
+//!
+//! for (name, record) in &stations {
+//!     println!("{};{};{};{}", name, record.min(), record.mean(), record.max());
+//! }
+//! # }
+//! ```
+//!
+//! Reading files needs the default `native` feature, and parsing in
+//! parallel the default `parallel` one too. Without `parallel` the same
+//! engines run on the calling thread, one chunk after another. Without
+//! `native` (e.g. for wasm32) the crate still parses bytes already in
+//! memory, with [`aggregate_bytes`].
+
+#[cfg(all(feature = "parallel", target_os = "linux"))]
+mod affinity;
+mod aggregator;
+#[cfg(feature = "tokio")]
+mod async_io;
+#[cfg(feature = "native")]
+mod buckets;
+#[cfg(feature = "native")]
+mod cache;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "native")]
+mod checkpoint;
+#[cfg(feature = "native")]
+pub mod chunk;
+#[cfg(feature = "native")]
+mod compression;
+#[cfg(all(feature = "native", any(target_os = "linux", windows)))]
+mod direct;
+#[cfg(feature = "native")]
+mod engine;
+mod error;
+pub mod filter;
+#[cfg(feature = "native")]
+mod follow;
+#[cfg(feature = "gpu")]
+mod gpu;
+#[cfg(feature = "native")]
+mod hll;
+#[cfg(feature = "native")]
+mod interned;
+#[cfg(feature = "inline-keys")]
+mod key;
+mod known;
+mod live;
+#[cfg(feature = "native")]
+mod metrics;
+mod normalize;
+#[cfg(all(feature = "parallel", target_os = "linux"))]
+mod numa;
+#[cfg(feature = "object-store")]
+mod object;
+#[cfg(feature = "native")]
+mod par;
+pub mod partial;
+mod parser;
+#[cfg(feature = "native")]
+mod pipeline;
+mod progress;
+#[cfg(feature = "native")]
+mod progressive;
+mod record;
+mod results;
+#[cfg(feature = "native")]
+mod sample;
+mod scan;
+#[cfg(feature = "native")]
+pub mod source;
+#[cfg(feature = "native")]
+mod storage;
+#[cfg(feature = "native")]
+mod table;
+mod temperature;
+mod timings;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+#[cfg(feature = "native")]
+mod validate;
+mod workers;
+
+#[cfg(feature = "native")]
+use std::collections::BTreeMap;
+#[cfg(feature = "native")]
+use std::io::{Read, Seek};
+#[cfg(feature = "native")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "native")]
+use par::*;
+
+use error::Invalid;
+use parser::BadLine;
+#[cfg(feature = "native")]
+pub use aggregator::{aggregate_by, aggregate_bytes_by};
+pub use aggregator::{merge_by, Aggregator, AggregatorMap};
+#[cfg(feature = "tokio")]
+pub use async_io::aggregate_async;
+#[cfg(feature = "native")]
+pub use buckets::{aggregate_buckets, aggregate_buckets_bytes, merge_buckets, BucketMap, TimeBucket};
+pub use error::Error;
+pub use filter::StationFilter;
+#[cfg(feature = "native")]
+pub use follow::Follower;
+#[cfg(feature = "native")]
+pub use hll::{sketch_bytes, sketch_file, HyperLogLog};
+#[cfg(feature = "inline-keys")]
+pub use key::InlineKey;
+pub use known::KnownStations;
+pub use live::Live;
+pub use normalize::{normalize_name, NormalizeKey};
+#[cfg(feature = "object-store")]
+pub use object::{aggregate_object, object_size};
+#[cfg(feature = "native")]
+pub use metrics::{aggregate_metrics, aggregate_metrics_bytes, merge_metrics, MetricMap};
+#[cfg(feature = "native")]
+pub use progressive::aggregate_progressive;
+pub use progress::OnProgress;
+pub use record::{Fixed, Histogram, Record, Tenths, Total};
+pub use results::{Results, Style, TopBy};
+#[cfg(feature = "native")]
+pub use sample::{aggregate_sample, Sample, Sampled};
+#[cfg(feature = "native")]
+pub use source::{aggregate_source, ChunkSource};
+pub use timings::{Phase, Timings};
+pub use workers::{WorkerStats, Workers};
+#[cfg(feature = "native")]
+pub use validate::{validate_bytes, validate_file, Validation};
+
+// The map every chunk aggregates into, and the hasher it uses.
+// Station names are short and trusted, so there's no need for
+// SipHash's DoS resistance
+#[cfg(feature = "fxhash")]
+type StationHasher = rustc_hash::FxBuildHasher;
+/// Name of the hasher [`StationMap`] uses
+#[cfg(feature = "fxhash")]
+pub const HASHER_NAME: &str = "FxHash";
+
+#[cfg(not(feature = "fxhash"))]
+type StationHasher = std::collections::hash_map::RandomState;
+/// Name of the hasher [`StationMap`] uses
+#[cfg(not(feature = "fxhash"))]
+pub const HASHER_NAME: &str = "SipHash";
+
+/// The raw bytes of a station name, as [`StationMap`] keys them: an
+/// [`InlineKey`] with the `inline-keys` feature, a `Vec<u8>` without
+#[cfg(feature = "inline-keys")]
+pub type StationKey = InlineKey;
+/// How [`StationKey`] stores names
+#[cfg(feature = "inline-keys")]
+pub const KEY_NAME: &str = "inline";
+
+/// The raw bytes of a station name, as [`StationMap`] keys them: an
+/// `InlineKey` with the `inline-keys` feature, a `Vec<u8>` without
+#[cfg(not(feature = "inline-keys"))]
+pub type StationKey = Vec<u8>;
+/// How [`StationKey`] stores names
+#[cfg(not(feature = "inline-keys"))]
+pub const KEY_NAME: &str = "Vec";
+
+// What the map is looked up by when a new station might be added
+#[cfg(feature = "inline-keys")]
+use key::NameRef;
+#[cfg(not(feature = "inline-keys"))]
+type NameRef = [u8];
+
+#[cfg(feature = "inline-keys")]
+#[inline]
+fn name_ref(name: &[u8]) -> &NameRef {
+    NameRef::new(name)
+}
+#[cfg(not(feature = "inline-keys"))]
+#[inline]
+fn name_ref(name: &[u8]) -> &NameRef {
+    name
+}
+
+// hashbrown rather than std's HashMap for entry_ref, which looks a
+// borrowed key up with a single hash and only allocates an owned key
+// when the station is new
+/// Per-station records keyed by the raw bytes of the station name
+pub type StationMap = hashbrown::HashMap<StationKey, Record, StationHasher>;
+
+/// A map keyed by station names the way [`StationMap`] is, for anything
+/// else there is to know about the stations
+pub type StationLookup<V> = hashbrown::HashMap<StationKey, V, StationHasher>;
+
+/// A chunk's `(start, end)` byte range and the stations in it, from
+/// [`aggregate_chunks`]
+#[cfg(feature = "native")]
+pub type ChunkResult = ((u64, u64), StationMap);
+
+// Size of the blocks aggregate_reader hands out to its workers
+#[cfg(feature = "native")]
+const STREAM_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+// The smallest block --max-memory leaves aggregate_reader, below which
+// it runs fewer workers instead
+#[cfg(feature = "native")]
+const MIN_STREAM_BLOCK_SIZE: usize = 64 * 1024;
+
+/// How the measurements file is read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Engine {
+    /// Pick for the platform: mmap on 64-bit targets, buffered on
+    /// 32-bit ones, where a big file won't fit in the address space
+    #[default]
+    Auto,
+    /// Read each chunk through its own buffered file handle
+    Buffered,
+    /// Memory-map the whole file and parse slices of it
+    Mmap,
+    /// Read each chunk through io_uring, overlapping the reads with
+    /// parsing. Helps most when the file isn't in the page cache
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    IoUring,
+    /// Experimental: scan and reduce each chunk in a wgpu compute
+    /// shader, and merge the chunks on the CPU
+    #[cfg(feature = "gpu")]
+    Gpu,
+    /// Read the file with tokio's async I/O, and parse it on blocking
+    /// tasks (see [`aggregate_async`])
+    #[cfg(feature = "tokio")]
+    #[value(help = "Read the file with tokio's async I/O, and parse it on blocking tasks")]
+    Async,
+}
+
+impl Engine {
+    /// The engine [`Engine::Auto`] stands for on this platform, or the
+    /// engine itself for any other
+    pub fn resolve(self) -> Engine {
+        match self {
+            Engine::Auto if cfg!(target_pointer_width = "64") => Engine::Mmap,
+            Engine::Auto => Engine::Buffered,
+            engine => engine,
+        }
+    }
+}
+
+/// Which CPUs [`Options::pin_cores`] pins the workers to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum PinCores {
+    /// Every CPU the process may run on, SMT siblings included
+    All,
+    /// One CPU per physical core, skipping SMT siblings
+    Physical,
+}
+
+/// How [`Options::merge`] combines the workers' maps
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Merge {
+    /// Pairwise, as the workers finish. The last few merges have only a
+    /// couple of maps left, so they run one at a time
+    #[default]
+    Tree,
+    /// Route every worker's records by a hash of the station name to
+    /// one shard per thread, whose owner merges just those. Every
+    /// thread merges at once, and the shards' stations don't overlap,
+    /// so putting them together is only a matter of moving them
+    Sharded,
+}
+
+/// What [`Options::on_error`] does with a line that can't be parsed: a
+/// bad temperature, no delimiter, or a name the options don't allow
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+pub enum OnError {
+    /// Stop, failing with an [`Error::Parse`] that says where it is
+    #[default]
+    #[value(help = "Stop at the line, with an error that says where it is")]
+    Abort,
+    /// Leave the line out
+    Skip,
+    /// Leave the line out, counting it in [`Options::bad_lines`]
+    #[value(help = "Leave the line out, and say how many were left out")]
+    Count,
+}
+
+/// Which fields of a line are the station and the temperature, for
+/// delimited files with other fields as well (a CSV export, say),
+/// counted from 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Columns {
+    pub key: usize,
+    pub value: usize,
+}
+
+/// Options for [`aggregate`]
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// How the file is read
+    pub engine: Engine,
+    /// The byte between the station name and the temperature, `;` by
+    /// default
+    pub delimiter: u8,
+    /// Read with O_DIRECT (FILE_FLAG_NO_BUFFERING on Windows), bypassing
+    /// the page cache, to measure cold disk-bound performance. This
+    /// replaces the engine for uncompressed files. Other platforms fall
+    /// back to the buffered engine
+    pub direct: bool,
+    /// Tell the kernel how the mmap engine reads the map, with
+    /// MADV_SEQUENTIAL for the whole file and MADV_WILLNEED for each
+    /// chunk as a worker picks it up
+    pub madvise: bool,
+    /// Ask for transparent huge pages on the map (Linux only, and only
+    /// if the kernel supports them for file mappings)
+    pub huge_pages: bool,
+    /// Only aggregate the stations this matches. Lines for the others
+    /// are skipped as they're parsed
+    pub stations: Option<StationFilter>,
+    /// Keep a histogram of every station's temperatures, which
+    /// [`Record::percentile`] needs. Costs 8 KB per station per thread
+    pub histograms: bool,
+    /// Increased by the number of input bytes processed as the workers
+    /// go, so another thread can report progress
+    pub progress: Option<Arc<AtomicU64>>,
+    /// Called with the bytes done so far and the total as the workers
+    /// go, counting from 0 for every call into the library
+    pub on_progress: Option<OnProgress>,
+    /// Stop once this is set: the workers finish the chunk or block
+    /// they're on and take no more, and the aggregation returns
+    /// [`Error::Cancelled`] with what they'd got through
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Number of worker threads. Defaults to rayon's global pool (one
+    /// thread per CPU unless `RAYON_NUM_THREADS` says otherwise).
+    /// Without the `parallel` feature there's only the calling thread
+    pub threads: Option<usize>,
+    /// Save every chunk's results in this directory as it's finished,
+    /// and reuse any already there rather than parsing their chunks
+    /// again, so an interrupted run can be resumed. Uncompressed files
+    /// only, and they're always read with the mmap engine
+    pub checkpoint: Option<PathBuf>,
+    /// Adds up the time spent in each phase of the aggregation. Only
+    /// uncompressed files read by an engine are broken down into I/O
+    /// and parsing
+    pub timings: Option<Arc<Timings>>,
+    /// Check that every station name is valid UTF-8 as it's parsed, so
+    /// a bad one fails as an [`Error::Parse`] with its line number
+    /// rather than at output time. Each name is only checked the first
+    /// time a thread sees it
+    pub validate_utf8: bool,
+    /// Update the stations a small batch of lines at a time, having
+    /// started to fetch where every station of the batch is in the table
+    /// before touching any of them, so their cache misses overlap rather
+    /// than come one after another. Worth it when there are too many
+    /// stations for the table to stay in cache
+    pub prefetch: bool,
+    /// With the mmap engine, give each station a dense id the first time
+    /// a worker sees it and keep the records in an array by id, rather
+    /// than in the table's slots. The workers' dictionaries are merged
+    /// pairwise, whatever [`Options::merge`] says
+    pub interned: bool,
+    /// The longest a station name can be, in bytes. A longer one is an
+    /// [`Error::Parse`], unless `truncate_names` is set. The challenge
+    /// allows up to 100
+    pub max_name_len: Option<usize>,
+    /// Cut names longer than `max_name_len` down to it (at a character
+    /// boundary) rather than failing on them
+    pub truncate_names: bool,
+    /// Number of decimals the temperatures are given to, 1 (the
+    /// challenge's) to 3. Records keep their temperatures in units of
+    /// that many decimals, and values given to fewer places are padded
+    /// out. Whatever the precision, temperatures beyond about 3276.7 degrees
+    /// either way are an [`Error::Parse`]. [`Options::histograms`] needs 1
+    pub precision: u8,
+    /// The lowest and highest temperatures allowed, in units of
+    /// `precision` decimals, as `(-999, 999)` for the challenge's -99.9
+    /// to 99.9. A temperature outside them is an [`Error::Parse`] (or
+    /// skipped, as `on_error` says) rather than aggregated
+    pub range: Option<(i32, i32)>,
+    /// Stations expected in the input, whose records are kept in a flat
+    /// array behind a perfect hash rather than in the map. Any others
+    /// still go through the map
+    pub known_stations: Option<Arc<KnownStations>>,
+    /// On a machine with several NUMA nodes, give each node a pool of
+    /// workers pinned to its CPUs and a contiguous share of the chunks,
+    /// so it parses memory it faulted in itself. Replaces `threads` for
+    /// uncompressed files read by an engine. Linux only
+    pub numa: bool,
+    /// Pin each worker to a CPU of its own, so the scheduler can't move
+    /// it and benchmarks repeat better. Without `threads` there's one
+    /// worker per CPU. Linux only
+    pub pin_cores: Option<PinCores>,
+    /// Read uncompressed files through [`aggregate_reader`]'s pipeline
+    /// rather than an engine, with its blocks cut down (and if need be
+    /// its workers too) so they add up to about this many bytes. For
+    /// machines with far less memory than the file, where mapping it
+    /// thrashes the page cache
+    pub max_memory: Option<usize>,
+    /// Size of the blocks each worker of the buffered, direct and
+    /// io_uring engines reads in. By default it's picked for the
+    /// storage the file is on: larger for spinning disks and network
+    /// filesystems than for flash
+    pub read_size: Option<usize>,
+    /// How big the chunks the input is cut into are: about
+    /// [`chunk::TARGET_CHUNK_SIZE`] by default, or sized for the input
+    /// with [`ChunkSize::Auto`](chunk::ChunkSize::Auto)
+    #[cfg(feature = "native")]
+    pub chunk_size: chunk::ChunkSize,
+    /// Keep each file's results in this directory, and return them from
+    /// there while the file's path, size, modification time and a
+    /// fingerprint of its contents (and the options that change the
+    /// results) are the same. Streams aren't cached
+    pub cache: Option<PathBuf>,
+    /// Hold the input to the challenge's rules: every temperature in one
+    /// of its shapes (`d.d`, `dd.d`, `-d.d` or `-dd.d`, so -99.9 to 99.9
+    /// to one decimal), and no empty station names. Anything else is an
+    /// [`Error::Parse`]. Needs a `precision` of 1
+    pub strict: bool,
+    /// How the workers' maps are combined once their chunks are parsed
+    pub merge: Merge,
+    /// What a line that can't be parsed does
+    pub on_error: OnError,
+    /// Increased by the number of bad lines passed over under
+    /// [`OnError::Count`]
+    pub bad_lines: Option<Arc<AtomicU64>>,
+    /// Report each worker's progress and the hottest stations so far
+    /// here, for a dashboard. Looking for the hottest costs a pass over
+    /// a worker's stations every time it finishes a block
+    pub live: Option<Arc<Live>>,
+    /// Count the bytes, rows, chunks and stations each worker gets
+    /// through here, and how long it's busy with its chunks
+    pub workers: Option<Arc<Workers>>,
+    /// Take the station and the temperature from these fields, rather
+    /// than the name from before the last delimiter and the temperature
+    /// from after it. The temperatures can then be whole numbers too
+    pub columns: Option<Columns>,
+    /// Let fields be quoted (RFC 4180), so a name in quotes can have the
+    /// delimiter in it. Slower, and a quoted field still can't span
+    /// lines
+    pub quoted: bool,
+    /// Take these differences out of the names, in this order, so a
+    /// station written in different ways gets one record. It's done as
+    /// the results are finished, after the station filter and the known
+    /// stations have seen the names as they are in the input
+    pub normalize_keys: Vec<NormalizeKey>,
+    /// The most stations there can be. A worker fails with an
+    /// [`Error::Parse`] at the line that takes it past them, rather
+    /// than carry on filling memory with a file keyed by something that
+    /// isn't a station (a timestamp, say), and so do results that come
+    /// to more once the workers' are combined
+    pub max_map_entries: Option<usize>,
+    /// Increased by the number of bytes appended to an uncompressed file
+    /// while it was read, which are left out: the engines only read as
+    /// far as the file went when they started. A file appended to
+    /// partway through its last line is an error instead
+    pub appended: Option<Arc<AtomicU64>>,
+    /// Read uncompressed files with this many threads that only read,
+    /// handing blocks of `read_size` bytes over a bounded queue to
+    /// `parse_threads` threads that only parse, rather than with an
+    /// engine. Either one sets the pipeline going: by default there's
+    /// one reader, and a parser for every other thread
+    pub io_threads: Option<usize>,
+    /// The pipeline's parsers (see `io_threads`)
+    pub parse_threads: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            engine: Engine::default(),
+            delimiter: b';',
+            direct: false,
+            madvise: false,
+            huge_pages: false,
+            stations: None,
+            histograms: false,
+            progress: None,
+            on_progress: None,
+            cancel: None,
+            threads: None,
+            checkpoint: None,
+            timings: None,
+            validate_utf8: false,
+            prefetch: false,
+            interned: false,
+            max_name_len: None,
+            truncate_names: false,
+            precision: 1,
+            range: None,
+            known_stations: None,
+            numa: false,
+            pin_cores: None,
+            max_memory: None,
+            read_size: None,
+            #[cfg(feature = "native")]
+            chunk_size: chunk::ChunkSize::default(),
+            cache: None,
+            strict: false,
+            merge: Merge::default(),
+            on_error: OnError::default(),
+            bad_lines: None,
+            live: None,
+            workers: None,
+            columns: None,
+            quoted: false,
+            normalize_keys: Vec::new(),
+            max_map_entries: None,
+            appended: None,
+            io_threads: None,
+            parse_threads: None,
+        }
+    }
+}
+
+// How many workers aggregate_reader runs, and how big its blocks are.
+// There are 2 * workers + 1 blocks: one filling, and up to one parsing
+// and one queued for each worker
+#[cfg(feature = "native")]
+fn stream_buffers(options: &Options) -> (usize, usize) {
+    let workers = options.threads.unwrap_or_else(current_num_threads).max(1);
+    let Some(max_memory) = options.max_memory else {
+        return (workers, STREAM_BLOCK_SIZE);
+    };
+
+    let workers = workers.min((max_memory / MIN_STREAM_BLOCK_SIZE).saturating_sub(1) / 2).max(1);
+    let block_size = (max_memory / (2 * workers + 1)).clamp(MIN_STREAM_BLOCK_SIZE, STREAM_BLOCK_SIZE);
+    (workers, block_size)
+}
+
+// Counts bytes towards options.progress, if anyone is watching
+fn report_progress(options: &Options, bytes: usize) {
+    if let Some(progress) = &options.progress {
+        progress.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    if let Some(live) = &options.live {
+        live.add_bytes(bytes);
+    }
+    if let Some(workers) = &options.workers {
+        workers.add_bytes(bytes);
+    }
+    if let Some(on_progress) = &options.on_progress {
+        on_progress.add(bytes);
+    }
+}
+
+// Whether options.cancel has been set, so there's no point starting on
+// another chunk or block
+#[inline]
+fn cancelled(options: &Options) -> bool {
+    options.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+}
+
+// The options for one call into the library, with options.on_progress
+// counting its bytes from 0 out of total
+fn starting(options: &Options, total: impl FnOnce() -> u64) -> std::borrow::Cow<'_, Options> {
+    match &options.on_progress {
+        Some(on_progress) => std::borrow::Cow::Owned(Options { on_progress: Some(on_progress.start(total())), ..options.clone() }),
+        None => std::borrow::Cow::Borrowed(options),
+    }
+}
+
+// The finished results, or what there is of them if the run was cancelled
+fn finish_run(data: StationMap, options: &Options) -> Result<StationMap, Error> {
+    let data = unless_cancelled(finish_map(data, options), options)?;
+    match options.max_map_entries {
+        Some(max) if data.len() > max => Err(format!("{} ({} in all)", Invalid::TooManyStations(max), data.len()).into()),
+        _ => Ok(data),
+    }
+}
+
+fn unless_cancelled(data: StationMap, options: &Options) -> Result<StationMap, Error> {
+    match cancelled(options) {
+        true => Err(Error::Cancelled { partial: data }),
+        false => Ok(data),
+    }
+}
+
+// Runs f on a dedicated pool when options asks for a specific number
+// of threads, and on rayon's global pool otherwise. The minimum chunk
+// count goes by rayon::current_num_threads(), so it follows the pool
+#[cfg(feature = "parallel")]
+fn in_pool<T: Send>(options: &Options, f: impl FnOnce() -> Result<T, Error> + Send) -> Result<T, Error> {
+    if let Some(pin) = options.pin_cores {
+        return in_pinned_pool(pin, options, f);
+    }
+
+    match options.threads {
+        Some(threads) => rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|e| Error::Other(e.to_string()))?
+            .install(f),
+        None => f(),
+    }
+}
+
+// in_pool, with every worker pinned to its own CPU. More workers than
+// CPUs have to double up
+#[cfg(all(feature = "parallel", target_os = "linux"))]
+fn in_pinned_pool<T: Send>(pin: PinCores, options: &Options, f: impl FnOnce() -> Result<T, Error> + Send) -> Result<T, Error> {
+    let cpus = affinity::cpus(pin);
+    let threads = options.threads.unwrap_or(cpus.len());
+    if threads > cpus.len() {
+        log::warn!("{} workers but only {} CPUs to pin them to, so some share", threads, cpus.len());
+    }
+    log::debug!("Pinning {} workers to CPUs {:?}", threads, &cpus[..threads.min(cpus.len())]);
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .start_handler(move |i| affinity::pin(cpus[i % cpus.len()]))
+        .build()
+        .map_err(|e| Error::Other(e.to_string()))?
+        .install(f)
+}
+
+#[cfg(all(feature = "parallel", not(target_os = "linux")))]
+fn in_pinned_pool<T: Send>(_: PinCores, options: &Options, f: impl FnOnce() -> Result<T, Error> + Send) -> Result<T, Error> {
+    log::warn!("Workers can only be pinned to cores on Linux");
+    in_pool(&Options { pin_cores: None, ..options.clone() }, f)
+}
+
+// Without rayon there's no pool, only the calling thread
+#[cfg(all(feature = "native", not(feature = "parallel")))]
+fn in_pool<T: Send>(options: &Options, f: impl FnOnce() -> Result<T, Error> + Send) -> Result<T, Error> {
+    if options.threads.is_some_and(|threads| threads > 1) || options.pin_cores.is_some() {
+        log::warn!("Built without the parallel feature, so there's only the one unpinned thread");
+    }
+    f()
+}
+
+/// Aggregates the measurements file at `path` into one [`Record`] per
+/// station, ordered by name.
+///
+/// Names that aren't valid UTF-8 are converted lossily; use
+/// [`aggregate_stations`] to get at the raw names.
+#[cfg(feature = "native")]
+pub fn aggregate(path: &Path, options: &Options) -> Result<BTreeMap<String, Record>, Error> {
+    let stations = aggregate_stations(path, options)?;
+
+    Ok(stations.into_iter()
+        .map(|(key, value)| (String::from_utf8_lossy(&key).into_owned(), value))
+        .collect())
+}
+
+/// Aggregates several measurements files (e.g. daily shards) as one
+/// dataset. The files are processed in parallel, each split into
+/// chunks as usual, and the per-file results are combined.
+#[cfg(feature = "native")]
+pub fn aggregate_files<P: AsRef<Path> + Sync>(paths: &[P], options: &Options) -> Result<StationMap, Error> {
+    let options = &starting(options, || paths.iter().map(|path| input_size(path.as_ref()).unwrap_or(0)).sum());
+    in_pool(options, || {
+        reduce_maps(paths.par_iter().map(|path| aggregate_path(path.as_ref(), options)), options)
+    }).and_then(|data| finish_run(data, options))
+}
+
+/// Aggregates the measurements file at `path` into a map from raw
+/// station names to their [`Record`]s.
+///
+/// gzip and zstd compressed files are recognised by their magic bytes
+/// and decompressed on the fly. A FIFO or other special file is read
+/// through once, as [`aggregate_reader`] reads stdin.
+#[cfg(feature = "native")]
+pub fn aggregate_stations(path: &Path, options: &Options) -> Result<StationMap, Error> {
+    let options = &starting(options, || input_size(path).unwrap_or(0));
+    in_pool(options, || aggregate_path(path, options)).and_then(|data| finish_run(data, options))
+}
+
+/// Aggregates every chunk of the uncompressed measurements file at
+/// `path` separately, returning each chunk's `(start, end)` byte range
+/// with its stations. Merging them all with [`merge_maps`] gives the
+/// same result as [`aggregate_stations`].
+#[cfg(feature = "native")]
+pub fn aggregate_chunks(path: &Path, options: &Options) -> Result<Vec<ChunkResult>, Error> {
+    let options = &starting(options, || input_size(path).unwrap_or(0));
+    let chunks = in_pool(options, || with_file(path, |file| {
+        regular_file(path, file)?;
+        if compression::detect_file(file)? != compression::Compression::None {
+            return Err(format!("{}: per-chunk results need an uncompressed file", path.display()).into());
+        }
+
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        let bytes = &mmap[..];
+
+        // The chunks left when the run is cancelled are None
+        chunk::chunks_in(bytes, options).into_par_iter().map(|(start, end)| {
+            if cancelled(options) {
+                return Ok(None);
+            }
+            let mut data_map = new_map();
+            parse_to_end(&bytes[start..end], &mut data_map, options)
+                .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, bytes, start + at, 0))?;
+            report_progress(options, end - start);
+            Ok(Some(((start as u64, end as u64), finish_map(data_map, options))))
+        }).collect::<Result<Vec<Option<ChunkResult>>, Error>>()
+    }))?;
+
+    let chunks = chunks.into_iter().flatten();
+    if cancelled(options) {
+        let partial = chunks.fold(new_map(), |data, (_, data_map)| merge_maps(data, data_map));
+        return Err(Error::Cancelled { partial });
+    }
+    Ok(chunks.collect())
+}
+
+/// Aggregates the lines in bytes `start..end` of the uncompressed
+/// measurements file at `path`, which must start and end on line
+/// boundaries (as the ranges from [`chunk::split_in`] do). The range is
+/// split into chunks and parsed in parallel like a whole file.
+#[cfg(feature = "native")]
+pub fn aggregate_range(path: &Path, start: u64, end: u64, options: &Options) -> Result<StationMap, Error> {
+    let options = &starting(options, || end.saturating_sub(start));
+    in_pool(options, || with_file(path, |file| {
+        regular_file(path, file)?;
+        if compression::detect_file(file)? != compression::Compression::None {
+            return Err(format!("{}: byte ranges need an uncompressed file", path.display()).into());
+        }
+
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        let bytes = mmap.get(start as usize..end as usize)
+            .ok_or_else(|| format!("{}: range {}..{} is past the end of the file", path.display(), start, end))?;
+
+        aggregate_slice(bytes, start, options)
+    })).and_then(|data| finish_run(data, options))
+}
+
+/// Aggregates measurements that are already in memory, e.g. a file
+/// uploaded to a web page.
+///
+/// With the `native` feature the bytes are split into chunks and parsed
+/// in parallel like a file; without it (as on wasm32), on the calling
+/// thread. A bad line is reported with its line number.
+pub fn aggregate_bytes(bytes: &[u8], options: &Options) -> Result<StationMap, Error> {
+    let options = &starting(options, || bytes.len() as u64);
+    #[cfg(feature = "native")]
+    let data = in_pool(options, || aggregate_slice(bytes, 0, options));
+    #[cfg(not(feature = "native"))]
+    let data = {
+        let mut data_map = new_map();
+        report_progress(options, bytes.len());
+        parse_to_end(bytes, &mut data_map, options)
+            .map(|()| data_map)
+            .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, bytes, at, 0))
+    };
+
+    data.and_then(|data| finish_run(data, options)).map_err(|e| with_line_number(e, bytes))
+}
+
+// Parses bytes, which start at base in the input, in parallel chunks
+#[cfg(feature = "native")]
+fn aggregate_slice(bytes: &[u8], base: u64, options: &Options) -> Result<StationMap, Error> {
+    workers::spread(options, || reduce_maps(chunk::chunks_in(bytes, options).into_par_iter().try_fold(new_map, |mut data_map, (start, end)| {
+        if cancelled(options) {
+            return Ok(data_map);
+        }
+        workers::chunk(options, || parse_to_end(&bytes[start..end], &mut data_map, options))
+            .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, bytes, start + at, base))?;
+        report_progress(options, end - start);
+        Ok(data_map)
+    }), options))
+}
+
+// An error from parsing bytes, with the line number of a bad line
+// filled in from its offset
+fn with_line_number(e: Error, bytes: &[u8]) -> Error {
+    match e {
+        Error::Parse { path, line: None, offset, reason, text } => Error::Parse {
+            path,
+            line: Some(memchr::memchr_iter(b'\n', &bytes[..offset as usize]).count() as u64 + 1),
+            offset,
+            reason,
+            text,
+        },
+        e => e,
+    }
+}
+
+// Puts right what the parser leaves for later, before anyone else sees
+// the map. While aggregating, stations the filter rejects stay in the
+// map as empty records, so every later line for them is rejected with
+// the one lookup. Quoted names with escaped quotes in them are kept as
+// they are in the file, quotes and all, and so are names to be normalised
+fn finish_map(mut data_map: StationMap, options: &Options) -> StationMap {
+    data_map.retain(|_, record| record.count > 0);
+    if options.quoted {
+        data_map = aggregator::unescape_names(data_map);
+    }
+    normalize::normalize_names(data_map, &options.normalize_keys)
+}
+
+// The placeholder for a rejected station, which combines with other
+// records without changing them
+fn filtered_out(options: &Options) -> Record {
+    Record {
+        min: i32::MAX,
+        max: i32::MIN,
+        total: 0,
+        count: 0,
+        sum_squares: 0,
+        histogram: None,
+        decimals: options.precision,
+    }
+}
+
+#[cfg(feature = "native")]
+fn aggregate_path(path: &Path, options: &Options) -> Result<StationMap, Error> {
+    if let Some(url) = object_url(path) {
+        #[cfg(feature = "object-store")]
+        return object::aggregate_object(url, options);
+        #[cfg(not(feature = "object-store"))]
+        return Err(no_object_store(url));
+    }
+
+    // This file reads in 1 billion rows of data from
+    // the measurements file.
+    // The file is a "csv" file, each line being name;temp
+    // name is a string and temp is a float with one decimal
+    with_file(path, |file| {
+        // A FIFO or device can't be mapped, seeked or even looked at
+        // without taking from it, so it's read once through, as stdin is
+        if !file.metadata()?.is_file() {
+            if options.checkpoint.is_some() || options.cache.is_some() {
+                return Err(format!("{}: only regular files can be checkpointed or cached", path.display()).into());
+            }
+            log::debug!("{}: not a regular file, reading it as a stream", path.display());
+            return read_stream(file, options);
+        }
+        match &options.cache {
+            Some(cache) => cache::aggregate_cached(path, file, cache, options),
+            None => aggregate_file(path, file, options),
+        }
+    })
+}
+
+/// The URL in `path` if it's an object's, as `s3://bucket/key`, rather
+/// than a file's. Inputs given as paths can be either
+#[cfg(feature = "native")]
+pub fn object_url(path: &Path) -> Option<&str> {
+    let url = path.to_str()?;
+    let (scheme, rest) = url.split_once("://")?;
+    // Longer than a drive letter, which is all C:// could be
+    (scheme.len() > 1 && scheme.bytes().all(|byte| byte.is_ascii_alphanumeric()) && !rest.is_empty()).then_some(url)
+}
+
+/// The size of the input at `path`: the file's, or for a URL (see
+/// [`object_url`]) the object's
+#[cfg(feature = "native")]
+pub fn input_size(path: &Path) -> Result<u64, Error> {
+    match object_url(path) {
+        #[cfg(feature = "object-store")]
+        Some(url) => object_size(url),
+        #[cfg(not(feature = "object-store"))]
+        Some(url) => Err(no_object_store(url)),
+        None => Ok(std::fs::metadata(path).map_err(|e| Error::from(e).in_file(path))?.len()),
+    }
+}
+
+#[cfg(all(feature = "native", not(feature = "object-store")))]
+fn no_object_store(url: &str) -> Error {
+    format!("{}: reading objects needs the object-store feature", url).into()
+}
+
+// Opens path and runs f on it, attributing any error to the file
+#[cfg(feature = "native")]
+fn with_file<T>(path: &Path, f: impl FnOnce(&std::fs::File) -> Result<T, Error>) -> Result<T, Error> {
+    let file = std::fs::File::open(path).map_err(|e| Error::from(e).in_file(path))?;
+
+    f(&file).map_err(|e| match e {
+        // The parsers only know byte offsets, so work out the line
+        // number now that something has gone wrong
+        Error::Parse { path: None, line: None, offset, reason, text } => Error::Parse {
+            path: Some(path.to_path_buf()),
+            line: line_number(&file, offset),
+            offset,
+            reason,
+            text,
+        },
+        e => e.in_file(path),
+    })
+}
+
+// For the readers that map the file, which a FIFO or device can't be
+#[cfg(feature = "native")]
+fn regular_file(path: &Path, file: &std::fs::File) -> Result<(), Error> {
+    if !file.metadata()?.is_file() {
+        return Err(format!("{}: not a regular file, which can only be aggregated as a stream", path.display()).into());
+    }
+    Ok(())
+}
+
+// The 1-based number of the line starting at offset in an uncompressed
+// file, by counting the newlines before it
+#[cfg(feature = "native")]
+fn line_number(file: &std::fs::File, offset: u64) -> Option<u64> {
+    // Reading a pipe again would only take lines meant for someone else
+    if !file.metadata().ok()?.is_file() || compression::detect_file(file).ok()? != compression::Compression::None {
+        return None;
+    }
+
+    let mmap = unsafe { memmap2::Mmap::map(file).ok()? };
+    let before = mmap.get(..offset as usize)?;
+    Some(memchr::memchr_iter(b'\n', before).count() as u64 + 1)
+}
+
+#[cfg(feature = "native")]
+fn aggregate_file(path: &Path, file: &std::fs::File, options: &Options) -> Result<StationMap, Error> {
+    // Compressed files are decompressed on the fly whichever engine
+    // was asked for, since they can't be chunked by byte offset
+    let compression = compression::detect_file(file)?;
+    if let Some(checkpoint) = &options.checkpoint {
+        if compression != compression::Compression::None {
+            return Err(format!("{}: compressed files can't be checkpointed", path.display()).into());
+        }
+        return checkpoint::aggregate_checkpointed(path, file, checkpoint, options);
+    }
+    if compression != compression::Compression::None {
+        log::debug!("{}: {:?} compressed, decompressing as a stream", path.display(), compression);
+        // The stream doesn't know how far through the file it is, so
+        // progress jumps by the whole (compressed) file at the end
+        let read_options = Options { progress: None, on_progress: None, ..options.clone() };
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        let data = compression::aggregate_compressed(compression, &mmap, &read_options)?;
+        report_progress(options, mmap.len());
+        return Ok(data);
+    }
+
+    let size = file.metadata()?.len();
+    if let Some(max_memory) = options.max_memory {
+        log::debug!("{}: streaming in at most {} bytes of blocks", path.display(), max_memory);
+        // Back to the start, from wherever detecting the compression left it
+        let mut file = file;
+        file.rewind()?;
+        let data = read_stream(file.take(size), options)?;
+        check_appended(path, file, size, options)?;
+        return Ok(data);
+    }
+
+    // The mmap engine doesn't read at all, so doesn't need to look
+    let read_size = || options.read_size.map(|size| size.max(1)).unwrap_or_else(|| {
+        let storage = storage::Storage::detect(path, file);
+        log::debug!("{}: on {:?} storage, reading {} bytes at a time", path.display(), storage, storage.read_size());
+        storage.read_size()
+    });
+    if options.io_threads.is_some() || options.parse_threads.is_some() {
+        log::debug!("{}: {} bytes, read and parsed by a pipeline", path.display(), size);
+        let data = pipeline::aggregate(file, size, read_size(), options)?;
+        check_appended(path, file, size, options)?;
+        return Ok(data);
+    }
+    let engine: Box<dyn engine::Engine> = if options.direct {
+        #[cfg(any(target_os = "linux", windows))]
+        {
+            Box::new(direct::Direct::open(path, size, read_size())?)
+        }
+        #[cfg(not(any(target_os = "linux", windows)))]
+        {
+            log::warn!("Unbuffered reads are only supported on Linux and Windows, so reading through the page cache");
+            Box::new(engine::Buffered { source: file, size, read_size: read_size() })
+        }
+    } else {
+        match options.engine.resolve() {
+            Engine::Buffered => Box::new(engine::Buffered { source: file, size, read_size: read_size() }),
+            Engine::Mmap => Box::new(engine::Mapped::new(file, size, options)?),
+            Engine::Auto => unreachable!("resolve never returns Auto"),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Engine::IoUring => Box::new(uring::Uring::open(path, size, read_size())?),
+            #[cfg(feature = "gpu")]
+            Engine::Gpu => Box::new(gpu::Gpu::new(file, size)?),
+            #[cfg(feature = "tokio")]
+            Engine::Async => {
+                let data = async_io::aggregate(path, size, options)?;
+                check_appended(path, file, size, options)?;
+                return Ok(data);
+            }
+        }
+    };
+
+    log::debug!("{}: {} bytes, engine: {:?}, direct: {}", path.display(), size, options.engine.resolve(), options.direct);
+    let data = engine::aggregate(&*engine, options)?;
+    check_appended(path, file, size, options)?;
+    Ok(data)
+}
+
+// The file was read as it was at size bytes, which leaves out anything
+// appended since but is wrong if the last of them was partway through
+// a line
+#[cfg(feature = "native")]
+fn check_appended(path: &Path, file: &std::fs::File, size: u64, options: &Options) -> Result<(), Error> {
+    let now = file.metadata()?.len();
+    if now < size {
+        return Err(format!("{}: shrank from {} to {} bytes while it was read", path.display(), size, now).into());
+    }
+    if now == size {
+        return Ok(());
+    }
+    if size > 0 && file.read_range(size - 1, size)?.as_ref() != b"\n" {
+        return Err(format!("{}: appended to partway through its last line (at byte {}) while it was read, so aggregate it again", path.display(), size).into());
+    }
+    log::debug!("{}: {} bytes appended while it was read, which are left out", path.display(), now - size);
+    if let Some(appended) = &options.appended {
+        appended.fetch_add(now - size, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+
+/// Aggregates measurements from a sequential, non-seekable source
+/// such as stdin.
+///
+/// Since the input can't be split into chunks up front, it is read
+/// block by block on the calling thread, and every block (cut at its
+/// last `\n`) is parsed by one of the worker threads. The blocks come
+/// from a fixed pool that the workers hand back as they finish with
+/// them, sized by [`Options::max_memory`] if it's set.
+#[cfg(feature = "native")]
+pub fn aggregate_reader(reader: impl Read, options: &Options) -> Result<StationMap, Error> {
+    let options = &starting(options, || 0);
+    read_stream(reader, options).and_then(|data| unless_cancelled(finish_map(data, options), options))
+}
+
+// aggregate_reader's unfinished map, for a stream that's part of a
+// bigger run
+#[cfg(feature = "native")]
+pub(crate) fn read_stream(mut reader: impl Read, options: &Options) -> Result<StationMap, Error> {
+    let (workers, block_size) = stream_buffers(options);
+    let buffers = 2 * workers + 1;
+
+    // Bounded, so a slow parser pushes back on the reader instead
+    // of the whole input piling up in memory
+    // Every block goes with its offset in the stream, for errors
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<(u64, Vec<u8>)>(buffers);
+    let receiver = std::sync::Mutex::new(receiver);
+
+    // The blocks nobody is using, which the reader fills next
+    let (free_sender, free) = std::sync::mpsc::sync_channel::<Vec<u8>>(buffers);
+    for _ in 1..buffers {
+        let _ = free_sender.send(Vec::with_capacity(block_size));
+    }
+
+    // Set by a worker that finds a bad line, so the reader can stop
+    let failed = std::sync::atomic::AtomicBool::new(false);
+
+    std::thread::scope(|scope| {
+        let handles = (0..workers).map(|_| {
+            let receiver = &receiver;
+            let failed = &failed;
+            let free_sender = free_sender.clone();
+            scope.spawn(move || {
+                let mut data_map = new_map();
+                let mut error = None;
+
+                // Hold the lock only while waiting for the next block.
+                // After an error, keep draining so the reader never
+                // blocks on a full channel
+                loop {
+                    let block = receiver.lock().unwrap().recv();
+                    let Ok((offset, mut block)) = block else { break };
+                    if error.is_none() {
+                        if let Err(BadLine(at, invalid)) = parse_to_end(&block, &mut data_map, options) {
+                            error = Some(Error::bad_line(invalid, &block, at, offset));
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                    }
+
+                    block.clear();
+                    let _ = free_sender.send(block);
+                }
+
+                match error {
+                    Some(error) => Err(error),
+                    None => Ok(data_map),
+                }
+            })
+        }).collect::<Vec<_>>();
+        drop(free_sender);
+
+        let mut block = Vec::with_capacity(block_size);
+        let mut offset = 0;
+        let read_result = loop {
+            if failed.load(Ordering::Relaxed) || cancelled(options) {
+                break Ok(());
+            }
+
+            // Fill the rest of the block, which may already start with
+            // the tail of a line carried over from the previous one (or
+            // be full of one very long line, which needs more room)
+            let filled = block.len();
+            block.resize(if filled < block_size { block_size } else { filled * 2 }, 0);
+
+            let read = match reader.read(&mut block[filled..]) {
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    block.truncate(filled);
+                    continue;
+                }
+                Err(e) => break Err(e),
+            };
+            block.truncate(filled + read);
+            report_progress(options, read);
+
+            if read == 0 {
+                if !block.is_empty() {
+                    let _ = sender.send((offset, block));
+                }
+                break Ok(());
+            }
+
+            // Hand out everything up to the last complete line once the
+            // block is full, and carry the partial line over into the
+            // next free block. A block without any newline is one very
+            // long line, so keep reading, unless it's already too long
+            // to be valid: then the workers can reject it without
+            // waiting for the end of it
+            if block.len() >= block_size {
+                if max_line_len(options).is_some_and(|max| block.len() > max) && memchr::memchr(b'\n', &block).is_none() {
+                    let _ = sender.send((offset, std::mem::take(&mut block)));
+                    break Ok(());
+                }
+                if let Some(last) = memchr::memrchr(b'\n', &block) {
+                    // Every worker has gone if there's no block to come
+                    let Ok(mut next) = free.recv() else { break Ok(()) };
+                    next.extend_from_slice(&block[last + 1..]);
+                    block.truncate(last + 1);
+
+                    let full = std::mem::replace(&mut block, next);
+                    let full_len = full.len() as u64;
+                    if sender.send((offset, full)).is_err() {
+                        break Ok(());
+                    }
+                    offset += full_len;
+                }
+            }
+        };
+
+        // Hanging up lets the workers finish their last blocks and exit
+        drop(sender);
+
+        // Report the earliest bad line if there are several
+        let mut data = StationMap::default();
+        let mut error: Option<Error> = None;
+
+        for result in handles.into_iter().map(|handle| handle.join().unwrap()) {
+            match result {
+                Ok(data_map) => data = merge_maps(data, data_map),
+                Err(e) => {
+                    let earlier = match (&e, &error) {
+                        (Error::Parse { offset, .. }, Some(Error::Parse { offset: first, .. })) => offset < first,
+                        _ => error.is_none(),
+                    };
+                    if earlier {
+                        error = Some(e);
+                    }
+                }
+            }
+        }
+
+        read_result?;
+        match error {
+            Some(error) => Err(error),
+            None => Ok(data),
+        }
+    })
+}
+
+/// Merges the records of `map2` into `map1`
+pub fn merge_maps(mut map1: StationMap, map2: StationMap) -> StationMap {
+    for (key, value) in map2 {
+        match map1.entry(key) {
+            hashbrown::hash_map::Entry::Occupied(mut entry) => entry.get_mut().combine(&value),
+            hashbrown::hash_map::Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
+
+    map1
+}
+
+// Combines the workers' maps as options.merge says
+#[cfg(feature = "native")]
+fn reduce_maps(maps: impl ParallelIterator<Item = Result<StationMap, Error>>, options: &Options) -> Result<StationMap, Error> {
+    match options.merge {
+        Merge::Tree => maps.try_reduce(StationMap::default, |map1, map2| Ok(timings::timed(options, Phase::Merging, || merge_maps(map1, map2)))),
+        Merge::Sharded => {
+            let maps = maps.collect::<Result<Vec<_>, Error>>()?;
+            Ok(timings::timed(options, Phase::Merging, || merge_sharded(maps)))
+        }
+    }
+}
+
+// Merge::Sharded. The shards go by a hash of their own rather than the
+// map's, so a shard's stations don't all land in the same few buckets
+#[cfg(feature = "native")]
+fn merge_sharded(maps: Vec<StationMap>) -> StationMap {
+    let shards = current_num_threads();
+    if maps.len() <= 2 || shards == 1 {
+        return maps.into_iter().reduce(merge_maps).unwrap_or_default();
+    }
+    log::debug!("Merging {} maps in {} shards", maps.len(), shards);
+    let shard = |key: &[u8]| {
+        use std::hash::Hasher;
+        let mut hasher = std::hash::DefaultHasher::new();
+        hasher.write(key);
+        hasher.finish() as usize % shards
+    };
+
+    // Every map cut into its shards, then every shard's pieces merged
+    let pieces = maps.into_par_iter().map(|map| {
+        let mut pieces = (0..shards).map(|_| Vec::new()).collect::<Vec<_>>();
+        for (key, record) in map {
+            pieces[shard(&key)].push((key, record));
+        }
+        pieces
+    }).collect::<Vec<_>>();
+    let mut by_shard = (0..shards).map(|_| Vec::with_capacity(pieces.len())).collect::<Vec<_>>();
+    for map_pieces in pieces {
+        for (shard, piece) in by_shard.iter_mut().zip(map_pieces) {
+            shard.push(piece);
+        }
+    }
+
+    let merged = by_shard.into_par_iter().map(|pieces| {
+        let mut data_map = new_map();
+        for (key, record) in pieces.into_iter().flatten() {
+            match data_map.entry(key) {
+                hashbrown::hash_map::Entry::Occupied(mut entry) => entry.get_mut().combine(&record),
+                hashbrown::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(record);
+                }
+            }
+        }
+        data_map
+    }).collect::<Vec<_>>();
+
+    let mut data = StationMap::with_capacity_and_hasher(merged.iter().map(StationMap::len).sum(), Default::default());
+    for data_map in merged {
+        data.extend(data_map);
+    }
+    data
+}
+
+// Parses block, which starts at block_offset in the input and carries
+// on from the partial line left in carry by the previous block, and
+// leaves its own partial line in carry
+#[cfg(all(feature = "native", any(target_os = "linux", windows)))]
+fn parse_carried(block: &[u8], block_offset: u64, carry: &mut Vec<u8>, data_map: &mut StationMap, options: &Options) -> Result<(), Error> {
+    match (memchr::memchr(b'\n', block), memchr::memrchr(b'\n', block)) {
+        (Some(first), Some(last)) => {
+            let carry_offset = block_offset - carry.len() as u64;
+            carry.extend_from_slice(&block[..=first]);
+            parse_lines(carry, data_map, options)
+                .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, carry, at, carry_offset))?;
+            parse_lines(&block[first + 1..=last], data_map, options)
+                .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, block, first + 1 + at, block_offset))?;
+
+            carry.clear();
+            carry.extend_from_slice(&block[last + 1..]);
+        }
+        _ => carry.extend_from_slice(block),
+    }
+
+    Ok(())
+}
+
+/// About how much memory `data_map` takes up: its table's slots, and
+/// the names and histograms that are kept outside them
+pub fn map_memory(data_map: &StationMap) -> usize {
+    let slots = data_map.capacity() * (std::mem::size_of::<(StationKey, Record)>() + 1);
+    let outside = data_map.iter().map(|(name, record)| {
+        #[cfg(feature = "inline-keys")]
+        let name = name.heap_len();
+        #[cfg(not(feature = "inline-keys"))]
+        let name = name.capacity();
+        name + record.histogram.as_ref().map_or(0, |_| std::mem::size_of::<Histogram>())
+    });
+    slots + outside.sum::<usize>()
+}
+
+fn new_map() -> StationMap {
+    // Return a hashmap of the data, with the name as the key and the values of
+    // - min
+    // - max
+    // - total
+    // - count
+    // All temps are multiplied by 10
+
+    StationMap::with_capacity_and_hasher(10_000, Default::default())
+}
+
+// Parses every complete (\n terminated) line in bytes into data_map,
+// returning the number of bytes consumed
+fn parse_lines<'a>(bytes: &'a [u8], data_map: &mut impl Stations<'a>, options: &Options) -> Result<usize, BadLine> {
+    let mut scratch = Scratch::new(options);
+    let mut lines = parser::Lines::new(bytes, options.delimiter);
+
+    for line in lines.by_ref() {
+        let at = line.as_ref().map_or(0, |line| line.start);
+        let result = line.and_then(|parser::Line { start, end, name, temperature }| {
+            let (name, temperature) = if options.columns.is_some() || options.quoted {
+                split_fields(&bytes[start..end], options).map_err(|invalid| BadLine(start, invalid))?
+            } else {
+                (name, temperature)
+            };
+            add_line(name, temperature, &mut scratch, data_map, options).map_err(|invalid| BadLine(start, invalid))
+        });
+        match result {
+            Ok(()) => scratch.rows += 1,
+            Err(bad_line) => scratch.skip(bad_line, options)?,
+        }
+        if let Some(max) = options.max_map_entries {
+            if data_map.len() > max {
+                return Err(BadLine(at, Invalid::TooManyStations(max)));
+            }
+        }
+    }
+
+    scratch.flush(data_map, options);
+    if let Some(live) = &options.live {
+        live.observe(data_map);
+    }
+    Ok(lines.consumed())
+}
+
+// Same as parse_lines, for bytes that run to the end of the input: a
+// last line with no \n after it still counts
+fn parse_to_end<'a>(bytes: &'a [u8], data_map: &mut impl Stations<'a>, options: &Options) -> Result<(), BadLine> {
+    let consumed = parse_lines(bytes, data_map, options)?;
+
+    if consumed < bytes.len() {
+        let mut scratch = Scratch::new(options);
+        match parse_line(&bytes[consumed..], &mut scratch, data_map, options) {
+            Ok(()) => scratch.rows += 1,
+            Err(invalid) => scratch.skip(BadLine(consumed, invalid), options)?,
+        }
+        scratch.flush(data_map, options);
+    }
+
+    Ok(())
+}
+
+// What the parser adds measurements to: a StationMap, or a table keyed
+// by slices of the bytes being parsed, which never allocates for a name
+// (see table.rs)
+trait Stations<'a> {
+    fn contains(&self, name: &[u8]) -> bool;
+
+    // The station's record, started with new if it's the first time the
+    // station is seen, and whether it was
+    fn record(&mut self, name: &'a [u8], new: impl FnOnce() -> Record) -> (&mut Record, bool);
+
+    fn for_each(&self, f: impl FnMut(&[u8], &Record));
+
+    fn len(&self) -> usize;
+
+    // Hashes name for record_hashed, and starts fetching the memory it'll
+    // look at
+    #[inline]
+    fn prefetch(&self, _name: &[u8]) -> u64 {
+        0
+    }
+
+    // record, for a name prefetch has hashed
+    #[inline]
+    fn record_hashed(&mut self, _hash: u64, name: &'a [u8], new: impl FnOnce() -> Record) -> (&mut Record, bool) {
+        self.record(name, new)
+    }
+}
+
+impl<'a> Stations<'a> for StationMap {
+    #[inline]
+    fn contains(&self, name: &[u8]) -> bool {
+        self.contains_key(name)
+    }
+
+    #[inline]
+    fn record(&mut self, name: &'a [u8], new: impl FnOnce() -> Record) -> (&mut Record, bool) {
+        match self.entry_ref(name_ref(name)) {
+            hashbrown::hash_map::EntryRef::Occupied(entry) => (entry.into_mut(), false),
+            hashbrown::hash_map::EntryRef::Vacant(entry) => (entry.insert(new()), true),
+        }
+    }
+
+    fn for_each(&self, mut f: impl FnMut(&[u8], &Record)) {
+        for (name, record) in self {
+            f(name, record);
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        hashbrown::HashMap::len(self)
+    }
+
+    // hashbrown doesn't say where a key's buckets are, so there's only
+    // the hash to get out of the way
+    #[inline]
+    fn prefetch(&self, name: &[u8]) -> u64 {
+        std::hash::BuildHasher::hash_one(self.hasher(), name_ref(name))
+    }
+
+    #[inline]
+    fn record_hashed(&mut self, hash: u64, name: &'a [u8], new: impl FnOnce() -> Record) -> (&mut Record, bool) {
+        match self.raw_entry_mut().from_key_hashed_nocheck(hash, name_ref(name)) {
+            hashbrown::hash_map::RawEntryMut::Occupied(entry) => (entry.into_mut(), false),
+            hashbrown::hash_map::RawEntryMut::Vacant(entry) => (entry.insert_hashed_nocheck(hash, StationKey::from(name), new()).1, true),
+        }
+    }
+}
+
+// Adds a measurement to a station
+#[inline]
+fn add<'a>(data_map: &mut impl Stations<'a>, name: &'a [u8], temp: i32, options: &Options) {
+    let (record, new) = data_map.record(name, || new_record(temp, options));
+    if !new {
+        record.add(temp);
+    }
+}
+
+// Combines a record into a station's
+fn combine<'a>(data_map: &mut impl Stations<'a>, name: &'a [u8], record: Record) {
+    let mut record = Some(record);
+    let (entry, _) = data_map.record(name, || record.take().unwrap());
+    if let Some(record) = record {
+        entry.combine(&record);
+    }
+}
+
+// What the parser keeps between the lines of one parse_lines call
+struct Scratch<'o, 'a> {
+    // Space for the temperature digits
+    temp: Vec<u8>,
+    known: Option<&'o KnownStations>,
+    // The known stations' records by index, with the name they were
+    // first seen under, which are only added to the map once the lines
+    // have all been parsed
+    known_records: Vec<Option<(&'a [u8], Record)>>,
+    // Bad lines skipped, for options.bad_lines
+    skipped: u64,
+    // Lines parsed, for options.workers
+    rows: u64,
+    // Measurements waiting to be added with options.prefetch, with their
+    // names' hashes
+    batch: Vec<(u64, &'a [u8], i32)>,
+}
+
+// Lines in each of options.prefetch's batches: enough misses in flight to
+// cover one's latency, not so many the first are evicted before they're used
+const PREFETCH_BATCH: usize = 16;
+
+impl<'o, 'a> Scratch<'o, 'a> {
+    fn new(options: &'o Options) -> Self {
+        // A pattern filter has to see every new station itself
+        let patterns = options.stations.as_ref().is_some_and(|filter| !filter.exact_only());
+        let known = options.known_stations.as_deref().filter(|_| !patterns);
+
+        Scratch {
+            temp: Vec::with_capacity(8),
+            known,
+            known_records: vec![None; known.map_or(0, |known| known.names().len())],
+            skipped: 0,
+            rows: 0,
+            batch: Vec::with_capacity(if options.prefetch { PREFETCH_BATCH } else { 0 }),
+        }
+    }
+
+    // Holds a measurement back for the rest of its batch, once its
+    // station is on its way into cache
+    #[inline]
+    fn queue(&mut self, name: &'a [u8], temp: i32, data_map: &mut impl Stations<'a>, options: &Options) {
+        self.batch.push((data_map.prefetch(name), name, temp));
+        if self.batch.len() == PREFETCH_BATCH {
+            self.apply(data_map, options);
+        }
+    }
+
+    fn apply(&mut self, data_map: &mut impl Stations<'a>, options: &Options) {
+        for (hash, name, temp) in self.batch.drain(..) {
+            let (record, new) = data_map.record_hashed(hash, name, || new_record(temp, options));
+            if !new {
+                record.add(temp);
+            }
+        }
+    }
+
+    // Passes a bad line over, unless options.on_error says to fail on it
+    #[cold]
+    fn skip(&mut self, bad_line: BadLine, options: &Options) -> Result<(), BadLine> {
+        match options.on_error {
+            OnError::Abort => Err(bad_line),
+            OnError::Skip => Ok(()),
+            OnError::Count => {
+                self.skipped += 1;
+                Ok(())
+            }
+        }
+    }
+
+    fn flush(mut self, data_map: &mut impl Stations<'a>, options: &Options) {
+        self.apply(data_map, options);
+        for (name, record) in self.known_records.into_iter().flatten() {
+            combine(data_map, name, record);
+        }
+        if let Some(bad_lines) = options.bad_lines.as_ref().filter(|_| self.skipped > 0) {
+            bad_lines.fetch_add(self.skipped, Ordering::Relaxed);
+        }
+        if let Some(workers) = &options.workers {
+            workers.add_rows(self.rows, data_map.len());
+        }
+    }
+}
+
+// Parses a single line (without its \n), or says why it isn't a valid
+// measurement
+fn parse_line<'a>(line: &'a [u8], scratch: &mut Scratch<'_, 'a>, data_map: &mut impl Stations<'a>, options: &Options) -> Result<(), Invalid> {
+    let (name, temperature) = split_fields(line, options)?;
+    add_line(name, temperature, scratch, data_map, options)
+}
+
+// Cuts a line (without its \n) into its name and temperature, however
+// the options say its fields go
+fn split_fields<'a>(line: &'a [u8], options: &Options) -> Result<(&'a [u8], &'a [u8]), Invalid> {
+    match (options.columns, options.quoted) {
+        (Some(columns), false) => parser::select_columns(line, options.delimiter, columns),
+        (Some(columns), true) => parser::select_quoted(line, options.delimiter, columns),
+        (None, false) => parser::split_line(line, options.delimiter),
+        (None, true) => parser::split_quoted(line, options.delimiter),
+    }
+}
+
+// Adds the measurement on a line already cut into its name and
+// temperature (see parser.rs)
+#[inline]
+fn add_line<'a>(name: &'a [u8], temperature: &[u8], scratch: &mut Scratch<'_, 'a>, data_map: &mut impl Stations<'a>, options: &Options) -> Result<(), Invalid> {
+    if options.strict && name.is_empty() {
+        return Err(Invalid::EmptyName);
+    }
+
+    let name = match options.max_name_len {
+        Some(max) if name.len() > max => {
+            if !options.truncate_names {
+                return Err(Invalid::LongName(max));
+            }
+            truncate_name(name, max)
+        }
+        _ => name,
+    };
+
+    // A handful of exact names can be checked before hashing anything
+    if let Some(filter) = &options.stations {
+        if filter.exact_only() && !filter.matches(name) {
+            return Ok(());
+        }
+    }
+
+    let temp_num = match options.columns {
+        Some(_) => parser::parse_value(temperature, options.precision, options.strict, &mut scratch.temp)?,
+        None => parser::parse_temperature(temperature, options.precision, options.strict, &mut scratch.temp)?,
+    };
+    check_range(temp_num, options)?;
+
+    // Known names can't be invalid UTF-8, and skip the map
+    if let Some(known) = scratch.known {
+        if let Some(index) = known.index(name) {
+            match &mut scratch.known_records[index] {
+                Some((_, record)) => record.add(temp_num),
+                record => *record = Some((name, new_record(temp_num, options))),
+            }
+            return Ok(());
+        }
+    }
+
+    if options.validate_utf8 && !data_map.contains(name) && std::str::from_utf8(name).is_err() {
+        return Err(Invalid::NotUtf8);
+    }
+
+    if let Some(filter) = &options.stations {
+        if !filter.exact_only() {
+            add_filtered(name, temp_num, filter, data_map, options);
+            return Ok(());
+        }
+    }
+
+    if options.prefetch {
+        scratch.queue(name, temp_num, data_map, options);
+    } else {
+        add(data_map, name, temp_num, options);
+    }
+    Ok(())
+}
+
+// For the aggregations beside the main parser (by time, by several
+// values, by a custom Aggregator): passes every line in chunk (without
+// its \n, but with any \r) to f, the last one even with no \n after it,
+// and bad lines over as options.on_error says
+#[cfg(feature = "native")]
+fn for_each_line(chunk: &[u8], options: &Options, mut f: impl FnMut(&[u8]) -> Result<(), Invalid>) -> Result<(), BadLine> {
+    let mut skipped = 0;
+    let mut skip = |bad_line: BadLine| match options.on_error {
+        OnError::Abort => Err(bad_line),
+        OnError::Skip => Ok(()),
+        OnError::Count => {
+            skipped += 1;
+            Ok(())
+        }
+    };
+
+    let mut lines = parser::Lines::new(chunk, options.delimiter);
+    for line in lines.by_ref() {
+        if let Err(bad_line) = line.and_then(|line| f(&chunk[line.start..line.end]).map_err(|invalid| BadLine(line.start, invalid))) {
+            skip(bad_line)?;
+        }
+    }
+    let consumed = lines.consumed();
+    if consumed < chunk.len() {
+        if let Err(invalid) = f(&chunk[consumed..]) {
+            skip(BadLine(consumed, invalid))?;
+        }
+    }
+
+    if let Some(bad_lines) = options.bad_lines.as_ref().filter(|_| skipped > 0) {
+        bad_lines.fetch_add(skipped, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+// The name a measurement goes to under the options' rules for names, as
+// add_line has them, or None if the station filter rejects it
+#[cfg(feature = "native")]
+fn accept_name<'a>(name: &'a [u8], options: &Options) -> Result<Option<&'a [u8]>, Invalid> {
+    if options.strict && name.is_empty() {
+        return Err(Invalid::EmptyName);
+    }
+    let name = match options.max_name_len {
+        Some(max) if name.len() > max => {
+            if !options.truncate_names {
+                return Err(Invalid::LongName(max));
+            }
+            truncate_name(name, max)
+        }
+        _ => name,
+    };
+    if options.validate_utf8 && std::str::from_utf8(name).is_err() {
+        return Err(Invalid::NotUtf8);
+    }
+    Ok(Some(name).filter(|name| options.stations.as_ref().is_none_or(|filter| filter.matches(name))))
+}
+
+// Fails on a temperature outside options.range
+#[inline]
+fn check_range(temp: i32, options: &Options) -> Result<(), Invalid> {
+    match options.range {
+        Some((min, max)) if !(min..=max).contains(&temp) => Err(Invalid::OutOfRange(min, max, options.precision)),
+        _ => Ok(()),
+    }
+}
+
+// A station's record from its first measurement
+fn new_record(temp: i32, options: &Options) -> Record {
+    let record = if options.histograms { Record::with_histogram(temp) } else { Record::new(temp) };
+    Record { decimals: options.precision, ..record }
+}
+
+// The first max bytes of name, or fewer if that would split a UTF-8
+// character
+fn truncate_name(name: &[u8], max: usize) -> &[u8] {
+    let mut end = max;
+    while end > 0 && name[end] & 0b1100_0000 == 0b1000_0000 {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+// The longest a valid line can be under options.max_name_len: the name,
+// the delimiter, the longest temperature ("-3276.8") and a \r. There's
+// no limit when long names are truncated instead, or skipped, or when
+// the lines have other fields or quotes
+#[cfg(feature = "native")]
+fn max_line_len(options: &Options) -> Option<usize> {
+    let rejected = !options.truncate_names && options.on_error == OnError::Abort && options.columns.is_none() && !options.quoted;
+    options.max_name_len.filter(|_| rejected).map(|max| max + 9)
+}
+
+// Adds a measurement through a pattern filter, which only runs the
+// first time each station is seen (see finish_map)
+fn add_filtered<'a>(name: &'a [u8], temp: i32, filter: &StationFilter, data_map: &mut impl Stations<'a>, options: &Options) {
+    let (record, new) = data_map.record(name, || {
+        if !filter.matches(name) {
+            filtered_out(options)
+        } else {
+            new_record(temp, options)
+        }
+    });
+    if !new && record.count > 0 {
+        record.add(temp);
+    }
+}
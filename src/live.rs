@@ -0,0 +1,107 @@
+// What a dashboard can watch while the workers go: the bytes each has
+// parsed, and the hottest stations seen so far.
+//
+// The hottest stations are only looked for as each block of lines is
+// finished, in the map of the worker that parsed it, so they cost a
+// pass over that map per block and nothing per line. A station only
+// takes the lock if it's hotter than the coolest of the ones kept.
+
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::{Record, Stations};
+
+// How many of the hottest stations are kept
+const HOTTEST: usize = 5;
+
+/// Progress for [`Options::live`](crate::Options::live) to report to
+#[derive(Debug)]
+pub struct Live {
+    // Bytes parsed by each of the pool's threads, by index
+    threads: Vec<AtomicU64>,
+    // Hottest first, by their max in the records' units
+    hottest: Mutex<Vec<(String, i32)>>,
+    // The coolest of them, once there are HOTTEST
+    threshold: AtomicI32,
+}
+
+impl Live {
+    /// For a pool of `threads` workers
+    pub fn new(threads: usize) -> Self {
+        Live {
+            threads: (0..threads).map(|_| AtomicU64::new(0)).collect(),
+            hottest: Mutex::new(Vec::with_capacity(HOTTEST + 1)),
+            threshold: AtomicI32::new(i32::MIN),
+        }
+    }
+
+    /// Bytes parsed by each worker. Work done off the pool (say, by
+    /// the io_uring engine's reader) isn't counted
+    pub fn thread_bytes(&self) -> Vec<u64> {
+        self.threads.iter().map(|bytes| bytes.load(Ordering::Relaxed)).collect()
+    }
+
+    /// The hottest stations so far, by their highest temperature (in
+    /// the records' units), hottest first
+    pub fn hottest(&self) -> Vec<(String, i32)> {
+        self.hottest.lock().unwrap().clone()
+    }
+
+    pub(crate) fn add_bytes(&self, bytes: usize) {
+        #[cfg(feature = "native")]
+        if let Some(thread) = crate::par::current_thread_index().and_then(|index| self.threads.get(index)) {
+            thread.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+        #[cfg(not(feature = "native"))]
+        if let Some(thread) = self.threads.first() {
+            thread.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn observe<'a>(&self, data_map: &impl Stations<'a>) {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        let mut hotter = Vec::new();
+        data_map.for_each(|name, record: &Record| {
+            if record.max > threshold {
+                hotter.push((String::from_utf8_lossy(name).into_owned(), record.max));
+            }
+        });
+        if hotter.is_empty() {
+            return;
+        }
+
+        let mut hottest = self.hottest.lock().unwrap();
+        for (name, max) in hotter {
+            match hottest.iter_mut().find(|(known, _)| *known == name) {
+                Some((_, known)) => *known = (*known).max(max),
+                None => hottest.push((name, max)),
+            }
+            hottest.sort_by_key(|&(_, max)| std::cmp::Reverse(max));
+            hottest.truncate(HOTTEST);
+        }
+        if hottest.len() == HOTTEST {
+            self.threshold.store(hottest[HOTTEST - 1].1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Record, StationKey, StationMap};
+
+    #[test]
+    fn keeps_the_hottest() {
+        let live = Live::new(1);
+        let mut data_map = StationMap::default();
+        for (i, name) in ["a", "b", "c", "d", "e", "f", "g"].iter().enumerate() {
+            data_map.insert(StationKey::from(name.as_bytes()), Record::new(i as i32 * 10));
+        }
+        live.observe(&data_map);
+        data_map.get_mut(&b"a"[..]).unwrap().add(100);
+        live.observe(&data_map);
+
+        let hottest = live.hottest();
+        assert_eq!(hottest.iter().map(|(name, max)| (name.as_str(), *max)).collect::<Vec<_>>(), [("a", 100), ("g", 60), ("f", 50), ("e", 40), ("d", 30)]);
+    }
+}
@@ -0,0 +1,35 @@
+// Diagnostics, on stderr so stdout only ever carries results. -v and -q
+// move the level up and down from the default of info.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match record.level() {
+            // Info is the normal chatter, which needs no label
+            Level::Info => eprintln!("{}", record.args()),
+            level => eprintln!("[{}] {}", level, record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// Installs the logger, at info plus verbose levels minus quiet ones
+pub fn init(verbose: u8, quiet: u8) {
+    let levels = [LevelFilter::Off, LevelFilter::Error, LevelFilter::Warn, LevelFilter::Info, LevelFilter::Debug, LevelFilter::Trace];
+    let level = (3 + verbose as isize - quiet as isize).clamp(0, levels.len() as isize - 1);
+
+    log::set_logger(&StderrLogger).expect("the logger is only set once");
+    log::set_max_level(levels[level as usize]);
+}
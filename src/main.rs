@@ -1,133 +1,2092 @@
+mod collate;
+mod config;
+mod diff;
+mod distributed;
+#[cfg(any(feature = "arrow", feature = "parquet"))]
+mod export;
+#[cfg(feature = "generator")]
 mod generate;
+mod group;
+mod index;
+mod logger;
+mod memory;
+mod metadata;
+mod numbers;
+mod pretty;
+mod query;
+mod selftest;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod split;
+// The generator's own, when it isn't built
+#[cfg(not(feature = "generator"))]
+mod stations;
+mod timeline;
+mod tui;
+mod unit;
+mod values;
 
-use std::io::{Read, Seek};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 
-use rayon::prelude::*;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use indicatif::{HumanBytes, HumanCount, ProgressBar, ProgressStyle};
+use one_billion_row_challenge::{aggregate_files, aggregate_reader, aggregate_sample, chunk, input_size, map_memory, merge_maps, object_url, partial, sketch_file, Columns, Engine, Error, Fixed, Follower, Histogram, HyperLogLog, KnownStations, Live, Merge, NormalizeKey, OnError, OnProgress, Options, Phase, PinCores, Record, Sample, StationFilter, StationKey, StationMap, TimeBucket, Timings, TopBy, WorkerStats, Workers, HASHER_NAME, KEY_NAME};
+use serde::Serialize;
+use unit::{degrees, Unit};
+
+#[cfg(feature = "generator")]
+use generate::stations;
 
 const MEASUREMENTS_FILE: &str = "measurements.txt";
 
-struct Record {
-    min: i16,
-    max: i16,
-    total: i64,
-    count: u64,
+#[derive(Parser)]
+#[command(about = "One Billion Row Challenge aggregator")]
+#[command(after_help = "Exit codes: 0 on success, 2 for invalid arguments, 3 for I/O errors, \
+4 for malformed input, 5 for station names that aren't UTF-8, 1 for anything else")]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Running without a subcommand is the same as `run`
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// Print more diagnostics on stderr (-vv for even more)
+    #[arg(long, short = 'v', action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Print fewer diagnostics on stderr, and no "Time taken" line
+    /// (-qq for no diagnostics at all)
+    #[arg(long, short = 'q', action = clap::ArgAction::Count, global = true)]
+    quiet: u8,
+
+    /// Read defaults for the flags from this TOML file, rather than
+    /// ./brc.toml if there is one
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+}
+
+// Only ever one of these, so the size of the biggest doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+enum Command {
+    /// Aggregate a measurements file and print the per-station results
+    Run(RunArgs),
+    /// Generate a measurements file
+    #[cfg(feature = "generator")]
+    Generate(generate::GenerateArgs),
+    /// Aggregate a measurements file and compare the results against a baseline
+    Verify(VerifyArgs),
+    /// Check that every line of a measurements file is valid, without
+    /// aggregating it, and report the lines that aren't
+    Validate(ValidateArgs),
+    /// Run every engine over the small datasets built in, and check
+    /// the results against theirs exactly
+    SelfTest,
+    /// Aggregate a measurements file repeatedly and report timings
+    Bench(BenchArgs),
+    /// Compare two results files (in any --format but table) station by
+    /// station, and print the stations whose min, mean or max differ
+    Diff(diff::DiffArgs),
+    /// Combine partial results files (from `--format partial`, e.g. one
+    /// per machine that aggregated a shard) and print the results
+    Merge(MergeArgs),
+    /// Aggregate a measurements file a block at a time and keep the
+    /// results beside it (in FILE.1brcidx), for `query-indexed`
+    Index(index::IndexArgs),
+    /// Print the results for an indexed measurements file from its
+    /// index, reading only the blocks --stations are in and the lines
+    /// appended since
+    QueryIndexed(index::QueryIndexedArgs),
+    /// Aggregate a measurements file and run a SQL-like query over the
+    /// results, e.g. `SELECT station, mean WHERE mean > 20 ORDER BY mean DESC LIMIT 10`
+    Query(QueryArgs),
+    /// Aggregate a measurements file and add the results to a SQLite
+    /// database
+    #[cfg(feature = "sqlite")]
+    Export(sqlite::ExportArgs),
+    /// Aggregate a measurements file once and serve the results as JSON
+    /// over HTTP
+    #[cfg(feature = "serve")]
+    Serve(serve::ServeArgs),
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Write the results to this file instead of stdout
+    #[arg(long = "output", short = 'o', env = "BRC_OUTPUT")]
+    output_path: Option<PathBuf>,
+
+    /// Write each station's results (or with --group-by, each group's)
+    /// to a file of its own in this directory, in --format, named after
+    /// the station with anything unsafe in a file name %-escaped
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output_path", "follow"])]
+    split_output: Option<PathBuf>,
+
+    /// Keep watching the file after aggregating it, and print the
+    /// updated results whenever lines are appended
+    #[arg(long)]
+    follow: bool,
+
+    /// How often --follow checks the file for new lines, in seconds
+    #[arg(long, value_name = "SECONDS", default_value = "1", value_parser = parse_interval, requires = "follow")]
+    interval: std::time::Duration,
+
+    /// Coordinate a distributed run: wait for --workers workers to
+    /// connect on this address, share the work out between them and
+    /// print the combined results
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["connect", "follow"])]
+    listen: Option<String>,
+
+    /// Number of workers --listen waits for
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    workers: usize,
+
+    /// With --listen, have the workers send a CRC-32 of every byte range
+    /// they aggregate, and check it against the range as the coordinator
+    /// reads it, failing if a file isn't the same on every machine
+    #[arg(long, requires = "listen")]
+    verify_ranges: bool,
+
+    /// Work for the coordinator listening at this address rather than
+    /// printing any results. Unless --shard is given, the coordinator's
+    /// files have to be at the same paths on this machine
+    #[arg(long, value_name = "ADDR", conflicts_with = "follow")]
+    connect: Option<String>,
+
+    /// With --connect, aggregate this machine's own files and send the
+    /// results, rather than byte ranges of the coordinator's files
+    #[arg(long, requires = "connect")]
+    shard: bool,
+
+    /// Rather than aggregating, check how each file is split into chunks
+    /// (both by reading the file and in memory) and print every chunk
+    /// and anything wrong with them. Fails if anything is
+    #[arg(long, conflicts_with_all = ["follow", "listen", "connect", "emit_partial"])]
+    check_chunks: bool,
+
+    /// Rather than aggregating, estimate how many distinct stations
+    /// there are (to within about 1%, in 16 KiB however many), e.g. to
+    /// see whether the names are as clean as expected. Uncompressed
+    /// files only
+    #[arg(long, conflicts_with_all = ["follow", "listen", "connect", "emit_partial", "check_chunks"])]
+    estimate_cardinality: bool,
+
+    /// Read lines with a unix timestamp (in seconds) after the
+    /// temperature, `name;temperature;timestamp`, and aggregate each
+    /// station by the hour, day or month (UTC) they fall in. Printed as
+    /// lines, csv or json. Uncompressed files and stdin only
+    #[arg(long, value_enum, conflicts_with_all = ["follow", "listen", "connect", "emit_partial", "check_chunks", "estimate_cardinality", "sample", "limit_rows", "checkpoint", "cache"])]
+    time_bucket: Option<TimeBucket>,
+
+    /// Read lines with several values after the name, as
+    /// `name;temp;humidity;pressure`, and aggregate them all: these are
+    /// the values' names, in order. A value can be left empty. Printed
+    /// as lines, csv or json. Uncompressed files and stdin only
+    #[arg(long, value_name = "NAMES", value_delimiter = ',', num_args = 1.., conflicts_with_all = ["follow", "listen", "connect", "emit_partial", "check_chunks", "estimate_cardinality", "time_bucket", "sample", "limit_rows", "checkpoint", "cache", "key_col", "quoted"])]
+    values: Vec<String>,
+
+    /// Print how long each phase of the run took, on stderr
+    #[arg(long, conflicts_with_all = ["follow", "listen", "connect"])]
+    timings: bool,
+
+    /// Print what each worker thread did on stderr: the bytes, rows and
+    /// chunks it got through, the most stations it had in a map, and
+    /// how long it was busy with chunks or idle while others were
+    #[arg(long, conflicts_with_all = ["follow", "listen", "connect"])]
+    worker_report: bool,
+
+    /// Stop after this many seconds and print the results so far,
+    /// marked as partial, with how much of the input they cover. A run
+    /// that's stopped this way still exits with 0
+    #[arg(long, value_name = "SECONDS", value_parser = parse_interval, conflicts_with_all = ["follow", "listen", "connect"])]
+    timeout: Option<std::time::Duration>,
+
+    /// Also write the results to this file as partial results
+    /// (`--format partial`), for the `merge` subcommand
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["follow", "connect"])]
+    emit_partial: Option<PathBuf>,
+
+    /// Write statistics about the run (times, rows, bytes, threads,
+    /// engine) to this file as JSON
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["follow", "listen", "connect"])]
+    stats_json: Option<PathBuf>,
+
+    /// Hold the input and output to every rule of a challenge, so this
+    /// can stand in for a submission in its evaluation scripts. `1brc`
+    /// means the official format (and nothing else on stdout), --strict,
+    /// --validate-utf8, names of at most 100 bytes and at most 10,000
+    /// stations
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = [
+            "format", "aggregates", "stats", "histogram", "top", "sort", "unit", "with_count", "precision", "delimiter", "stations",
+            "lossy_utf8", "max_name_len", "truncate_names", "on_error", "follow", "listen", "connect", "check_chunks",
+            "sample", "limit_rows", "estimate_cardinality", "time_bucket", "group_by", "metadata", "key_col", "value_col", "quoted", "normalize_keys", "values",
+        ],
+    )]
+    compat: Option<Compat>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Compat {
+    /// The One Billion Row Challenge
+    #[value(name = "1brc")]
+    OneBrc,
+}
+
+// The most stations the challenge's input has
+const MAX_STATIONS: usize = 10_000;
+// And its temperatures' bounds
+const CHALLENGE_RANGE: (f64, f64) = (-99.9, 99.9);
+
+// What --stats-json writes. Times are in seconds
+#[derive(Serialize)]
+struct RunStats {
+    wall_time: f64,
+    phases: PhaseTimes,
+    rows: u64,
+    stations: usize,
+    // Of the input files, as stored. stdin isn't counted
+    bytes: u64,
+    threads: usize,
+    engine: String,
+    hasher: &'static str,
+    // The resident set at its largest, where it's known
+    peak_memory: Option<u64>,
+    // About how much of it the combined station map took
+    map_memory: usize,
+}
+
+#[derive(Serialize)]
+struct PhaseTimes {
+    chunking: f64,
+    io: f64,
+    parsing: f64,
+    merging: f64,
+    reader_stall: f64,
+    parser_stall: f64,
+    sorting: f64,
+    formatting: f64,
+    output: f64,
+}
+
+// Options shared by every subcommand that aggregates a measurements file
+#[derive(Args)]
+struct InputArgs {
+    /// Paths (or glob patterns) of the measurements files, or - to read
+    /// from stdin. Multiple files are aggregated together. With the
+    /// object-store feature, s3://, gs://, az:// and http(s):// URLs work
+    /// too
+    #[arg(default_value = MEASUREMENTS_FILE, env = "BRC_INPUT")]
+    paths: Vec<PathBuf>,
+
+    /// How the file is read
+    #[arg(long, value_enum, default_value_t = Engine::Auto, env = "BRC_ENGINE")]
+    engine: Engine,
+
+    /// Character between the station name and the temperature. Use
+    /// `tab` (or `\t`) for tab-separated files
+    #[arg(long, default_value = ";", value_parser = parse_delimiter, env = "BRC_DELIMITER")]
+    delimiter: u8,
+
+    /// For other delimited files: the field (counting from 1) to group
+    /// by, in place of the station name. A header line is a bad line,
+    /// so it needs --on-error skip
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), requires = "value_col")]
+    key_col: Option<usize>,
+
+    /// For other delimited files: the field (counting from 1) of the
+    /// number to aggregate, in place of the temperature. Whole numbers
+    /// are taken too
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), requires = "key_col")]
+    value_col: Option<usize>,
+
+    /// Let fields be quoted, as in CSV, so names like "Foo;Bar" keep
+    /// their delimiter. Slower, and a quoted field can't span lines
+    #[arg(long)]
+    quoted: bool,
+
+    /// Take these differences out of the station names, so the same
+    /// station written in different ways is aggregated as one: trim
+    /// (whitespace around the name), lowercase and nfc (accents as one
+    /// character), e.g. trim,lowercase. --stations and
+    /// --known-stations still match the names as they are in the file
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "NORMALIZATIONS")]
+    normalize_keys: Vec<NormalizeKey>,
+
+    /// Fail as soon as there are more than N distinct stations, rather
+    /// than fill memory with them when the input is keyed by something
+    /// else (a timestamp, say)
+    #[arg(long, value_name = "N")]
+    max_map_entries: Option<usize>,
+
+    /// Bypass the page cache with unbuffered reads, for cold-cache
+    /// benchmarking (Linux and Windows, elsewhere this falls back to the
+    /// buffered engine; overrides --engine)
+    #[arg(long)]
+    direct: bool,
+
+    /// Give the kernel madvise hints about how the mapped file is read
+    #[arg(long)]
+    madvise: bool,
+
+    /// Ask for transparent huge pages on the mapped file (Linux only)
+    #[arg(long)]
+    huge_pages: bool,
+
+    /// Size of the blocks each worker reads with the buffered and
+    /// io_uring engines and --direct (e.g. 1M or 16M). By default it's
+    /// picked for the device the file is on: NVMe, SSD, spinning disk
+    /// or network storage
+    #[arg(long, value_name = "BYTES", value_parser = parse_size)]
+    read_size: Option<usize>,
+
+    /// Read with this many threads that only read, handing blocks of
+    /// --read-size over a bounded queue to --parse-threads threads that
+    /// only parse, rather than with --engine. --timings then has how long
+    /// each side waited on the other. Uncompressed files only
+    #[arg(long, value_name = "N", conflicts_with_all = ["direct", "max_memory"])]
+    io_threads: Option<usize>,
+
+    /// Threads that parse what --io-threads read (by default, all but
+    /// the readers)
+    #[arg(long, value_name = "N", conflicts_with_all = ["direct", "max_memory"])]
+    parse_threads: Option<usize>,
+
+    /// Size of the chunks the file is cut into for the workers to share
+    /// (e.g. 8M), or auto to pick it from the file's size, the number of
+    /// threads and how fast the start of the file is read and parsed.
+    /// By default about 32M, and at least a chunk per thread
+    #[arg(long, value_name = "BYTES|auto", value_parser = parse_chunk_size)]
+    chunk_size: Option<chunk::ChunkSize>,
+
+    /// Show progress, throughput and an ETA on stderr while aggregating
+    #[arg(long)]
+    progress: bool,
+
+    /// Show a dashboard on stderr while aggregating: each worker's
+    /// progress, the throughput, the hottest stations so far and the
+    /// memory in use
+    #[arg(long, conflicts_with = "progress")]
+    tui: bool,
+
+    /// Number of worker threads. Defaults to one per CPU
+    #[arg(long, short = 't', env = "BRC_THREADS")]
+    threads: Option<usize>,
+
+    /// On a multi-socket machine, pin a pool of workers to each NUMA
+    /// node and give each node its own share of the file (Linux only)
+    #[arg(long, conflicts_with = "threads")]
+    numa: bool,
+
+    /// Pin each worker to a CPU of its own: any CPU with `all` (the
+    /// default), or one per physical core with `physical`, which skips
+    /// SMT siblings (Linux only)
+    #[arg(long, value_name = "CPUS", num_args = 0..=1, require_equals = true, default_missing_value = "all", conflicts_with = "numa")]
+    pin_cores: Option<PinCores>,
+
+    /// Stream the file through a fixed set of read buffers adding up to
+    /// about this much memory (e.g. 256M or 1G), rather than mapping it.
+    /// For machines with much less RAM than the file. Overrides --engine
+    #[arg(long, value_name = "BYTES", value_parser = parse_size, conflicts_with_all = ["direct", "numa", "checkpoint"])]
+    max_memory: Option<usize>,
+
+    /// Only aggregate stations matching this name, glob (`St*`) or
+    /// regex between slashes (`/^San /`). Can be given more than once
+    #[arg(long = "station", value_name = "PATTERN")]
+    stations: Vec<String>,
+
+    /// Save each chunk's partial results in this directory as it's
+    /// finished, and reuse the ones already there, so a run that's
+    /// interrupted can be resumed. Uncompressed files only
+    #[arg(long, value_name = "DIR")]
+    checkpoint: Option<PathBuf>,
+
+    /// Keep each file's results, and reuse them while the file is
+    /// unchanged (by size, modification time and a fingerprint of its
+    /// contents), e.g. when trying out output formats. `=DIR` is where;
+    /// without it, 1brc in the user's cache directory
+    #[arg(long, value_name = "DIR", require_equals = true)]
+    cache: Option<Option<PathBuf>>,
+
+    /// Aggregate only about this much of each file (e.g. 1% or 0.01),
+    /// in blocks of lines picked at random, for a quick estimate. The
+    /// counts are the sample's. Uncompressed files only
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction, conflicts_with_all = ["limit_rows", "checkpoint", "cache"])]
+    sample: Option<f64>,
+
+    /// Aggregate only the first N rows of each file. Uncompressed files
+    /// only
+    #[arg(long, value_name = "N", conflicts_with_all = ["checkpoint", "cache"])]
+    limit_rows: Option<u64>,
+
+    /// Fail as soon as a station name that isn't valid UTF-8 is parsed,
+    /// with its line number, rather than when the results are printed
+    #[arg(long, conflicts_with = "lossy_utf8")]
+    validate_utf8: bool,
+
+    /// Update the stations a batch of lines at a time, prefetching the
+    /// table slots of each batch first to hide cache misses. Helps with
+    /// many stations; time it with bench
+    #[arg(long)]
+    prefetch: bool,
+
+    /// With the mmap engine, intern station names to dense ids in each
+    /// worker and keep the records in an array by id
+    #[arg(long)]
+    interned: bool,
+
+    /// Print station names that aren't valid UTF-8 with the bad bytes
+    /// replaced by U+FFFD, rather than failing
+    #[arg(long)]
+    lossy_utf8: bool,
+
+    /// Fail on station names longer than this many bytes (the challenge
+    /// allows 100), and on lines too long to hold such a name rather
+    /// than reading on to their end
+    #[arg(long, value_name = "BYTES")]
+    max_name_len: Option<usize>,
+
+    /// Fail on temperatures outside MIN..MAX (as -99.9..99.9, the
+    /// challenge's bounds, which --compat 1brc checks by default), rather than
+    /// aggregating them. --on-error says what to do with them instead
+    #[arg(long, value_name = "MIN..MAX", value_parser = parse_range, allow_hyphen_values = true)]
+    assert_range: Option<(f64, f64)>,
+
+    /// Cut names longer than --max-name-len down to it instead of failing
+    #[arg(long, requires = "max_name_len")]
+    truncate_names: bool,
+
+    /// Fail on temperatures in any but the challenge's shapes (-99.9 to
+    /// 99.9, to exactly one decimal) and on empty station names, rather
+    /// than parsing whatever can be
+    #[arg(long)]
+    strict: bool,
+
+    /// What a line that can't be parsed does: stop with its line number
+    /// and offset (abort), leave it out (skip), or leave it out and say
+    /// how many were (count)
+    #[arg(long, value_enum, default_value_t = OnError::Abort)]
+    on_error: OnError,
+
+    /// Number of decimals the temperatures are given to: 1 as in the
+    /// challenge, or up to 3. Results are printed to as many
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=3))]
+    precision: u8,
+
+    /// Keep the records of these stations in a flat array behind a
+    /// perfect hash, which is faster than the map when the input's
+    /// stations are known in advance. `=FILE` lists them one per line,
+    /// in the generator's --stations format; without it, the official 413
+    #[arg(long, value_name = "FILE", require_equals = true)]
+    known_stations: Option<Option<PathBuf>>,
+
+    /// How the workers' results are combined: pairwise as they finish
+    /// (tree), or with each thread merging its own shard of the stations
+    /// (sharded), which helps with many stations and threads
+    #[arg(long, value_enum, default_value_t = Merge::Tree)]
+    merge: Merge,
+}
+
+// Options controlling how the aggregated results are printed
+#[derive(Args)]
+struct OutputArgs {
+    /// Format of the printed results
+    #[arg(long, value_enum, default_value_t = Format::Lines, env = "BRC_FORMAT")]
+    format: Format,
+
+    /// Field delimiter used by `--format csv`
+    #[arg(long, default_value_t = ',')]
+    csv_delimiter: char,
+
+    /// The statistics to print for each station, in this order: any
+    /// of min, mean, max, sum, count and range
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = MIN_MEAN_MAX)]
+    aggregates: Vec<Aggregate>,
+
+    /// Extra per-station statistics to print after --aggregates:
+    /// `median`, `pN` for the Nth percentile, `stddev` or `variance`,
+    /// e.g. `median,p95,p99`
+    #[arg(long, value_delimiter = ',', value_parser = parse_stat)]
+    stats: Vec<Stat>,
+
+    /// Print a histogram of each station's temperatures, in buckets
+    /// this many degrees wide (e.g. 5 or 0.5), instead of the summary
+    #[arg(long, value_name = "BUCKET_WIDTH", value_parser = parse_bucket_width)]
+    histogram: Option<i16>,
+
+    /// Only print the first N stations by --by, rather than every
+    /// station in alphabetical order
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+
+    /// What --top ranks stations by
+    #[arg(long, value_enum, default_value_t = TopBy::Mean, requires = "top")]
+    by: TopBy,
+
+    #[command(flatten)]
+    numbers: numbers::NumberArgs,
+
+    /// When `--format table` is coloured
+    #[arg(long, value_enum, default_value_t = pretty::Color::Auto)]
+    color: pretty::Color,
+
+    /// Print each station's number of measurements after --aggregates,
+    /// as the json, table, arrow and parquet formats always do
+    #[arg(long)]
+    with_count: bool,
+
+    /// What the stations are printed in order of, rather than their
+    /// names' bytes (or, with --top, its ranking). Ties stay in that
+    /// order
+    #[arg(long, value_enum, value_name = "KEY")]
+    sort: Option<SortBy>,
+
+    /// Sort from the largest (or last name) down
+    #[arg(long, requires = "sort")]
+    desc: bool,
+
+    /// How names are ordered: by their bytes (bytes), as the challenge
+    /// does, or as people would look for them (unicode), so Zürich
+    /// goes between Zurich and Zwolle. Ties in --sort and --top stay
+    /// in this order
+    #[arg(long, value_enum, default_value_t = collate::Collation::Bytes)]
+    collation: collate::Collation,
+
+    /// Unit to print temperatures in: degrees Celsius as measured (c),
+    /// degrees Fahrenheit (f) or kelvin (k)
+    #[arg(long, value_enum, default_value_t = Unit::C)]
+    unit: Unit,
+
+    /// Roll the stations up into groups, and print each group's results
+    /// rather than each station's: `prefix:N` groups them by the first
+    /// N characters of their names, `word` by the first word, and
+    /// `regex:PATTERN` by the pattern's first capture group (or whole
+    /// match), leaving out the names it doesn't match. Anything else is
+    /// a column of the --metadata file to group them by
+    #[arg(long, value_name = "HOW", value_parser = group::parse)]
+    group_by: Option<group::GroupBy>,
+
+    /// With --group-by, print the stations as well, with a `*` after
+    /// each group's name
+    #[arg(long, requires = "group_by")]
+    with_stations: bool,
+
+    /// A CSV file of what's known about each station, e.g.
+    /// `station,country,region`: the station's name first, then any
+    /// columns, named by the header. They're printed after the results
+    /// in the lines, csv, json and table formats, and can be grouped by
+    #[arg(long, value_name = "PATH", value_parser = metadata::read)]
+    metadata: Option<Arc<metadata::Metadata>>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SortBy {
+    /// The name's bytes, as the challenge orders them
+    Name,
+    /// The name, as people would look for it: ignoring case and accents
+    /// (so Zürich goes between Zurich and Zwolle), then by them
+    Collated,
+    Min,
+    Mean,
+    Max,
+    /// The number of measurements
+    Count,
 }
 
-impl Record {
-    #[inline]
-    fn new(temp: i16) -> Self {
-        Self {
-            min: temp,
-            max: temp,
-            total: temp as i64,
-            count: 1,
+fn parse_interval(seconds: &str) -> Result<std::time::Duration, String> {
+    seconds.parse::<f64>().ok()
+        .and_then(|seconds| std::time::Duration::try_from_secs_f64(seconds).ok())
+        .filter(|interval| !interval.is_zero())
+        .ok_or_else(|| format!("invalid interval {:?} (expected a positive number of seconds)", seconds))
+}
+
+// Parses a number of bytes, with an optional K, M or G (binary) suffix
+fn parse_size(size: &str) -> Result<usize, String> {
+    let (digits, unit) = match size.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        s if s.ends_with('K') => (s[..s.len() - 1].to_string(), 1 << 10),
+        s if s.ends_with('M') => (s[..s.len() - 1].to_string(), 1 << 20),
+        s if s.ends_with('G') => (s[..s.len() - 1].to_string(), 1 << 30),
+        s => (s.to_string(), 1),
+    };
+    digits.trim().parse::<usize>().ok()
+        .and_then(|n| n.checked_mul(unit))
+        .filter(|&bytes| bytes > 0)
+        .ok_or_else(|| format!("invalid size {:?} (expected a number of bytes, optionally with a K, M or G suffix)", size))
+}
+
+// auto, or a size as parse_size takes them
+fn parse_chunk_size(size: &str) -> Result<chunk::ChunkSize, String> {
+    match size {
+        "auto" => Ok(chunk::ChunkSize::Auto),
+        size => parse_size(size).map(|bytes| chunk::ChunkSize::Bytes(bytes as u64)),
+    }
+}
+
+// Parses MIN..MAX, both inclusive
+fn parse_range(range: &str) -> Result<(f64, f64), String> {
+    range.split_once("..")
+        .and_then(|(min, max)| Some((min.trim().parse::<f64>().ok()?, max.trim().parse::<f64>().ok()?)))
+        .filter(|(min, max)| min <= max)
+        .ok_or_else(|| format!("invalid range {:?} (expected MIN..MAX, e.g. -99.9..99.9)", range))
+}
+
+// A range of temperatures in the units of Options::precision
+fn in_units((min, max): (f64, f64), precision: u8) -> (i32, i32) {
+    let scale = 10f64.powi(precision as i32);
+    ((min * scale).round() as i32, (max * scale).round() as i32)
+}
+
+// Parses a fraction, as a percentage (1%) or not (0.01)
+fn parse_fraction(fraction: &str) -> Result<f64, String> {
+    let value = match fraction.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|percent| percent / 100.),
+        None => fraction.parse::<f64>(),
+    };
+    value.ok()
+        .filter(|value| *value > 0. && *value <= 1.)
+        .ok_or_else(|| format!("invalid fraction {:?} (expected e.g. 1% or 0.01, up to 100%)", fraction))
+}
+
+fn parse_delimiter(delimiter: &str) -> Result<u8, String> {
+    match delimiter {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if delimiter.len() == 1 && !matches!(delimiter.as_bytes()[0], b'0'..=b'9' | b'-' | b'.' | b'\n' | b'\r') => {
+            Ok(delimiter.as_bytes()[0])
         }
+        _ => Err("expected a single ASCII character that can't appear in a temperature".to_string()),
     }
-    
-    #[inline]
-    fn add(&mut self, temp: i16) {
-        self.min = self.min.min(temp);
-        self.max = self.max.max(temp);
-        self.total += temp as i64;
-        self.count += 1;
+}
+
+// Parses a bucket width in degrees to tenths
+fn parse_bucket_width(width: &str) -> Result<i16, String> {
+    let tenths = width.parse::<f64>().map(|width| width * 10.).ok()
+        .filter(|tenths| (tenths - tenths.round()).abs() < 1e-6)
+        .map(f64::round)
+        .filter(|tenths| (1. ..=Histogram::MAX as f64 * 2. + 1.).contains(tenths));
+    match tenths {
+        Some(tenths) => Ok(tenths as i16),
+        None => Err("expected a width of 0.1 to 199.9 degrees, with at most one decimal".to_string()),
     }
+}
 
-    fn mean(&self) -> f64 {
-        (self.total as f64 / self.count as f64) / 10.
+// An extra statistic for --stats
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stat {
+    Median,
+    Percentile(f64),
+    StdDev,
+    Variance,
+}
+
+impl Stat {
+    fn name(&self) -> String {
+        match self {
+            Stat::Median => "median".to_string(),
+            Stat::Percentile(p) => format!("p{}", p),
+            Stat::StdDev => "stddev".to_string(),
+            Stat::Variance => "variance".to_string(),
+        }
+    }
+
+    fn needs_histogram(&self) -> bool {
+        matches!(self, Stat::Median | Stat::Percentile(_))
     }
 
-    fn min(&self) -> f64 {
-        self.min as f64 / 10.
+    // The statistic for record in unit, rounded half up to the record's
+    // decimals. The record has a histogram if needs_histogram says it
+    // should
+    fn value(&self, record: &Record, unit: Unit) -> f64 {
+        let percentile = |p| {
+            let tenths = record.percentile(p).expect("record has no histogram");
+            degrees(unit.convert(tenths.into(), 1, 1))
+        };
+
+        match *self {
+            Stat::Median => percentile(50.),
+            Stat::Percentile(p) => percentile(p),
+            Stat::StdDev => record.round(record.stddev() * unit.degree()),
+            Stat::Variance => record.round(record.variance() * unit.degree().powi(2)),
+        }
     }
+}
 
-    fn max(&self) -> f64 {
-        self.max as f64 / 10.
+fn parse_stat(stat: &str) -> Result<Stat, String> {
+    match stat {
+        "median" => return Ok(Stat::Median),
+        "stddev" => return Ok(Stat::StdDev),
+        "variance" => return Ok(Stat::Variance),
+        _ => {}
     }
 
-    #[inline]
-    fn combine(&mut self, other: &Self) {
-        self.min = self.min.min(other.min);
-        self.max = self.max.max(other.max);
-        self.total += other.total;
-        self.count += other.count;
+    let percentile = stat.strip_prefix('p')
+        .and_then(|p| p.parse::<f64>().ok())
+        .filter(|p| *p > 0. && *p <= 100.);
+    match percentile {
+        Some(p) => Ok(Stat::Percentile(p)),
+        None => Err(format!("unknown statistic {:?} (expected median, pN with 0 < N <= 100, stddev or variance)", stat)),
     }
 }
 
+// A column of the results for --aggregates. Every one is read off the
+// station's Record, the Aggregator every engine finishes with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Aggregate {
+    /// The lowest temperature
+    Min,
+    /// The mean temperature
+    Mean,
+    /// The highest temperature
+    Max,
+    /// Every temperature added up
+    Sum,
+    /// The number of measurements
+    Count,
+    /// How far the max is above the min
+    Range,
+}
+
+// What --aggregates has when it isn't given
+const MIN_MEAN_MAX: [Aggregate; 3] = [Aggregate::Min, Aggregate::Mean, Aggregate::Max];
+
+impl Aggregate {
+    fn name(self) -> &'static str {
+        match self {
+            Aggregate::Min => "min",
+            Aggregate::Mean => "mean",
+            Aggregate::Max => "max",
+            Aggregate::Sum => "sum",
+            Aggregate::Count => "count",
+            Aggregate::Range => "range",
+        }
+    }
+
+    fn value(self, record: &Record, unit: Unit) -> AggregateValue {
+        let temperature = match self {
+            Aggregate::Min => unit.min(record),
+            Aggregate::Mean => unit.mean(record),
+            Aggregate::Max => unit.max(record),
+            Aggregate::Sum => unit.sum(record),
+            Aggregate::Range => unit.range(record),
+            Aggregate::Count => return AggregateValue::Count(record.count),
+        };
+        AggregateValue::Temperature(temperature)
+    }
+}
+
+// The aggregates --format prints: --aggregates, and then the count if
+// count says it's wanted and it isn't one of them already
+fn aggregates(output: &OutputArgs, count: bool) -> Vec<Aggregate> {
+    let mut aggregates = output.aggregates.clone();
+    if count && !aggregates.contains(&Aggregate::Count) {
+        aggregates.push(Aggregate::Count);
+    }
+    aggregates
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AggregateValue {
+    Temperature(Fixed),
+    Count(u64),
+}
+
+impl AggregateValue {
+    // As the lines and csv formats print numbers, without trailing zeros
+    fn plain(self) -> String {
+        match self {
+            AggregateValue::Temperature(temperature) => degrees(temperature).to_string(),
+            AggregateValue::Count(count) => count.to_string(),
+        }
+    }
+}
+
+// To every decimal, as the official format and tables print them
+impl std::fmt::Display for AggregateValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AggregateValue::Temperature(temperature) => temperature.fmt(f),
+            AggregateValue::Count(count) => count.fmt(f),
+        }
+    }
+}
+
+impl Serialize for AggregateValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            AggregateValue::Temperature(temperature) => serializer.serialize_f64(degrees(temperature)),
+            AggregateValue::Count(count) => serializer.serialize_u64(count),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// One `name;min;mean;max` line per station
+    Lines,
+    /// The reference implementation's `{name=min/mean/max, ...}` line
+    Official,
+    /// A JSON array with one object per station
+    Json,
+    /// CSV with a `station,min,mean,max` header row
+    Csv,
+    /// An aligned table for reading in a terminal, with every station
+    /// put together at the bottom
+    Table,
+    /// An Arrow IPC stream of a station, min, mean, max and count table
+    #[cfg(feature = "arrow")]
+    Arrow,
+    /// A Parquet file with station, min, mean, max and count columns
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// Partial results, for the `merge` subcommand to combine with
+    /// others. Histograms are only kept with --histogram or a
+    /// percentile in --stats
+    Partial,
+}
+
+impl Format {
+    // For files of results in this format
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Lines | Format::Official | Format::Table => "txt",
+            Format::Json => "json",
+            Format::Csv => "csv",
+            #[cfg(feature = "arrow")]
+            Format::Arrow => "arrow",
+            #[cfg(feature = "parquet")]
+            Format::Parquet => "parquet",
+            Format::Partial => "partial",
+        }
+    }
+
+    fn is_binary(self) -> bool {
+        match self {
+            #[cfg(feature = "arrow")]
+            Format::Arrow => true,
+            #[cfg(feature = "parquet")]
+            Format::Parquet => true,
+            Format::Partial => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Expected output to compare against, in either the lines
+    /// or the official format
+    #[arg(long, required_unless_present = "station_table")]
+    baseline: Option<PathBuf>,
+
+    /// Check that every station's mean is near the mean the generator
+    /// draws its temperatures around: those in `=CSV`, in the
+    /// generator's --stations format (as the official
+    /// weather_stations.csv is), or without it the official 413.
+    /// Stations the table hasn't got are passed over
+    #[arg(long, value_name = "CSV", require_equals = true)]
+    station_table: Option<Option<PathBuf>>,
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    /// The measurements files
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Character between the station name and the temperature
+    #[arg(long, default_value = ";", value_parser = parse_delimiter, env = "BRC_DELIMITER")]
+    delimiter: u8,
+
+    /// Number of decimals the temperatures should be given to
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=3))]
+    precision: u8,
+
+    /// Longest a station name can be, in bytes
+    #[arg(long, value_name = "BYTES", default_value_t = 100)]
+    max_name_len: usize,
+
+    /// Take any temperature and name the aggregator can parse, rather
+    /// than only the challenge's shapes (-99.9 to 99.9, to exactly one
+    /// decimal) and non-empty names
+    #[arg(long)]
+    lenient: bool,
+
+    /// Count temperatures outside MIN..MAX as invalid too, as with
+    /// --lenient, which otherwise takes any
+    #[arg(long, value_name = "MIN..MAX", value_parser = parse_range, allow_hyphen_values = true)]
+    assert_range: Option<(f64, f64)>,
+
+    /// Number of invalid lines to print for each file. The rest are
+    /// only counted
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    limit: usize,
+
+    /// Number of worker threads. Defaults to one per CPU
+    #[arg(long, short = 't', env = "BRC_THREADS")]
+    threads: Option<usize>,
+}
+
+#[derive(Args)]
+struct MergeArgs {
+    /// The partial results files
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Write the results to this file instead of stdout
+    #[arg(long = "output", short = 'o', env = "BRC_OUTPUT")]
+    output_path: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct QueryArgs {
+    /// The query: SELECT columns [WHERE conditions] [ORDER BY column
+    /// [ASC|DESC]] [LIMIT n]. Columns are station, min, mean, max,
+    /// count, stddev and variance, and * is station, min, mean, max and
+    /// count
+    query: String,
+
+    #[command(flatten)]
+    input: InputArgs,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Number of times to run the aggregation
+    #[arg(long, default_value_t = 5)]
+    iterations: u32,
+
+    /// Runs to do and throw away first, to warm the page cache
+    #[arg(long, default_value_t = 1)]
+    warmup: u32,
+
+    /// Engines to compare, one after another (defaults to --engine)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    engines: Vec<Engine>,
+
+    /// Before timing anything, run every one of --engines once and fail
+    /// unless they all give exactly the same results
+    #[arg(long, requires = "engines")]
+    cross_check: bool,
+
+    /// Drop the files from the page cache before every iteration
+    /// (with posix_fadvise, so Linux only), to time reading them from
+    /// storage rather than from memory
+    #[arg(long)]
+    cold: bool,
+}
+
 fn main() {
+    let args = match config::args(std::env::args_os().collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let cli = Cli::parse_from(args);
+    logger::init(cli.verbose, cli.quiet);
+
+    let result = match cli.command.unwrap_or(Command::Run(cli.run)) {
+        Command::Run(args) => run(args),
+        #[cfg(feature = "generator")]
+        Command::Generate(args) => generate(args),
+        Command::Verify(args) => verify(args),
+        Command::Validate(args) => validate(args),
+        Command::SelfTest => selftest::self_test(),
+        Command::Bench(args) => bench(args),
+        Command::Diff(args) => diff::diff(args),
+        Command::Merge(args) => merge(args),
+        Command::Index(args) => index::index(args),
+        Command::QueryIndexed(args) => index::query_indexed(args),
+        Command::Query(args) => run_query(args),
+        #[cfg(feature = "sqlite")]
+        Command::Export(args) => sqlite::export(args),
+        #[cfg(feature = "serve")]
+        Command::Serve(args) => serve::serve(args),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn run(mut args: RunArgs) -> Result<(), Error> {
+    args.output.color = args.output.color.resolve(args.output_path.is_none());
+    if let Some(Compat::OneBrc) = args.compat {
+        args.output.format = Format::Official;
+        args.input.strict = true;
+        args.input.validate_utf8 = true;
+        args.input.max_name_len = Some(100);
+        args.input.assert_range.get_or_insert(CHALLENGE_RANGE);
+    }
+    if let Some(connect) = &args.connect {
+        return distributed::work(connect, args.shard, &args.input);
+    }
+    if args.check_chunks {
+        return check_chunks(&args.input);
+    }
+    if args.estimate_cardinality {
+        return estimate_cardinality(&args.input);
+    }
+    if let Some(bucket) = args.time_bucket {
+        return timeline::run(&args, bucket);
+    }
+    if !args.values.is_empty() {
+        return values::run(&args, &args.values);
+    }
+
     let start_time = std::time::Instant::now();
-    // This file reads in 1 billion rows of data from
-    // MEASUREMENTS_FILE.
-    // The file is a "csv" file, each line being name;temp
-    // name is a string and temp is a float with one decimal
 
-    // Stream in the file as bytes, not all at once
-    let file = std::fs::File::open(MEASUREMENTS_FILE);
+    let histograms = args.output.histogram.is_some() || args.output.stats.iter().any(Stat::needs_histogram);
+
+    let timings = (args.timings || args.stats_json.is_some()).then(|| Arc::new(Timings::default()));
+    let workers = args.worker_report.then(|| Arc::new(Workers::new(args.input.threads.unwrap_or_else(default_threads))));
+    let mut follower = None;
+    let (mut interrupted, mut timed_out) = (false, false);
+    let data = if let Some(listen) = &args.listen {
+        distributed::coordinate(listen, args.workers, &args.input, histograms, args.verify_ranges)?
+    } else if args.follow {
+        let follower = follower.insert(follow(&args.input, histograms)?);
+        follower.stations()
+    } else {
+        // The bytes done out of the total, for saying how far an
+        // interrupted run got
+        let done = Arc::new([AtomicU64::new(0), AtomicU64::new(0)]);
+        let seen = done.clone();
+        let on_progress = OnProgress::new(move |bytes, total| {
+            seen[0].store(bytes, Ordering::Relaxed);
+            seen[1].store(total, Ordering::Relaxed);
+        });
+        let cancel = Arc::new(AtomicBool::new(false));
+        cancel_on_interrupt(&cancel);
+        if let Some(timeout) = args.timeout {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(timeout);
+                cancel.store(true, Ordering::Relaxed);
+            });
+        }
+        let options = Options {
+            timings: timings.clone(),
+            workers: workers.clone(),
+            on_progress: Some(on_progress),
+            cancel: Some(cancel),
+            ..options(&args.input, histograms)?
+        };
+        match aggregate_with(&args.input, &options) {
+            Err(Error::Cancelled { partial }) => {
+                let [bytes, total] = [0, 1].map(|i| done[i].load(Ordering::Relaxed));
+                let got = match total {
+                    0 => format!("{} of the input", HumanBytes(bytes)),
+                    _ => format!("{:.1}% of the input ({} of {})", bytes as f64 / total as f64 * 100., HumanBytes(bytes), HumanBytes(total)),
+                };
+                match args.timeout.filter(|&timeout| start_time.elapsed() >= timeout) {
+                    Some(timeout) => {
+                        timed_out = true;
+                        eprintln!("Stopped at the {:?} timeout after {}, so these results are PARTIAL", timeout, got);
+                    }
+                    None => {
+                        interrupted = true;
+                        eprintln!("Interrupted after {}, so these results are PARTIAL", got);
+                    }
+                }
+                partial
+            }
+            result => result?,
+        }
+    };
+
+    if args.output.format.is_binary() && args.output_path.is_none() && args.split_output.is_none() && std::io::stdout().is_terminal() {
+        return Err("refusing to write binary results to a terminal, use -o or redirect stdout".into());
+    }
+    if args.compat.is_some() && data.len() > MAX_STATIONS {
+        return Err(format!("found {} stations, and the challenge has at most {}", data.len(), MAX_STATIONS).into());
+    }
+
+    // Partial results saved for merging would pass for the whole input's
+    if let (Some(partial_path), false) = (&args.emit_partial, interrupted || timed_out) {
+        write_atomically(partial_path, &partial::encode(&data))
+            .map_err(|source| Error::Io { path: Some(partial_path.clone()), source })?;
+    }
+
+    let map_memory = map_memory(&data);
+    let sort_start = std::time::Instant::now();
+    let data = sort_stations(data);
+    let (rows, stations) = (data.iter().map(|(_, value)| value.count).sum::<u64>(), data.len());
+    let format_start = std::time::Instant::now();
+    let output_start = match &args.split_output {
+        // Each file is formatted as it's written, so that's all output
+        Some(dir) => {
+            let files = split::write(dir, data, &args.output, args.input.threads.unwrap_or_else(default_threads))?;
+            log::info!("Wrote {} files to {}", HumanCount(files as u64), dir.display());
+            format_start
+        }
+        None => {
+            let results = format_results(data, &args.output)?;
+            let output_start = std::time::Instant::now();
+            if !write_results(&results, &args)? {
+                return Ok(());
+            }
+            output_start
+        }
+    };
+    let output_end = std::time::Instant::now();
+
+    let wall_time = start_time.elapsed();
+    log::info!("Time taken: {:?} (hasher: {})", wall_time, HASHER_NAME);
+
+    // Of the input files as stored, so not stdin, and compressed files
+    // by their compressed size. An interrupted run didn't get through
+    // them all, so has no rate to speak of
+    let (paths, stdin) = input_paths(&args.input)?;
+    let bytes = paths.iter().map(|path| input_size(path)).sum::<Result<u64, Error>>()?;
+    if !args.follow && !interrupted && !timed_out {
+        let seconds = wall_time.as_secs_f64();
+        let mut summary = format!("{} rows of {} stations", HumanCount(rows), HumanCount(stations as u64));
+        if !stdin {
+            write!(summary, " from {}", HumanBytes(bytes)).unwrap();
+        }
+        write!(summary, ": {} rows/s", HumanCount((rows as f64 / seconds) as u64)).unwrap();
+        if !stdin {
+            write!(summary, ", {:.2} GB/s", bytes as f64 / seconds / 1e9).unwrap();
+        }
+        log::info!("{}", summary);
+    }
+    let peak_memory = memory::peak();
+    if !args.follow {
+        let peak = peak_memory.map_or_else(|| "?".to_string(), |bytes| HumanBytes(bytes).to_string());
+        log::info!("Memory: {} at its peak, about {} of it the station map", peak, HumanBytes(map_memory as u64));
+    }
+
+    if let (Some(stats_path), Some(timings)) = (&args.stats_json, &timings) {
+        let stats = RunStats {
+            wall_time: wall_time.as_secs_f64(),
+            phases: PhaseTimes {
+                chunking: timings.get(Phase::Chunking).as_secs_f64(),
+                io: timings.get(Phase::Io).as_secs_f64(),
+                parsing: timings.get(Phase::Parsing).as_secs_f64(),
+                merging: timings.get(Phase::Merging).as_secs_f64(),
+                reader_stall: timings.get(Phase::ReaderStall).as_secs_f64(),
+                parser_stall: timings.get(Phase::ParserStall).as_secs_f64(),
+                sorting: (format_start - sort_start).as_secs_f64(),
+                formatting: (output_start - format_start).as_secs_f64(),
+                output: (output_end - output_start).as_secs_f64(),
+            },
+            rows,
+            stations,
+            bytes,
+            threads: args.input.threads.unwrap_or_else(default_threads),
+            engine: if args.input.direct { "direct".into() } else { engine_name(args.input.engine.resolve()) },
+            hasher: HASHER_NAME,
+            peak_memory,
+            map_memory,
+        };
+        write_atomically(stats_path, serde_json::to_string_pretty(&stats).unwrap().as_bytes())
+            .map_err(|source| Error::Io { path: Some(stats_path.clone()), source })?;
+    }
+
+    if let (true, Some(timings)) = (args.timings, &timings) {
+        eprintln!("Timings (I/O, parsing, merging and stalls are summed over the threads):");
+        for phase in Phase::ALL {
+            eprintln!("  {:<12} {:?}", phase, timings.get(phase));
+        }
+        eprintln!("  {:<12} {:?}", "sorting", format_start - sort_start);
+        eprintln!("  {:<12} {:?}", "formatting", output_start - format_start);
+        eprintln!("  {:<12} {:?}", "output", output_end - output_start);
+    }
+    if let Some(workers) = &workers {
+        print_workers(&workers.stats());
+    }
+    if interrupted {
+        // As if SIGINT had killed it, so a script can tell
+        std::process::exit(130);
+    }
+
+    // Each update is printed in full, the same way as the first results
+    if let Some(mut follower) = follower {
+        loop {
+            std::thread::sleep(args.interval);
+            if follower.poll()? && !write_results(&format_results(sort_stations(follower.stations()), &args.output)?, &args)? {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Prints the --worker-report table, and how far the busiest thread was
+// from the average
+fn print_workers(stats: &[WorkerStats]) {
+    eprintln!("Workers (idle is while other threads still had chunks):");
+    eprintln!("  {:>6} {:>12} {:>14} {:>7} {:>9} {:>12} {:>12}", "thread", "bytes", "rows", "chunks", "stations", "busy", "idle");
+    for (i, worker) in stats.iter().enumerate() {
+        eprintln!(
+            "  {:>6} {:>12} {:>14} {:>7} {:>9} {:>12} {:>12}",
+            i, HumanBytes(worker.bytes).to_string(), HumanCount(worker.rows).to_string(), worker.chunks, worker.stations,
+            format!("{:.1?}", worker.busy), format!("{:.1?}", worker.idle),
+        );
+    }
+    let mean = stats.iter().map(|worker| worker.busy.as_secs_f64()).sum::<f64>() / stats.len().max(1) as f64;
+    if let Some(busiest) = stats.iter().map(|worker| worker.busy.as_secs_f64()).max_by(f64::total_cmp).filter(|_| mean > 0.) {
+        eprintln!("  The busiest thread was busy {:.0}% longer than the average", (busiest / mean - 1.) * 100.);
+    }
+}
+
+// Prints about how many distinct stations the files have between them
+fn estimate_cardinality(input: &InputArgs) -> Result<(), Error> {
+    let (paths, stdin) = input_paths(input)?;
+    if stdin {
+        return Err("stdin can't be sketched".into());
+    }
+
+    let options = Options { delimiter: input.delimiter, threads: input.threads, ..Default::default() };
+    let mut sketch = HyperLogLog::default();
+    for path in &paths {
+        sketch.merge(&sketch_file(path, &options)?);
+    }
+    println!("About {} distinct stations (±{:.1}%)", HumanCount(sketch.estimate().round() as u64), HyperLogLog::ERROR * 100.);
+    Ok(())
+}
+
+// Prints each file's chunks, as both splitters cut them, and whatever
+// chunk::check finds wrong with them
+fn check_chunks(input: &InputArgs) -> Result<(), Error> {
+    let (paths, stdin) = input_paths(input)?;
+    if stdin {
+        log::warn!("stdin is read as a stream rather than in chunks, so there's nothing to check");
+    }
+
+    let check_all = || -> Result<usize, Error> {
+        let mut problems = 0;
+
+        for path in &paths {
+            let in_file = |source| Error::Io { path: Some(path.clone()), source };
+            let file = std::fs::File::open(path).map_err(in_file)?;
+            let bytes = unsafe { memmap2::Mmap::map(&file) }.map_err(in_file)?;
+            let size = bytes.len() as u64;
+
+            let read = chunk::chunk_specs(path, size).map_err(in_file)?;
+            let in_memory = chunk::chunk_specs_in(&bytes).into_iter().map(|(start, end)| (start as u64, end as u64)).collect::<Vec<_>>();
+
+            println!("{}: {} bytes", path.display(), size);
+            for (how, chunks) in [("while reading the file", &read), ("in memory", &in_memory)] {
+                println!("  Split {}: {} chunks", how, chunks.len());
+                for (i, (start, end)) in chunks.iter().enumerate() {
+                    println!("    {:>5}  {}..{} ({} bytes)", i, start, end, end.saturating_sub(*start));
+                }
+                for problem in chunk::check(&bytes, chunks) {
+                    println!("  Problem: {}", problem);
+                    problems += 1;
+                }
+            }
+            if read != in_memory {
+                println!("  The two splits differ");
+            }
+        }
+
+        Ok(problems)
+    };
+
+    // The number of chunks depends on the number of threads
+    #[cfg(feature = "parallel")]
+    let problems = match input.threads {
+        Some(threads) => rayon::ThreadPoolBuilder::new().num_threads(threads).build()
+            .map_err(|e| Error::Other(e.to_string()))?
+            .install(check_all)?,
+        None => check_all()?,
+    };
+    #[cfg(not(feature = "parallel"))]
+    let problems = check_all()?;
+    match problems {
+        0 => Ok(()),
+        problems => Err(format!("found {} problems with the chunks", problems).into()),
+    }
+}
+
+// The workers there are without --threads: rayon's pool, or the one
+// thread there is without the parallel feature
+fn default_threads() -> usize {
+    #[cfg(feature = "parallel")]
+    {
+        rayon::current_num_threads()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        1
+    }
+}
+
+// The name --engine takes for engine
+fn engine_name(engine: Engine) -> String {
+    engine.to_possible_value().map_or_else(|| format!("{:?}", engine), |value| value.get_name().to_string())
+}
+
+// Starts following the one file in input
+fn follow(input: &InputArgs, histograms: bool) -> Result<Follower, Error> {
+    match input_paths(input)? {
+        (paths, false) if paths.len() == 1 => Follower::new(&paths[0], &options(input, histograms)?),
+        _ => Err("--follow needs exactly one file, and not stdin".into()),
+    }
+}
+
+// Writes the results to -o or stdout, returning false if stdout has
+// been closed
+fn write_results(results: &[u8], args: &RunArgs) -> Result<bool, Error> {
+    // A blank line separates the results from --follow's next update
+    let separate = !args.output.format.is_binary() && args.follow;
+    write_output(results, args.output_path.as_deref(), separate)
+}
+
+// Writes results to output_path, or else to stdout (followed by a blank
+// line if separate), returning false if stdout has been closed
+fn write_output(results: &[u8], output_path: Option<&Path>, separate: bool) -> Result<bool, Error> {
+    if let Some(output_path) = output_path {
+        write_atomically(output_path, results)
+            .map_err(|source| Error::Io { path: Some(output_path.to_path_buf()), source })?;
+        return Ok(true);
+    }
+
+    // One locked write for the whole result rather than a flush per
+    // line. A reader that stops early (`| head`) isn't an error
+    let mut stdout = std::io::stdout().lock();
+    let written = stdout.write_all(results)
+        .and_then(|_| if separate { stdout.write_all(b"\n") } else { Ok(()) })
+        .and_then(|_| stdout.flush());
+    match written {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(false),
+        written => written.map(|_| true).map_err(Error::from),
+    }
+}
+
+// Writes contents to a temporary file next to path and renames it into
+// place, so path never holds partially written results
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut temp_name = path.as_os_str().to_owned();
+    temp_name.push(format!(".tmp{}", std::process::id()));
+    let temp_path = PathBuf::from(temp_name);
+
+    let result = std::fs::write(&temp_path, contents).and_then(|_| std::fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+#[cfg(feature = "generator")]
+fn generate(args: generate::GenerateArgs) -> Result<(), Error> {
+    generate::generate(&args).map_err(|e| match e.downcast::<std::io::Error>() {
+        Ok(e) => Error::from(*e),
+        Err(e) => Error::Other(e.to_string()),
+    })
+}
+
+fn merge(mut args: MergeArgs) -> Result<(), Error> {
+    args.output.color = args.output.color.resolve(args.output_path.is_none());
+    if args.output.format.is_binary() && args.output_path.is_none() && std::io::stdout().is_terminal() {
+        return Err("refusing to write binary results to a terminal, use -o or redirect stdout".into());
+    }
+
+    let histograms = args.output.histogram.is_some() || args.output.stats.iter().any(Stat::needs_histogram);
+    let mut data = StationMap::default();
+    let mut decimals = None;
+
+    for path in &args.paths {
+        let partial = partial::load(path)?;
+
+        // Records only combine with records of the same precision, and
+        // a percentile needs every measurement in a histogram
+        for record in partial.values() {
+            if *decimals.get_or_insert(record.decimals) != record.decimals {
+                return Err(format!("{} was aggregated with a different --precision from the files before it", path.display()).into());
+            }
+            if histograms && record.histogram.is_none() {
+                return Err(format!("{} has no histograms, which --histogram and percentiles need (aggregate it with one of them)", path.display()).into());
+            }
+        }
+        data = merge_maps(data, partial);
+    }
+
+    write_output(&format_results(sort_stations(data), &args.output)?, args.output_path.as_deref(), false)?;
+    Ok(())
+}
+
+fn validate(args: ValidateArgs) -> Result<(), Error> {
+    let options = Options {
+        delimiter: args.delimiter,
+        precision: args.precision,
+        strict: !args.lenient && args.precision == 1,
+        max_name_len: Some(args.max_name_len),
+        range: args.assert_range.map(|range| in_units(range, args.precision)),
+        threads: args.threads,
+        ..Default::default()
+    };
+
+    let mut invalid = 0;
+    for path in &args.paths {
+        let validation = one_billion_row_challenge::validate_file(path, &options, args.limit)?;
+        for violation in &validation.violations {
+            println!("{}", violation);
+        }
+        if validation.invalid > validation.violations.len() as u64 {
+            println!("... and {} more", HumanCount(validation.invalid - validation.violations.len() as u64));
+        }
+        println!("{}: {} lines, {} invalid", path.display(), HumanCount(validation.lines), HumanCount(validation.invalid));
+        for (reason, count) in &validation.reasons {
+            println!("  {:>12}  {}", HumanCount(*count).to_string(), reason);
+        }
+        invalid += validation.invalid;
+    }
+
+    match invalid {
+        0 => Ok(()),
+        invalid => Err(format!("found {} invalid lines", invalid).into()),
+    }
+}
+
+fn verify(args: VerifyArgs) -> Result<(), Error> {
+    // Baselines are the challenge's, to one decimal
+    if args.baseline.is_some() && args.input.precision != 1 {
+        return Err("verify needs --precision 1".into());
+    }
+
+    let data = aggregate(&args.input, false)?;
+    if let Some(baseline) = &args.baseline {
+        compare_baseline(baseline, &data)?;
+    }
+    match &args.station_table {
+        Some(table) => check_means(table.as_deref(), &data),
+        None => Ok(()),
+    }
+}
+
+// Prints how data differs from the results in baseline, failing if it
+// does at all
+fn compare_baseline(baseline_path: &Path, data: &StationMap) -> Result<(), Error> {
+    let baseline = std::fs::read_to_string(baseline_path)
+        .map_err(|source| Error::Io { path: Some(baseline_path.to_path_buf()), source })?;
+    let expected = parse_baseline(&baseline)
+        .ok_or_else(|| format!("could not parse {}", baseline_path.display()))?;
+
+    // Compare in tenths of a degree so that formatting differences
+    // like 12 vs 12.0 don't count as mismatches
+    let actual = data.iter().map(|(key, value)| {
+        let name = String::from_utf8_lossy(key).into_owned();
+        (name, [value.min as i64, value.mean_scaled(), value.max as i64])
+    }).collect::<BTreeMap<_, _>>();
+
+    let tenths = |values: &[i64; 3]| {
+        values.map(|v| format!("{:.1}", v as f64 / 10.)).join("/")
+    };
+
+    let mut mismatched = 0;
+    let mut rounding = 0;
+    let mut missing = 0;
+
+    for (name, expected) in &expected {
+        let Some(actual) = actual.get(name) else {
+            println!("Missing:  {} (expected {})", name, tenths(expected));
+            missing += 1;
+            continue;
+        };
+
+        if actual == expected {
+            continue;
+        }
+
+        // Off by a single tenth on the mean is almost always the two
+        // implementations rounding differently rather than a parsing bug
+        let only_mean_off_by_one = actual[0] == expected[0]
+            && actual[2] == expected[2]
+            && (actual[1] - expected[1]).abs() == 1;
+
+        if only_mean_off_by_one {
+            println!("Rounding: {} (expected {}, got {})", name, tenths(expected), tenths(actual));
+            rounding += 1;
+        } else {
+            println!("Mismatch: {} (expected {}, got {})", name, tenths(expected), tenths(actual));
+            mismatched += 1;
+        }
+    }
+
+    let mut extra = 0;
+    for (name, actual) in &actual {
+        if !expected.contains_key(name) {
+            println!("Extra:    {} (got {})", name, tenths(actual));
+            extra += 1;
+        }
+    }
+
+    println!(
+        "{} stations checked: {} mismatched, {} rounding differences, {} missing, {} extra",
+        expected.len(), mismatched, rounding, missing, extra,
+    );
 
-    // If the file is not found, generate the file
-    if file.is_err() {
-        println!("File not found, generating file");
-        let _ = generate::main();
-        return;
+    if mismatched + rounding + missing + extra == 0 {
+        println!("OK: output matches {}", baseline_path.display());
+        Ok(())
+    } else {
+        Err(format!("output does not match {}", baseline_path.display()).into())
     }
+}
+
+// Prints the stations whose means are further from the table's than a
+// generator's draws would put them: five standard errors of its
+// N(mean, STD_DEV), and a tenth for the table's rounding. A station
+// listed more than once is drawn for that much more often, so is
+// expected at the mean of its means
+fn check_means(table: Option<&Path>, data: &StationMap) -> Result<(), Error> {
+    let table = match table {
+        Some(path) => stations::read_stations(path).map_err(|e| e.to_string())?,
+        None => stations::STATIONS.iter().map(|(name, mean)| (name.to_string(), *mean)).collect(),
+    };
+    let mut expected = BTreeMap::<String, (f64, u32)>::new();
+    for (name, mean) in table {
+        let (total, listed) = expected.entry(name).or_default();
+        *total += mean;
+        *listed += 1;
+    }
+
+    let actual = data.iter().map(|(key, value)| (String::from_utf8_lossy(key).into_owned(), value)).collect::<BTreeMap<_, _>>();
+    let (mut checked, mut far_off) = (0, 0);
+    for (name, record) in &actual {
+        let Some(&(total, listed)) = expected.get(name) else {
+            continue;
+        };
+        let drawn_around = total / listed as f64;
+        let tolerance = 5. * stations::STD_DEV / (record.count as f64).sqrt() + 0.1;
+        if (record.mean() - drawn_around).abs() > tolerance {
+            println!("Far off:  {} (mean {:.1} of {} rows, drawn around {:.1})", name, record.mean(), record.count, drawn_around);
+            far_off += 1;
+        }
+        checked += 1;
+    }
+
+    println!("{} stations' means checked: {} far off, {} not in the table", checked, far_off, actual.len() - checked);
+    match far_off {
+        0 => Ok(()),
+        far_off => Err(format!("{} stations' means are far from the station table's", far_off).into()),
+    }
+}
+
+// Parses an output file in either the lines or the official format
+// into station -> [min, mean, max] in tenths of a degree
+fn parse_baseline(baseline: &str) -> Option<BTreeMap<String, [i64; 3]>> {
+    let parse_tenths = |value: &str| {
+        value.trim().parse::<f64>().ok().map(|v| (v * 10.).round() as i64)
+    };
+
+    let mut stations = BTreeMap::new();
+    let baseline = baseline.trim();
+
+    if let Some(inner) = baseline.strip_prefix('{') {
+        // {name=min/mean/max, name=min/mean/max, ...}
+        // Names can contain ", " (e.g. "Washington, D.C."), but the
+        // values can't, so split on '=' first and then find the end
+        // of the values
+        let first_line = inner.lines().next()?.trim_end();
+        let mut rest = first_line.strip_suffix('}')?;
+
+        while !rest.is_empty() {
+            let (name, after) = rest.split_once('=')?;
+            let (values, after) = after.split_once(", ").unwrap_or((after, ""));
+
+            let mut values = values.split('/').map(parse_tenths);
+            let parsed = [values.next()??, values.next()??, values.next()??];
+
+            stations.insert(name.to_string(), parsed);
+            rest = after;
+        }
+    } else {
+        // name;min;mean;max, one per line. Lines without a semicolon
+        // (blank lines, a trailing "Time taken") aren't results
+        for line in baseline.lines().map(str::trim_end).filter(|l| l.contains(';')) {
+            let mut fields = line.rsplitn(4, ';');
+            let max = parse_tenths(fields.next()?)?;
+            let mean = parse_tenths(fields.next()?)?;
+            let min = parse_tenths(fields.next()?)?;
+            let name = fields.next()?;
 
-    let file = file.unwrap();
+            stations.insert(name.to_string(), [min, mean, max]);
+        }
+    }
+
+    Some(stations)
+}
+
+fn run_query(args: QueryArgs) -> Result<(), Error> {
+    // Parse first, so a typo doesn't have to wait for the aggregation
+    let query = query::Query::parse(&args.query)?;
+
+    let mut data = aggregate(&args.input, false)?.into_iter().collect::<Vec<_>>();
+    data.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    let stations = data.iter()
+        .map(|(key, value)| Ok((station_name(key)?, value)))
+        .collect::<Result<Vec<_>, Error>>()?;
 
-    let size = file.metadata().unwrap().len();
+    print!("{}", query.run(&stations));
+    Ok(())
+}
+
+fn bench(args: BenchArgs) -> Result<(), Error> {
+    let (paths, stdin) = input_paths(&args.input)?;
+    if stdin {
+        return Err("bench needs files, not stdin, to read them more than once".into());
+    }
+    let bytes = paths.iter().map(|path| input_size(path)).sum::<Result<u64, Error>>()?;
 
-    // Read in the file in chunks
-    let mut chunk_start = 0;
-    let mut chunk_end = 0;
-    // Chunk size is file size divided by number of threads
-    let chunk_size = size / rayon::current_num_threads() as u64;
+    let engines = if args.engines.is_empty() { vec![args.input.engine] } else { args.engines.clone() };
+    if args.cross_check {
+        cross_check(&args.input, &engines)?;
+    }
 
-    // Create a vector of tuples, each tuple containing the start and end of a chunk
-    let chunk_specs = (0..rayon::current_num_threads()).map(|_| {
-        chunk_end = chunk_start + chunk_size;
+    // The two are different measurements, so every result says which
+    let cache = if args.cold { "cold" } else { "warm" };
+    let prefetch = if args.input.prefetch { "on" } else { "off" };
+    for engine in engines {
+        let options = Options { engine, ..options(&args.input, false)? };
+        println!("Engine: {:?} (hasher: {}, keys: {}, cache: {}, prefetch: {})", engine.resolve(), HASHER_NAME, KEY_NAME, cache, prefetch);
 
-        // The end of the chunk doesn't necessarily end at the end of a line, 
-        // so we need to read until we hit a \n character
-        // We do this by creating a new reader for each chunk, seeking to the end of the chunk,
-        // and reading until we hit a \n character
-        let mut reader = std::io::BufReader::new(std::fs::File::open(MEASUREMENTS_FILE).unwrap());
-        reader.seek(std::io::SeekFrom::Start(chunk_end)).unwrap();
-        let mut reader_bytes = reader.bytes();
-        let mut offset = 0;
+        for i in 0..args.warmup {
+            let start_time = std::time::Instant::now();
+            aggregate_with(&args.input, &options)?;
+            println!("Warm-up #{}: {:?}", i + 1, start_time.elapsed());
+        }
 
-        while let Some(Ok(c)) = reader_bytes.next() {
-            offset += 1;
-            if c == b'\n' {
-                break;
+        let mut times = Vec::with_capacity(args.iterations as usize);
+        let mut rows = 0;
+        for i in 0..args.iterations {
+            if args.cold {
+                paths.iter().try_for_each(|path| drop_cached(path))?;
             }
+            let start_time = std::time::Instant::now();
+            let data = aggregate_with(&args.input, &options)?;
+            let elapsed = start_time.elapsed();
+
+            println!("Iteration #{}: {:?}", i + 1, elapsed);
+            times.push(elapsed);
+            rows = data.values().map(|value| value.count).sum::<u64>();
         }
 
-        let chunk_end = chunk_end + offset;
+        if let Some(summary) = BenchSummary::new(&mut times) {
+            let seconds = summary.median.as_secs_f64();
+            println!("Min time: {:?}", summary.min);
+            println!("Median time: {:?}", summary.median);
+            println!("Mean time: {:?}", summary.mean);
+            println!("Rows/sec: {:.0}", rows as f64 / seconds);
+            println!("GB/s: {:.3}", bytes as f64 / seconds / 1e9);
+        }
+    }
 
-        // Return the start and end of the chunk
-        let to_return = (chunk_start, size.min(chunk_end));
+    Ok(())
+}
+
+// Asks the kernel to forget the cached pages of the file at path, so
+// the next read of it comes from storage. Objects aren't cached at all
+fn drop_cached(path: &Path) -> Result<(), Error> {
+    if object_url(path).is_some() {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        let in_file = |source| Error::Io { path: Some(path.to_path_buf()), source };
+        let file = std::fs::File::open(path).map_err(in_file)?;
+        // Only clean pages are dropped
+        file.sync_data().map_err(in_file)?;
+        match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) } {
+            0 => Ok(()),
+            errno => Err(in_file(std::io::Error::from_raw_os_error(errno))),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Err("bench --cold needs Linux, to drop files from the page cache".into())
+    }
+}
+
+// Fails unless every engine gives the same results
+fn cross_check(input: &InputArgs, engines: &[Engine]) -> Result<(), Error> {
+    if engines.len() < 2 {
+        return Err("--cross-check needs at least two --engines".into());
+    }
+
+    let results = engines.iter()
+        .map(|&engine| Ok((engine, sort_stations(aggregate_with(input, &Options { engine, ..options(input, false)? })?))))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let (first, expected) = &results[0];
+
+    for (engine, data) in &results[1..] {
+        if let Some(difference) = first_difference(expected, data) {
+            return Err(format!("the {} and {} engines disagree: {}", engine_name(*first), engine_name(*engine), difference).into());
+        }
+    }
+
+    println!("Cross-check: {} engines agree on {} stations", engines.len(), expected.len());
+    Ok(())
+}
 
-        // Before next loop, set the start of the next chunk to the end of the current chunk
-        chunk_start = chunk_end;
-        
-        to_return
-    }).collect::<Vec<(u64, u64)>>();
+// The first station (by name) two sorted sets of results differ on
+fn first_difference(a: &[(StationKey, Record)], b: &[(StationKey, Record)]) -> Option<String> {
+    let name = |key: &StationKey| String::from_utf8_lossy(key).into_owned();
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+
+    loop {
+        match (a.peek(), b.peek()) {
+            (None, None) => return None,
+            (Some((key, _)), None) => return Some(format!("only the first has {}", name(key))),
+            (None, Some((key, _))) => return Some(format!("only the second has {}", name(key))),
+            (Some((key_a, record_a)), Some((key_b, record_b))) => match key_a.cmp(key_b) {
+                std::cmp::Ordering::Less => return Some(format!("only the first has {}", name(key_a))),
+                std::cmp::Ordering::Greater => return Some(format!("only the second has {}", name(key_b))),
+                std::cmp::Ordering::Equal if record_a != record_b => {
+                    return Some(format!("{} is {:?} in the first and {:?} in the second", name(key_a), record_a, record_b));
+                }
+                std::cmp::Ordering::Equal => {
+                    a.next();
+                    b.next();
+                }
+            },
+        }
+    }
+}
+
+// The wall times of a benchmark's iterations. Throughput is worked out
+// from the median, which a single slow run doesn't drag around
+struct BenchSummary {
+    min: std::time::Duration,
+    median: std::time::Duration,
+    mean: std::time::Duration,
+}
+
+impl BenchSummary {
+    fn new(times: &mut [std::time::Duration]) -> Option<Self> {
+        if times.is_empty() {
+            return None;
+        }
+        times.sort_unstable();
+
+        let middle = times.len() / 2;
+        let median = if times.len().is_multiple_of(2) { (times[middle - 1] + times[middle]) / 2 } else { times[middle] };
+
+        Some(BenchSummary {
+            min: times[0],
+            median,
+            mean: times.iter().sum::<std::time::Duration>() / times.len() as u32,
+        })
+    }
+}
+
+// histograms is whether the records need histograms for the output
+fn aggregate(input: &InputArgs, histograms: bool) -> Result<StationMap, Error> {
+    aggregate_with(input, &options(input, histograms)?)
+}
 
-    // Parallelize the reading of the file, calling the read_chunk function on each chunk
-    let data = chunk_specs.into_par_iter().map(|(start, end)| {
-        read_chunk(MEASUREMENTS_FILE, start, end)
-    }).reduce(HashMap::new, |mut map1, map2| {
-        for (key, value) in map2 {
-            if map1.contains_key(&key) {
-                map1.get_mut(&key).unwrap().combine(&value);
+fn aggregate_with(input: &InputArgs, options: &Options) -> Result<StationMap, Error> {
+    let (paths, stdin) = input_paths(input)?;
+
+    let sample = input.sample.map(Sample::Fraction).or(input.limit_rows.map(Sample::Rows));
+    let aggregate_all = || {
+        if let Some(sample) = sample {
+            return aggregate_sampled(&paths, stdin, sample, options);
+        }
+        let mut data = aggregate_files(&paths, options)?;
+
+        if stdin {
+            data = match aggregate_reader(std::io::stdin().lock(), options) {
+                Err(Error::Cancelled { partial }) => return Err(Error::Cancelled { partial: merge_maps(data, partial) }),
+                result => merge_maps(data, result?),
+            };
+        }
+
+        Ok(data)
+    };
+
+    let data = match show_progress(options, &paths, stdin, aggregate_all) {
+        Err(Error::Cancelled { partial }) if input.lossy_utf8 => return Err(Error::Cancelled { partial: lossy_names(partial) }),
+        result => result?,
+    };
+    if let Some(bad_lines) = &options.bad_lines {
+        log::warn!("Skipped {} bad lines", HumanCount(bad_lines.load(Ordering::Relaxed)));
+    }
+    match options.appended.as_ref().map_or(0, |appended| appended.load(Ordering::Relaxed)) {
+        0 => {}
+        appended => log::info!("{} were appended to the input while it was read, which aren't in the results (--follow keeps up with them)", HumanBytes(appended)),
+    }
+    Ok(if input.lossy_utf8 { lossy_names(data) } else { data })
+}
+
+// Has Ctrl-C set cancel, to stop the aggregation after the chunks it's
+// on rather than the process, so the results so far can still be
+// printed. Once it's been cancelled (by Ctrl-C or --timeout), Ctrl-C
+// doesn't wait for them
+fn cancel_on_interrupt(cancel: &Arc<AtomicBool>) {
+    let cancel = cancel.clone();
+    let set = ctrlc::set_handler(move || {
+        if cancel.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+    });
+    if let Err(e) = set {
+        log::debug!("Can't catch Ctrl-C: {}", e);
+    }
+}
+
+// Aggregates a sample of each file, and says how much of the input the
+// sample was
+fn aggregate_sampled(paths: &[PathBuf], stdin: bool, sample: Sample, options: &Options) -> Result<StationMap, Error> {
+    if stdin {
+        return Err("stdin can't be sampled".into());
+    }
+
+    let (mut data, mut bytes, mut total_bytes) = (StationMap::default(), 0, 0);
+    for path in paths {
+        let sampled = aggregate_sample(path, sample, options)?;
+        data = merge_maps(data, sampled.stations);
+        bytes += sampled.bytes;
+        total_bytes += sampled.total_bytes;
+    }
+
+    let fraction = if total_bytes == 0 { 1. } else { bytes as f64 / total_bytes as f64 };
+    let rows = data.values().map(|record| record.count).sum::<u64>();
+    log::info!(
+        "Sampled {:.2}% of the input ({} of {}): {} rows, so about {} in all",
+        fraction * 100.,
+        HumanBytes(bytes),
+        HumanBytes(total_bytes),
+        HumanCount(rows),
+        HumanCount((rows as f64 / fraction).round() as u64),
+    );
+    Ok(data)
+}
+
+// Replaces the bytes of names that aren't valid UTF-8 with U+FFFD.
+// Names that only differ in their bad bytes end up the same, so their
+// records are combined
+fn lossy_names(data: StationMap) -> StationMap {
+    let mut lossy = StationMap::default();
+
+    for (key, value) in data {
+        let key = String::from_utf8_lossy(&key);
+        match lossy.entry(StationKey::from(key.as_bytes())) {
+            hashbrown::hash_map::Entry::Occupied(mut entry) => entry.get_mut().combine(&value),
+            hashbrown::hash_map::Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
+
+    lossy
+}
+
+// Runs f, with a progress bar if options counts progress
+fn show_progress<T>(options: &Options, paths: &[PathBuf], stdin: bool, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    match &options.progress {
+        Some(progress) => {
+            // stdin has no length, so there's only a total to aim for
+            // without it
+            let total = if stdin {
+                None
             } else {
-                map1.insert(key.clone(), value);
+                Some(paths.iter().map(|path| input_size(path)).sum::<Result<u64, Error>>()?)
+            };
+            match &options.live {
+                Some(live) => tui::with_tui(progress, live, total, options.precision, f),
+                None => with_progress(progress, total, f),
             }
         }
+        None => f(),
+    }
+}
 
-        map1
+fn options(input: &InputArgs, histograms: bool) -> Result<Options, Error> {
+    // Histograms only have buckets for tenths
+    if histograms && input.precision != 1 {
+        return Err("--histogram and percentiles need --precision 1".into());
+    }
+
+    Ok(Options {
+        engine: input.engine,
+        delimiter: input.delimiter,
+        direct: input.direct,
+        madvise: input.madvise,
+        huge_pages: input.huge_pages,
+        stations: (!input.stations.is_empty()).then(|| StationFilter::new(&input.stations)).transpose()?,
+        histograms,
+        progress: (input.progress || input.tui).then(|| Arc::new(AtomicU64::new(0))),
+        on_progress: None,
+        cancel: None,
+        threads: input.threads,
+        checkpoint: input.checkpoint.clone(),
+        timings: None,
+        workers: None,
+        validate_utf8: input.validate_utf8,
+        prefetch: input.prefetch,
+        interned: input.interned,
+        max_name_len: input.max_name_len,
+        truncate_names: input.truncate_names,
+        strict: input.strict,
+        merge: input.merge,
+        on_error: input.on_error,
+        bad_lines: (input.on_error == OnError::Count).then(|| Arc::new(AtomicU64::new(0))),
+        live: input.tui.then(|| Arc::new(Live::new(input.threads.unwrap_or_else(default_threads)))),
+        precision: input.precision,
+        range: input.assert_range.map(|range| in_units(range, input.precision)),
+        known_stations: known_stations(input)?,
+        numa: input.numa,
+        pin_cores: input.pin_cores,
+        max_memory: input.max_memory,
+        read_size: input.read_size,
+        io_threads: input.io_threads,
+        parse_threads: input.parse_threads,
+        chunk_size: input.chunk_size.unwrap_or_default(),
+        cache: input.cache.clone().map(|dir| dir.map_or_else(default_cache_dir, Ok)).transpose()?,
+        columns: input.key_col.zip(input.value_col).map(|(key, value)| Columns { key: key - 1, value: value - 1 }),
+        quoted: input.quoted,
+        normalize_keys: input.normalize_keys.clone(),
+        max_map_entries: input.max_map_entries,
+        appended: Some(Arc::new(AtomicU64::new(0))),
+    })
+}
+
+// $XDG_CACHE_HOME/1brc, ~/.cache/1brc, or on Windows %LOCALAPPDATA%\1brc
+fn default_cache_dir() -> Result<PathBuf, Error> {
+    let non_empty = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+
+    non_empty("XDG_CACHE_HOME")
+        .or_else(|| non_empty("HOME").map(|home| home.join(".cache")))
+        .or_else(|| non_empty("LOCALAPPDATA"))
+        .map(|dir| dir.join("1brc"))
+        .ok_or_else(|| "--cache needs =DIR, as there's no cache directory to default to".into())
+}
+
+fn known_stations(input: &InputArgs) -> Result<Option<Arc<KnownStations>>, Error> {
+    let names: Vec<String> = match &input.known_stations {
+        None => return Ok(None),
+        Some(None) => stations::STATIONS.iter().map(|(name, _)| name.to_string()).collect(),
+        Some(Some(path)) => stations::read_stations(path).map_err(|e| e.to_string())?.into_iter().map(|(name, _)| name).collect(),
+    };
+    Ok(Some(Arc::new(KnownStations::new(names))))
+}
+
+// The files to read, with globs expanded, and whether stdin is one of
+// the inputs
+fn input_paths(input: &InputArgs) -> Result<(Vec<PathBuf>, bool), Error> {
+    let mut paths = Vec::with_capacity(input.paths.len());
+    let mut stdin = false;
+
+    for path in &input.paths {
+        if path.as_os_str() == "-" {
+            if stdin {
+                return Err("stdin can only be read once".into());
+            }
+            stdin = true;
+        } else if path.exists() || object_url(path).is_some() {
+            paths.push(path.clone());
+        } else {
+            paths.extend(expand_glob(path)?);
+        }
+    }
+
+    Ok((paths, stdin))
+}
+
+// Runs f while a progress bar on stderr follows the byte counter the
+// workers increase
+fn with_progress<T>(progress: &AtomicU64, total: Option<u64>, f: impl FnOnce() -> T) -> T {
+    let bar = match total {
+        Some(total) => ProgressBar::new(total).with_style(
+            ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} ({binary_bytes_per_sec}, ETA {eta})").unwrap(),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} {bytes} ({binary_bytes_per_sec})").unwrap(),
+        ),
+    };
+
+    let done = AtomicBool::new(false);
+
+    let result = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                bar.set_position(progress.load(Ordering::Relaxed));
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+        });
+
+        let result = f();
+        done.store(true, Ordering::Relaxed);
+        result
     });
 
+    bar.finish_and_clear();
+    result
+}
+
+// Shells expand globs themselves, but not when they're quoted (or on
+// Windows), so paths that don't exist are tried as patterns
+fn expand_glob(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let does_not_exist = || {
+        if path.as_os_str() == MEASUREMENTS_FILE {
+            format!("{} does not exist (use `1brc generate` to create it)", path.display())
+        } else {
+            format!("{} does not exist", path.display())
+        }
+    };
 
+    let pattern = path.to_str().ok_or_else(does_not_exist)?;
+    if !pattern.contains(['*', '?', '[']) {
+        return Err(does_not_exist().into());
+    }
+
+    let mut paths = glob::glob(pattern)
+        .map_err(|e| format!("invalid pattern {}: {}", pattern, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::Io { path: Some(e.path().to_path_buf()), source: e.into() })?;
+    if paths.is_empty() {
+        return Err(format!("no files match {}", pattern).into());
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+// The stations in alphabetical order, which every output starts from
+fn sort_stations(data: StationMap) -> Vec<(StationKey, Record)> {
+    let mut data = data.into_iter().collect::<Vec<_>>();
+    data.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    data
+}
+
+fn format_results(mut data: Vec<(StationKey, Record)>, output: &OutputArgs) -> Result<Vec<u8>, Error> {
     /*
     The program should print out the min, mean, and max values per station, alphabetically ordered. The format that is expected varies slightly from language to language, but the following example shows the expected output for the first three stations:
 
@@ -136,94 +2095,274 @@ fn main() {
     Palembang;38.8;39.9;41.0
      */
 
-    let mut data = data.into_iter().collect::<Vec<_>>();
-    data.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    // Every station, with whatever histograms there are, for merging
+    if let Format::Partial = output.format {
+        return Ok(partial::encode(&data.into_iter().collect()));
+    }
 
-    let mut to_print = String::new();
+    if let Some(by) = &output.group_by {
+        data = group::rollup(data, by, output.with_stations, output.metadata.as_deref())?;
+    }
+    if output.collation == collate::Collation::Unicode {
+        collate::sort(&mut data);
+    }
+    if let Some(top) = output.top {
+        keep_top(&mut data, top, output.by);
+    }
+    if let Some(by) = output.sort {
+        sort_by(&mut data, by, output.desc);
+    }
+    format_rows(&data, output)
+}
+
+// The stations in data, grouped and ordered already, as --format has them
+fn format_rows(data: &[(StationKey, Record)], output: &OutputArgs) -> Result<Vec<u8>, Error> {
+    let (stats, unit) = (&output.stats, output.unit);
+
+    if let Some(width) = output.histogram {
+        return format_histograms(data, width, output).map(String::into_bytes);
+    }
+
+    match output.format {
+        Format::Official => return format_official(data, output).map(String::into_bytes),
+        Format::Json => return format_json(data, output).map(String::into_bytes),
+        Format::Csv => return format_csv(data, output).map(String::into_bytes),
+        Format::Table => return pretty::format_table(data, output).map(String::into_bytes),
+        #[cfg(feature = "arrow")]
+        Format::Arrow => return export::format_arrow(data, &aggregates(output, true), stats, unit),
+        #[cfg(feature = "parquet")]
+        Format::Parquet => return export::format_parquet(data, &aggregates(output, true), stats, unit),
+        Format::Partial => return Ok(partial::encode(&data.iter().cloned().collect())),
+        Format::Lines => {}
+    }
+
+    // Build the whole output up front (around 30 bytes a station) so
+    // it's written in one go rather than a line at a time
+    let mut to_print = String::with_capacity(data.len() * 32);
+    let aggregates = aggregates(output, output.with_count);
 
     for (key, value) in data {
-        let min = (value.min() * 10.).round() / 10.;
-        let mean = (value.mean() * 10.).round() / 10.;
-        let max = (value.max() * 10.).round() / 10.;
-        to_print = format!("{}{};{};{};{}\n", to_print, std::str::from_utf8(&key).unwrap(), min, mean, max);
+        to_print.push_str(station_name(key)?);
+        for aggregate in &aggregates {
+            write!(to_print, ";{}", output.numbers.aggregate(aggregate.value(value, unit))).unwrap();
+        }
+        for stat in stats {
+            write!(to_print, ";{}", output.numbers.stat(stat.value(value, unit), value.decimals)).unwrap();
+        }
+        if let Some(metadata) = metadata::enrichment(output) {
+            for value in metadata.values(key) {
+                write!(to_print, ";{}", value).unwrap();
+            }
+        }
+        to_print.push('\n');
     }
 
-    println!("{}", to_print);
+    Ok(to_print.into_bytes())
+}
 
-    println!("Time taken: {:?}", start_time.elapsed());
+// Keeps the first n of the alphabetically ordered stations by by. The
+// sorts are stable, so ties stay in alphabetical order
+fn keep_top(data: &mut Vec<(StationKey, Record)>, n: usize, by: TopBy) {
+    data.sort_by(|(_, a), (_, b)| by.cmp(a, b));
+    data.truncate(n);
 }
 
-fn read_chunk(file: &str, start: u64, end: u64) -> HashMap<Vec<u8>, Record>{
-    let mut reader = std::io::BufReader::new(std::fs::File::open(file).unwrap());
-    reader.seek(std::io::SeekFrom::Start(start)).unwrap();
-    let mut reader_bytes = reader.bytes();
+// Reorders the stations by by, keeping the order they were in for ties
+fn sort_by(data: &mut [(StationKey, Record)], by: SortBy, desc: bool) {
+    let order = |ordering: std::cmp::Ordering| if desc { ordering.reverse() } else { ordering };
+    match by {
+        SortBy::Name => data.sort_by(|(a, _), (b, _)| order(a.cmp(b))),
+        SortBy::Collated if desc => data.sort_by_cached_key(|(key, _)| std::cmp::Reverse(collate::key(&String::from_utf8_lossy(key)))),
+        SortBy::Collated => collate::sort(data),
+        SortBy::Min => data.sort_by(|(_, a), (_, b)| order(a.min.cmp(&b.min))),
+        SortBy::Mean => data.sort_by(|(_, a), (_, b)| order(a.mean_scaled().cmp(&b.mean_scaled()))),
+        SortBy::Max => data.sort_by(|(_, a), (_, b)| order(a.max.cmp(&b.max))),
+        SortBy::Count => data.sort_by(|(_, a), (_, b)| order(a.count.cmp(&b.count))),
+    }
+}
 
-    // Return a hashmap of the data, with the name as the key and the values of
-    // - min
-    // - max
-    // - total
-    // - count
-    // All temps are multiplied by 10
-    
-    // Quickest hasher in std
-    let mut data_map: HashMap<Vec<u8>, Record> = std::collections::HashMap::with_capacity_and_hasher(10_000, Default::default());
+// Station names are printed as they are, so they have to be UTF-8
+fn station_name(key: &[u8]) -> Result<&str, Error> {
+    std::str::from_utf8(key).map_err(|_| Error::InvalidUtf8 {
+        station: String::from_utf8_lossy(key).into_owned(),
+    })
+}
 
-    let mut bytes_consumed = 0;
-    let mut c;
-    
-    let mut name = Vec::with_capacity(124);
-    let mut temp = Vec::with_capacity(8);
+fn format_official(data: &[(StationKey, Record)], output: &OutputArgs) -> Result<String, Error> {
+    // Everything on one line, e.g.
+    // {Abha=-23.0/18.0/59.2, Abidjan=-16.2/26.0/67.3, ...}
+    // with every value printed to exactly one decimal (or --precision
+    // decimals). Other --aggregates take the place of min/mean/max,
+    // and any --with-count and --stats follow them, separated the same way
+    let unit = output.unit;
+    let aggregates = aggregates(output, output.with_count);
+    let stations = data.iter().map(|(key, value)| {
+        let values = aggregates.iter().map(|aggregate| aggregate.value(value, unit).to_string()).collect::<Vec<_>>();
+        let mut station = format!("{}={}", station_name(key)?, values.join("/"));
+        for stat in &output.stats {
+            write!(station, "/{:.*}", value.decimals as usize, stat.value(value, unit)).unwrap();
+        }
+        Ok(station)
+    }).collect::<Result<Vec<_>, Error>>()?;
 
-    let total_bytes = end - start;
+    Ok(format!("{{{}}}\n", stations.join(", ")))
+}
 
-    loop {
-        name.clear();
-        temp.clear();
+fn format_histograms(data: &[(StationKey, Record)], width: i16, output: &OutputArgs) -> Result<String, Error> {
+    let buckets = |record: &Record| {
+        record.histogram.as_ref().expect("record has no histogram").buckets(width)
+    };
+    // A bucket's bound, from tenths of a degree
+    let edge = |tenths: i16| output.unit.convert(tenths.into(), 1, 1);
 
-        // Read in the name, byte by byte until
-        // the semicolon is found. We don't want
-        // to include the semicolon in the name so
-        // we break the loop when we find it
-        loop {
-            c = reader_bytes.next().unwrap().unwrap();
-            bytes_consumed += 1;
+    let mut to_print = String::new();
 
-            if c == b';' {
-                break;
-            } else {
-                name.push(c);
+    match output.format {
+        // Hamburg;-5.0=12;0.0=40;... with each bucket by its lowest
+        // temperature, and empty buckets left out
+        Format::Lines => {
+            for (key, value) in data {
+                to_print.push_str(station_name(key)?);
+                for (start, count) in buckets(value) {
+                    write!(to_print, ";{}={}", edge(start), count).unwrap();
+                }
+                to_print.push('\n');
             }
         }
+        Format::Json => {
+            #[derive(Serialize)]
+            struct Bucket {
+                from: f64,
+                to: f64,
+                count: u64,
+            }
 
-        // Read in the temperature, byte by byte.
-        // It's the same general idea as the name,
-        // but we also need to check for a period
-        // which we skip
-        loop {
-            c = reader_bytes.next().unwrap().unwrap();
-            bytes_consumed += 1;
+            #[derive(Serialize)]
+            struct StationHistogram<'a> {
+                station: &'a str,
+                histogram: Vec<Bucket>,
+            }
 
-            if c == b'\n' {
-                break;
-            } else if c != b'.' {
-                temp.push(c);
+            let stations = data.iter().map(|(key, value)| Ok(StationHistogram {
+                station: station_name(key)?,
+                histogram: buckets(value).into_iter().map(|(start, count)| Bucket {
+                    from: degrees(edge(start)),
+                    to: degrees(edge(start + width)),
+                    count,
+                }).collect(),
+            })).collect::<Result<Vec<_>, Error>>()?;
+
+            to_print = serde_json::to_string_pretty(&stations).unwrap() + "\n";
+        }
+        // One station,from,to,count row per bucket
+        Format::Csv => {
+            let d = output.csv_delimiter;
+            writeln!(to_print, "station{d}from{d}to{d}count").unwrap();
+            for (key, value) in data {
+                let name = csv_field(station_name(key)?, d);
+                for (start, count) in buckets(value) {
+                    writeln!(to_print, "{}{d}{}{d}{}{d}{}", name, edge(start), edge(start + width), count).unwrap();
+                }
             }
         }
-        
-        // Read from the cache if the temperature has been seen before
-        // Otherwise, parse the temperature and add it to the cache
-        let temp_num: i16 = atoi_simd::parse(&temp).unwrap();
+        Format::Official => return Err("--histogram can't be printed in the official format".into()),
+        Format::Table => return Err("--histogram can't be printed as a table".into()),
+        #[cfg(feature = "arrow")]
+        Format::Arrow => return Err("--histogram can't be written as arrow".into()),
+        #[cfg(feature = "parquet")]
+        Format::Parquet => return Err("--histogram can't be written as parquet".into()),
+        Format::Partial => unreachable!("partials are encoded before anything is formatted"),
+    }
 
-        if let Some(entry) = data_map.get_mut(&name) {
-            entry.add(temp_num as i16);
-        } else {
-            data_map.insert(name.clone(), Record::new(temp_num as i16));
-        }
+    Ok(to_print)
+}
+
+// Quotes a CSV field only when it contains the delimiter, a quote or a
+// line break, with quotes doubled (RFC 4180)
+fn csv_field(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[derive(Serialize)]
+struct StationSummary<'a> {
+    station: &'a str,
+    // --aggregates and the count, in order
+    #[serde(flatten, serialize_with = "serialize_aggregates")]
+    aggregates: Vec<(&'static str, AggregateValue)>,
+    // --stats, by name
+    #[serde(flatten)]
+    stats: BTreeMap<String, f64>,
+    // --metadata, by column
+    #[serde(flatten)]
+    metadata: BTreeMap<&'a str, &'a str>,
+}
 
-        if bytes_consumed >= total_bytes {
-            break;
+impl<'a> StationSummary<'a> {
+    fn new(key: &'a [u8], value: &Record, aggregates: &[Aggregate], stats: &[Stat], unit: Unit) -> Result<Self, Error> {
+        Ok(StationSummary {
+            station: station_name(key)?,
+            aggregates: aggregates.iter().map(|aggregate| (aggregate.name(), aggregate.value(value, unit))).collect(),
+            stats: stats.iter().map(|stat| (stat.name(), stat.value(value, unit))).collect(),
+            metadata: BTreeMap::new(),
+        })
+    }
+}
+
+fn serialize_aggregates<S: serde::Serializer>(aggregates: &[(&str, AggregateValue)], serializer: S) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeMap;
+    let mut map = serializer.serialize_map(Some(aggregates.len()))?;
+    for (name, value) in aggregates {
+        map.serialize_entry(name, value)?;
+    }
+    map.end()
+}
+
+fn format_json(data: &[(StationKey, Record)], output: &OutputArgs) -> Result<String, Error> {
+    let metadata = metadata::enrichment(output);
+    let aggregates = aggregates(output, true);
+    let stations = data.iter()
+        .map(|(key, value)| {
+            let mut summary = StationSummary::new(key, value, &aggregates, &output.stats, output.unit)?;
+            if let Some(metadata) = metadata {
+                summary.metadata = metadata.columns.iter().map(String::as_str).zip(metadata.values(key)).collect();
+            }
+            Ok(summary)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(serde_json::to_string_pretty(&stations).unwrap() + "\n")
+}
+
+fn format_csv(data: &[(StationKey, Record)], output: &OutputArgs) -> Result<String, Error> {
+    let (stats, unit, delimiter) = (&output.stats, output.unit, output.csv_delimiter);
+    if output.numbers.marks().contains(&delimiter) {
+        return Err(format!("the numbers would have the csv delimiter {:?} in them, so pick another --csv-delimiter", delimiter).into());
+    }
+    let aggregates = aggregates(output, output.with_count);
+    let mut header = vec!["station".to_string()];
+    header.extend(aggregates.iter().map(|aggregate| aggregate.name().to_string()));
+    header.extend(stats.iter().map(Stat::name));
+    let metadata = metadata::enrichment(output);
+    header.extend(metadata.iter().flat_map(|metadata| metadata.columns.iter().map(|column| csv_field(column, delimiter))));
+    let mut to_print = header.join(&delimiter.to_string()) + "\n";
+
+    for (key, value) in data {
+        to_print.push_str(&csv_field(station_name(key)?, delimiter));
+        for aggregate in &aggregates {
+            write!(to_print, "{}{}", delimiter, output.numbers.aggregate(aggregate.value(value, unit))).unwrap();
         }
+        for stat in stats {
+            write!(to_print, "{}{}", delimiter, output.numbers.stat(stat.value(value, unit), value.decimals)).unwrap();
+        }
+        for value in metadata.iter().flat_map(|metadata| metadata.values(key)) {
+            write!(to_print, "{}{}", delimiter, csv_field(value, delimiter)).unwrap();
+        }
+        to_print.push('\n');
     }
 
-    data_map
-}
\ No newline at end of file
+    Ok(to_print)
+}
@@ -0,0 +1,19 @@
+// How much memory the process has, where /proc says (Linux). Pages of a
+// mapped input file count, as they're resident too.
+
+/// The resident set size now
+pub fn resident() -> Option<u64> {
+    status("VmRSS:")
+}
+
+/// The most the resident set has been
+pub fn peak() -> Option<u64> {
+    status("VmHWM:")
+}
+
+fn status(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find_map(|line| line.strip_prefix(field))?;
+    let kb = line.trim().strip_suffix("kB")?.trim().parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
@@ -0,0 +1,116 @@
+// `--metadata`: what else is known about the stations, from a CSV file
+// whose first column is the station and whose header names the others
+// (e.g. `station,country,region`). It's read once, into a map keyed the
+// way the stations' own is, and its columns are printed alongside the
+// results or grouped by with `--group-by COLUMN`.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use one_billion_row_challenge::{StationKey, StationLookup};
+
+use crate::OutputArgs;
+
+#[derive(Debug)]
+pub struct Metadata {
+    /// The columns after the station's, by name
+    pub columns: Vec<String>,
+    stations: StationLookup<Vec<String>>,
+}
+
+impl Metadata {
+    /// The station's value in each column, empty where it has none
+    pub fn values(&self, name: &[u8]) -> Vec<&str> {
+        let values = self.stations.get(name).map_or(&[][..], Vec::as_slice);
+        (0..self.columns.len()).map(|column| values.get(column).map_or("", String::as_str)).collect()
+    }
+
+    /// The station's value in the column, if it has one
+    pub fn get(&self, name: &[u8], column: usize) -> Option<&str> {
+        self.stations.get(name).and_then(|values| values.get(column)).map(String::as_str).filter(|value| !value.is_empty())
+    }
+
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column == name)
+    }
+}
+
+/// Reads a metadata file, for `--metadata`
+pub fn read(path: &str) -> Result<Arc<Metadata>, String> {
+    let text = std::fs::read_to_string(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+    let mut records = parse_csv(&text).into_iter();
+    let mut header = records.next().ok_or_else(|| format!("{}: no header", path))?;
+    if header.len() < 2 {
+        return Err(format!("{}: the header only has the station's column", path));
+    }
+    header.remove(0);
+
+    let mut stations = StationLookup::default();
+    for mut record in records.filter(|record| record.iter().any(|field| !field.is_empty())) {
+        let station = record.remove(0);
+        stations.insert(StationKey::from(station.as_bytes()), record);
+    }
+    Ok(Arc::new(Metadata { columns: header, stations }))
+}
+
+// The records of a CSV file, with fields in double quotes able to hold
+// commas, newlines and (doubled) quotes
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let (mut record, mut field) = (Vec::new(), String::new());
+    let (mut quoted, mut chars) = (false, text.chars().peekable());
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+/// The metadata to print alongside the results: none once the stations
+/// are rolled up into groups, unless they're printed too
+pub fn enrichment(output: &OutputArgs) -> Option<&Metadata> {
+    output.metadata.as_deref().filter(|_| output.group_by.is_none() || output.with_stations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_csv() {
+        assert_eq!(parse_csv("station,country\nHamburg,Germany\r\n\"St. John's, NL\",\"Canada \"\"CA\"\"\"\nOslo"), [
+            vec!["station", "country"], vec!["Hamburg", "Germany"], vec!["St. John's, NL", "Canada \"CA\""], vec!["Oslo"],
+        ]);
+    }
+
+    #[test]
+    fn looks_up() {
+        let path = std::env::temp_dir().join(format!("brc-metadata-{}.csv", std::process::id()));
+        std::fs::write(&path, "station,country,region\nHamburg,Germany,Europe\nOslo,Norway\n\n").unwrap();
+        let metadata = read(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(metadata.columns, ["country", "region"]);
+        assert_eq!(metadata.values(b"Hamburg"), ["Germany", "Europe"]);
+        assert_eq!(metadata.values(b"Oslo"), ["Norway", ""]);
+        assert_eq!(metadata.values(b"Abha"), ["", ""]);
+        assert_eq!((metadata.get(b"Oslo", 0), metadata.get(b"Oslo", 1)), (Some("Norway"), None));
+    }
+}
@@ -0,0 +1,138 @@
+// Aggregating several values per line, as `name;temp;humidity;pressure`,
+// in the one pass over the file. Each station keeps a record for every
+// value after its name, side by side, so a line is still the one lookup
+// however many values it has.
+
+use std::path::Path;
+
+use crate::par::*;
+
+use crate::error::Invalid;
+use crate::parser::{self, BadLine};
+use crate::{accept_name, chunk, compression, for_each_line, in_pool, name_ref, new_record, regular_file, with_file, with_line_number, Error, Options, Record, StationHasher, StationKey};
+
+/// Records keyed by a station's raw name, one for each of the values on
+/// its lines, in order. A value that was always left empty has none
+pub type MetricMap = hashbrown::HashMap<StationKey, Vec<Option<Record>>, StationHasher>;
+
+/// Aggregates lines with `metrics` values after the station name, in
+/// parallel chunks. A value can be left empty, and a line with more
+/// values than that has the rest passed over. Values are parsed as
+/// [`Options::columns`] ones are, and the options' precision,
+/// strictness, name rules, station filter and [`OnError`] apply as they
+/// do to [`aggregate`](crate::aggregate)
+pub fn aggregate_metrics_bytes(bytes: &[u8], metrics: usize, options: &Options) -> Result<MetricMap, Error> {
+    in_pool(options, || {
+        chunk::chunks_in(bytes, options)
+            .into_par_iter()
+            .map(|(start, end)| {
+                aggregate_chunk(&bytes[start..end], metrics, options).map_err(|BadLine(at, invalid)| Error::bad_line(invalid, bytes, start + at, 0))
+            })
+            .try_reduce(MetricMap::default, |map1, map2| Ok(merge_metrics(map1, map2)))
+    })
+    .map_err(|e| with_line_number(e, bytes))
+}
+
+/// [`aggregate_metrics_bytes`] for an uncompressed file
+pub fn aggregate_metrics(path: &Path, metrics: usize, options: &Options) -> Result<MetricMap, Error> {
+    with_file(path, |file| {
+        regular_file(path, file)?;
+        if compression::detect_file(file)? != compression::Compression::None {
+            return Err(format!("{}: compressed files can't be aggregated by several values", path.display()).into());
+        }
+        // Mapping an empty file fails on some platforms
+        if file.metadata()?.len() == 0 {
+            return Ok(MetricMap::default());
+        }
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        aggregate_metrics_bytes(&mmap, metrics, options)
+    })
+}
+
+/// Combines the records of two maps
+pub fn merge_metrics(mut map1: MetricMap, map2: MetricMap) -> MetricMap {
+    for (key, records) in map2 {
+        match map1.entry(key) {
+            hashbrown::hash_map::Entry::Occupied(mut entry) => {
+                for (record, other) in entry.get_mut().iter_mut().zip(records) {
+                    match (record.as_mut(), other) {
+                        (Some(record), Some(other)) => record.combine(&other),
+                        (None, other) => *record = other,
+                        (Some(_), None) => {}
+                    }
+                }
+            }
+            hashbrown::hash_map::Entry::Vacant(entry) => {
+                entry.insert(records);
+            }
+        }
+    }
+    map1
+}
+
+fn aggregate_chunk(chunk: &[u8], metrics: usize, options: &Options) -> Result<MetricMap, BadLine> {
+    let mut data_map = MetricMap::default();
+    let mut values = Vec::with_capacity(metrics);
+    let mut scratch = Vec::new();
+    for_each_line(chunk, options, |line| add_line(line, metrics, &mut data_map, &mut values, &mut scratch, options))?;
+    Ok(data_map)
+}
+
+// values is space for the line's values, in the records' units
+fn add_line(line: &[u8], metrics: usize, data_map: &mut MetricMap, values: &mut Vec<Option<i32>>, scratch: &mut Vec<u8>, options: &Options) -> Result<(), Invalid> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let mut fields = line.split(|&byte| byte == options.delimiter);
+    let name = fields.next().unwrap_or_default();
+
+    values.clear();
+    for _ in 0..metrics {
+        let field = fields.next().ok_or(if values.is_empty() { Invalid::NoDelimiter } else { Invalid::Columns(metrics + 1) })?;
+        let value = (!field.is_empty()).then(|| parser::parse_value(field, options.precision, options.strict, scratch)).transpose()?;
+        values.push(value);
+    }
+    let Some(name) = accept_name(name, options)? else {
+        return Ok(());
+    };
+
+    let new = |value: Option<i32>| value.map(|value| new_record(value, options));
+    match data_map.entry_ref(name_ref(name)) {
+        hashbrown::hash_map::EntryRef::Occupied(mut entry) => {
+            for (record, &value) in entry.get_mut().iter_mut().zip(values.iter()) {
+                match (record.as_mut(), value) {
+                    (Some(record), Some(value)) => record.add(value),
+                    (None, value) => *record = new(value),
+                    (Some(_), None) => {}
+                }
+            }
+        }
+        hashbrown::hash_map::EntryRef::Vacant(entry) => {
+            entry.insert(values.iter().map(|&value| new(value)).collect());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OnError;
+
+    #[test]
+    fn aggregates_every_value() {
+        let bytes = b"Hamburg;12.0;80;1013.2\nOslo;1.5;;990\nHamburg;-3.0;60;1001.0\r\nOslo;2.5;70\nOslo;x;1;2";
+        assert!(aggregate_metrics_bytes(bytes, 3, &Options::default()).is_err());
+
+        let options = Options { on_error: OnError::Skip, ..Default::default() };
+        let data = aggregate_metrics_bytes(bytes, 3, &options).unwrap();
+        assert_eq!(data.len(), 2);
+        let hamburg = &data[&b"Hamburg"[..]];
+        let ranges = hamburg.iter().map(|record| record.as_ref().map(|record| (record.min, record.max, record.count))).collect::<Vec<_>>();
+        assert_eq!(ranges, [Some((-30, 120, 2)), Some((600, 800, 2)), Some((10010, 10132, 2))]);
+
+        // Oslo's humidity was left empty, and its second line was short
+        let oslo = &data[&b"Oslo"[..]];
+        assert_eq!(oslo[0].as_ref().unwrap().count, 1);
+        assert!(oslo[1].is_none());
+        assert_eq!(oslo[2].as_ref().unwrap().max, 9900);
+    }
+}
@@ -0,0 +1,94 @@
+// Making the names a feed writes one station under in different ways
+// the same, for Options::normalize_keys.
+//
+// The parser keys the records by the names as they are in the input,
+// and they're put right as each run finishes, as quoted names are
+// unescaped: each name is only normalised once, however many lines it
+// has, and with no normalisation the lines are parsed as they always
+// were. The records of names that come out the same are combined. The
+// station filter and the known stations see the names as they were.
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{Aggregator, AggregatorMap, StationKey};
+
+/// A way names of the same station can differ, which
+/// [`Options::normalize_keys`](crate::Options::normalize_keys) takes out
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum NormalizeKey {
+    /// Whitespace before or after the name
+    Trim,
+    /// Case, by lowercasing the names
+    Lowercase,
+    /// How accented letters are made up: as one character, or a letter
+    /// and combining accents (Unicode's NFC)
+    Nfc,
+}
+
+impl NormalizeKey {
+    // name with this taken out, or None if it has nothing to take out.
+    // Names that aren't UTF-8 only have their ASCII trimmed or lowercased
+    fn apply(self, name: &[u8]) -> Option<Vec<u8>> {
+        let text = std::str::from_utf8(name);
+        let normalized: Vec<u8> = match (self, text) {
+            (NormalizeKey::Trim, Ok(text)) => text.trim().into(),
+            (NormalizeKey::Trim, Err(_)) => name.trim_ascii().into(),
+            (NormalizeKey::Lowercase, Ok(text)) => text.to_lowercase().into(),
+            (NormalizeKey::Lowercase, Err(_)) => name.to_ascii_lowercase(),
+            (NormalizeKey::Nfc, Ok(text)) => text.nfc().collect::<String>().into(),
+            (NormalizeKey::Nfc, Err(_)) => return None,
+        };
+        Some(normalized).filter(|normalized| normalized != name)
+    }
+}
+
+/// `name` with each of `keys` taken out of it in turn
+pub fn normalize_name(name: &[u8], keys: &[NormalizeKey]) -> StationKey {
+    let mut normalized = None;
+    for key in keys {
+        if let Some(changed) = key.apply(normalized.as_deref().unwrap_or(name)) {
+            normalized = Some(changed);
+        }
+    }
+    StationKey::from(normalized.as_deref().unwrap_or(name))
+}
+
+// The map keyed by the normalised names
+pub(crate) fn normalize_names<A: Aggregator>(data_map: AggregatorMap<A>, keys: &[NormalizeKey]) -> AggregatorMap<A> {
+    if keys.is_empty() {
+        return data_map;
+    }
+    let mut normalized = AggregatorMap::<A>::default();
+    normalized.reserve(data_map.len());
+    for (name, value) in data_map {
+        match normalized.entry(normalize_name(&name, keys)) {
+            hashbrown::hash_map::Entry::Occupied(mut entry) => entry.get_mut().combine(&value),
+            hashbrown::hash_map::Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(name: &str, keys: &[NormalizeKey]) -> String {
+        String::from_utf8(normalize_name(name.as_bytes(), keys).to_vec()).unwrap()
+    }
+
+    #[test]
+    fn normalizes_names() {
+        use NormalizeKey::*;
+
+        assert_eq!(normalized(" Hamburg \t", &[Trim]), "Hamburg");
+        assert_eq!(normalized("HAMBURG", &[Lowercase]), "hamburg");
+        assert_eq!(normalized("Zu\u{308}rich", &[Nfc]), "Zürich");
+        assert_eq!(normalized(" ÍZMIR ", &[Trim, Lowercase]), "ízmir");
+        assert_eq!(normalized("Abha", &[Trim, Lowercase, Nfc]), "abha");
+        assert_eq!(&normalize_name(b" \xffAB ", &[Trim, Lowercase, Nfc])[..], b"\xffab");
+    }
+}
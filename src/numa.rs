@@ -0,0 +1,92 @@
+// Keeping each NUMA node's threads on the part of the file it faulted in.
+//
+// The nodes and their CPUs come from /sys/devices/system/node. Every
+// node gets a rayon pool of its own, with one worker pinned to each of
+// its CPUs, and a contiguous share of the chunks (by CPU count) to
+// aggregate. The page cache allocates a page on the node of the thread
+// that first faults it in, so a node's workers then only read back
+// memory local to them. The nodes' results are merged at the end.
+
+use std::path::Path;
+
+use crate::affinity::{parse_cpu_list, pin};
+use crate::engine::Engine;
+use crate::timings::{timed, Phase};
+use crate::{merge_maps, Error, Options, StationMap};
+
+/// The online NUMA nodes, as the CPUs in each, or `None` if there's no
+/// topology to read (e.g. a kernel without NUMA support)
+pub(crate) fn nodes() -> Option<Vec<Vec<usize>>> {
+    let root = Path::new("/sys/devices/system/node");
+    let online = std::fs::read_to_string(root.join("online")).ok()?;
+
+    parse_cpu_list(&online)?.into_iter()
+        .map(|node| parse_cpu_list(&std::fs::read_to_string(root.join(format!("node{}/cpulist", node))).ok()?))
+        .filter(|cpus| cpus.as_ref().is_none_or(|cpus| !cpus.is_empty()))
+        .collect()
+}
+
+// Splits chunks into one contiguous run per node, sized by how many CPUs
+// the node has
+fn split_chunks(chunks: Vec<(u64, u64)>, nodes: &[Vec<usize>]) -> Vec<Vec<(u64, u64)>> {
+    let total_cpus: usize = nodes.iter().map(Vec::len).sum();
+    let mut chunks = chunks.into_iter();
+    let mut cpus_so_far = 0;
+    let mut taken = 0;
+    let count = chunks.len();
+
+    nodes.iter().map(|cpus| {
+        cpus_so_far += cpus.len();
+        let end = count * cpus_so_far / total_cpus;
+        let share = chunks.by_ref().take(end - taken).collect();
+        taken = end;
+        share
+    }).collect()
+}
+
+// Aggregates chunks with each node's pinned workers doing its share
+pub(crate) fn aggregate(engine: &dyn Engine, chunks: Vec<(u64, u64)>, nodes: &[Vec<usize>], options: &Options) -> Result<StationMap, Error> {
+    let pools = nodes.iter().map(|cpus| {
+        let cpus = cpus.clone();
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(cpus.len())
+            .start_handler(move |i| pin(cpus[i]))
+            .build()
+            .map_err(|e| Error::Other(e.to_string()))
+    }).collect::<Result<Vec<_>, _>>()?;
+
+    let results = std::thread::scope(|scope| {
+        let handles = pools.iter().zip(split_chunks(chunks, nodes))
+            .map(|(pool, share)| scope.spawn(move || pool.install(|| engine.aggregate_chunks(share, options))))
+            .collect::<Vec<_>>();
+
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Result<Vec<_>, _>>()
+    })?;
+
+    Ok(results.into_iter().fold(StationMap::default(), |map1, map2| timed(options, Phase::Merging, || merge_maps(map1, map2))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_split_by_cpus() {
+        let chunks: Vec<(u64, u64)> = (0..10).map(|i| (i, i + 1)).collect();
+        let shares = split_chunks(chunks.clone(), &[vec![0, 1, 2], vec![3]]);
+
+        assert_eq!(shares.iter().map(Vec::len).collect::<Vec<_>>(), [7, 3]);
+        assert_eq!(shares.concat(), chunks);
+    }
+
+    #[test]
+    fn same_results_split_between_nodes() {
+        let file = std::fs::File::open("tests/data/crlf.txt").unwrap();
+        let options = Options::default();
+        let engine = crate::engine::Mapped::new(&file, file.metadata().unwrap().len(), &options).unwrap();
+
+        // Two nodes that happen to be the same CPU, which any machine has
+        let split = aggregate(&engine, engine.chunks(&options).unwrap(), &[vec![0], vec![0]], &options).unwrap();
+        assert_eq!(split, crate::engine::aggregate(&engine, &options).unwrap());
+    }
+}
@@ -0,0 +1,137 @@
+// How the lines, csv and table formats write numbers, for reports read
+// by people, or compared by tools, that expect 12,5 rather than 12.5,
+// 1,234,567 rather than 1234567, 12.0 rather than 12, or every column
+// the same width.
+
+use clap::Args;
+
+use crate::AggregateValue;
+
+#[derive(Args, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NumberArgs {
+    /// Write decimals with a comma, as in 12,5, in the lines, csv and
+    /// table formats, and separate thousands with points
+    #[arg(long)]
+    pub decimal_comma: bool,
+
+    /// Separate the thousands of big numbers (sums and counts, say)
+    /// with commas in the lines and csv formats, as the table format
+    /// does its counts
+    #[arg(long)]
+    pub thousands: bool,
+
+    /// Write the temperatures and --stats in the lines and csv formats
+    /// to every decimal, 12.0 rather than 12, as the official and table
+    /// formats do
+    #[arg(long)]
+    pub fixed_decimals: bool,
+
+    /// Pad the numbers in the lines and csv formats on the left with
+    /// spaces to at least this many characters, so they line up
+    #[arg(long, value_name = "CHARS")]
+    pub number_width: Option<usize>,
+}
+
+impl NumberArgs {
+    /// An aggregate as the lines and csv formats write it
+    pub fn aggregate(&self, value: AggregateValue) -> String {
+        let digits = match self.fixed_decimals {
+            true => value.to_string(),
+            false => value.plain(),
+        };
+        self.padded(self.marked(&digits, self.thousands))
+    }
+
+    /// A statistic of a record with `decimals` decimals, as the lines
+    /// and csv formats write it
+    pub fn stat(&self, value: f64, decimals: u8) -> String {
+        let digits = match self.fixed_decimals {
+            true => format!("{:.*}", decimals as usize, value),
+            false => value.to_string(),
+        };
+        self.padded(self.marked(&digits, self.thousands))
+    }
+
+    /// A number the table has already written to every decimal, with
+    /// thousands separated if `thousands`
+    pub fn cell(&self, digits: &str, thousands: bool) -> String {
+        self.marked(digits, thousands)
+    }
+
+    /// The characters numbers are written with besides digits and the
+    /// sign, which a csv delimiter can't be one of
+    pub fn marks(&self) -> Vec<char> {
+        let (point, separator) = self.separators();
+        let mut marks = vec![point];
+        if self.thousands {
+            marks.push(separator);
+        }
+        marks
+    }
+
+    // The decimal mark and the thousands separator
+    fn separators(&self) -> (char, char) {
+        match self.decimal_comma {
+            true => (',', '.'),
+            false => ('.', ','),
+        }
+    }
+
+    // digits, as Rust writes numbers, with this style's decimal mark, and
+    // separated thousands if thousands. Anything else (NaN, say) is as it is
+    fn marked(&self, digits: &str, thousands: bool) -> String {
+        let (sign, unsigned) = digits.strip_prefix('-').map_or(("", digits), |unsigned| ("-", unsigned));
+        let (whole, fraction) = unsigned.split_once('.').map_or((unsigned, None), |(whole, fraction)| (whole, Some(fraction)));
+        if whole.is_empty() || !whole.bytes().all(|byte| byte.is_ascii_digit()) {
+            return digits.to_string();
+        }
+
+        let (point, separator) = self.separators();
+        let mut marked = sign.to_string();
+        for (i, digit) in whole.chars().enumerate() {
+            if thousands && i > 0 && (whole.len() - i) % 3 == 0 {
+                marked.push(separator);
+            }
+            marked.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            marked.push(point);
+            marked.push_str(fraction);
+        }
+        marked
+    }
+
+    fn padded(&self, number: String) -> String {
+        match self.number_width {
+            Some(width) => format!("{:>width$}", number),
+            None => number,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_numbers_in_style() {
+        let plain = NumberArgs::default();
+        assert_eq!(plain.stat(12., 1), "12");
+        assert_eq!(plain.stat(1234567.25, 2), "1234567.25");
+
+        let fixed = NumberArgs { fixed_decimals: true, ..plain };
+        assert_eq!(fixed.stat(12., 1), "12.0");
+        assert_eq!(fixed.stat(-0.05, 2), "-0.05");
+
+        let report = NumberArgs { decimal_comma: true, thousands: true, fixed_decimals: true, number_width: Some(12) };
+        assert_eq!(report.stat(-1234567.31, 1), "-1.234.567,3");
+        assert_eq!(report.stat(999., 1), "       999,0");
+        assert_eq!(report.stat(f64::NAN, 1), "         NaN");
+        assert_eq!(report.marks(), [',', '.']);
+
+        let english = NumberArgs { thousands: true, ..plain };
+        assert_eq!(english.cell("1234", true), "1,234");
+        assert_eq!(english.stat(123456., 1), "123,456");
+        assert_eq!(plain.cell("1234.5", false), "1234.5");
+    }
+}
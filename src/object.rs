@@ -0,0 +1,193 @@
+// Inputs in an object store (S3, GCS or Azure) or on a web server, read
+// with a ranged GET per chunk rather than through a file.
+//
+// The chunks are found as chunk_specs finds a file's: cut at even
+// offsets (with a GET of the start timing the store for --chunk-size
+// auto), with each cut moved on to just past the next \n. Working that
+// out takes a small GET at every cut, which are all made at once. After
+// that the object is an engine like any other, with every worker
+// fetching its own chunks and parsing each part of the response as it
+// arrives, so nothing touches the local disk and no more than a part of a
+// chunk is held at a time.
+// The requests run on a tokio runtime of their own that the workers
+// block on.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use object_store::path::Path as ObjectPath;
+use futures_util::StreamExt;
+use object_store::{GetOptions, ObjectStore, ObjectStoreExt};
+
+use crate::engine::{self, Engine};
+use crate::error::Invalid;
+use crate::timings::{timed, Phase};
+use crate::{chunk, compression, max_line_len, parse_lines, parse_to_end, report_progress, BadLine, Error, Options, StationMap};
+
+// Bytes fetched at a time when looking for the end of a line
+const PROBE_SIZE: u64 = 4096;
+
+/// Aggregates the object at `url`, as `s3://bucket/key`, `gs://bucket/key`,
+/// `az://container/blob` or `https://host/path`. Credentials and the
+/// region come from the usual environment variables (`AWS_ACCESS_KEY_ID`,
+/// `AWS_REGION`, `GOOGLE_SERVICE_ACCOUNT`, `AZURE_STORAGE_ACCOUNT_NAME`
+/// and so on). A web server has to answer HEAD and Range requests.
+///
+/// Compressed objects are fetched whole and decompressed as a stream
+pub fn aggregate_object(url: &str, options: &Options) -> Result<StationMap, Error> {
+    if options.checkpoint.is_some() || options.cache.is_some() {
+        return Err(format!("{}: objects can't be checkpointed or cached", url).into());
+    }
+    let aggregate = || {
+        let object = Object::open(url)?;
+        let magic = object.get(0..object.size.min(4))?;
+        let compression = compression::detect(&magic);
+        if compression != compression::Compression::None {
+            log::debug!("{}: {:?} compressed, fetching it whole", url, compression);
+            let bytes = object.get(0..object.size)?;
+            let data = compression::aggregate_compressed(compression, &bytes, &Options { progress: None, ..options.clone() })?;
+            report_progress(options, bytes.len());
+            return Ok(data);
+        }
+
+        log::debug!("{}: {} bytes, read with ranged GETs", url, object.size);
+        engine::aggregate(&object, options)
+    };
+    aggregate().map_err(|e| e.in_file(Path::new(url)))
+}
+
+/// The size in bytes of the object at `url` (see [`aggregate_object`])
+pub fn object_size(url: &str) -> Result<u64, Error> {
+    Object::open(url).map(|object| object.size).map_err(|e| e.in_file(Path::new(url)))
+}
+
+pub(crate) struct Object {
+    store: Arc<dyn ObjectStore>,
+    path: ObjectPath,
+    size: u64,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl Object {
+    fn open(url: &str) -> Result<Self, Error> {
+        let parsed = url::Url::parse(url).map_err(|e| format!("{}: {}", url, e))?;
+        // The builders read their settings from the lowercased names. An
+        // http:// URL is plain HTTP on purpose, which they'd otherwise refuse
+        let plain_http = (parsed.scheme() == "http").then(|| ("allow_http".to_string(), "true".to_string()));
+        let (store, path) = object_store::parse_url_opts(&parsed, std::env::vars().chain(plain_http)).map_err(store_error)?;
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+        Self::new(store.into(), path, runtime)
+    }
+
+    fn new(store: Arc<dyn ObjectStore>, path: ObjectPath, runtime: tokio::runtime::Runtime) -> Result<Self, Error> {
+        let size = runtime.block_on(store.head(&path)).map_err(store_error)?.size;
+        Ok(Object { store, path, size, runtime })
+    }
+
+    fn get(&self, range: std::ops::Range<u64>) -> Result<bytes::Bytes, Error> {
+        if range.is_empty() {
+            return Ok(bytes::Bytes::new());
+        }
+        self.runtime.block_on(self.store.get_range(&self.path, range)).map_err(store_error)
+    }
+}
+
+impl Engine for Object {
+    fn chunks(&self, options: &Options) -> Result<Vec<(u64, u64)>, Error> {
+        let count = chunk::count_for(self.size, options, |len| self.get(0..len as u64).map(|probe| probe.to_vec()))?;
+        let cuts = (1..count).map(|i| self.size / count * i).collect::<Vec<_>>();
+
+        // Where the line each cut falls in ends, all looked up at once
+        let ends = self.runtime.block_on(async {
+            let probes = cuts.into_iter().map(|cut| {
+                let (store, path, size) = (self.store.clone(), self.path.clone(), self.size);
+                tokio::spawn(async move { line_end(&*store, &path, cut, size).await })
+            }).collect::<Vec<_>>();
+            let mut ends = Vec::with_capacity(probes.len());
+            for probe in probes {
+                ends.push(probe.await.map_err(|e| Error::Other(e.to_string()))?.map_err(store_error)?);
+            }
+            Ok::<_, Error>(ends)
+        })?;
+
+        let mut chunks = Vec::with_capacity(ends.len() + 1);
+        let mut start = 0;
+        for end in ends.into_iter().chain([self.size]) {
+            // On tiny objects several cuts can land in the same line
+            if start < end {
+                chunks.push((start, end));
+                start = end;
+            }
+        }
+        Ok(chunks)
+    }
+
+    fn read_chunk(&self, start: u64, end: u64, data_map: &mut StationMap, options: &Options) -> Result<(), Error> {
+        // Same as Buffered's loop, with the parts of the response for reads
+        let mut buffer = Vec::new();
+        // Offset of the start of the buffer in the object
+        let mut buffer_offset = start;
+        let get = GetOptions { range: Some((start..end).into()), ..Default::default() };
+        let mut parts = timed(options, Phase::Io, || self.runtime.block_on(self.store.get_opts(&self.path, get))).map_err(store_error)?.into_stream();
+        while let Some(part) = timed(options, Phase::Io, || self.runtime.block_on(parts.next())) {
+            let part = part.map_err(store_error)?;
+            report_progress(options, part.len());
+            buffer.extend_from_slice(&part);
+
+            let consumed = timed(options, Phase::Parsing, || parse_lines(&buffer, data_map, options))
+                .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &buffer, at, buffer_offset))?;
+            buffer.drain(..consumed);
+            buffer_offset += consumed as u64;
+            if let Some(max) = max_line_len(options).filter(|&max| buffer.len() > max) {
+                return Err(Error::bad_line(Invalid::LongLine(max), &buffer, 0, buffer_offset));
+            }
+        }
+
+        // The end of the object, if it doesn't end in a newline
+        timed(options, Phase::Parsing, || parse_to_end(&buffer, data_map, options))
+            .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &buffer, at, buffer_offset))
+    }
+}
+
+// The offset just past the first \n at or after at, or size if there isn't one
+async fn line_end(store: &dyn ObjectStore, path: &ObjectPath, mut at: u64, size: u64) -> object_store::Result<u64> {
+    while at < size {
+        let end = size.min(at + PROBE_SIZE);
+        let bytes = store.get_range(path, at..end).await?;
+        if let Some(newline) = memchr::memchr(b'\n', &bytes) {
+            return Ok(at + newline as u64 + 1);
+        }
+        at = end;
+    }
+    Ok(size)
+}
+
+// Failing to reach the store is as much an I/O error as failing to read a file
+fn store_error(e: object_store::Error) -> Error {
+    std::io::Error::other(e).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn ranged_gets() {
+        let mut bytes = Vec::new();
+        for i in 0..20_000 {
+            bytes.extend_from_slice(format!("Station {};{}.{}\n", i % 97, i % 50, i % 10).as_bytes());
+        }
+        let store = Arc::new(InMemory::new());
+        let path = ObjectPath::from("measurements.txt");
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+        runtime.block_on(store.put(&path, bytes.clone().into())).unwrap();
+        let object = Object::new(store, path, runtime).unwrap();
+
+        assert_eq!(chunk::check(&bytes, &object.chunks(&Options::default()).unwrap()), []);
+
+        let options = Options::default();
+        let expected = crate::aggregate_bytes(&bytes, &options).unwrap();
+        assert_eq!(engine::aggregate(&object, &options).unwrap(), expected);
+    }
+}
@@ -0,0 +1,113 @@
+// Rayon, or without the parallel feature a stand-in for the little of it
+// the crate uses: its parallel iterators as plain ones on the calling
+// thread. The engines, the parser and the merges are the same code
+// either way, only run one chunk after another.
+
+#[cfg(feature = "parallel")]
+pub(crate) use rayon::prelude::*;
+#[cfg(feature = "parallel")]
+pub(crate) use rayon::{current_num_threads, current_thread_index};
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) use sequential::*;
+
+#[cfg(not(feature = "parallel"))]
+mod sequential {
+    use std::iter::{Map, Once, Zip};
+
+    /// There's only the calling thread
+    pub(crate) fn current_num_threads() -> usize {
+        1
+    }
+
+    pub(crate) fn current_thread_index() -> Option<usize> {
+        Some(0)
+    }
+
+    /// An iterator standing in for a parallel one
+    pub(crate) struct Seq<I>(I);
+
+    pub(crate) trait IntoParallelIterator: IntoIterator + Sized {
+        fn into_par_iter(self) -> Seq<Self::IntoIter> {
+            Seq(self.into_iter())
+        }
+    }
+
+    impl<T: IntoIterator> IntoParallelIterator for T {}
+
+    pub(crate) trait IntoParallelRefIterator<'a> {
+        type Iter: Iterator;
+
+        fn par_iter(&'a self) -> Seq<Self::Iter>;
+    }
+
+    impl<'a, T: ?Sized + 'a> IntoParallelRefIterator<'a> for T
+    where
+        &'a T: IntoIterator,
+    {
+        type Iter = <&'a T as IntoIterator>::IntoIter;
+
+        fn par_iter(&'a self) -> Seq<Self::Iter> {
+            Seq(self.into_iter())
+        }
+    }
+
+    /// The methods of rayon's that the crate calls, with the same
+    /// signatures (less the Send and Sync bounds)
+    pub(crate) trait ParallelIterator: Sized {
+        type Item;
+        type Iter: Iterator<Item = Self::Item>;
+
+        fn into_seq(self) -> Self::Iter;
+
+        fn map<B, F: FnMut(Self::Item) -> B>(self, f: F) -> Seq<Map<Self::Iter, F>> {
+            Seq(self.into_seq().map(f))
+        }
+
+        // Only zstd's seekable frames are zipped with their offsets
+        #[cfg_attr(not(feature = "zstd"), allow(dead_code))]
+        fn zip<U: IntoIterator>(self, other: U) -> Seq<Zip<Self::Iter, U::IntoIter>> {
+            Seq(self.into_seq().zip(other))
+        }
+
+        fn collect<C: FromIterator<Self::Item>>(self) -> C {
+            self.into_seq().collect()
+        }
+
+        /// Folds everything into one accumulator, as rayon would with
+        /// a single job
+        fn try_fold<T, E, ID, F>(self, identity: ID, fold: F) -> Seq<Once<Result<T, E>>>
+        where
+            ID: Fn() -> T,
+            F: Fn(T, Self::Item) -> Result<T, E>,
+        {
+            Seq(std::iter::once(self.into_seq().try_fold(identity(), fold)))
+        }
+
+        fn try_reduce<T, E, ID, OP>(self, identity: ID, op: OP) -> Result<T, E>
+        where
+            Self: ParallelIterator<Item = Result<T, E>>,
+            ID: Fn() -> T,
+            OP: Fn(T, T) -> Result<T, E>,
+        {
+            self.into_seq().try_fold(identity(), |a, b| op(a, b?))
+        }
+
+        fn reduce<ID, OP>(self, identity: ID, op: OP) -> Self::Item
+        where
+            ID: Fn() -> Self::Item,
+            OP: Fn(Self::Item, Self::Item) -> Self::Item,
+        {
+            self.into_seq().fold(identity(), op)
+        }
+    }
+
+    impl<I: Iterator> ParallelIterator for Seq<I> {
+        type Item = I::Item;
+        type Iter = I;
+
+        fn into_seq(self) -> I {
+            self.0
+        }
+    }
+}
@@ -0,0 +1,460 @@
+// Cutting lines into a station name and a temperature, and reading the
+// temperature, apart from the options and maps the rest of the parser
+// (parse_lines in lib.rs) deals with, so the two can be tested without
+// a file to aggregate.
+
+use crate::error::Invalid;
+use crate::{scan, temperature, Columns};
+
+/// A line the parser couldn't make sense of, by the offset of its start
+/// in the bytes being parsed, and why
+pub(crate) struct BadLine(pub usize, pub Invalid);
+
+/// A line cut into its station name and temperature, with the offsets of
+/// its start and its `\n` in the bytes being parsed
+pub(crate) struct Line<'a> {
+    pub start: usize,
+    pub end: usize,
+    pub name: &'a [u8],
+    pub temperature: &'a [u8],
+}
+
+/// The complete (`\n` terminated) lines in a buffer, cut at their last
+/// delimiter
+pub(crate) struct Lines<'a> {
+    bytes: &'a [u8],
+    lines: scan::Lines<'a>,
+}
+
+impl<'a> Lines<'a> {
+    pub fn new(bytes: &'a [u8], delimiter: u8) -> Self {
+        Lines { bytes, lines: scan::Lines::new(bytes, delimiter) }
+    }
+
+    /// How much of the buffer the lines returned so far cover, i.e.
+    /// where a partial line at its end starts
+    pub fn consumed(&self) -> usize {
+        self.lines.consumed()
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = Result<Line<'a>, BadLine>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, split, end) = self.lines.next()?;
+        Some(match split {
+            Some(split) => {
+                let (name, temperature) = split_at(&self.bytes[start..end], split - start);
+                Ok(Line { start, end, name, temperature })
+            }
+            None => Err(BadLine(start, Invalid::NoDelimiter)),
+        })
+    }
+}
+
+/// Cuts a line (without its `\n`) into its name and temperature.
+/// Temperatures can't contain the delimiter but names might (e.g. a
+/// comma), so it goes by the last one
+pub(crate) fn split_line(line: &[u8], delimiter: u8) -> Result<(&[u8], &[u8]), Invalid> {
+    let split = memchr::memrchr(delimiter, line).ok_or(Invalid::NoDelimiter)?;
+    Ok(split_at(line, split))
+}
+
+/// Picks the station's and the temperature's fields out of a line
+/// (without its `\n`) with other fields too
+pub(crate) fn select_columns(line: &[u8], delimiter: u8, columns: Columns) -> Result<(&[u8], &[u8]), Invalid> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let mut start = 0;
+    let fields = memchr::memchr_iter(delimiter, line).chain([line.len()]).map(|end| {
+        let field = (start, end);
+        start = end + 1;
+        Ok(field)
+    });
+    select(line, fields, columns)
+}
+
+/// [`split_line`] for a line whose fields can be quoted (RFC 4180), so
+/// a name can have the delimiter in it. The name is everything before
+/// the last field, and both come without their quotes
+pub(crate) fn split_quoted(line: &[u8], delimiter: u8) -> Result<(&[u8], &[u8]), Invalid> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let (mut fields, mut last) = (0, (0, 0));
+    for field in quoted_fields(line, delimiter) {
+        last = field?;
+        fields += 1;
+    }
+    if fields < 2 {
+        return Err(Invalid::NoDelimiter);
+    }
+    Ok((unquote(&line[..last.0 - 1], delimiter)?, unquote(&line[last.0..last.1], delimiter)?))
+}
+
+/// [`select_columns`] for a line whose fields can be quoted
+pub(crate) fn select_quoted(line: &[u8], delimiter: u8, columns: Columns) -> Result<(&[u8], &[u8]), Invalid> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let (key, value) = select(line, quoted_fields(line, delimiter), columns)?;
+    Ok((unquote(key, delimiter)?, unquote(value, delimiter)?))
+}
+
+// The key's and the value's fields, from the spans of a line's fields
+fn select(line: &[u8], fields: impl Iterator<Item = Result<(usize, usize), Invalid>>, columns: Columns) -> Result<(&[u8], &[u8]), Invalid> {
+    let last = columns.key.max(columns.value);
+    let (mut key, mut value) = (None, None);
+    for (i, field) in fields.enumerate() {
+        let (start, end) = field?;
+        if i == columns.key {
+            key = Some(&line[start..end]);
+        }
+        if i == columns.value {
+            value = Some(&line[start..end]);
+        }
+        if i == last {
+            break;
+        }
+    }
+    key.zip(value).ok_or(Invalid::Columns(last + 1))
+}
+
+// The spans of a line's fields, quotes and all
+fn quoted_fields(line: &[u8], delimiter: u8) -> impl Iterator<Item = Result<(usize, usize), Invalid>> + '_ {
+    let mut next = Some(0);
+    std::iter::from_fn(move || {
+        let start = next?;
+        let field = quoted_field_end(line, start, delimiter).map(|end| (start, end));
+        next = match field {
+            Ok((_, end)) if end < line.len() => Some(end + 1),
+            _ => None,
+        };
+        Some(field)
+    })
+}
+
+// Where the field starting at start ends. A quoted one goes up to its
+// closing quote, which has to be followed by the delimiter or nothing
+fn quoted_field_end(line: &[u8], start: usize, delimiter: u8) -> Result<usize, Invalid> {
+    if line.get(start) != Some(&b'"') {
+        return Ok(memchr::memchr(delimiter, &line[start..]).map_or(line.len(), |end| start + end));
+    }
+    let mut at = start + 1;
+    loop {
+        let quote = at + memchr::memchr(b'"', &line[at..]).ok_or(Invalid::Quote)?;
+        match line.get(quote + 1) {
+            // An escaped quote
+            Some(b'"') => at = quote + 2,
+            Some(&next) if next != delimiter => return Err(Invalid::Quote),
+            _ => return Ok(quote + 1),
+        }
+    }
+}
+
+// A field without its quotes. One with escaped quotes in it is left
+// quoted, as it can't be unescaped in place, for unescape to put right
+// once the stations are all aggregated
+fn unquote(field: &[u8], delimiter: u8) -> Result<&[u8], Invalid> {
+    if field.first() != Some(&b'"') {
+        return Ok(field);
+    }
+    if quoted_field_end(field, 0, delimiter)? != field.len() {
+        return Err(Invalid::Quote);
+    }
+    let inside = &field[1..field.len() - 1];
+    Ok(if memchr::memchr(b'"', inside).is_some() { field } else { inside })
+}
+
+/// A name the parser left quoted, without its quotes and with its
+/// escaped ones unescaped
+pub(crate) fn unescape(name: &[u8]) -> Vec<u8> {
+    let inside = &name[1..name.len() - 1];
+    let mut unescaped = Vec::with_capacity(inside.len());
+    let mut quote = false;
+    for &byte in inside {
+        if byte == b'"' && quote {
+            quote = false;
+            continue;
+        }
+        quote = byte == b'"';
+        unescaped.push(byte);
+    }
+    unescaped
+}
+
+// split_line, for a line whose last delimiter is already known
+#[inline]
+fn split_at(line: &[u8], split: usize) -> (&[u8], &[u8]) {
+    // Tolerate Windows line endings
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    (&line[..split], &line[split + 1..])
+}
+
+/// Parses a temperature into units of `10^-precision` degrees. Strictly,
+/// only the challenge's own shapes are taken. scratch is space for the
+/// digits
+#[inline]
+pub(crate) fn parse_temperature(digits: &[u8], precision: u8, strict: bool, scratch: &mut Vec<u8>) -> Result<i32, Invalid> {
+    // Nearly every temperature is in one of the challenge's own shapes,
+    // which don't need the general parser
+    let tenths = if precision == 1 { temperature::parse_tenths(digits) } else { None };
+    match tenths {
+        Some(tenths) => Ok(tenths),
+        None if strict => Err(Invalid::Temperature),
+        None => parse_general(digits, precision, scratch),
+    }
+}
+
+/// Parses a value from a column of a delimited file. That's whatever
+/// parse_temperature takes, and also numbers with fewer decimals than
+/// the precision (so 12 is 12.0, rather than 1.2)
+pub(crate) fn parse_value(digits: &[u8], precision: u8, strict: bool, scratch: &mut Vec<u8>) -> Result<i32, Invalid> {
+    let tenths = if precision == 1 { temperature::parse_tenths(digits) } else { None };
+    match tenths {
+        Some(tenths) => Ok(tenths),
+        None if strict => Err(Invalid::Temperature),
+        None => parse_padded(digits, precision, scratch),
+    }
+}
+
+// Parses any temperature the precision allows
+fn parse_general(digits: &[u8], precision: u8, scratch: &mut Vec<u8>) -> Result<i32, Invalid> {
+    if precision != 1 {
+        return parse_padded(digits, precision, scratch);
+    }
+
+    // Exactly one decimal, or 12 and 1.23 would be read as 1.2 and 12.3
+    let decimals = memchr::memchr(b'.', digits).map_or(0, |dot| digits.len() - dot - 1);
+    if decimals != 1 || memchr::memrchr(b'.', digits) != Some(digits.len() - 2) {
+        return Err(Invalid::Temperature);
+    }
+
+    // We skip the period in the temperature so
+    // that it parses as an integer number of tenths
+    scratch.clear();
+    scratch.extend(digits.iter().filter(|&&c| c != b'.'));
+    Ok(parse_integer::<i16>(scratch).ok_or(Invalid::Temperature)? as i32)
+}
+
+// Parses a number with up to precision decimals into units of
+// 10^-precision
+fn parse_padded(digits: &[u8], precision: u8, scratch: &mut Vec<u8>) -> Result<i32, Invalid> {
+    scratch.clear();
+    scratch.extend(digits.iter().filter(|&&c| c != b'.'));
+
+    // With more decimals, fewer are padded out (12.5 is 12.50) but
+    // more can't be represented
+    let decimals = memchr::memchr(b'.', digits).map_or(0, |dot| digits.len() - dot - 1);
+    let padding = (precision as usize).checked_sub(decimals).ok_or(Invalid::Temperature)?;
+    scratch.resize(scratch.len() + padding, b'0');
+    let value = parse_integer::<i32>(scratch).ok_or(Invalid::Temperature)?;
+
+    // Held to the range a tenth of a degree has, as the squares of
+    // anything much larger would soon overflow a record's sum of them
+    if value.unsigned_abs() as u64 > max_magnitude(precision) {
+        return Err(Invalid::Temperature);
+    }
+    Ok(value)
+}
+
+/// Parses an optionally negative run of ASCII digits, with atoi_simd
+/// when the simd feature is on
+#[cfg(feature = "simd")]
+#[inline]
+pub(crate) fn parse_integer<T: atoi_simd::Parse>(digits: &[u8]) -> Option<T> {
+    atoi_simd::parse(digits).ok()
+}
+
+#[cfg(not(feature = "simd"))]
+#[inline]
+pub(crate) fn parse_integer<T: TryFrom<i64>>(digits: &[u8]) -> Option<T> {
+    parse_scalar(digits)
+}
+
+// What atoi_simd takes, with a plain loop: no `+`, and no more digits
+// (leading zeros included) than it reads for the type
+#[cfg(any(not(feature = "simd"), test))]
+fn parse_scalar<T: TryFrom<i64>>(digits: &[u8]) -> Option<T> {
+    let (negative, digits) = match digits.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, digits),
+    };
+    let max_digits = if std::mem::size_of::<T>() > 4 { 19 } else { 16 };
+    if digits.is_empty() || digits.len() > max_digits || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    // Summed below zero, which reaches i64::MIN
+    let value = digits.iter().try_fold(0i64, |value, &digit| value.checked_mul(10)?.checked_sub((digit - b'0') as i64))?;
+    T::try_from(if negative { value } else { value.checked_neg()? }).ok()
+}
+
+// The furthest from zero a temperature can be, in its precision's units:
+// 3276.7 degrees, as far as an i16 of tenths goes
+fn max_magnitude(precision: u8) -> u64 {
+    10u64.checked_pow(precision as u32).map_or(u64::MAX, |scale| (i16::MAX as u64).saturating_mul(scale) / 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A line's start, name and temperature in tenths
+    type Parsed<'a> = (usize, &'a [u8], i16);
+
+    // Every line in bytes, or where and why the first bad one is bad
+    fn parse_all(bytes: &[u8]) -> Result<Vec<Parsed<'_>>, (usize, Invalid)> {
+        Lines::new(bytes, b';').map(|line| {
+            let Line { start, name, temperature, .. } = line.map_err(|BadLine(at, invalid)| (at, invalid))?;
+            let tenths = parse_temperature(temperature, 1, false, &mut Vec::new()).map_err(|invalid| (start, invalid))?;
+            Ok((start, name, tenths as i16))
+        }).collect()
+    }
+
+    // A line (without its \n) in the challenge's own format as the
+    // aggregator reads it, into its name and temperature in tenths: by
+    // Lines when it's \n terminated, and with split_line when it's the
+    // last in the input, which have to agree
+    fn read_line(line: &[u8]) -> Result<(Vec<u8>, i16), Invalid> {
+        let terminated = parse_all(&[line, b"\n"].concat())
+            .map(|parsed| (parsed[0].1.to_vec(), parsed[0].2))
+            .map_err(|(_, invalid)| invalid);
+        let last = split_line(line, b';')
+            .and_then(|(name, digits)| Ok((name.to_vec(), parse_temperature(digits, 1, false, &mut Vec::new())? as i16)));
+        assert_eq!(terminated, last, "{:?}", String::from_utf8_lossy(line));
+        terminated
+    }
+
+    #[test]
+    fn temperatures() {
+        for (line, tenths) in [
+            (&b"Hamburg;12.0"[..], 120), (b"Hamburg;-12.0", -120), (b"Hamburg;0.0", 0), (b"Hamburg;-0.0", 0),
+            (b"Hamburg;1.2", 12), (b"Hamburg;-1.2", -12), (b"Hamburg;99.9", 999), (b"Hamburg;-99.9", -999),
+            (b"Hamburg;100.0", 1000), (b"Hamburg;-273.1", -2731), (b"Hamburg;1.2\r", 12),
+        ] {
+            assert_eq!(read_line(line), Ok((b"Hamburg".to_vec(), tenths)), "{:?}", String::from_utf8_lossy(line));
+        }
+    }
+
+    #[test]
+    fn scalar_integers() {
+        let inputs = [
+            "", "-", "+1", "0", "-0", "12", "-12", "0012", "-00001", "1a", " 1", "--1", "1-",
+            "32767", "32768", "-32768", "-32769", "2147483647", "2147483648", "-2147483648", "-2147483649",
+            "0000000000000001", "00000000000000001", "-0000000000000001", "-00000000000000001",
+            "9223372036854775807", "9223372036854775808", "-9223372036854775808", "-9223372036854775809",
+            "0000000000000000001", "00000000000000000001",
+        ];
+        for input in inputs {
+            let digits = input.as_bytes();
+            assert_eq!(parse_scalar::<i16>(digits), input.parse::<i16>().ok().filter(|_| fits(input, 16)), "{:?}", input);
+            assert_eq!(parse_scalar::<i32>(digits), input.parse::<i32>().ok().filter(|_| fits(input, 16)), "{:?}", input);
+            assert_eq!(parse_scalar::<i64>(digits), input.parse::<i64>().ok().filter(|_| fits(input, 19)), "{:?}", input);
+            #[cfg(feature = "simd")]
+            {
+                assert_eq!(parse_scalar::<i16>(digits), parse_integer::<i16>(digits), "{:?}", input);
+                assert_eq!(parse_scalar::<i32>(digits), parse_integer::<i32>(digits), "{:?}", input);
+                assert_eq!(parse_scalar::<i64>(digits), parse_integer::<i64>(digits), "{:?}", input);
+            }
+        }
+    }
+
+    // Whether input has no `+` and at most max digits
+    fn fits(input: &str, max: usize) -> bool {
+        !input.starts_with('+') && input.trim_start_matches('-').len() <= max
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(read_line(b"St. John's;15.2"), Ok((b"St. John's".to_vec(), 152)));
+        assert_eq!(read_line(b"a;b;1.0"), Ok((b"a;b".to_vec(), 10)));
+        assert_eq!(read_line("Zürich;-3.4".as_bytes()), Ok(("Zürich".as_bytes().to_vec(), -34)));
+        assert_eq!(read_line(b";1.0"), Ok((Vec::new(), 10)));
+    }
+
+    #[test]
+    fn bad_lines() {
+        assert_eq!(read_line(b"Hamburg 12.0"), Err(Invalid::NoDelimiter));
+        assert_eq!(read_line(b""), Err(Invalid::NoDelimiter));
+        for line in [&b"Hamburg;"[..], b"Hamburg;-", b"Hamburg;abc", b"Hamburg;1.x", b"Hamburg;3276.8", b"Hamburg;12.0;", b"Hamburg;1.23", b"Hamburg;12", b"Hamburg;1..", b"Hamburg;1.2."] {
+            assert_eq!(read_line(line), Err(Invalid::Temperature), "{:?}", String::from_utf8_lossy(line));
+        }
+    }
+
+    #[test]
+    fn strict_and_precision() {
+        assert_eq!(parse_temperature(b"100.0", 1, true, &mut Vec::new()), Err(Invalid::Temperature));
+        assert_eq!(parse_temperature(b"-9.9", 1, true, &mut Vec::new()), Ok(-99));
+        assert_eq!(parse_temperature(b"12.5", 2, false, &mut Vec::new()), Ok(1250));
+        assert_eq!(parse_temperature(b"-12.25", 2, false, &mut Vec::new()), Ok(-1225));
+        assert_eq!(parse_temperature(b"12.125", 2, false, &mut Vec::new()), Err(Invalid::Temperature));
+        assert_eq!(parse_temperature(b"-3276.7", 3, false, &mut Vec::new()), Ok(-3_276_700));
+        assert_eq!(parse_temperature(b"3276.701", 3, false, &mut Vec::new()), Err(Invalid::Temperature));
+    }
+
+    #[test]
+    fn columns() {
+        let columns = Columns { key: 1, value: 3 };
+        assert_eq!(select_columns(b"2024,Hamburg,x,12.5,y\r", b',', columns), Ok((&b"Hamburg"[..], &b"12.5"[..])));
+        assert_eq!(select_columns(b"2024,Hamburg,x,12.5", b',', columns), Ok((&b"Hamburg"[..], &b"12.5"[..])));
+        assert_eq!(select_columns(b"2024,Hamburg,x", b',', columns), Err(Invalid::Columns(4)));
+        assert_eq!(select_columns(b"12.5;Hamburg", b';', Columns { key: 1, value: 0 }), Ok((&b"Hamburg"[..], &b"12.5"[..])));
+
+        assert_eq!(parse_value(b"12", 1, false, &mut Vec::new()), Ok(120));
+        assert_eq!(parse_value(b"-7.5", 1, false, &mut Vec::new()), Ok(-75));
+        assert_eq!(parse_value(b"1.25", 1, false, &mut Vec::new()), Err(Invalid::Temperature));
+        assert_eq!(parse_value(b"3", 2, false, &mut Vec::new()), Ok(300));
+    }
+
+    #[test]
+    fn quoted() {
+        assert_eq!(split_quoted(b"\"Foo;Bar\";12.3\r", b';'), Ok((&b"Foo;Bar"[..], &b"12.3"[..])));
+        assert_eq!(split_quoted(b"Foo;\"12.3\"", b';'), Ok((&b"Foo"[..], &b"12.3"[..])));
+        assert_eq!(split_quoted(b"Foo;Bar;12.3", b';'), Ok((&b"Foo;Bar"[..], &b"12.3"[..])));
+        assert_eq!(split_quoted(b"\"Foo;12.3\"", b';'), Err(Invalid::NoDelimiter));
+        assert_eq!(split_quoted(b"\"Foo\"x;12.3", b';'), Err(Invalid::Quote));
+        assert_eq!(split_quoted(b"\"Foo;12.3", b';'), Err(Invalid::Quote));
+
+        let (name, _) = split_quoted(b"\"Foo \"\"Bar\"\"\";12.3", b';').unwrap();
+        assert_eq!(name, b"\"Foo \"\"Bar\"\"\"");
+        assert_eq!(unescape(name), b"Foo \"Bar\"");
+
+        let columns = Columns { key: 1, value: 2 };
+        assert_eq!(select_quoted(b"1,\"Hamburg, DE\",\"12\",x", b',', columns), Ok((&b"Hamburg, DE"[..], &b"12"[..])));
+        assert_eq!(select_quoted(b"\"1,2\",Hamburg", b',', columns), Err(Invalid::Columns(3)));
+    }
+
+    #[test]
+    fn lines() {
+        let bytes = b"a;1.0\nb;-2.5\r\nc;3";
+        assert_eq!(parse_all(bytes), Ok(vec![(0, &b"a"[..], 10), (6, &b"b"[..], -25)]));
+
+        let mut lines = Lines::new(bytes, b';');
+        assert_eq!(lines.by_ref().count(), 2);
+        assert_eq!(lines.consumed(), 14);
+
+        assert_eq!(parse_all(b"a;1.0\nb 2.0\n").err(), Some((6, Invalid::NoDelimiter)));
+        assert_eq!(parse_all(b"a;1.0\nb;x\n").err(), Some((6, Invalid::Temperature)));
+        assert_eq!(parse_all(b""), Ok(vec![]));
+    }
+
+    // A buffer cut anywhere, and the partial line left at the end of the
+    // first chunk carried on to the second, parses the same as it did whole
+    #[test]
+    fn chunk_boundaries() {
+        let bytes = b"Hamburg;12.0\nBulawayo;8.9\nPalembang;-38.8\nSt. John's;1.5\nCracow;-0.2\n";
+        let whole = parse_all(bytes).unwrap().into_iter().map(|(_, name, tenths)| (name, tenths)).collect::<Vec<_>>();
+
+        for cut in 0..=bytes.len() {
+            let mut lines = Lines::new(&bytes[..cut], b';');
+            let mut parsed = lines.by_ref().map(|line| {
+                let line = line.ok().unwrap();
+                (line.name.to_vec(), parse_temperature(line.temperature, 1, false, &mut Vec::new()).unwrap() as i16)
+            }).collect::<Vec<_>>();
+
+            let mut carry = bytes[lines.consumed()..cut].to_vec();
+            carry.extend_from_slice(&bytes[cut..]);
+            parsed.extend(parse_all(&carry).unwrap().into_iter().map(|(_, name, tenths)| (name.to_vec(), tenths)));
+
+            let parsed = parsed.iter().map(|(name, tenths)| (&name[..], *tenths)).collect::<Vec<_>>();
+            assert_eq!(parsed, whole, "cut at {}", cut);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! A compact binary encoding of a [`StationMap`], for partial results
+//! that are saved between runs or sent between machines.
+//!
+//! Everything is little-endian:
+//!
+//! ```text
+//! magic      8 bytes, "1BRCPAR" and the version, "4"
+//! stations   u64
+//! then for each station
+//!   name         u32 length, then the bytes
+//!   decimals     u8
+//!   min, max     i32 each
+//!   total        i128
+//!   count        u64
+//!   sum_squares  i128
+//!   histogram    u32 number of non-empty buckets (u32::MAX for none),
+//!                then an (i16 temperature, u32 count) pair for each
+//! checksum   u64 FNV-1a hash of everything before it
+//! ```
+
+use std::path::Path;
+
+use crate::{Error, Histogram, Record, StationKey, StationMap};
+
+// Version 2 widened min and max, 3 the totals, and 4 added the
+// checksum. Totals are always written as i128, so builds with and
+// without wide-totals can read each other's partials
+const MAGIC: &[u8; 7] = b"1BRCPAR";
+const VERSION: u8 = b'4';
+const NO_HISTOGRAM: u32 = u32::MAX;
+
+/// Encodes the stations in `data`
+// The casts to i128 do nothing with wide-totals
+#[allow(clippy::unnecessary_cast)]
+pub fn encode(data: &StationMap) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(16 + data.len() * 64);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+
+    for (name, record) in data {
+        bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(name);
+        bytes.push(record.decimals);
+        bytes.extend_from_slice(&record.min.to_le_bytes());
+        bytes.extend_from_slice(&record.max.to_le_bytes());
+        bytes.extend_from_slice(&(record.total as i128).to_le_bytes());
+        bytes.extend_from_slice(&record.count.to_le_bytes());
+        bytes.extend_from_slice(&(record.sum_squares as i128).to_le_bytes());
+
+        match &record.histogram {
+            Some(histogram) => {
+                let buckets = (Histogram::MIN..=Histogram::MAX)
+                    .map(|temp| (temp, histogram.count(temp)))
+                    .filter(|&(_, count)| count > 0)
+                    .collect::<Vec<_>>();
+                bytes.extend_from_slice(&(buckets.len() as u32).to_le_bytes());
+                for (temp, count) in buckets {
+                    bytes.extend_from_slice(&temp.to_le_bytes());
+                    bytes.extend_from_slice(&count.to_le_bytes());
+                }
+            }
+            None => bytes.extend_from_slice(&NO_HISTOGRAM.to_le_bytes()),
+        }
+    }
+
+    let checksum = fnv1a(&bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+/// Decodes stations encoded by [`encode`], or returns `None` if `bytes`
+/// aren't exactly one encoded map of this version
+pub fn decode(bytes: &[u8]) -> Option<StationMap> {
+    check(bytes).ok()
+}
+
+// Why bytes can't be decoded
+#[derive(Debug, PartialEq, Eq)]
+enum Problem {
+    NotPartial,
+    Version(u8),
+    Checksum,
+    Malformed,
+}
+
+fn check(bytes: &[u8]) -> Result<StationMap, Problem> {
+    let (body, checksum) = bytes.split_last_chunk::<8>().ok_or(Problem::NotPartial)?;
+    match body.get(..MAGIC.len() + 1) {
+        Some([magic @ .., version]) if magic == MAGIC => {
+            if *version != VERSION {
+                return Err(Problem::Version(*version));
+            }
+        }
+        _ => return Err(Problem::NotPartial),
+    }
+    if fnv1a(body) != u64::from_le_bytes(*checksum) {
+        return Err(Problem::Checksum);
+    }
+
+    decode_stations(&body[MAGIC.len() + 1..]).ok_or(Problem::Malformed)
+}
+
+// The conversions from i128 do nothing with wide-totals
+#[allow(clippy::useless_conversion)]
+fn decode_stations(bytes: &[u8]) -> Option<StationMap> {
+    let mut reader = Reader(bytes);
+    let stations = u64::from_le_bytes(reader.array()?);
+    let mut data = StationMap::default();
+
+    for _ in 0..stations {
+        let len = u32::from_le_bytes(reader.array()?) as usize;
+        let name = StationKey::from(reader.take(len)?);
+
+        let [decimals] = reader.array()?;
+        let min = i32::from_le_bytes(reader.array()?);
+        let max = i32::from_le_bytes(reader.array()?);
+        // Totals too big for this build's Total make the partial invalid
+        let total = i128::from_le_bytes(reader.array()?).try_into().ok()?;
+        let count = u64::from_le_bytes(reader.array()?);
+        let sum_squares = i128::from_le_bytes(reader.array()?).try_into().ok()?;
+
+        let histogram = match u32::from_le_bytes(reader.array()?) {
+            NO_HISTOGRAM => None,
+            buckets => {
+                let mut histogram = Box::new(Histogram::new());
+                for _ in 0..buckets {
+                    let temp = i16::from_le_bytes(reader.array()?);
+                    histogram.set_count(temp, u32::from_le_bytes(reader.array()?));
+                }
+                Some(histogram)
+            }
+        };
+
+        data.insert(name, Record { min, max, total, count, sum_squares, histogram, decimals });
+    }
+
+    reader.0.is_empty().then_some(data)
+}
+
+/// Reads and decodes the partial results file at `path`
+pub fn load(path: &Path) -> Result<StationMap, Error> {
+    let bytes = std::fs::read(path).map_err(|source| Error::Io { path: Some(path.to_path_buf()), source })?;
+    check(&bytes).map_err(|problem| match problem {
+        Problem::NotPartial => format!("{} isn't a partial results file", path.display()),
+        Problem::Version(version) => format!(
+            "{} is a version {} partial, and this build reads version {}",
+            path.display(), version as char, VERSION as char,
+        ),
+        Problem::Checksum => format!("{} is corrupt (its checksum doesn't match)", path.display()),
+        Problem::Malformed => format!("{} is malformed, or has totals too big for this build (see the wide-totals feature)", path.display()),
+    }.into())
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)?.try_into().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut data = StationMap::default();
+        let mut hamburg = Record::with_histogram(120);
+        hamburg.add(-35);
+        hamburg.add(999);
+        data.insert(StationKey::from(&b"Hamburg"[..]), hamburg);
+        data.insert(StationKey::from("Zürich".as_bytes()), Record::new(-999));
+
+        let bytes = encode(&data);
+        assert_eq!(decode(&bytes), Some(data));
+
+        assert_eq!(decode(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(decode(b"1BRCPAR"), None);
+    }
+
+    #[test]
+    fn checks_versions_and_checksums() {
+        let mut bytes = encode(&StationMap::from_iter([(StationKey::from(&b"Hamburg"[..]), Record::new(120))]));
+        assert!(check(&bytes).is_ok());
+
+        // Any bit flipped
+        bytes[20] ^= 1;
+        assert_eq!(check(&bytes), Err(Problem::Checksum));
+        bytes[20] ^= 1;
+        bytes[7] = b'3';
+        assert_eq!(check(&bytes), Err(Problem::Version(b'3')));
+        assert_eq!(check(b"Hamburg;12.0\n"), Err(Problem::NotPartial));
+    }
+
+    #[test]
+    fn loads_files() {
+        let path = std::env::temp_dir().join(format!("brc-partial-{}.partial", std::process::id()));
+        let data = StationMap::from_iter([(StationKey::from(&b"Hamburg"[..]), Record::new(120))]);
+        std::fs::write(&path, encode(&data)).unwrap();
+        assert_eq!(load(&path).unwrap(), data);
+
+        std::fs::write(&path, "Hamburg;12.0\n").unwrap();
+        assert!(load(&path).unwrap_err().to_string().contains("isn't a partial"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(load(&path), Err(Error::Io { .. })));
+    }
+}
@@ -0,0 +1,226 @@
+// Reading and parsing as stages of their own, for storage and CPUs that
+// aren't matched.
+//
+// Options::io_threads readers take the chunks in turn and read each a
+// block at a time into buffers from a shared pool. A full block is cut
+// after its last \n, the rest carried over to the start of the next,
+// and handed over a bounded queue to Options::parse_threads parsers,
+// which give the buffer back to the pool once they've parsed it. Slow
+// storage leaves the parsers waiting on the queue, and fast storage
+// fills it until the readers wait on the pool; --timings has how long
+// each of them waited, which says which stage wants more threads.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Mutex;
+
+use crate::timings::{timed, Phase};
+use crate::{cancelled, chunk, max_line_len, merge_maps, new_map, parse_to_end, report_progress, BadLine, ChunkSource, Error, Options, StationMap};
+
+// A block of whole lines, with where it starts in the input for errors
+type Block = (u64, Vec<u8>);
+
+// What every reader shares
+struct Readers<'a, S> {
+    source: &'a S,
+    chunks: Vec<(u64, u64)>,
+    // The next chunk for a reader to take
+    next: AtomicUsize,
+    read_size: usize,
+    // The buffers nobody is using
+    pool: Mutex<Receiver<Vec<u8>>>,
+    // Set once a parser finds a bad line or a read fails, so the reads stop
+    failed: &'a AtomicBool,
+    options: &'a Options,
+}
+
+/// Aggregates the first `size` bytes of `source`, which is uncompressed,
+/// reading blocks of `read_size` bytes on the readers and parsing them on
+/// the parsers
+pub(crate) fn aggregate(source: &impl ChunkSource, size: u64, read_size: usize, options: &Options) -> Result<StationMap, Error> {
+    let readers = options.io_threads.unwrap_or(1).max(1);
+    let parsers = options.parse_threads
+        .unwrap_or_else(|| options.threads.unwrap_or_else(crate::par::current_num_threads).saturating_sub(readers))
+        .max(1);
+    let chunks = timed(options, Phase::Chunking, || chunk::source_chunks(source, size, options))?;
+    log::debug!("{} chunks, read by {} threads and parsed by {}", chunks.len(), readers, parsers);
+
+    // A block being filled by every reader, one being parsed by every
+    // parser and as many again queued up for them
+    let buffers = readers + 2 * parsers;
+    let (sender, queue) = sync_channel::<Block>(buffers);
+    let queue = Mutex::new(queue);
+    let (give_back, pool) = sync_channel::<Vec<u8>>(buffers);
+    for _ in readers..buffers {
+        let _ = give_back.send(Vec::with_capacity(read_size));
+    }
+    let failed = AtomicBool::new(false);
+    let shared = Readers { source, chunks, next: AtomicUsize::new(0), read_size, pool: Mutex::new(pool), failed: &failed, options };
+
+    std::thread::scope(|scope| {
+        let parsers = (0..parsers).map(|_| {
+            let (queue, give_back, failed) = (&queue, give_back.clone(), &failed);
+            scope.spawn(move || parse_blocks(queue, give_back, failed, options))
+        }).collect::<Vec<_>>();
+        drop(give_back);
+        let readers = (0..readers).map(|_| {
+            let (shared, sender) = (&shared, sender.clone());
+            scope.spawn(move || shared.read_chunks(sender))
+        }).collect::<Vec<_>>();
+        // The parsers finish once the last reader hangs up
+        drop(sender);
+
+        let read_result = readers.into_iter().map(|reader| reader.join().unwrap()).collect::<Result<Vec<()>, Error>>();
+
+        // The earliest bad line, if there are several
+        let mut data = StationMap::default();
+        let mut error: Option<Error> = None;
+        for result in parsers.into_iter().map(|parser| parser.join().unwrap()) {
+            match result {
+                Ok(data_map) => data = timed(options, Phase::Merging, || merge_maps(data, data_map)),
+                Err(e) => {
+                    let earlier = match (&e, &error) {
+                        (Error::Parse { offset, .. }, Some(Error::Parse { offset: first, .. })) => offset < first,
+                        _ => error.is_none(),
+                    };
+                    if earlier {
+                        error = Some(e);
+                    }
+                }
+            }
+        }
+
+        read_result?;
+        match error {
+            Some(error) => Err(error),
+            None => Ok(data),
+        }
+    })
+}
+
+// Parses blocks off the queue until the readers are done. After a bad
+// line it keeps taking them, so the readers never wait on a full queue
+fn parse_blocks(queue: &Mutex<Receiver<Block>>, give_back: SyncSender<Vec<u8>>, failed: &AtomicBool, options: &Options) -> Result<StationMap, Error> {
+    let mut data_map = new_map();
+    let mut error = None;
+
+    loop {
+        let block = timed(options, Phase::ParserStall, || queue.lock().unwrap().recv());
+        let Ok((offset, mut block)) = block else { break };
+        if error.is_none() {
+            if let Err(BadLine(at, invalid)) = timed(options, Phase::Parsing, || parse_to_end(&block, &mut data_map, options)) {
+                error = Some(Error::bad_line(invalid, &block, at, offset));
+                failed.store(true, Ordering::Relaxed);
+            }
+        }
+        block.clear();
+        let _ = give_back.send(block);
+    }
+
+    match error {
+        Some(error) => Err(error),
+        None => Ok(data_map),
+    }
+}
+
+impl<S: ChunkSource> Readers<'_, S> {
+    // Reads chunks until there are none left, or the parsers have gone
+    fn read_chunks(&self, sender: SyncSender<Block>) -> Result<(), Error> {
+        let mut block = Vec::with_capacity(self.read_size);
+        while let Some(&(start, end)) = self.chunks.get(self.next.fetch_add(1, Ordering::Relaxed)) {
+            match self.read_chunk(start, end, block, &sender)? {
+                Some(next) => block = next,
+                None => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    // Reads the chunk from start to end into blocks, starting with block,
+    // and sends them on. Returns the block to start the next chunk with,
+    // or None if there's to be no next chunk
+    fn read_chunk(&self, start: u64, end: u64, mut block: Vec<u8>, sender: &SyncSender<Block>) -> Result<Option<Vec<u8>>, Error> {
+        let options = self.options;
+        // Where block starts, and how far has been read
+        let (mut offset, mut at) = (start, start);
+
+        loop {
+            if self.failed.load(Ordering::Relaxed) || cancelled(options) {
+                return Ok(None);
+            }
+
+            // Fill the rest of the block, which may already start with the
+            // tail of a line carried over (or be full of one very long
+            // line, which needs more room)
+            let filled = block.len();
+            let room = match filled < self.read_size {
+                true => self.read_size - filled,
+                false => filled,
+            };
+            let room = room.min((end - at) as usize);
+            block.resize(filled + room, 0);
+            let read = match timed(options, Phase::Io, || self.source.read_at(at, &mut block[filled..])) {
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    block.truncate(filled);
+                    continue;
+                }
+                Err(e) => {
+                    self.failed.store(true, Ordering::Relaxed);
+                    return Err(e.into());
+                }
+            };
+            block.truncate(filled + read);
+            at += read as u64;
+            report_progress(options, read);
+
+            // The chunk ends with a whole line (or the input), so all of
+            // it goes, as does everything there is of a chunk that's
+            // been cut short
+            if at == end || read == 0 {
+                if block.is_empty() {
+                    return Ok(Some(block));
+                }
+                if !self.send((offset, block), sender) {
+                    return Ok(None);
+                }
+                return Ok(self.free_block());
+            }
+            if block.len() < self.read_size {
+                continue;
+            }
+
+            // Everything up to the last whole line goes, and the rest
+            // starts the next block. A block without a newline is one
+            // long line, which the parsers can reject once it's too long
+            match memchr::memrchr(b'\n', &block) {
+                Some(last) => {
+                    let Some(mut next) = self.free_block() else { return Ok(None) };
+                    next.extend_from_slice(&block[last + 1..]);
+                    block.truncate(last + 1);
+                    let len = block.len() as u64;
+                    if !self.send((offset, std::mem::replace(&mut block, next)), sender) {
+                        return Ok(None);
+                    }
+                    offset += len;
+                }
+                None if max_line_len(options).is_some_and(|max| block.len() > max) => {
+                    self.send((offset, block), sender);
+                    return Ok(None);
+                }
+                None => {}
+            }
+        }
+    }
+
+    // Queues a block for the parsers, waiting while the queue is full.
+    // False if the parsers have all gone
+    fn send(&self, block: Block, sender: &SyncSender<Block>) -> bool {
+        timed(self.options, Phase::ReaderStall, || sender.send(block)).is_ok()
+    }
+
+    // A buffer from the pool, waiting until a parser gives one back
+    fn free_block(&self) -> Option<Vec<u8>> {
+        timed(self.options, Phase::ReaderStall, || self.pool.lock().unwrap().recv()).ok()
+    }
+}
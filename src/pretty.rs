@@ -0,0 +1,120 @@
+// `--format table`: the results in aligned columns, for reading in a
+// terminal, with every station put together in a row at the bottom.
+
+use std::io::IsTerminal;
+
+use clap::ValueEnum;
+use one_billion_row_challenge::{Error, Record, StationKey};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{aggregates, metadata, station_name, Aggregate, AggregateValue, OutputArgs};
+
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const BLUE: &str = "\x1b[34m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// When the table is coloured
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Color {
+    /// When stdout is a terminal, and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    /// Auto, decided for results written to stdout or not
+    pub fn resolve(self, to_stdout: bool) -> Color {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        match self {
+            Color::Auto if to_stdout && !no_color && std::io::stdout().is_terminal() => Color::Always,
+            Color::Auto => Color::Never,
+            color => color,
+        }
+    }
+}
+
+pub fn format_table(data: &[(StationKey, Record)], output: &OutputArgs) -> Result<String, Error> {
+    let (unit, numbers) = (output.unit, &output.numbers);
+    let aggregates = aggregates(output, true);
+    let cells = |name: String, record: &Record| {
+        let mut cells = vec![name];
+        cells.extend(aggregates.iter().map(|aggregate| match aggregate.value(record, unit) {
+            AggregateValue::Count(count) => numbers.cell(&count.to_string(), true),
+            value => numbers.cell(&value.to_string(), numbers.thousands),
+        }));
+        cells
+    };
+
+    let mut header = vec!["Station".to_string()];
+    header.extend(aggregates.iter().map(|aggregate| capitalized(aggregate.name())));
+    header.extend(output.stats.iter().map(|stat| stat.name()));
+    let metadata = metadata::enrichment(output);
+    header.extend(metadata.iter().flat_map(|metadata| metadata.columns.iter().cloned()));
+    let mut rows = vec![header];
+    for (key, record) in data {
+        let mut row = cells(station_name(key)?.to_string(), record);
+        row.extend(output.stats.iter().map(|stat| numbers.cell(&format!("{:.*}", record.decimals as usize, stat.value(record, unit)), numbers.thousands)));
+        row.extend(metadata.iter().flat_map(|metadata| metadata.values(key)).map(str::to_string));
+        rows.push(row);
+    }
+    // The totals leave the stats out, as the histograms aren't combined,
+    // and aren't there alongside groups, which would count twice
+    let all = totals(data).filter(|_| !output.with_stations);
+    if let Some(all) = &all {
+        let stations = if data.len() == 1 { "station" } else { "stations" };
+        rows.push(cells(format!("{} {}", numbers.cell(&data.len().to_string(), true), stations), all));
+    }
+
+    let columns = rows[0].len();
+    // The metadata's columns are text, so they line up on the left
+    let text = columns - metadata.map_or(0, |metadata| metadata.columns.len());
+    let widths = (0..columns)
+        .map(|column| rows.iter().filter_map(|row| row.get(column)).map(|cell| cell.width()).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let color = output.color == Color::Always;
+    let style = |text: String, style: &str| if color { format!("{}{}{}", style, text, RESET) } else { text };
+
+    let mut to_print = String::new();
+    let last = if all.is_some() { rows.len() - 1 } else { usize::MAX };
+    for (i, row) in rows.iter().enumerate() {
+        if i == last {
+            let rule = "─".repeat(widths.iter().sum::<usize>() + 2 * (columns - 1));
+            to_print.push_str(&style(rule, DIM));
+            to_print.push('\n');
+        }
+
+        let line = row.iter().enumerate().map(|(column, cell)| {
+            let padding = " ".repeat(widths[column] - cell.width());
+            let cell = if column == 0 || column >= text { format!("{}{}", cell, padding) } else { format!("{}{}", padding, cell) };
+            match aggregates.get(column.wrapping_sub(1)) {
+                _ if i == 0 || i == last => style(cell, BOLD),
+                Some(Aggregate::Min) => style(cell, BLUE),
+                Some(Aggregate::Max) => style(cell, RED),
+                _ => cell,
+            }
+        }).collect::<Vec<_>>();
+        to_print.push_str(line.join("  ").trim_end());
+        to_print.push('\n');
+    }
+
+    Ok(to_print)
+}
+
+// Every station's measurements in one record
+fn totals(data: &[(StationKey, Record)]) -> Option<Record> {
+    data.iter()
+        .map(|(_, record)| Record { histogram: None, ..*record })
+        .reduce(|mut all, record| {
+            all.combine(&record);
+            all
+        })
+}
+
+// A column's header, from its name: Min from min
+fn capitalized(name: &str) -> String {
+    let mut chars = name.chars();
+    chars.next().map_or_else(String::new, |first| first.to_uppercase().chain(chars).collect())
+}
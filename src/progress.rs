@@ -0,0 +1,40 @@
+// A callback for how far an aggregation has got, for programs that would
+// rather be told than poll Options::progress.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Calls a function with the input bytes done so far and the total, for
+/// [`Options::on_progress`](crate::Options::on_progress). It's called
+/// from the workers, every time one finishes a block, so it should be
+/// quick. The total is 0 for a stream, whose size isn't known
+#[derive(Clone)]
+pub struct OnProgress {
+    callback: Arc<dyn Fn(u64, u64) + Send + Sync>,
+    // This run's bytes done and total
+    run: Arc<(AtomicU64, u64)>,
+}
+
+impl OnProgress {
+    pub fn new(callback: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        OnProgress { callback: Arc::new(callback), run: Arc::new((AtomicU64::new(0), 0)) }
+    }
+
+    // The same callback, counting from 0 of total
+    pub(crate) fn start(&self, total: u64) -> Self {
+        OnProgress { callback: self.callback.clone(), run: Arc::new((AtomicU64::new(0), total)) }
+    }
+
+    pub(crate) fn add(&self, bytes: usize) {
+        let (done, total) = &*self.run;
+        let done = done.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+        (self.callback)(done, *total);
+    }
+}
+
+impl fmt::Debug for OnProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OnProgress").field("done", &self.run.0).field("total", &self.run.1).finish_non_exhaustive()
+    }
+}
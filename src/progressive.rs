@@ -0,0 +1,68 @@
+// Results that fill in as the chunks are finished, for a program that
+// wants to show them converging rather than wait for the whole file.
+//
+// Every chunk is parsed into a map of its own, which the worker sends
+// back to the calling thread as soon as it's done. That thread merges
+// them into the running total as they come, and hands the callback a
+// copy of it every so often, so the callback doesn't need to be Send
+// and the workers never wait on it.
+
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::par::*;
+
+use crate::{cancelled, chunk, compression, finish_map, finish_run, in_pool, input_size, merge_maps, new_map, parse_to_end, regular_file, report_progress, starting, with_file, BadLine, Error, Options, Results};
+
+/// Aggregates the uncompressed measurements file at `path` as
+/// [`aggregate_stations`](crate::aggregate_stations) does, calling
+/// `on_snapshot` with the results of the chunks finished so far at most
+/// once every `every`, and once more with everything at the end, which
+/// is also what's returned. The callback runs on the calling thread
+/// while the workers carry on. A cancelled run has no last snapshot
+pub fn aggregate_progressive(path: &Path, options: &Options, every: Duration, mut on_snapshot: impl FnMut(&Results)) -> Result<Results, Error> {
+    let options = &starting(options, || input_size(path).unwrap_or(0));
+    let data = with_file(path, |file| {
+        regular_file(path, file)?;
+        if compression::detect_file(file)? != compression::Compression::None {
+            return Err(format!("{}: progressive results need an uncompressed file", path.display()).into());
+        }
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        let bytes = &mmap[..];
+
+        let (sender, chunk_maps) = mpsc::channel();
+        std::thread::scope(|scope| {
+            let workers = scope.spawn(move || in_pool(options, || {
+                chunk::chunks_in(bytes, options).into_par_iter().map(|(start, end)| {
+                    if cancelled(options) {
+                        return Ok(());
+                    }
+                    let mut data_map = new_map();
+                    parse_to_end(&bytes[start..end], &mut data_map, options)
+                        .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, bytes, start + at, 0))?;
+                    report_progress(options, end - start);
+                    // Nobody's listening once the calling thread has gone
+                    let _ = sender.send(data_map);
+                    Ok(())
+                }).collect::<Result<Vec<()>, Error>>()
+            }));
+
+            let mut data = new_map();
+            let mut last_snapshot = Instant::now();
+            for data_map in chunk_maps {
+                data = merge_maps(data, data_map);
+                if last_snapshot.elapsed() >= every {
+                    on_snapshot(&Results::from(finish_map(data.clone(), options)));
+                    last_snapshot = Instant::now();
+                }
+            }
+            workers.join().unwrap()?;
+            Ok(data)
+        })
+    })?;
+
+    let results = Results::from(finish_run(data, options)?);
+    on_snapshot(&results);
+    Ok(results)
+}
@@ -0,0 +1,436 @@
+// A small SQL-like query language over the aggregated stations, e.g.
+//
+//   SELECT station, mean, max WHERE mean > 20 AND count >= 100 ORDER BY max DESC LIMIT 10
+//
+// Every clause but SELECT is optional. Columns are station, min, mean,
+// max, count, stddev and variance, and `*` selects station, min, mean,
+// max and count. Conditions compare a column with a number, or station with a
+// 'quoted' name, and can be combined with AND, OR and parentheses.
+
+use std::cmp::Ordering;
+use std::fmt::Write as _;
+
+use one_billion_row_challenge::{Error, Record};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Column {
+    Station,
+    Min,
+    Mean,
+    Max,
+    Count,
+    StdDev,
+    Variance,
+}
+
+impl Column {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "station" => Column::Station,
+            "min" => Column::Min,
+            "mean" => Column::Mean,
+            "max" => Column::Max,
+            "count" => Column::Count,
+            "stddev" => Column::StdDev,
+            "variance" => Column::Variance,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Column::Station => "station",
+            Column::Min => "min",
+            Column::Mean => "mean",
+            Column::Max => "max",
+            Column::Count => "count",
+            Column::StdDev => "stddev",
+            Column::Variance => "variance",
+        }
+    }
+
+    fn value<'a>(self, station: &'a str, record: &Record) -> Value<'a> {
+        match self {
+            Column::Station => Value::Text(station),
+            Column::Min => Value::Number(record.min()),
+            Column::Mean => Value::Number(record.mean_rounded()),
+            Column::Max => Value::Number(record.max()),
+            Column::Count => Value::Number(record.count as f64),
+            Column::StdDev => Value::Number(record.round(record.stddev())),
+            Column::Variance => Value::Number(record.round(record.variance())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value<'a> {
+    Text(&'a str),
+    Number(f64),
+}
+
+impl Value<'_> {
+    fn compare(&self, other: &Literal) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Text(a), Literal::Text(b)) => Some(a.cmp(&b.as_str())),
+            (Value::Number(a), Literal::Number(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Literal {
+    Text(String),
+    Number(f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug)]
+enum Condition {
+    Compare(Column, Op, Literal),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    fn matches(&self, station: &str, record: &Record) -> bool {
+        match self {
+            Condition::Compare(column, op, literal) => {
+                let Some(ordering) = column.value(station, record).compare(literal) else { return false };
+                match op {
+                    Op::Eq => ordering == Ordering::Equal,
+                    Op::Ne => ordering != Ordering::Equal,
+                    Op::Lt => ordering == Ordering::Less,
+                    Op::Le => ordering != Ordering::Greater,
+                    Op::Gt => ordering == Ordering::Greater,
+                    Op::Ge => ordering != Ordering::Less,
+                }
+            }
+            Condition::And(a, b) => a.matches(station, record) && b.matches(station, record),
+            Condition::Or(a, b) => a.matches(station, record) || b.matches(station, record),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Query {
+    columns: Vec<Column>,
+    condition: Option<Condition>,
+    order_by: Option<(Column, bool)>,
+    limit: Option<usize>,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Self, Error> {
+        let tokens = tokenize(query)?;
+        let mut parser = Parser { tokens: &tokens, position: 0 };
+
+        let query = parser.query()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(token) => Err(format!("unexpected {} in query", token).into()),
+        }
+    }
+
+    // Runs the query over stations, which are in alphabetical order,
+    // and returns the results as a header line and one `;` separated
+    // line per station
+    pub fn run(&self, stations: &[(&str, &Record)]) -> String {
+        let mut rows = stations.iter()
+            .filter(|(station, record)| self.condition.as_ref().is_none_or(|c| c.matches(station, record)))
+            .collect::<Vec<_>>();
+
+        // Stable, so ties stay alphabetical
+        if let Some((column, descending)) = self.order_by {
+            rows.sort_by(|(s1, r1), (s2, r2)| {
+                let ordering = match (column.value(s1, r1), column.value(s2, r2)) {
+                    (Value::Text(a), Value::Text(b)) => a.cmp(b),
+                    (Value::Number(a), Value::Number(b)) => a.total_cmp(&b),
+                    _ => Ordering::Equal,
+                };
+                if descending { ordering.reverse() } else { ordering }
+            });
+        }
+        if let Some(limit) = self.limit {
+            rows.truncate(limit);
+        }
+
+        let mut output = self.columns.iter().map(|c| c.name()).collect::<Vec<_>>().join(";");
+        output.push('\n');
+
+        for (station, record) in rows {
+            for (i, column) in self.columns.iter().enumerate() {
+                if i > 0 {
+                    output.push(';');
+                }
+                match column.value(station, record) {
+                    Value::Text(text) => output.push_str(text),
+                    Value::Number(number) if *column == Column::Count => write!(output, "{}", number).unwrap(),
+                    Value::Number(number) => write!(output, "{:.*}", record.decimals as usize, number).unwrap(),
+                }
+            }
+            output.push('\n');
+        }
+
+        output
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f64),
+    Text(String),
+    Symbol(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{}`", word),
+            Token::Number(number) => write!(f, "`{}`", number),
+            Token::Text(text) => write!(f, "'{}'", text),
+            Token::Symbol(symbol) => write!(f, "`{}`", symbol),
+        }
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, Error> {
+    const SYMBOLS: [&str; 11] = ["<=", ">=", "!=", "<>", "=", "<", ">", ",", "(", ")", "*"];
+
+    let mut tokens = Vec::new();
+    let mut rest = query.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+            tokens.push(Token::Symbol(if *symbol == "<>" { "!=" } else { symbol }));
+            rest = &rest[symbol.len()..];
+        } else if c == '\'' {
+            // Quotes inside strings are doubled, as in SQL
+            let mut text = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '\'')) if rest[1 + i + 1..].starts_with('\'') => {
+                        text.push('\'');
+                        chars.next();
+                    }
+                    Some((i, '\'')) => break 1 + i + 1,
+                    Some((_, c)) => text.push(c),
+                    None => return Err("unterminated string in query".into()),
+                }
+            };
+            tokens.push(Token::Text(text));
+            rest = &rest[end..];
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '-' || c == '.')).unwrap_or(rest.len());
+            let number = rest[..end].parse().map_err(|_| format!("invalid number `{}` in query", &rest[..end]))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            return Err(format!("unexpected `{}` in query", c).into());
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    // Consumes the keyword if it's next, ignoring case
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(Token::Symbol(next)) if *next == symbol => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expected(&self, what: &str) -> Error {
+        match self.peek() {
+            Some(token) => format!("expected {} but found {} in query", what, token).into(),
+            None => format!("expected {} at the end of the query", what).into(),
+        }
+    }
+
+    fn query(&mut self) -> Result<Query, Error> {
+        if !self.keyword("select") {
+            return Err(self.expected("SELECT"));
+        }
+
+        let columns = if self.symbol("*") {
+            vec![Column::Station, Column::Min, Column::Mean, Column::Max, Column::Count]
+        } else {
+            let mut columns = vec![self.column()?];
+            while self.symbol(",") {
+                columns.push(self.column()?);
+            }
+            columns
+        };
+
+        let condition = if self.keyword("where") { Some(self.or()?) } else { None };
+
+        let order_by = if self.keyword("order") {
+            if !self.keyword("by") {
+                return Err(self.expected("BY"));
+            }
+            let column = self.column()?;
+            let descending = self.keyword("desc") || {
+                self.keyword("asc");
+                false
+            };
+            Some((column, descending))
+        } else {
+            None
+        };
+
+        let limit = if self.keyword("limit") {
+            match self.next() {
+                Some(&Token::Number(limit)) if limit >= 0. && limit.fract() == 0. => Some(limit as usize),
+                _ => {
+                    self.position -= 1;
+                    return Err(self.expected("a row count after LIMIT"));
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(Query { columns, condition, order_by, limit })
+    }
+
+    fn column(&mut self) -> Result<Column, Error> {
+        match self.peek() {
+            Some(Token::Word(word)) => match Column::parse(word) {
+                Some(column) => {
+                    self.position += 1;
+                    Ok(column)
+                }
+                None => Err(format!("unknown column `{}` in query", word).into()),
+            },
+            _ => Err(self.expected("a column")),
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition, Error> {
+        let mut condition = self.and()?;
+        while self.keyword("or") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, Error> {
+        let mut condition = self.comparison()?;
+        while self.keyword("and") {
+            condition = Condition::And(Box::new(condition), Box::new(self.comparison()?));
+        }
+        Ok(condition)
+    }
+
+    fn comparison(&mut self) -> Result<Condition, Error> {
+        if self.symbol("(") {
+            let condition = self.or()?;
+            if !self.symbol(")") {
+                return Err(self.expected("`)`"));
+            }
+            return Ok(condition);
+        }
+
+        let column = self.column()?;
+
+        let op = match self.peek() {
+            Some(Token::Symbol("=")) => Op::Eq,
+            Some(Token::Symbol("!=")) => Op::Ne,
+            Some(Token::Symbol("<")) => Op::Lt,
+            Some(Token::Symbol("<=")) => Op::Le,
+            Some(Token::Symbol(">")) => Op::Gt,
+            Some(Token::Symbol(">=")) => Op::Ge,
+            _ => return Err(self.expected("a comparison")),
+        };
+        self.position += 1;
+
+        let literal = match (column, self.peek()) {
+            (Column::Station, Some(Token::Text(text))) => Literal::Text(text.clone()),
+            (Column::Station, _) => return Err(self.expected("a 'quoted' station name")),
+            (_, Some(&Token::Number(number))) => Literal::Number(number),
+            (_, _) => return Err(self.expected("a number")),
+        };
+        self.position += 1;
+
+        Ok(Condition::Compare(column, op, literal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(temps: &[i32]) -> Record {
+        let mut record = Record::new(temps[0]);
+        for &temp in &temps[1..] {
+            record.add(temp);
+        }
+        record
+    }
+
+    #[test]
+    fn filters_sorts_and_limits() {
+        let (a, b, c) = (record(&[100, 300]), record(&[250]), record(&[-50, 50, 400]));
+        let stations = [("Abha", &a), ("Bulawayo", &b), ("It's", &c)];
+
+        let query = Query::parse("select station, max, count where mean > 1 or station = 'It''s' order by max desc limit 2").unwrap();
+        assert_eq!(query.run(&stations), "station;max;count\nIt's;40.0;3\nAbha;30.0;2\n");
+
+        let query = Query::parse("SELECT * WHERE (min >= 0 AND max <> 30) ORDER BY station").unwrap();
+        assert_eq!(query.run(&stations), "station;min;mean;max;count\nBulawayo;25.0;25.0;25.0;1\n");
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        for query in ["", "select", "select foo", "select station where", "select max where station > 1", "select max limit -1", "select max extra"] {
+            assert!(Query::parse(query).is_err(), "{:?}", query);
+        }
+    }
+}
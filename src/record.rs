@@ -0,0 +1,464 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// What [`Record::total`] and [`Record::sum_squares`] add up in: `i64`,
+/// or `i128` with the `wide-totals` feature. An i64 sum of squares runs
+/// out after about 10^9 measurements of ±99.999 at three decimals
+#[cfg(not(feature = "wide-totals"))]
+pub type Total = i64;
+/// What [`Record::total`] and [`Record::sum_squares`] add up in: `i64`,
+/// or `i128` with the `wide-totals` feature
+#[cfg(feature = "wide-totals")]
+pub type Total = i128;
+
+/// Running statistics for a single station.
+///
+/// Temperatures are stored as integers in units of the record's
+/// precision: tenths of a degree unless it says otherwise, so `12.3` is
+/// stored as `123`, or as `12300` with three decimals.
+///
+/// Records (and their histograms) serialize with serde as they are, so
+/// results can be saved or sent on without printing them first.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// Lowest temperature seen
+    pub min: i32,
+    /// Highest temperature seen
+    pub max: i32,
+    /// Sum of every temperature seen
+    pub total: Total,
+    /// Number of measurements seen
+    pub count: u64,
+    /// Sum of the squares of every temperature seen, in units squared,
+    /// for the variance
+    pub sum_squares: Total,
+    /// Every temperature seen, if the record was started with
+    /// [`Record::with_histogram`]. Only kept in tenths
+    pub histogram: Option<Box<Histogram>>,
+    /// Number of decimals the temperatures have, 1 to 3
+    pub decimals: u8,
+}
+
+impl Record {
+    /// Starts a record from a single measurement, in tenths of a degree
+    #[inline]
+    pub fn new(temp: i32) -> Self {
+        Self {
+            min: temp,
+            max: temp,
+            total: temp as Total,
+            count: 1,
+            sum_squares: temp as Total * temp as Total,
+            histogram: None,
+            decimals: 1,
+        }
+    }
+
+    /// Starts a record from a single measurement given to `decimals`
+    /// places, e.g. `1234` with 2 for 12.34 degrees
+    pub fn with_decimals(temp: i32, decimals: u8) -> Self {
+        Self { decimals, ..Self::new(temp) }
+    }
+
+    /// Starts a record that also keeps a [`Histogram`], for percentiles
+    pub fn with_histogram(temp: i32) -> Self {
+        let mut histogram = Box::new(Histogram::new());
+        histogram.add(Histogram::clamp(temp));
+
+        Self {
+            histogram: Some(histogram),
+            ..Self::new(temp)
+        }
+    }
+
+    /// Adds a measurement to the record
+    #[inline]
+    pub fn add(&mut self, temp: i32) {
+        self.min = self.min.min(temp);
+        self.max = self.max.max(temp);
+        self.total += temp as Total;
+        self.count += 1;
+        self.sum_squares += temp as Total * temp as Total;
+
+        if let Some(histogram) = &mut self.histogram {
+            histogram.add(Histogram::clamp(temp));
+        }
+    }
+
+    /// How many of the record's units make a degree: 10 for tenths
+    pub fn scale(&self) -> f64 {
+        10f64.powi(self.decimals as i32)
+    }
+
+    /// Mean temperature in degrees
+    pub fn mean(&self) -> f64 {
+        (self.total as f64 / self.count as f64) / self.scale()
+    }
+
+    /// Mean temperature in units of 10^-decimals of a degree (so tenths
+    /// with the default one decimal, hundredths with two), rounded half up (towards positive
+    /// infinity) as the challenge's reference implementation does. Done
+    /// in integers, so it's exact where `mean()` isn't
+    // The casts are only no-ops in one of the two widths of Total
+    #[allow(clippy::unnecessary_cast)]
+    pub fn mean_scaled(&self) -> i64 {
+        let count = self.count as Total;
+        // floor(total / count + 1/2), which is within a temperature's
+        // range whatever the total was
+        (2 * self.total + count).div_euclid(2 * count) as i64
+    }
+
+    /// [`mean_scaled`](Record::mean_scaled) in degrees, i.e. the mean
+    /// rounded to the record's decimals
+    pub fn mean_rounded(&self) -> f64 {
+        self.mean_scaled() as f64 / self.scale()
+    }
+
+    /// Rounds a value in degrees to the record's decimals, half up
+    pub fn round(&self, value: f64) -> f64 {
+        (value * self.scale() + 0.5).floor() / self.scale()
+    }
+
+    /// Population variance of the temperatures, in degrees squared
+    #[allow(clippy::unnecessary_cast)]
+    pub fn variance(&self) -> f64 {
+        // n * sum(x^2) - sum(x)^2 is computed exactly, so the only
+        // rounding is in the final division. Only i128 totals can be
+        // too big for that, and then it's done in floats
+        let count = self.count as i128;
+        let total = self.total as i128;
+        let spread = count.checked_mul(self.sum_squares as i128)
+            .zip(total.checked_mul(total))
+            .and_then(|(a, b)| a.checked_sub(b));
+
+        let scale = self.scale() * self.scale();
+        match spread {
+            Some(spread) => spread as f64 / (count as f64 * count as f64) / scale,
+            None => {
+                let mean = self.total as f64 / self.count as f64;
+                (self.sum_squares as f64 / self.count as f64 - mean * mean) / scale
+            }
+        }
+    }
+
+    /// Population standard deviation of the temperatures, in degrees
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Lowest temperature in degrees
+    pub fn min(&self) -> f64 {
+        self.min as f64 / self.scale()
+    }
+
+    /// Highest temperature in degrees
+    pub fn max(&self) -> f64 {
+        self.max as f64 / self.scale()
+    }
+
+    /// Merges another record for the same station into this one,
+    /// e.g. the results of two chunks of the same file
+    #[inline]
+    pub fn combine(&mut self, other: &Self) {
+        debug_assert_eq!(self.decimals, other.decimals, "records with different precisions");
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.total += other.total;
+        self.count += other.count;
+        self.sum_squares += other.sum_squares;
+
+        match (&mut self.histogram, &other.histogram) {
+            (Some(histogram), Some(other)) => histogram.combine(other),
+            (None, Some(other)) => self.histogram = Some(other.clone()),
+            _ => {}
+        }
+    }
+
+    /// The `p`th percentile temperature (0 < `p` <= 100) in tenths of a
+    /// degree, or `None` if the record has no histogram
+    pub fn percentile(&self, p: f64) -> Option<i16> {
+        self.histogram.as_ref().map(|histogram| histogram.percentile(p))
+    }
+}
+
+/// Count of measurements at each temperature from -99.9 to 99.9, the
+/// range the challenge allows. Anything outside it counts towards the
+/// nearest end
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    counts: [u32; Histogram::BUCKETS],
+}
+
+impl Histogram {
+    /// Lowest temperature with its own bucket, in tenths of a degree
+    pub const MIN: i16 = -999;
+    /// Highest temperature with its own bucket, in tenths of a degree
+    pub const MAX: i16 = 999;
+    const BUCKETS: usize = (Self::MAX - Self::MIN) as usize + 1;
+
+    // A record's temperature, which may be outside i16, as the nearest
+    // temperature with a bucket
+    fn clamp(temp: i32) -> i16 {
+        temp.clamp(Self::MIN as i32, Self::MAX as i32) as i16
+    }
+
+    /// An empty histogram
+    pub fn new() -> Self {
+        Self { counts: [0; Self::BUCKETS] }
+    }
+
+    /// Counts a temperature, in tenths of a degree
+    #[inline]
+    pub fn add(&mut self, temp: i16) {
+        self.counts[(temp.clamp(Self::MIN, Self::MAX) - Self::MIN) as usize] += 1;
+    }
+
+    /// Adds the counts of another histogram to this one
+    pub fn combine(&mut self, other: &Self) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+    }
+
+    /// Number of measurements at `temp`, in tenths of a degree
+    pub fn count(&self, temp: i16) -> u32 {
+        self.counts[(temp.clamp(Self::MIN, Self::MAX) - Self::MIN) as usize]
+    }
+
+    // Overwrites the count at temp, for reading saved histograms back
+    pub(crate) fn set_count(&mut self, temp: i16, count: u32) {
+        self.counts[(temp.clamp(Self::MIN, Self::MAX) - Self::MIN) as usize] = count;
+    }
+
+    /// The `p`th percentile (0 < `p` <= 100) by the nearest-rank
+    /// method, so always a temperature that was actually seen. The
+    /// median is the 50th percentile. An empty histogram gives `MIN`
+    pub fn percentile(&self, p: f64) -> i16 {
+        let total = self.counts.iter().map(|&count| count as u64).sum::<u64>();
+        let rank = ((p / 100. * total as f64).ceil() as u64).clamp(1, total.max(1));
+
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count as u64;
+            if seen >= rank {
+                return Self::MIN + i as i16;
+            }
+        }
+
+        Self::MIN
+    }
+
+    /// Regroups the counts into buckets `width` tenths of a degree wide,
+    /// aligned to multiples of `width`, as (lowest temperature in the
+    /// bucket, count) pairs. Empty buckets are left out
+    pub fn buckets(&self, width: i16) -> Vec<(i16, u64)> {
+        let mut buckets: Vec<(i16, u64)> = Vec::new();
+
+        for (i, &count) in self.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+
+            let temp = Self::MIN + i as i16;
+            let start = temp.div_euclid(width) * width;
+            match buckets.last_mut() {
+                Some((last, total)) if *last == start => *total += count as u64,
+                _ => buckets.push((start, count as u64)),
+            }
+        }
+
+        buckets
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Displays a temperature given in tenths of a degree with exactly one
+/// decimal, e.g. `-5` as `-0.5`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tenths(pub i64);
+
+impl std::fmt::Display for Tenths {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        Fixed(self.0, 1).fmt(f)
+    }
+}
+
+/// Displays a temperature given in units of `decimals` decimal places
+/// with exactly that many decimals, e.g. `Fixed(-5, 2)` as `-0.05`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fixed(pub i64, pub u8);
+
+impl std::fmt::Display for Fixed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = 10u64.pow(self.1 as u32);
+        write!(f, "{}{}.{:0width$}", sign, abs / scale, abs % scale, width = self.1 as usize)
+    }
+}
+
+// Serialized as the count at every temperature, lowest first, as serde
+// can't derive it for an array this long
+impl Serialize for Histogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.counts.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Histogram {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let counts = Vec::<u32>::deserialize(deserializer)?;
+        let counts = counts.try_into().map_err(|counts: Vec<u32>| D::Error::invalid_length(counts.len(), &"a count for every tenth from -99.9 to 99.9"))?;
+        Ok(Histogram { counts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(temps: &[i32]) -> Record {
+        let mut record = Record::new(temps[0]);
+        for &temp in &temps[1..] {
+            record.add(temp);
+        }
+        record
+    }
+
+    #[test]
+    fn mean_rounds_half_up() {
+        // Exact halves of a tenth go towards positive infinity
+        assert_eq!(record(&[1, 2]).mean_scaled(), 2);
+        assert_eq!(record(&[-1, -2]).mean_scaled(), -1);
+        assert_eq!(record(&[0, 1]).mean_scaled(), 1);
+        assert_eq!(record(&[0, -1]).mean_scaled(), 0);
+        assert_eq!(record(&[-999, 998]).mean_scaled(), 0);
+        assert_eq!(record(&[999, -998]).mean_scaled(), 1);
+    }
+
+    #[test]
+    fn mean_rounds_to_nearest() {
+        assert_eq!(record(&[1, 1, 2]).mean_scaled(), 1);
+        assert_eq!(record(&[1, 2, 2]).mean_scaled(), 2);
+        assert_eq!(record(&[-1, -1, -2]).mean_scaled(), -1);
+        assert_eq!(record(&[-1, -2, -2]).mean_scaled(), -2);
+        assert_eq!(record(&[999, 999, 999]).mean_scaled(), 999);
+        assert_eq!(record(&[-999]).mean_scaled(), -999);
+    }
+
+    #[test]
+    fn mean_where_floats_go_wrong() {
+        // 0.15, 0.35 etc. aren't representable, so rounding the f64 mean
+        // can land on the wrong side of the half
+        for tenths in [1, 3, 5, 7, 9, 11, 13, 15, 17, 19, 21, 23, 25, 27, 29] {
+            let record = record(&[tenths, tenths + 1]);
+            assert_eq!(record.mean_scaled(), tenths as i64 + 1, "mean of {} and {}", tenths, tenths + 1);
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut record = Record::with_histogram(10);
+        for temp in 11..=109 {
+            record.add(temp);
+        }
+
+        // 100 measurements, 1.0 to 10.9
+        assert_eq!(record.percentile(50.), Some(59));
+        assert_eq!(record.percentile(95.), Some(104));
+        assert_eq!(record.percentile(99.), Some(108));
+        assert_eq!(record.percentile(100.), Some(109));
+        assert_eq!(record.percentile(0.1), Some(10));
+        assert_eq!(Record::new(10).percentile(50.), None);
+    }
+
+    #[test]
+    fn percentiles_after_combine() {
+        let mut first = Record::with_histogram(-999);
+        let mut second = Record::with_histogram(999);
+        second.add(0);
+        first.combine(&second);
+
+        assert_eq!(first.percentile(50.), Some(0));
+        assert_eq!(first.percentile(34.), Some(0));
+        assert_eq!(first.percentile(33.), Some(-999));
+        assert_eq!(first.percentile(100.), Some(999));
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = Histogram::new();
+        for temp in [-999, -51, -50, -1, 0, 0, 49, 50, 999] {
+            histogram.add(temp);
+        }
+
+        assert_eq!(histogram.buckets(50), [(-1000, 1), (-100, 1), (-50, 2), (0, 3), (50, 1), (950, 1)]);
+        assert_eq!(histogram.buckets(1).len(), 8);
+        assert_eq!(histogram.buckets(2000), [(-2000, 4), (0, 5)]);
+    }
+
+    #[test]
+    fn variance() {
+        assert_eq!(record(&[120]).variance(), 0.);
+
+        // 2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0 has a mean of 5 and a
+        // standard deviation of 2
+        let temps = [20, 40, 40, 40, 50, 50, 70, 90];
+        assert_eq!(record(&temps).variance(), 4.);
+        assert_eq!(record(&temps).stddev(), 2.);
+
+        // Merging chunks gives the same as seeing everything at once
+        let mut merged = record(&temps[..3]);
+        merged.combine(&record(&temps[3..]));
+        assert_eq!(merged.stddev(), 2.);
+
+        let negative = temps.map(|temp| -temp);
+        assert_eq!(record(&negative).stddev(), 2.);
+    }
+
+    #[test]
+    fn tenths_display() {
+        assert_eq!(Tenths(0).to_string(), "0.0");
+        assert_eq!(Tenths(5).to_string(), "0.5");
+        assert_eq!(Tenths(-5).to_string(), "-0.5");
+        assert_eq!(Tenths(120).to_string(), "12.0");
+        assert_eq!(Tenths(-999).to_string(), "-99.9");
+        assert_eq!(Tenths(999).to_string(), "99.9");
+    }
+
+    #[test]
+    fn more_decimals() {
+        assert_eq!(Fixed(-5, 2).to_string(), "-0.05");
+        assert_eq!(Fixed(1234, 2).to_string(), "12.34");
+        assert_eq!(Fixed(-99999, 3).to_string(), "-99.999");
+
+        let mut record = Record::with_decimals(1234, 2);
+        record.add(1235);
+        assert_eq!((record.min(), record.max()), (12.34, 12.35));
+        assert_eq!(record.mean_scaled(), 1235);
+        assert_eq!(record.mean_rounded(), 12.35);
+    }
+
+    #[test]
+    #[cfg(feature = "wide-totals")]
+    fn wide_totals() {
+        // 2^40 measurements of 99.999, whose sum of squares is too big
+        // for an i64
+        let count = 1 << 40;
+        let mut record = Record::with_decimals(99999, 3);
+        record.count = count;
+        record.total = 99999 * count as Total;
+        record.sum_squares = 99999 * 99999 * count as Total;
+        assert!(record.sum_squares > i64::MAX as Total);
+
+        let mut merged = record.clone();
+        merged.combine(&record);
+        assert_eq!(merged.mean_rounded(), 99.999);
+        assert_eq!(merged.variance(), 0.);
+    }
+}
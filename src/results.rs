@@ -0,0 +1,223 @@
+// Results for programs that use the library, rather than a raw map: the
+// stations by name, in alphabetical order, with the binary's ways of
+// picking the top stations and of printing them.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Fixed, Record, StationMap};
+
+/// The stations and their records, by name in (byte) alphabetical order
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Results {
+    stations: BTreeMap<String, Record>,
+}
+
+/// What [`Results::top_n_by`] ranks stations by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TopBy {
+    /// Highest maximum first
+    Max,
+    /// Lowest minimum first
+    Min,
+    /// Highest mean first
+    Mean,
+    /// Most measurements first
+    Count,
+}
+
+impl TopBy {
+    /// How a and b rank, first first
+    pub fn cmp(self, a: &Record, b: &Record) -> std::cmp::Ordering {
+        match self {
+            TopBy::Max => b.max.cmp(&a.max),
+            TopBy::Min => a.min.cmp(&b.min),
+            TopBy::Mean => b.mean_scaled().cmp(&a.mean_scaled()),
+            TopBy::Count => b.count.cmp(&a.count),
+        }
+    }
+}
+
+/// The ways [`Results::render`] prints results, as the binary's
+/// `--format` of the same names does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// One `name;min;mean;max` line per station
+    Lines,
+    /// The reference implementation's `{name=min/mean/max, ...}` line
+    Official,
+    /// CSV with a `station,min,mean,max` header row
+    Csv,
+    /// A JSON array with one object per station
+    Json,
+}
+
+impl Results {
+    /// The record of the station called name
+    pub fn get(&self, name: &str) -> Option<&Record> {
+        self.stations.get(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty()
+    }
+
+    /// The stations in alphabetical order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Record)> {
+        self.stations.iter().map(|(name, record)| (name.as_str(), record))
+    }
+
+    /// The first n stations by by. Ties stay in alphabetical order
+    pub fn top_n_by(&self, n: usize, by: TopBy) -> Vec<(&str, &Record)> {
+        let mut stations = self.iter().collect::<Vec<_>>();
+        stations.sort_by(|(_, a), (_, b)| by.cmp(a, b));
+        stations.truncate(n);
+        stations
+    }
+
+    /// Adds other's measurements to these, e.g. another file's
+    pub fn merge(&mut self, other: Results) {
+        for (name, record) in other.stations {
+            self.add(name, record);
+        }
+    }
+
+    /// The results printed in a style, with the number of measurements
+    /// after the max if with_count
+    pub fn render(&self, style: Style, with_count: bool) -> String {
+        let mut to_print = String::with_capacity(self.len() * 32);
+        match style {
+            Style::Lines => {
+                for (name, record) in self.iter() {
+                    write!(to_print, "{};{};{};{}", name, record.min(), record.mean_rounded(), record.max()).unwrap();
+                    if with_count {
+                        write!(to_print, ";{}", record.count).unwrap();
+                    }
+                    to_print.push('\n');
+                }
+            }
+            Style::Official => {
+                let stations = self.iter().map(|(name, record)| {
+                    let decimals = record.decimals;
+                    let mut station = format!(
+                        "{}={}/{}/{}",
+                        name,
+                        Fixed(record.min.into(), decimals),
+                        Fixed(record.mean_scaled(), decimals),
+                        Fixed(record.max.into(), decimals),
+                    );
+                    if with_count {
+                        write!(station, "/{}", record.count).unwrap();
+                    }
+                    station
+                });
+                to_print = format!("{{{}}}\n", stations.collect::<Vec<_>>().join(", "));
+            }
+            Style::Csv => {
+                to_print.push_str(if with_count { "station,min,mean,max,count\n" } else { "station,min,mean,max\n" });
+                for (name, record) in self.iter() {
+                    // Quoted when it has to be (RFC 4180)
+                    let name = if name.contains([',', '"', '\n', '\r']) { format!("\"{}\"", name.replace('"', "\"\"")) } else { name.to_string() };
+                    write!(to_print, "{},{},{},{}", name, record.min(), record.mean_rounded(), record.max()).unwrap();
+                    if with_count {
+                        write!(to_print, ",{}", record.count).unwrap();
+                    }
+                    to_print.push('\n');
+                }
+            }
+            Style::Json => {
+                #[derive(Serialize)]
+                struct Summary<'a> {
+                    station: &'a str,
+                    min: f64,
+                    mean: f64,
+                    max: f64,
+                    #[serde(skip_serializing_if = "Option::is_none")]
+                    count: Option<u64>,
+                }
+                let stations = self.iter()
+                    .map(|(station, record)| Summary {
+                        station,
+                        min: record.min(),
+                        mean: record.mean_rounded(),
+                        max: record.max(),
+                        count: with_count.then_some(record.count),
+                    })
+                    .collect::<Vec<_>>();
+                to_print = serde_json::to_string_pretty(&stations).unwrap() + "\n";
+            }
+        }
+        to_print
+    }
+
+    fn add(&mut self, name: String, record: Record) {
+        match self.stations.entry(name) {
+            std::collections::btree_map::Entry::Occupied(mut entry) => entry.get_mut().combine(&record),
+            std::collections::btree_map::Entry::Vacant(entry) => {
+                entry.insert(record);
+            }
+        }
+    }
+}
+
+/// As the challenge prints them: `{name=min/mean/max, ...}`
+impl fmt::Display for Results {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.render(Style::Official, false).trim_end())
+    }
+}
+
+/// Names that aren't valid UTF-8 are converted lossily, and combined
+/// if that makes two the same
+impl From<StationMap> for Results {
+    fn from(data_map: StationMap) -> Self {
+        data_map.into_iter().map(|(name, record)| (String::from_utf8_lossy(&name).into_owned(), record)).collect()
+    }
+}
+
+impl From<BTreeMap<String, Record>> for Results {
+    fn from(stations: BTreeMap<String, Record>) -> Self {
+        Results { stations }
+    }
+}
+
+impl From<Results> for BTreeMap<String, Record> {
+    fn from(results: Results) -> Self {
+        results.stations
+    }
+}
+
+impl FromIterator<(String, Record)> for Results {
+    fn from_iter<I: IntoIterator<Item = (String, Record)>>(stations: I) -> Self {
+        let mut results = Results::default();
+        for (name, record) in stations {
+            results.add(name, record);
+        }
+        results
+    }
+}
+
+impl<'a> IntoIterator for &'a Results {
+    type Item = (&'a String, &'a Record);
+    type IntoIter = std::collections::btree_map::Iter<'a, String, Record>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.stations.iter()
+    }
+}
+
+impl IntoIterator for Results {
+    type Item = (String, Record);
+    type IntoIter = std::collections::btree_map::IntoIter<String, Record>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.stations.into_iter()
+    }
+}
@@ -0,0 +1,128 @@
+// Aggregating part of a file for a quick look at it: either its first
+// rows, or blocks of lines picked at random from all through it, so a
+// file sorted by station (or by time) still gives a fair estimate.
+
+use std::path::Path;
+
+use rand::seq::index;
+use crate::par::*;
+
+use crate::{compression, finish_map, in_pool, new_map, parse_to_end, reduce_maps, regular_file, report_progress, with_file, BadLine, Error, Options, StationMap};
+
+// The size of the blocks a fraction is picked in. Smaller blocks are a
+// fairer sample, but more of them are read at a time
+const BLOCK: usize = 256 * 1024;
+
+/// How much of a file [`aggregate_sample`] reads
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sample {
+    /// About this fraction of the file (more than 0, up to 1), in
+    /// blocks of lines picked at random
+    Fraction(f64),
+    /// The first this many rows
+    Rows(u64),
+}
+
+/// The stations in a sample of a file, and how much of it they're from
+#[derive(Debug)]
+pub struct Sampled {
+    pub stations: StationMap,
+    /// Bytes of the file aggregated
+    pub bytes: u64,
+    /// Bytes in the whole file
+    pub total_bytes: u64,
+}
+
+impl Sampled {
+    /// How much of the file was aggregated, by its bytes
+    pub fn fraction(&self) -> f64 {
+        if self.total_bytes == 0 {
+            1.
+        } else {
+            self.bytes as f64 / self.total_bytes as f64
+        }
+    }
+}
+
+/// Aggregates a sample of the uncompressed measurements file at `path`
+pub fn aggregate_sample(path: &Path, sample: Sample, options: &Options) -> Result<Sampled, Error> {
+    if let Sample::Fraction(fraction) = sample {
+        if !(fraction > 0. && fraction <= 1.) {
+            return Err(format!("can't sample {} of a file", fraction).into());
+        }
+    }
+
+    in_pool(options, || with_file(path, |file| {
+        regular_file(path, file)?;
+        if compression::detect_file(file)? != compression::Compression::None {
+            return Err(format!("{}: compressed files can't be sampled", path.display()).into());
+        }
+        let total_bytes = file.metadata()?.len();
+        // Mapping an empty file fails on some platforms
+        if total_bytes == 0 {
+            return Ok(Sampled { stations: StationMap::default(), bytes: 0, total_bytes });
+        }
+
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        let blocks = match sample {
+            Sample::Rows(rows) => {
+                let end = rows.checked_sub(1).map_or(0, |last| {
+                    memchr::memchr_iter(b'\n', &mmap).nth(last as usize).map_or(mmap.len(), |newline| newline + 1)
+                });
+                vec![(0, end)]
+            }
+            Sample::Fraction(fraction) => random_blocks(&mmap, fraction),
+        };
+
+        let bytes = blocks.iter().map(|(start, end)| (end - start) as u64).sum();
+        let stations = reduce_maps(blocks.into_par_iter().try_fold(new_map, |mut data_map, (start, end)| {
+            parse_to_end(&mmap[start..end], &mut data_map, options)
+                .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &mmap, start + at, 0))?;
+            report_progress(options, end - start);
+            Ok(data_map)
+        }), options)?;
+
+        Ok(Sampled { stations: finish_map(stations, options), bytes, total_bytes })
+    }))
+}
+
+// About fraction of bytes, as line-aligned blocks picked at random, in
+// order. A block holds the lines that start in it
+fn random_blocks(bytes: &[u8], fraction: f64) -> Vec<(usize, usize)> {
+    let count = bytes.len().div_ceil(BLOCK);
+    let picked = ((count as f64 * fraction).ceil() as usize).clamp(1, count);
+
+    let mut blocks = index::sample(&mut rand::thread_rng(), count, picked).into_vec();
+    blocks.sort_unstable();
+    blocks
+        .into_iter()
+        .map(|block| (line_start(bytes, block * BLOCK), line_start(bytes, (block + 1) * BLOCK)))
+        .filter(|(start, end)| start < end)
+        .collect()
+}
+
+// The start of the first line starting at or after offset
+fn line_start(bytes: &[u8], offset: usize) -> usize {
+    if offset == 0 || offset >= bytes.len() {
+        return offset.min(bytes.len());
+    }
+    memchr::memchr(b'\n', &bytes[offset - 1..]).map_or(bytes.len(), |newline| offset + newline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_start_lines() {
+        let bytes = "Hamburg;12.0\n".repeat(100_000);
+        let bytes = bytes.as_bytes();
+        let blocks = random_blocks(bytes, 0.1);
+        assert_eq!(blocks.len(), bytes.len().div_ceil(BLOCK).div_ceil(10));
+        for (start, end) in blocks {
+            assert!(start == 0 || bytes[start - 1] == b'\n');
+            assert!(end == bytes.len() || bytes[end - 1] == b'\n');
+        }
+        assert_eq!(random_blocks(bytes, 1.).iter().map(|(start, end)| end - start).sum::<usize>(), bytes.len());
+    }
+}
@@ -0,0 +1,197 @@
+// Finding the lines and delimiters in a buffer 64 bytes at a time.
+//
+// Rather than searching for each \n and then back through the line for
+// its delimiter, every 64-byte block is compared against both at once
+// and the matches packed into a bitmask each, one bit per byte. Lines
+// then come out of the masks with a few bit operations, and a block
+// costs the same however many lines are in it. The comparisons use
+// SSE2 (or AVX2, when the build targets it) on x86_64 with the simd
+// feature, and a plain loop the compiler can vectorize everywhere else.
+
+const BLOCK: usize = 64;
+
+/// The complete (`\n` terminated) lines in a buffer, as the offsets of
+/// their start, their last delimiter if they have one, and their `\n`
+pub(crate) struct Lines<'a> {
+    bytes: &'a [u8],
+    delimiter: u8,
+    // Offset of the block the masks are for
+    block: usize,
+    newlines: u64,
+    delimiters: u64,
+    start: usize,
+    split: Option<usize>,
+}
+
+impl<'a> Lines<'a> {
+    pub fn new(bytes: &'a [u8], delimiter: u8) -> Self {
+        let (newlines, delimiters) = masks(bytes, delimiter);
+        Lines { bytes, delimiter, block: 0, newlines, delimiters, start: 0, split: None }
+    }
+
+    /// Where the line after the last one returned starts, i.e. how much
+    /// of the buffer has been consumed
+    pub fn consumed(&self) -> usize {
+        self.start
+    }
+}
+
+impl Iterator for Lines<'_> {
+    type Item = (usize, Option<usize>, usize);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.newlines != 0 {
+                let newline = self.newlines.trailing_zeros();
+                // Delimiters before the newline are in this line, and any
+                // after it in the next
+                let before = self.delimiters & ((1 << newline) - 1);
+                if before != 0 {
+                    self.split = Some(self.block + 63 - before.leading_zeros() as usize);
+                }
+                self.delimiters &= u64::MAX.checked_shl(newline + 1).unwrap_or(0);
+                self.newlines &= self.newlines - 1;
+
+                let line = (self.start, self.split.take(), self.block + newline as usize);
+                self.start = line.2 + 1;
+                return Some(line);
+            }
+
+            // The rest of the line carries on into the next block
+            if self.delimiters != 0 {
+                self.split = Some(self.block + 63 - self.delimiters.leading_zeros() as usize);
+            }
+
+            self.block += BLOCK;
+            if self.block >= self.bytes.len() {
+                self.block = self.bytes.len();
+                return None;
+            }
+            (self.newlines, self.delimiters) = masks(&self.bytes[self.block..], self.delimiter);
+        }
+    }
+}
+
+// The \n and delimiter masks for the first (up to) 64 bytes of bytes
+#[inline]
+fn masks(bytes: &[u8], delimiter: u8) -> (u64, u64) {
+    match bytes.first_chunk::<BLOCK>() {
+        Some(block) => simd::masks(block, delimiter),
+        None => portable_masks(bytes, delimiter),
+    }
+}
+
+#[inline]
+fn portable_masks(bytes: &[u8], delimiter: u8) -> (u64, u64) {
+    let (mut newlines, mut delimiters) = (0, 0);
+    for (i, &byte) in bytes.iter().enumerate().take(BLOCK) {
+        newlines |= ((byte == b'\n') as u64) << i;
+        delimiters |= ((byte == delimiter) as u64) << i;
+    }
+    (newlines, delimiters)
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
+mod simd {
+    use std::arch::x86_64::*;
+
+    #[inline]
+    pub fn masks(block: &[u8; 64], delimiter: u8) -> (u64, u64) {
+        // Safe: AVX2 is there whenever this is compiled in, and the loads
+        // are unaligned and within the block
+        unsafe {
+            let newline = _mm256_set1_epi8(b'\n' as i8);
+            let delimiter = _mm256_set1_epi8(delimiter as i8);
+            let (mut newlines, mut delimiters) = (0, 0);
+
+            for half in 0..2 {
+                let bytes = _mm256_loadu_si256(block.as_ptr().add(half * 32).cast());
+                newlines |= (_mm256_movemask_epi8(_mm256_cmpeq_epi8(bytes, newline)) as u32 as u64) << (half * 32);
+                delimiters |= (_mm256_movemask_epi8(_mm256_cmpeq_epi8(bytes, delimiter)) as u32 as u64) << (half * 32);
+            }
+            (newlines, delimiters)
+        }
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64", not(target_feature = "avx2")))]
+mod simd {
+    use std::arch::x86_64::*;
+
+    #[inline]
+    pub fn masks(block: &[u8; 64], delimiter: u8) -> (u64, u64) {
+        // Safe: SSE2 is part of x86_64, and the loads are unaligned and
+        // within the block
+        unsafe {
+            let newline = _mm_set1_epi8(b'\n' as i8);
+            let delimiter = _mm_set1_epi8(delimiter as i8);
+            let (mut newlines, mut delimiters) = (0, 0);
+
+            for quarter in 0..4 {
+                let bytes = _mm_loadu_si128(block.as_ptr().add(quarter * 16).cast());
+                newlines |= (_mm_movemask_epi8(_mm_cmpeq_epi8(bytes, newline)) as u16 as u64) << (quarter * 16);
+                delimiters |= (_mm_movemask_epi8(_mm_cmpeq_epi8(bytes, delimiter)) as u16 as u64) << (quarter * 16);
+            }
+            (newlines, delimiters)
+        }
+    }
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+mod simd {
+    #[inline]
+    pub fn masks(block: &[u8; 64], delimiter: u8) -> (u64, u64) {
+        super::portable_masks(block, delimiter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What Lines should find, the slow way
+    fn expected(bytes: &[u8], delimiter: u8) -> Vec<(usize, Option<usize>, usize)> {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        while let Some(len) = memchr::memchr(b'\n', &bytes[start..]) {
+            let split = memchr::memrchr(delimiter, &bytes[start..start + len]).map(|i| start + i);
+            lines.push((start, split, start + len));
+            start += len + 1;
+        }
+        lines
+    }
+
+    #[test]
+    fn masks_match_the_loop() {
+        let block = std::array::from_fn::<u8, 64, _>(|i| [b'a', b';', b'\n', b'\t', 0xff][i * 7 % 5]);
+        for delimiter in [b';', b'\t', b'\n', 0xff] {
+            assert_eq!(simd::masks(&block, delimiter), portable_masks(&block, delimiter));
+        }
+    }
+
+    #[test]
+    fn matches_memchr() {
+        let mut bytes = Vec::new();
+        for i in 0..500 {
+            // Names of every length, some with the delimiter in them,
+            // so lines start and end all over the blocks
+            bytes.extend(std::iter::repeat_n(b'a', i % 70));
+            if i % 7 == 0 {
+                bytes.extend_from_slice(b";x");
+            }
+            if i % 11 != 0 {
+                bytes.extend_from_slice(b";12.3");
+            }
+            bytes.push(b'\n');
+        }
+        bytes.extend_from_slice(b"no newline;1.0");
+
+        for len in [0, 1, 63, 64, 65, 200, bytes.len()] {
+            let bytes = &bytes[..len];
+            let mut lines = Lines::new(bytes, b';');
+            assert_eq!(lines.by_ref().collect::<Vec<_>>(), expected(bytes, b';'), "{} bytes", len);
+            assert_eq!(lines.consumed(), memchr::memrchr(b'\n', bytes).map_or(0, |i| i + 1));
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// The `self-test` subcommand: every engine, and the other ways in,
+// over small datasets built into the binary, checked against their
+// results in the official format byte for byte. The results were
+// worked out apart from the aggregator (tests/data/corpus), so a change
+// that breaks the parsing or the rounding shows up here first.
+
+use std::path::Path;
+
+use clap::ValueEnum;
+use one_billion_row_challenge::{aggregate_bytes, aggregate_stations, chunk, Engine, Error, Options, StationMap};
+
+use crate::engine_name;
+use crate::unit::Unit;
+
+// Name, measurements and expected results
+const CORPUS: [(&str, &str, &str); 5] = [
+    ("edge-cases", include_str!("../tests/data/edge_cases/measurements.txt"), include_str!("../tests/data/edge_cases/expected.txt")),
+    ("no-trailing-newline", include_str!("../tests/data/edge_cases/no-trailing-newline.txt"), include_str!("../tests/data/edge_cases/expected.txt")),
+    ("negative", include_str!("../tests/data/corpus/negative.txt"), include_str!("../tests/data/corpus/negative.expected")),
+    ("unicode", include_str!("../tests/data/corpus/unicode.txt"), include_str!("../tests/data/corpus/unicode.expected")),
+    ("boundaries", include_str!("../tests/data/corpus/boundaries.txt"), include_str!("../tests/data/corpus/boundaries.expected")),
+];
+
+// Small enough that most lines have a chunk boundary near them
+const SMALL_CHUNKS: chunk::ChunkSize = chunk::ChunkSize::Bytes(64);
+
+struct Check {
+    name: String,
+    options: Options,
+    // Parsed from memory, as the wasm module does, rather than a file
+    in_memory: bool,
+}
+
+fn checks() -> Vec<Check> {
+    let check = |name: String, options: Options| Check { name, options, in_memory: false };
+    let small = |options: Options| Options { threads: Some(2), chunk_size: SMALL_CHUNKS, ..options };
+
+    let mut checks = Vec::new();
+    for &engine in Engine::value_variants().iter().filter(|&&engine| engine != Engine::Auto) {
+        let name = engine_name(engine);
+        checks.push(check(name.clone(), Options { engine, ..Default::default() }));
+        checks.push(check(format!("{}, small chunks", name), small(Options { engine, ..Default::default() })));
+    }
+    let mmap = Options { engine: Engine::Mmap, ..Default::default() };
+    checks.push(check("mmap, prefetch".into(), small(Options { prefetch: true, ..mmap.clone() })));
+    checks.push(check("mmap, interned".into(), small(Options { interned: true, ..mmap.clone() })));
+    checks.push(check("mmap, strict".into(), Options { strict: true, ..mmap }));
+    // Any limit at all streams the file through aggregate_reader
+    checks.push(check("stream".into(), Options { max_memory: Some(1), ..Default::default() }));
+    checks.push(Check { name: "bytes".into(), options: small(Options::default()), in_memory: true });
+    checks
+}
+
+pub fn self_test() -> Result<(), Error> {
+    let dir = std::env::temp_dir().join(format!("brc-self-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).map_err(|source| Error::Io { path: Some(dir.clone()), source })?;
+    let result = run(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+fn run(dir: &Path) -> Result<(), Error> {
+    let checks = checks();
+    let mut failed = 0;
+    // Only the gpu engine can be skipped
+    #[cfg_attr(not(feature = "gpu"), allow(unused_mut))]
+    let mut skipped = 0;
+
+    for (dataset, measurements, expected) in CORPUS {
+        let path = dir.join(format!("{}.txt", dataset));
+        std::fs::write(&path, measurements).map_err(|source| Error::Io { path: Some(path.clone()), source })?;
+
+        for check in &checks {
+            let result = match check.in_memory {
+                true => aggregate_bytes(measurements.as_bytes(), &check.options),
+                false => aggregate_stations(&path, &check.options),
+            };
+            let got = match result {
+                Ok(data) => official(data),
+                // There may be no device to run it on
+                #[cfg(feature = "gpu")]
+                Err(e) if check.options.engine == Engine::Gpu => {
+                    println!("skip  {:<24} {:<20} {}", check.name, dataset, e);
+                    skipped += 1;
+                    continue;
+                }
+                Err(e) => e.to_string(),
+            };
+
+            match first_difference(&got, expected) {
+                None => println!("ok    {:<24} {}", check.name, dataset),
+                Some(difference) => {
+                    println!("FAIL  {:<24} {:<20} {}", check.name, dataset, difference);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    let total = checks.len() * CORPUS.len();
+    println!("{} checks of {} datasets: {} passed, {} failed, {} skipped", total, CORPUS.len(), total - failed - skipped, failed, skipped);
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of {} checks failed", failed, total).into()),
+    }
+}
+
+// The results as `1brc --format official` prints them
+fn official(data: StationMap) -> String {
+    let mut data = data.into_iter().collect::<Vec<_>>();
+    data.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    let stations = data.iter()
+        .map(|(name, record)| format!("{}={}/{}/{}", String::from_utf8_lossy(name), Unit::C.min(record), Unit::C.mean(record), Unit::C.max(record)))
+        .collect::<Vec<_>>();
+    format!("{{{}}}\n", stations.join(", "))
+}
+
+// The first station that isn't as expected, or None if none are
+fn first_difference<'a>(got: &'a str, expected: &'a str) -> Option<String> {
+    if got == expected {
+        return None;
+    }
+    let stations = |results: &'a str| results.trim_end().trim_matches(['{', '}']).split(", ");
+    let (got, expected) = (stations(got), stations(expected));
+    match got.zip(expected).find(|(got, expected)| got != expected) {
+        Some((got, expected)) => Some(format!("got {}, expected {}", got, expected)),
+        None => Some("a different number of stations".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corpus_passes() {
+        self_test().unwrap();
+    }
+
+    #[test]
+    fn finds_differences() {
+        assert_eq!(first_difference("{A=1.0/1.0/1.0}\n", "{A=1.0/1.0/1.0}\n"), None);
+        assert_eq!(first_difference("{A=1.0/1.0/1.0, B=2.0/2.0/2.0}\n", "{A=1.0/1.0/1.0, B=2.0/2.1/2.2}\n").unwrap(), "got B=2.0/2.0/2.0, expected B=2.0/2.1/2.2");
+        assert_eq!(first_difference("{A=1.0/1.0/1.0}\n", "{A=1.0/1.0/1.0, B=2.0/2.0/2.0}\n").unwrap(), "a different number of stations");
+    }
+}
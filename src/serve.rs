@@ -0,0 +1,258 @@
+// Serving the aggregated results as JSON over HTTP:
+//
+//   GET /stations                 every station, in alphabetical order
+//   GET /stations/{name}          one station, by its percent-encoded name
+//   GET /top?by=mean&n=10         the first n stations by max, min, mean or count
+//   GET /metrics                  per-station gauges and processing counters
+//                                 in Prometheus' text format
+//
+// The file is only aggregated once, at startup, so every request is
+// answered from the same results.
+
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use clap::{Args, ValueEnum};
+use one_billion_row_challenge::{Error, Record, StationKey, TopBy};
+use serde::Serialize;
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::unit::Unit;
+use crate::{aggregate, input_paths, keep_top, Aggregate, InputArgs, StationSummary};
+
+// As --format json has them
+const AGGREGATES: [Aggregate; 4] = [Aggregate::Min, Aggregate::Mean, Aggregate::Max, Aggregate::Count];
+
+#[derive(Args)]
+pub struct ServeArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+}
+
+// What it took to aggregate the input, for /metrics
+struct Processing {
+    rows: u64,
+    // Of the input files, as stored. stdin isn't counted
+    bytes: u64,
+    elapsed: Duration,
+}
+
+pub fn serve(args: ServeArgs) -> Result<(), Error> {
+    let start_time = std::time::Instant::now();
+    let mut data = aggregate(&args.input, false)?.into_iter().collect::<Vec<_>>();
+    let elapsed = start_time.elapsed();
+    data.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    let (paths, _) = input_paths(&args.input)?;
+    let processing = Processing {
+        rows: data.iter().map(|(_, value)| value.count).sum(),
+        bytes: paths.iter().map(|path| std::fs::metadata(path).map(|m| m.len())).sum::<std::io::Result<u64>>()?,
+        elapsed,
+    };
+
+    // Check every name once now, so no request can fail on one later
+    for (key, value) in &data {
+        StationSummary::new(key, value, &AGGREGATES, &[], Unit::C)?;
+    }
+
+    let server = Server::http(&args.listen).map_err(|e| format!("could not listen on {}: {}", args.listen, e))?;
+    log::info!("Serving {} stations on http://{}", data.len(), args.listen);
+
+    for request in server.incoming_requests() {
+        let path = request.url().split('?').next().unwrap_or_default();
+
+        let (status, body, content_type) = match request.method() {
+            Method::Get if path == "/metrics" => (200, metrics(&data, &processing), "text/plain; version=0.0.4"),
+            Method::Get => {
+                let (status, body) = route(&data, request.url());
+                (status, body, "application/json")
+            }
+            _ => {
+                let (status, body) = error(405, "only GET is supported");
+                (status, body, "application/json")
+            }
+        };
+
+        let response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(Header::from_bytes("Content-Type", content_type).unwrap());
+        // The client going away mid-response is its own problem
+        let _ = request.respond(response);
+    }
+
+    Ok(())
+}
+
+// The status code and JSON body for a GET of url
+fn route(data: &[(StationKey, Record)], url: &str) -> (u16, String) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+
+    if path == "/stations" {
+        return json(&summaries(data));
+    }
+
+    if let Some(name) = path.strip_prefix("/stations/") {
+        let Some(name) = percent_decode(name, false) else { return error(400, "invalid percent-encoding") };
+        return match data.binary_search_by(|(key, _)| (**key).cmp(&name[..])) {
+            Ok(i) => json(&summaries(&data[i..=i])[0]),
+            Err(_) => error(404, &format!("no station named {:?}", String::from_utf8_lossy(&name))),
+        };
+    }
+
+    if path == "/top" {
+        let mut by = TopBy::Mean;
+        let mut n = 10;
+
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let value = percent_decode(value, true).and_then(|value| String::from_utf8(value).ok()).unwrap_or_default();
+            match name {
+                "by" => match TopBy::from_str(&value, true) {
+                    Ok(value) => by = value,
+                    Err(_) => return error(400, "by must be max, min, mean or count"),
+                },
+                "n" => match value.parse() {
+                    Ok(value) => n = value,
+                    Err(_) => return error(400, "n must be a number"),
+                },
+                _ => return error(400, &format!("unknown parameter {:?}", name)),
+            }
+        }
+
+        let mut top = data.to_vec();
+        keep_top(&mut top, n, by);
+        return json(&summaries(&top));
+    }
+
+    error(404, "not found")
+}
+
+// Prometheus' text exposition format
+fn metrics(data: &[(StationKey, Record)], processing: &Processing) -> String {
+    let mut metrics = String::new();
+
+    type Gauge = (&'static str, &'static str, fn(&Record) -> f64);
+    let gauges: [Gauge; 4] = [
+        ("onebrc_station_min_celsius", "Lowest temperature measured at the station", Record::min),
+        ("onebrc_station_mean_celsius", "Mean temperature measured at the station", |record| record.mean_rounded()),
+        ("onebrc_station_max_celsius", "Highest temperature measured at the station", Record::max),
+        ("onebrc_station_measurements", "Number of measurements for the station", |record| record.count as f64),
+    ];
+    for (name, help, value) in gauges {
+        writeln!(metrics, "# HELP {} {}\n# TYPE {} gauge", name, help, name).unwrap();
+        for (key, record) in data {
+            writeln!(metrics, "{}{{station=\"{}\"}} {}", name, label_value(key), value(record)).unwrap();
+        }
+    }
+
+    let seconds = processing.elapsed.as_secs_f64();
+    let processed: [(&str, &str, &str, f64); 4] = [
+        ("onebrc_rows_total", "counter", "Measurements aggregated", processing.rows as f64),
+        ("onebrc_bytes_read_total", "counter", "Bytes of input files read", processing.bytes as f64),
+        ("onebrc_aggregation_seconds", "gauge", "Time taken to aggregate the input", seconds),
+        ("onebrc_rows_per_second", "gauge", "Measurements aggregated per second", processing.rows as f64 / seconds),
+    ];
+    for (name, kind, help, value) in processed {
+        writeln!(metrics, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value).unwrap();
+    }
+
+    metrics
+}
+
+// Escapes a station name for use between the quotes of a label
+fn label_value(key: &[u8]) -> String {
+    String::from_utf8_lossy(key).replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Names were checked at startup, so these can't fail
+fn summaries(data: &[(StationKey, Record)]) -> Vec<StationSummary<'_>> {
+    data.iter().map(|(key, value)| StationSummary::new(key, value, &AGGREGATES, &[], Unit::C).unwrap()).collect()
+}
+
+fn json(value: &impl Serialize) -> (u16, String) {
+    (200, serde_json::to_string_pretty(value).unwrap())
+}
+
+fn error(status: u16, message: &str) -> (u16, String) {
+    (status, serde_json::json!({ "error": message }).to_string())
+}
+
+// Decodes %XX escapes, and + as a space in query strings
+fn percent_decode(text: &str, query: bool) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b'+' if query => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> Vec<(StationKey, Record)> {
+        let mut abha = Record::new(-52);
+        abha.add(300);
+        vec![
+            (StationKey::from(&b"Abha"[..]), abha),
+            (StationKey::from(&b"St. John's"[..]), Record::new(5)),
+            (StationKey::from("Zürich".as_bytes()), Record::new(400)),
+        ]
+    }
+
+    fn stations(body: &str) -> Vec<String> {
+        let value: serde_json::Value = serde_json::from_str(body).unwrap();
+        value.as_array().unwrap().iter().map(|s| s["station"].as_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn routes() {
+        let data = data();
+
+        let (status, body) = route(&data, "/stations");
+        assert_eq!((status, stations(&body)), (200, vec!["Abha".into(), "St. John's".into(), "Zürich".into()]));
+
+        let (status, body) = route(&data, "/stations/Z%C3%BCrich");
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!((status, value["max"].as_f64()), (200, Some(40.)));
+        assert_eq!(route(&data, "/stations/St.%20John's").0, 200);
+        assert_eq!(route(&data, "/stations/Hamburg").0, 404);
+
+        let (status, body) = route(&data, "/top?by=min&n=2");
+        assert_eq!((status, stations(&body)), (200, vec!["Abha".into(), "St. John's".into()]));
+        assert_eq!(stations(&route(&data, "/top").1)[0], "Zürich");
+
+        assert_eq!(route(&data, "/top?by=median").0, 400);
+        assert_eq!(route(&data, "/top?n=x").0, 400);
+        assert_eq!(route(&data, "/").0, 404);
+    }
+
+    #[test]
+    fn prometheus_metrics() {
+        let mut data = data();
+        data.push((StationKey::from(&b"Say \"hi\""[..]), Record::new(0)));
+        let processing = Processing { rows: 5, bytes: 60, elapsed: Duration::from_millis(500) };
+        let metrics = metrics(&data, &processing);
+
+        assert!(metrics.contains("# TYPE onebrc_station_min_celsius gauge\nonebrc_station_min_celsius{station=\"Abha\"} -5.2\n"));
+        assert!(metrics.contains("onebrc_station_mean_celsius{station=\"Abha\"} 12.4\n"));
+        assert!(metrics.contains("onebrc_station_measurements{station=\"Zürich\"} 1\n"));
+        assert!(metrics.contains("onebrc_station_max_celsius{station=\"Say \\\"hi\\\"\"} 0\n"));
+        assert!(metrics.contains("# TYPE onebrc_rows_total counter\nonebrc_rows_total 5\n"));
+        assert!(metrics.contains("onebrc_rows_per_second 10\n"));
+    }
+}
@@ -0,0 +1,138 @@
+//! Where the bytes of the chunks come from.
+//!
+//! The buffered engine reads every chunk through a [`ChunkSource`]
+//! rather than opening the file itself, so anything that can hand out
+//! byte ranges is aggregated the same way: a file, a memory map, bytes
+//! already in memory, or a source elsewhere that implements the trait.
+
+use std::fs::File;
+
+use crate::storage::Storage;
+use crate::{engine, finish_run, in_pool, starting, Error, Options, StationMap};
+
+/// Uncompressed measurements that can be read a range at a time, from
+/// any number of threads at once.
+pub trait ChunkSource: Sync {
+    /// How many bytes there are
+    fn size(&self) -> std::io::Result<u64>;
+
+    /// The bytes from `start` to `end`, which is no further than
+    /// [`size`](ChunkSource::size)
+    fn read_range(&self, start: u64, end: u64) -> std::io::Result<impl AsRef<[u8]> + '_>;
+
+    /// Reads the bytes from `offset` on into `buffer`, returning how
+    /// many were read: 0 at the end. The default copies them out of
+    /// [`read_range`](ChunkSource::read_range), so a source that can
+    /// read straight into the buffer should do that instead
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let end = self.size()?.min(offset.saturating_add(buffer.len() as u64));
+        if offset >= end {
+            return Ok(0);
+        }
+        let bytes = self.read_range(offset, end)?;
+        let bytes = bytes.as_ref();
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        Ok(bytes.len())
+    }
+}
+
+fn past_the_end(start: u64, end: u64, size: usize) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("range {}..{} is past the end of {} bytes", start, end, size))
+}
+
+impl ChunkSource for [u8] {
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> std::io::Result<impl AsRef<[u8]> + '_> {
+        self.get(start as usize..end as usize).ok_or_else(|| past_the_end(start, end, self.len()))
+    }
+}
+
+impl ChunkSource for Vec<u8> {
+    fn size(&self) -> std::io::Result<u64> {
+        self.as_slice().size()
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> std::io::Result<impl AsRef<[u8]> + '_> {
+        self.as_slice().read_range(start, end)
+    }
+}
+
+impl ChunkSource for memmap2::Mmap {
+    fn size(&self) -> std::io::Result<u64> {
+        self[..].size()
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> std::io::Result<impl AsRef<[u8]> + '_> {
+        self[..].read_range(start, end)
+    }
+}
+
+// Positioned reads, so the threads never share a cursor
+impl ChunkSource for File {
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> std::io::Result<impl AsRef<[u8]> + '_> {
+        let mut bytes = vec![0; end.saturating_sub(start) as usize];
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match self.read_at(start + filled as u64, &mut bytes[filled..]) {
+                Ok(0) => return Err(past_the_end(start, end, (start as usize) + filled)),
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(bytes)
+    }
+
+    #[cfg(unix)]
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> std::io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buffer, offset)
+    }
+
+    // Moves the handle's cursor too, which nothing here reads through
+    #[cfg(windows)]
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> std::io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buffer, offset)
+    }
+
+    // Through a handle of its own, whose cursor nothing else moves
+    #[cfg(not(any(unix, windows)))]
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> std::io::Result<usize> {
+        use std::io::{Read, Seek};
+        let mut file = self.try_clone()?;
+        file.seek(std::io::SeekFrom::Start(offset))?;
+        file.read(buffer)
+    }
+}
+
+impl<S: ChunkSource + ?Sized> ChunkSource for &S {
+    fn size(&self) -> std::io::Result<u64> {
+        (**self).size()
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> std::io::Result<impl AsRef<[u8]> + '_> {
+        (**self).read_range(start, end)
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> std::io::Result<usize> {
+        (**self).read_at(offset, buffer)
+    }
+}
+
+/// Aggregates the measurements in `source` as
+/// [`aggregate_stations`](crate::aggregate_stations) does a file with
+/// the buffered engine: chunked, and each chunk read in blocks of
+/// [`Options::read_size`] bytes and parsed in parallel.
+pub fn aggregate_source<S: ChunkSource + ?Sized>(source: &S, options: &Options) -> Result<StationMap, Error> {
+    let size = source.size()?;
+    let options = &starting(options, || size);
+    let read_size = options.read_size.map_or(Storage::Unknown.read_size(), |size| size.max(1));
+    in_pool(options, || engine::aggregate(&engine::Buffered { source, size, read_size }, options))
+        .and_then(|data| finish_run(data, options))
+}
@@ -0,0 +1,119 @@
+// `--split-output DIR`: a file of results for each station, or for each
+// group with --group-by, rather than the one file of them all. Each file
+// holds what --format prints for that station alone, and is named after
+// it with whatever can't be in a file name (or would mean something else
+// in one) %-escaped, so different stations never share a file. The files
+// are written by --threads threads at once.
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use one_billion_row_challenge::{Error, Record, StationKey};
+
+use crate::{collate, format_rows, group, keep_top, write_atomically, OutputArgs};
+
+// The stems Windows keeps for devices, whatever the extension
+const DEVICES: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+
+/// Writes the files into dir, returning how many there are
+pub fn write(dir: &Path, mut data: Vec<(StationKey, Record)>, output: &OutputArgs, threads: usize) -> Result<usize, Error> {
+    if let Some(by) = &output.group_by {
+        data = group::rollup(data, by, output.with_stations, output.metadata.as_deref())?;
+    }
+    // Only the order --top's ties are broken in matters, as every
+    // station has a file of its own
+    if output.collation == collate::Collation::Unicode && output.top.is_some() {
+        collate::sort(&mut data);
+    }
+    if let Some(top) = output.top {
+        keep_top(&mut data, top, output.by);
+    }
+    std::fs::create_dir_all(dir).map_err(|source| Error::Io { path: Some(dir.to_path_buf()), source })?;
+
+    let next = AtomicUsize::new(0);
+    let write_next = || -> Result<(), Error> {
+        while let Some(row) = data.get(next.fetch_add(1, Ordering::Relaxed)) {
+            let path = dir.join(file_name(&row.0, output.format.extension()));
+            let written = format_rows(std::slice::from_ref(row), output)
+                .and_then(|contents| write_atomically(&path, &contents).map_err(|source| Error::Io { path: Some(path), source }));
+            if let Err(e) = written {
+                // Leaves the rest for the other threads to pass over
+                next.store(data.len(), Ordering::Relaxed);
+                return Err(e);
+            }
+        }
+        Ok(())
+    };
+    std::thread::scope(|scope| {
+        let writers = (0..threads.clamp(1, data.len().max(1))).map(|_| scope.spawn(write_next)).collect::<Vec<_>>();
+        writers.into_iter().try_for_each(|writer| writer.join().unwrap())
+    })?;
+    Ok(data.len())
+}
+
+// name with extension, as a file name that works the same everywhere.
+// Letters (in any script), digits, spaces, `-`, `_` and `.` are kept,
+// and anything else is %XX for each of its bytes, as is a leading `.`, a
+// trailing space or `.`, and the first letter of a device name
+fn file_name(name: &[u8], extension: &str) -> String {
+    let mut file_name = String::with_capacity(name.len() + extension.len() + 1);
+    let escape = |file_name: &mut String, bytes: &[u8]| {
+        for byte in bytes {
+            write!(file_name, "%{:02X}", byte).unwrap();
+        }
+    };
+
+    let stem = name.split(|&byte| byte == b'.').next().unwrap_or_default();
+    let device = DEVICES.iter().any(|device| stem.eq_ignore_ascii_case(device.as_bytes()))
+        || (stem.len() == 4 && (stem[..3].eq_ignore_ascii_case(b"COM") || stem[..3].eq_ignore_ascii_case(b"LPT")) && (b'1'..=b'9').contains(&stem[3]));
+
+    let mut at = 0;
+    for chunk in name.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            let end = at + c.len_utf8();
+            let kept = (c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.'))
+                && !(at == 0 && (c == '.' || device))
+                && !(end == name.len() && matches!(c, ' ' | '.'));
+            if kept {
+                file_name.push(c);
+            } else {
+                escape(&mut file_name, &name[at..end]);
+            }
+            at = end;
+        }
+        escape(&mut file_name, chunk.invalid());
+        at += chunk.invalid().len();
+    }
+
+    // Nothing else is a lone %
+    if name.is_empty() {
+        file_name.push('%');
+    }
+    file_name.push('.');
+    file_name.push_str(extension);
+    file_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_file_names() {
+        let name = |station: &[u8]| file_name(station, "txt");
+        assert_eq!(name(b"Hamburg"), "Hamburg.txt");
+        assert_eq!(name("São Paulo".as_bytes()), "São Paulo.txt");
+        assert_eq!(name(b"St. John's"), "St. John%27s.txt");
+        assert_eq!(name(b"a/b\\c:d"), "a%2Fb%5Cc%3Ad.txt");
+        assert_eq!(name(b".."), "%2E%2E.txt");
+        assert_eq!(name(b"Ends. "), "Ends.%20.txt");
+        assert_eq!(name(b"nul"), "%6Eul.txt");
+        assert_eq!(name(b"COM1.x"), "%43OM1.x.txt");
+        assert_eq!(name(b"Console"), "Console.txt");
+        assert_eq!(name(b"\xffbad"), "%FFbad.txt");
+        assert_eq!(name(b""), "%.txt");
+        // Escaping the escape keeps names apart
+        assert_ne!(name(b"a%2F"), name(b"a/"));
+    }
+}
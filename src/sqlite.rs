@@ -0,0 +1,140 @@
+// Writing results into a SQLite database. Every export is a new row in
+// `runs`, so one database keeps the history of many runs:
+//
+//   runs(id, created_at, inputs)
+//   stations(run_id, station, min, mean, max, count)
+//   partials(run_id, file, chunk_start, chunk_end, station, min, max, sum, count)
+//
+// partials is only filled in with --partials, one row per station per
+// chunk. sum is the total of the chunk's temperatures, so means can be
+// recombined with SUM(sum) / SUM(count).
+
+use std::path::PathBuf;
+
+use clap::Args;
+use one_billion_row_challenge::{aggregate_chunks, merge_maps, Error, StationMap};
+use rusqlite::{params, Connection};
+
+use crate::{aggregate, input_paths, options, show_progress, station_name, InputArgs};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        inputs TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS stations (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        station TEXT NOT NULL,
+        min REAL NOT NULL,
+        mean REAL NOT NULL,
+        max REAL NOT NULL,
+        count INTEGER NOT NULL,
+        PRIMARY KEY (run_id, station)
+    );
+    CREATE TABLE IF NOT EXISTS partials (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        file TEXT NOT NULL,
+        chunk_start INTEGER NOT NULL,
+        chunk_end INTEGER NOT NULL,
+        station TEXT NOT NULL,
+        min REAL NOT NULL,
+        max REAL NOT NULL,
+        sum REAL NOT NULL,
+        count INTEGER NOT NULL,
+        PRIMARY KEY (run_id, file, chunk_start, station)
+    );
+";
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// SQLite database to add the results to, created if it doesn't exist
+    #[arg(long, value_name = "PATH")]
+    sqlite: PathBuf,
+
+    /// Also store every chunk's partial results in the `partials` table
+    #[arg(long)]
+    partials: bool,
+}
+
+// A chunk of one of the inputs: (file, start, end) and its stations
+type Partial = ((PathBuf, u64, u64), StationMap);
+
+pub fn export(args: ExportArgs) -> Result<(), Error> {
+    let (data, partials) = if args.partials {
+        let partials = aggregate_partials(&args.input)?;
+        let data = partials.iter().map(|(_, map)| map.clone()).reduce(merge_maps).unwrap_or_default();
+        (data, partials)
+    } else {
+        (aggregate(&args.input, false)?, Vec::new())
+    };
+
+    let path = &args.sqlite;
+    let db_error = |e: rusqlite::Error| Error::Other(format!("{}: {}", path.display(), e));
+
+    let mut connection = Connection::open(path).map_err(db_error)?;
+    let transaction = connection.transaction().map_err(db_error)?;
+    transaction.execute_batch(SCHEMA).map_err(db_error)?;
+
+    let inputs = args.input.paths.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>();
+    transaction.execute("INSERT INTO runs (inputs) VALUES (?1)", [serde_json::to_string(&inputs).unwrap()])
+        .map_err(db_error)?;
+    let run_id = transaction.last_insert_rowid();
+
+    // SQLite integers are signed, but counts and offsets are nowhere
+    // near i64::MAX
+    {
+        let mut insert = transaction
+            .prepare("INSERT INTO stations (run_id, station, min, mean, max, count) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
+            .map_err(db_error)?;
+        for (key, value) in &data {
+            insert.execute(params![run_id, station_name(key)?, value.min(), value.mean_rounded(), value.max(), value.count as i64])
+                .map_err(db_error)?;
+        }
+
+        let mut insert = transaction
+            .prepare("INSERT INTO partials (run_id, file, chunk_start, chunk_end, station, min, max, sum, count) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)")
+            .map_err(db_error)?;
+        for ((file, start, end), map) in &partials {
+            for (key, value) in map {
+                insert.execute(params![
+                    run_id,
+                    file.to_string_lossy(),
+                    *start as i64,
+                    *end as i64,
+                    station_name(key)?,
+                    value.min(),
+                    value.max(),
+                    value.total as f64 / value.scale(),
+                    value.count as i64,
+                ]).map_err(db_error)?;
+            }
+        }
+    }
+
+    transaction.commit().map_err(db_error)?;
+
+    println!("Added {} stations to {} as run {}", data.len(), path.display(), run_id);
+    Ok(())
+}
+
+// Aggregates every chunk of every input separately
+fn aggregate_partials(input: &InputArgs) -> Result<Vec<Partial>, Error> {
+    let options = options(input, false)?;
+    let (paths, stdin) = input_paths(input)?;
+    if stdin {
+        return Err("--partials needs files, not stdin".into());
+    }
+
+    show_progress(&options, &paths, false, || {
+        let mut partials = Vec::new();
+        for path in &paths {
+            let chunks = aggregate_chunks(path, &options)?;
+            partials.extend(chunks.into_iter().map(|((start, end), map)| ((path.clone(), start, end), map)));
+        }
+        Ok(partials)
+    })
+}
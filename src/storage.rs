@@ -0,0 +1,96 @@
+// What kind of storage a file is on, to size the engines' reads by.
+//
+// On Linux a network filesystem is told apart by its statfs magic, and
+// a local one by its block device in /sys/dev/block: NVMe devices by
+// name, and spinning disks by queue/rotational. Anything else (tmpfs,
+// overlays) is Unknown. Elsewhere only a UNC path (\\server\share on
+// Windows) is known to be on the network.
+
+use std::fs::File;
+use std::path::Path;
+
+const MB: usize = 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) enum Storage {
+    Nvme,
+    Ssd,
+    Rotational,
+    Network,
+    Unknown,
+}
+
+impl Storage {
+    /// The read size for a worker. Flash is quick to seek, so moderate
+    /// blocks are plenty, whereas a spinning disk wants long sequential
+    /// runs between the workers' seeks, and a network filesystem as few
+    /// round trips as it can get
+    pub fn read_size(self) -> usize {
+        match self {
+            Storage::Nvme | Storage::Ssd | Storage::Unknown => 4 * MB,
+            Storage::Rotational | Storage::Network => 16 * MB,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn detect(_path: &Path, file: &File) -> Storage {
+        use std::os::unix::fs::MetadataExt;
+        use std::os::unix::io::AsRawFd;
+
+        // NFS, SMB2, CIFS, Ceph and FUSE (which sshfs and the like use)
+        const NETWORK: [i64; 5] = [0x6969, 0xfe53_4d42, 0xff53_4d42, 0x00c3_6400, 0x6573_5546];
+
+        // Safe: the struct is zeroed before use and outlives the call
+        let network = unsafe {
+            let mut stats: libc::statfs = std::mem::zeroed();
+            libc::fstatfs(file.as_raw_fd(), &mut stats) == 0 && NETWORK.contains(&(stats.f_type as i64))
+        };
+        if network {
+            return Storage::Network;
+        }
+
+        let Ok(metadata) = file.metadata() else { return Storage::Unknown };
+        // Safe: these only pick the bits apart (newer libcs don't even
+        // mark them unsafe)
+        #[allow(unused_unsafe)]
+        let (major, minor) = unsafe { (libc::major(metadata.dev()), libc::minor(metadata.dev())) };
+        let Ok(device) = std::fs::canonicalize(format!("/sys/dev/block/{}:{}", major, minor)) else {
+            return Storage::Unknown;
+        };
+
+        // A partition's queue is its whole disk's, one level up
+        let disk = if device.join("partition").exists() { device.parent().unwrap_or(&device) } else { &device };
+        let name = disk.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        let rotational = std::fs::read_to_string(disk.join("queue/rotational"));
+
+        match rotational.as_deref().map(str::trim) {
+            _ if name.starts_with("nvme") => Storage::Nvme,
+            Ok("1") => Storage::Rotational,
+            Ok("0") => Storage::Ssd,
+            _ => Storage::Unknown,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn detect(path: &Path, _file: &File) -> Storage {
+        use std::path::{Component, Prefix};
+
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        match path.components().next() {
+            Some(Component::Prefix(prefix)) if matches!(prefix.kind(), Prefix::UNC(..) | Prefix::VerbatimUNC(..)) => Storage::Network,
+            _ => Storage::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_something() {
+        let storage = Storage::detect("Cargo.toml".as_ref(), &File::open("Cargo.toml").unwrap());
+        assert!((4 * MB..=16 * MB).contains(&storage.read_size()));
+    }
+}
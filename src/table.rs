@@ -0,0 +1,192 @@
+// The table the mmap engine's workers aggregate into.
+//
+// A general map either hashes a name twice for a new station (once to
+// look it up, once to insert it) or needs an owned key to look it up
+// by. This one only ever sees names that are slices of the mapped file,
+// and never removes anything, so it can be much simpler: open
+// addressing with linear probing, no tombstones, and every slot's hash
+// kept in a dense array of its own. A probe walks that array and only
+// compares names where the whole hash matches.
+
+use std::hash::BuildHasher;
+
+use crate::{Record, StationHasher, StationKey, StationMap, Stations};
+
+// A hash that marks an empty slot. Real hashes always have their top
+// bit set, and the slot comes from the low bits
+const EMPTY: u64 = 0;
+
+// Enough for the challenge's 413 stations without growing
+const INITIAL_SLOTS: usize = 1024;
+
+pub(crate) struct Table<'a> {
+    hashes: Vec<u64>,
+    entries: Vec<Option<(&'a [u8], Record)>>,
+    len: usize,
+    hasher: StationHasher,
+}
+
+impl<'a> Table<'a> {
+    pub fn new() -> Self {
+        Self::with_slots(INITIAL_SLOTS)
+    }
+
+    fn with_slots(slots: usize) -> Self {
+        debug_assert!(slots.is_power_of_two());
+        Table {
+            hashes: vec![EMPTY; slots],
+            entries: vec![None; slots],
+            len: 0,
+            hasher: StationHasher::default(),
+        }
+    }
+
+    #[inline]
+    fn hash(&self, name: &[u8]) -> u64 {
+        self.hasher.hash_one(name) | 1 << 63
+    }
+
+    // The slot name is in, or the empty slot it would go in
+    #[inline]
+    fn find(&self, name: &[u8], hash: u64) -> Result<usize, usize> {
+        let mask = self.hashes.len() - 1;
+        let mut slot = hash as usize & mask;
+
+        loop {
+            match self.hashes[slot] {
+                EMPTY => return Err(slot),
+                found if found == hash && self.entries[slot].as_ref().is_some_and(|(key, _)| *key == name) => return Ok(slot),
+                _ => slot = (slot + 1) & mask,
+            }
+        }
+    }
+
+    // Doubles the slots once they're half full, which keeps the probes
+    // short
+    fn grow(&mut self) {
+        let mut grown = Table::with_slots(self.hashes.len() * 2);
+        for (hash, entry) in self.hashes.iter().zip(std::mem::take(&mut self.entries)) {
+            if let Some((name, record)) = entry {
+                let Err(slot) = grown.find(name, *hash) else { unreachable!() };
+                grown.hashes[slot] = *hash;
+                grown.entries[slot] = Some((name, record));
+            }
+        }
+        grown.len = self.len;
+        *self = grown;
+    }
+
+    // Stations::record, for a name already hashed
+    #[inline]
+    fn record_with(&mut self, name: &'a [u8], hash: u64, new: impl FnOnce() -> Record) -> (&mut Record, bool) {
+        let slot = match self.find(name, hash) {
+            Ok(slot) => return (&mut self.entries[slot].as_mut().unwrap().1, false),
+            Err(_) if (self.len + 1) * 2 > self.hashes.len() => {
+                self.grow();
+                self.find(name, hash).unwrap_err()
+            }
+            Err(slot) => slot,
+        };
+
+        self.hashes[slot] = hash;
+        self.len += 1;
+        (&mut self.entries[slot].insert((name, new())).1, true)
+    }
+
+    /// Copies the names out, for the merge
+    pub fn into_map(self) -> StationMap {
+        let mut map = StationMap::with_capacity_and_hasher(self.len, Default::default());
+        map.extend(self.entries.into_iter().flatten().map(|(name, record)| (StationKey::from(name), record)));
+        map
+    }
+}
+
+impl<'a> Stations<'a> for Table<'a> {
+    #[inline]
+    fn contains(&self, name: &[u8]) -> bool {
+        self.find(name, self.hash(name)).is_ok()
+    }
+
+    #[inline]
+    fn record(&mut self, name: &'a [u8], new: impl FnOnce() -> Record) -> (&mut Record, bool) {
+        self.record_with(name, self.hash(name), new)
+    }
+
+    fn for_each(&self, mut f: impl FnMut(&[u8], &Record)) {
+        for (name, record) in self.entries.iter().flatten() {
+            f(name, record);
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    // The probe starts at the same slot of both arrays, and usually ends there
+    #[inline]
+    fn prefetch(&self, name: &[u8]) -> u64 {
+        let hash = self.hash(name);
+        let slot = hash as usize & (self.hashes.len() - 1);
+        prefetch(&self.hashes[slot]);
+        prefetch(&self.entries[slot]);
+        hash
+    }
+
+    #[inline]
+    fn record_hashed(&mut self, hash: u64, name: &'a [u8], new: impl FnOnce() -> Record) -> (&mut Record, bool) {
+        self.record_with(name, hash, new)
+    }
+}
+
+// Starts the cache line at item on its way, without waiting for it
+#[inline]
+pub(crate) fn prefetch<T>(item: &T) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        std::arch::x86_64::_mm_prefetch::<{ std::arch::x86_64::_MM_HINT_T0 }>(item as *const T as *const i8);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = item;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Total;
+
+    #[test]
+    fn finds_what_it_added() {
+        let mut table = Table::new();
+        assert!(!table.contains(b"Abha"));
+
+        let (record, new) = table.record(b"Abha", || Record::new(10));
+        assert!(new);
+        record.add(20);
+        let (record, new) = table.record(b"Abha", || unreachable!());
+        assert!(!new);
+        assert_eq!(record.count, 2);
+
+        assert!(table.contains(b"Abha"));
+        assert!(!table.contains(b"Abh"));
+        assert_eq!(table.into_map().len(), 1);
+    }
+
+    #[test]
+    fn grows() {
+        let names: Vec<String> = (0..10_000).map(|i| format!("Station {}", i)).collect();
+        let mut table = Table::new();
+        for (i, name) in names.iter().enumerate() {
+            table.record(name.as_bytes(), || Record::new(i as i32));
+        }
+        for (i, name) in names.iter().enumerate() {
+            table.record(name.as_bytes(), || unreachable!()).0.add(i as i32);
+        }
+        let map = table.into_map();
+        assert_eq!(map.len(), names.len());
+        for (i, name) in names.iter().enumerate() {
+            let record = &map[name.as_bytes()];
+            assert_eq!((record.count, record.min, record.total), (2, i as i32, 2 * i as Total));
+        }
+    }
+}
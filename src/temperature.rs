@@ -0,0 +1,55 @@
+// Parsing the temperatures the challenge itself gives, without a loop.
+
+/// Parses a temperature in one of the four shapes the challenge has
+/// (`d.d`, `dd.d`, `-d.d` and `-dd.d`) into tenths, or returns `None`
+/// for anything else, which is left to the general parser.
+///
+/// The digits (padded to two before the point) are checked and read
+/// all at once as the bytes of a u32, so which shape it was doesn't
+/// cost a branch per byte.
+#[inline]
+pub(crate) fn parse_tenths(digits: &[u8]) -> Option<i32> {
+    let negative = digits.first() == Some(&b'-');
+    let body = &digits[negative as usize..];
+    if !(3..=4).contains(&body.len()) {
+        return None;
+    }
+
+    let mut padded = *b"0000";
+    padded[4 - body.len()..].copy_from_slice(body);
+
+    // Every byte that should be a digit is now 0 to 9, and the point 0
+    let x = u32::from_le_bytes(padded) ^ u32::from_le_bytes(*b"00.0");
+    let invalid = ((x | x.wrapping_add(0x0606_0606)) & 0xF0F0_F0F0) | (x & 0x00FF_0000);
+    if invalid != 0 {
+        return None;
+    }
+
+    let [tens, units, _, tenths] = x.to_le_bytes().map(|digit| digit as i32);
+    let value = tens * 100 + units * 10 + tenths;
+    let sign = -(negative as i32);
+    Some((value ^ sign) - sign)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes() {
+        assert_eq!(parse_tenths(b"0.0"), Some(0));
+        assert_eq!(parse_tenths(b"-0.0"), Some(0));
+        assert_eq!(parse_tenths(b"1.2"), Some(12));
+        assert_eq!(parse_tenths(b"12.3"), Some(123));
+        assert_eq!(parse_tenths(b"-1.2"), Some(-12));
+        assert_eq!(parse_tenths(b"-99.9"), Some(-999));
+        assert_eq!(parse_tenths(b"99.9"), Some(999));
+    }
+
+    #[test]
+    fn everything_else() {
+        for digits in [&b""[..], b"-", b"1", b"12", b"1.", b".1", b"1.23", b"123.4", b"--1.2", b"1/2", b"a.b", b"1.-", b"+1.2", b"1:2", b"-1.2\r"] {
+            assert_eq!(parse_tenths(digits), None, "{:?}", String::from_utf8_lossy(digits));
+        }
+    }
+}
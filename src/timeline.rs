@@ -0,0 +1,117 @@
+// `--time-bucket`: the results of measurements with a timestamp, one row
+// per station per hour, day or month, in order of station and then time.
+
+use std::fmt::Write as _;
+use std::sync::atomic::Ordering;
+
+use indicatif::HumanCount;
+use one_billion_row_challenge::{aggregate_buckets, aggregate_buckets_bytes, merge_buckets, BucketMap, Error, Record, StationKey, TimeBucket};
+use serde::Serialize;
+
+use crate::numbers::NumberArgs;
+use crate::unit::degrees;
+use crate::{csv_field, input_paths, options, station_name, write_output, Format, OutputArgs, RunArgs, MIN_MEAN_MAX};
+
+pub fn run(args: &RunArgs, bucket: TimeBucket) -> Result<(), Error> {
+    let output = &args.output;
+    if output.histogram.is_some() || output.aggregates != MIN_MEAN_MAX || !output.stats.is_empty() || output.top.is_some() || output.sort.is_some() || output.numbers != NumberArgs::default() {
+        return Err("--time-bucket results can't have --histogram, --aggregates, --stats, --top, --sort or the flags for how numbers are written".into());
+    }
+
+    let options = options(&args.input, false)?;
+    let (paths, stdin) = input_paths(&args.input)?;
+    let mut data = BucketMap::default();
+    for path in &paths {
+        data = merge_buckets(data, aggregate_buckets(path, bucket, &options)?);
+    }
+    // Read in whole, as it can't be mapped
+    if stdin {
+        let mut bytes = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::stdin().lock(), &mut bytes)?;
+        data = merge_buckets(data, aggregate_buckets_bytes(&bytes, bucket, &options)?);
+    }
+
+    if let Some(bad_lines) = &options.bad_lines {
+        log::warn!("Skipped {} bad lines", HumanCount(bad_lines.load(Ordering::Relaxed)));
+    }
+
+    let mut data = data.into_iter().collect::<Vec<_>>();
+    data.sort_unstable_by(|((a, a_bucket), _), ((b, b_bucket), _)| a.cmp(b).then(a_bucket.cmp(b_bucket)));
+
+    let results = match output.format {
+        Format::Lines => format_lines(&data, bucket, output)?,
+        Format::Csv => format_csv(&data, bucket, output)?,
+        Format::Json => format_json(&data, bucket, output)?,
+        _ => return Err("--time-bucket results can only be printed as lines, csv or json".into()),
+    };
+    write_output(results.as_bytes(), args.output_path.as_deref(), false)?;
+    Ok(())
+}
+
+type Buckets = [((StationKey, i64), Record)];
+
+fn format_lines(data: &Buckets, bucket: TimeBucket, output: &OutputArgs) -> Result<String, Error> {
+    let unit = output.unit;
+    let mut to_print = String::with_capacity(data.len() * 48);
+    for ((key, number), value) in data {
+        let (min, mean, max) = (degrees(unit.min(value)), degrees(unit.mean(value)), degrees(unit.max(value)));
+        write!(to_print, "{};{};{};{};{}", station_name(key)?, bucket.label(*number), min, mean, max).unwrap();
+        if output.with_count {
+            write!(to_print, ";{}", value.count).unwrap();
+        }
+        to_print.push('\n');
+    }
+    Ok(to_print)
+}
+
+fn format_csv(data: &Buckets, bucket: TimeBucket, output: &OutputArgs) -> Result<String, Error> {
+    let (unit, delimiter) = (output.unit, output.csv_delimiter);
+    let mut header = ["station", "bucket", "min", "mean", "max"].map(String::from).to_vec();
+    if output.with_count {
+        header.push("count".to_string());
+    }
+    let mut to_print = header.join(&delimiter.to_string()) + "\n";
+
+    for ((key, number), value) in data {
+        write!(
+            to_print,
+            "{}{d}{}{d}{}{d}{}{d}{}",
+            csv_field(station_name(key)?, delimiter),
+            bucket.label(*number),
+            degrees(unit.min(value)),
+            degrees(unit.mean(value)),
+            degrees(unit.max(value)),
+            d = delimiter,
+        ).unwrap();
+        if output.with_count {
+            write!(to_print, "{}{}", delimiter, value.count).unwrap();
+        }
+        to_print.push('\n');
+    }
+    Ok(to_print)
+}
+
+#[derive(Serialize)]
+struct BucketSummary<'a> {
+    station: &'a str,
+    bucket: String,
+    min: f64,
+    mean: f64,
+    max: f64,
+    count: u64,
+}
+
+fn format_json(data: &Buckets, bucket: TimeBucket, output: &OutputArgs) -> Result<String, Error> {
+    let unit = output.unit;
+    let buckets = data.iter()
+        .map(|((key, number), value)| Ok(BucketSummary {
+            station: station_name(key)?,
+            bucket: bucket.label(*number),
+            min: degrees(unit.min(value)),
+            mean: degrees(unit.mean(value)),
+            max: degrees(unit.max(value)),
+            count: value.count,
+        }))
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(serde_json::to_string_pretty(&buckets).unwrap() + "\n")
+}
@@ -0,0 +1,86 @@
+// Where an aggregation spends its time, for --timings.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+#[cfg(feature = "native")]
+use std::time::Instant;
+
+#[cfg(feature = "native")]
+use crate::Options;
+
+/// A phase of an aggregation that [`Timings`] keeps track of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Splitting the files into line-aligned chunks
+    Chunking,
+    /// Waiting on reads. Page faults on a memory map can't be told
+    /// apart from parsing, so the mmap engine counts them as parsing
+    Io,
+    /// Parsing lines into each thread's map, which is also where the
+    /// per-thread aggregation happens
+    Parsing,
+    /// Merging the per-thread and per-file maps into one
+    Merging,
+    /// Readers waiting for the parsers to take a block, or give a
+    /// buffer back, with [`Options::io_threads`](crate::Options::io_threads)
+    /// or `parse_threads`: the parsing is behind
+    ReaderStall,
+    /// Parsers waiting for the readers to fill a block: the reading is
+    /// behind
+    ParserStall,
+}
+
+impl Phase {
+    /// Every phase, in the order an aggregation goes through them, and
+    /// then the pipeline's stalls
+    pub const ALL: [Phase; 6] = [Phase::Chunking, Phase::Io, Phase::Parsing, Phase::Merging, Phase::ReaderStall, Phase::ParserStall];
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.pad(match self {
+            Phase::Chunking => "chunking",
+            Phase::Io => "I/O",
+            Phase::Parsing => "parsing",
+            Phase::Merging => "merging",
+            Phase::ReaderStall => "reader stall",
+            Phase::ParserStall => "parser stall",
+        })
+    }
+}
+
+/// Time spent in each [`Phase`], added up by the workers as they go.
+///
+/// The phases workers run in parallel are summed over all of them, so
+/// they can add up to more than the wall time. Pass one in
+/// [`Options::timings`] to have it filled in.
+#[derive(Debug, Default)]
+pub struct Timings {
+    nanos: [AtomicU64; 6],
+}
+
+impl Timings {
+    /// The total time spent in phase so far
+    pub fn get(&self, phase: Phase) -> Duration {
+        Duration::from_nanos(self.nanos[phase as usize].load(Ordering::Relaxed))
+    }
+
+    #[cfg(feature = "native")]
+    pub(crate) fn add(&self, phase: Phase, elapsed: Duration) {
+        self.nanos[phase as usize].fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+// Runs f, counting its time towards phase if options is keeping timings
+#[cfg(feature = "native")]
+pub(crate) fn timed<T>(options: &Options, phase: Phase, f: impl FnOnce() -> T) -> T {
+    match &options.timings {
+        Some(timings) => {
+            let start = Instant::now();
+            let result = f();
+            timings.add(phase, start.elapsed());
+            result
+        }
+        None => f(),
+    }
+}
@@ -0,0 +1,67 @@
+// `--tui`: a dashboard on stderr while aggregating, with a bar for each
+// worker under one for the whole input, and the hottest stations so far
+// and the memory in use underneath.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use one_billion_row_challenge::{Fixed, Live};
+
+/// Runs f while the dashboard follows `progress` and `live`
+pub fn with_tui<T>(progress: &AtomicU64, live: &Live, total: Option<u64>, precision: u8, f: impl FnOnce() -> T) -> T {
+    let bars = MultiProgress::new();
+    let overall = bars.add(match total {
+        Some(total) => ProgressBar::new(total).with_style(
+            ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} ({binary_bytes_per_sec}, ETA {eta})").unwrap(),
+        ),
+        None => ProgressBar::new_spinner().with_style(ProgressStyle::with_template("{spinner} {bytes} ({binary_bytes_per_sec})").unwrap()),
+    });
+
+    // Each worker's bar is out of an even share of the input
+    let threads = live.thread_bytes().len();
+    let style = match total {
+        Some(_) => ProgressStyle::with_template("{prefix:>10} {bar:29} {bytes} ({binary_bytes_per_sec})").unwrap(),
+        None => ProgressStyle::with_template("{prefix:>10} {bytes} ({binary_bytes_per_sec})").unwrap(),
+    };
+    let workers = (0..threads)
+        .map(|i| {
+            let share = total.map_or(0, |total| total.div_ceil(threads as u64));
+            bars.add(ProgressBar::new(share).with_style(style.clone()).with_prefix(format!("thread {}", i)))
+        })
+        .collect::<Vec<_>>();
+    let status = bars.add(ProgressBar::new_spinner().with_style(ProgressStyle::with_template("{msg}").unwrap()));
+
+    let done = AtomicBool::new(false);
+
+    let result = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                overall.set_position(progress.load(Ordering::Relaxed));
+                for (bar, bytes) in workers.iter().zip(live.thread_bytes()) {
+                    bar.set_position(bytes);
+                }
+                status.set_message(status_line(live, precision));
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        });
+
+        let result = f();
+        done.store(true, Ordering::Relaxed);
+        result
+    });
+
+    bars.clear().ok();
+    result
+}
+
+fn status_line(live: &Live, precision: u8) -> String {
+    let hottest = live.hottest();
+    let hottest = if hottest.is_empty() {
+        "-".to_string()
+    } else {
+        hottest.iter().map(|(name, max)| format!("{} {}", name, Fixed(*max as i64, precision))).collect::<Vec<_>>().join(", ")
+    };
+    let memory = crate::memory::resident().map_or_else(|| "?".to_string(), |bytes| HumanBytes(bytes).to_string());
+    format!("Hottest: {}\nMemory: {}", hottest, memory)
+}
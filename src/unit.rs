@@ -0,0 +1,139 @@
+// Printing the results in Fahrenheit or Kelvin.
+//
+// Temperatures are converted from the records' fixed-point values, so
+// a mean is rounded once, from the exact total, rather than rounded in
+// Celsius and then again after converting. Results keep the records'
+// decimals, and are rounded half up as means are.
+
+use clap::ValueEnum;
+use one_billion_row_challenge::{Fixed, Record};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Unit {
+    /// Degrees Celsius, as measured
+    #[default]
+    C,
+    /// Degrees Fahrenheit
+    F,
+    /// Kelvin
+    K,
+}
+
+impl Unit {
+    /// The temperature num / den, in units of `decimals` decimal places
+    /// of a degree Celsius, in this unit to as many decimals
+    pub fn convert(self, num: i128, den: i128, decimals: u8) -> Fixed {
+        let scale = 10i128.pow(decimals as u32);
+        let (num, den) = match self {
+            Unit::C => (num, den),
+            // 9/5 C + 32
+            Unit::F => (9 * num + 160 * scale * den, 5 * den),
+            // C + 273.15
+            Unit::K => (100 * num + 27_315 * scale * den, 100 * den),
+        };
+        Fixed((2 * num + den).div_euclid(2 * den) as i64, decimals)
+    }
+
+    pub fn min(self, record: &Record) -> Fixed {
+        self.convert(record.min.into(), 1, record.decimals)
+    }
+
+    // The cast is only a no-op in one of the two widths of Total
+    #[allow(clippy::unnecessary_cast)]
+    pub fn mean(self, record: &Record) -> Fixed {
+        self.convert(record.total as i128, record.count.into(), record.decimals)
+    }
+
+    pub fn max(self, record: &Record) -> Fixed {
+        self.convert(record.max.into(), 1, record.decimals)
+    }
+
+    /// Every measurement added up, each converted, so the offset counts
+    /// once a measurement
+    // As in mean, the cast of the total
+    #[allow(clippy::unnecessary_cast)]
+    pub fn sum(self, record: &Record) -> Fixed {
+        let scale = 10i128.pow(record.decimals as u32);
+        let (total, count) = (record.total as i128, i128::from(record.count));
+        let (num, den) = match self {
+            Unit::C => (total, 1),
+            Unit::F => (9 * total + 160 * scale * count, 5),
+            Unit::K => (100 * total + 27_315 * scale * count, 100),
+        };
+        Fixed((2 * num + den).div_euclid(2 * den) as i64, record.decimals)
+    }
+
+    /// How far the max is above the min, which has no offset
+    pub fn range(self, record: &Record) -> Fixed {
+        let spread = i128::from(record.max) - i128::from(record.min);
+        let (num, den) = match self {
+            Unit::C | Unit::K => (spread, 1),
+            Unit::F => (9 * spread, 5),
+        };
+        Fixed((2 * num + den).div_euclid(2 * den) as i64, record.decimals)
+    }
+
+    /// How many of this unit make a degree Celsius, for the spread of
+    /// the temperatures rather than the temperatures themselves
+    pub fn degree(self) -> f64 {
+        match self {
+            Unit::C | Unit::K => 1.,
+            Unit::F => 1.8,
+        }
+    }
+}
+
+/// A fixed-point temperature in degrees
+pub fn degrees(temp: Fixed) -> f64 {
+    temp.0 as f64 / 10f64.powi(temp.1 as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(temps: &[i32]) -> Record {
+        let mut record = Record::new(temps[0]);
+        for &temp in &temps[1..] {
+            record.add(temp);
+        }
+        record
+    }
+
+    #[test]
+    fn celsius_is_unchanged() {
+        let record = record(&[-52, 300, 7]);
+        assert_eq!((Unit::C.min(&record), Unit::C.mean(&record), Unit::C.max(&record)), (Fixed(-52, 1), Fixed(85, 1), Fixed(300, 1)));
+        assert_eq!(degrees(Unit::C.mean(&record)), record.mean_rounded());
+    }
+
+    #[test]
+    fn conversions() {
+        let record = record(&[-400, 0, 1000]);
+        assert_eq!((Unit::F.min(&record), Unit::F.max(&record)), (Fixed(-400, 1), Fixed(2120, 1)));
+        assert_eq!((Unit::K.min(&record), Unit::K.max(&record)), (Fixed(2332, 1), Fixed(3732, 1)));
+        // -37.3 C is -35.14 F and 235.85 K, which round half up
+        assert_eq!(Unit::F.convert(-373, 1, 1), Fixed(-351, 1));
+        assert_eq!(Unit::K.convert(-373, 1, 1), Fixed(2359, 1));
+    }
+
+    #[test]
+    fn means_are_rounded_once() {
+        // The mean is 0.05 C, which is 0.1 rounded, but 32.09 F, which
+        // isn't 32.2 (0.1 C converted)
+        let record = record(&[0, 1]);
+        assert_eq!(Unit::C.mean(&record), Fixed(1, 1));
+        assert_eq!(Unit::F.mean(&record), Fixed(321, 1));
+        // 273.2 K, rather than 273.3 from 0.1 C
+        assert_eq!(Unit::K.mean(&record), Fixed(2732, 1));
+    }
+
+    #[test]
+    fn sums_and_ranges() {
+        let record = record(&[-400, 0, 1000]);
+        assert_eq!((Unit::C.sum(&record), Unit::C.range(&record)), (Fixed(600, 1), Fixed(1400, 1)));
+        // -40 + 32 + 212 F, and a range of 140 C is 252 F but still 140 K
+        assert_eq!((Unit::F.sum(&record), Unit::F.range(&record)), (Fixed(2040, 1), Fixed(2520, 1)));
+        assert_eq!((Unit::K.sum(&record), Unit::K.range(&record)), (Fixed(8795, 1), Fixed(1400, 1)));
+    }
+}
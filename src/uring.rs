@@ -0,0 +1,119 @@
+// io_uring read engine (Linux only).
+//
+// Each chunk is read in large blocks through its own small ring, with
+// two buffers: while one block is being parsed, the read of the next
+// one is already in flight. On a cold page cache this overlaps the
+// disk with the parser instead of alternating between them.
+
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use io_uring::{opcode, types, IoUring};
+
+use crate::engine::Engine;
+use crate::timings::{timed, Phase};
+use crate::{chunk, parse_carried, parse_to_end, report_progress, BadLine, Error, Options, StationMap};
+
+pub(crate) struct Uring<'a> {
+    file: File,
+    path: &'a Path,
+    size: u64,
+    read_size: usize,
+}
+
+impl<'a> Uring<'a> {
+    pub fn open(path: &'a Path, size: u64, read_size: usize) -> Result<Self, Error> {
+        Ok(Uring { file: File::open(path)?, path, size, read_size })
+    }
+}
+
+impl Engine for Uring<'_> {
+    fn chunks(&self, options: &Options) -> Result<Vec<(u64, u64)>, Error> {
+        Ok(chunk::file_chunks(self.path, self.size, options)?)
+    }
+
+    fn read_chunk(&self, start: u64, end: u64, data_map: &mut StationMap, options: &Options) -> Result<(), Error> {
+        read_chunk(&self.file, start, end, self.read_size, data_map, options)
+    }
+}
+
+fn read_chunk(file: &File, start: u64, end: u64, read_size: usize, data_map: &mut StationMap, options: &Options) -> Result<(), Error> {
+    let mut ring = IoUring::new(2)?;
+    let fd = types::Fd(file.as_raw_fd());
+
+    let mut buffers = [vec![0u8; read_size], vec![0u8; read_size]];
+    let mut current = 0;
+
+    // The partial line at the end of each block, completed by the
+    // start of the next one
+    let mut carry = Vec::new();
+
+    let submit = |ring: &mut IoUring, buffer: &mut Vec<u8>, offset: u64| -> std::io::Result<()> {
+        let len = (end - offset).min(buffer.len() as u64) as u32;
+        let read = opcode::Read::new(fd, buffer.as_mut_ptr(), len)
+            .offset(offset)
+            .build();
+
+        // Safety: the buffer isn't touched again, or dropped, until this
+        // read's completion has been reaped below
+        unsafe { ring.submission().push(&read).expect("submission queue is full") };
+        ring.submit()?;
+        Ok(())
+    };
+
+    let mut offset = start;
+    if offset < end {
+        submit(&mut ring, &mut buffers[current], offset)?;
+    }
+
+    while offset < end {
+        timed(options, Phase::Io, || ring.submit_and_wait(1))?;
+        let result = ring.completion().next().expect("completion queue is empty").result();
+
+        if result < 0 {
+            let error = std::io::Error::from_raw_os_error(-result);
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                submit(&mut ring, &mut buffers[current], offset)?;
+                continue;
+            }
+            return Err(error.into());
+        }
+        if result == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+
+        // Short reads just mean the next read starts earlier
+        let read = result as usize;
+        let block_offset = offset;
+        offset += read as u64;
+        report_progress(options, read);
+
+        // Get the next block coming while this one is parsed
+        let (block, next) = split_pair(&mut buffers, current);
+        let in_flight = offset < end;
+        if in_flight {
+            submit(&mut ring, next, offset)?;
+        }
+
+        if let Err(e) = timed(options, Phase::Parsing, || parse_carried(&block[..read], block_offset, &mut carry, data_map, options)) {
+            // The read into the other buffer has to land before the
+            // buffers can be freed
+            if in_flight {
+                ring.submit_and_wait(1)?;
+            }
+            return Err(e);
+        }
+
+        current = 1 - current;
+    }
+
+    timed(options, Phase::Parsing, || parse_to_end(&carry, data_map, options))
+        .map_err(|BadLine(at, invalid)| Error::bad_line(invalid, &carry, at, end - carry.len() as u64))
+}
+
+// The buffer at index current and the other one, both mutably
+fn split_pair(buffers: &mut [Vec<u8>; 2], current: usize) -> (&mut Vec<u8>, &mut Vec<u8>) {
+    let [first, second] = buffers;
+    if current == 0 { (first, second) } else { (second, first) }
+}
@@ -0,0 +1,140 @@
+// Checking a measurements file line by line without aggregating it:
+// every line is put through what the parser would, under the options'
+// rules, and the bad ones are kept with their line numbers. Chunks are
+// checked in parallel, each numbering its lines from its own start, and
+// the numbers are put right once every chunk's line count is known.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::par::*;
+
+use crate::error::Invalid;
+use crate::{chunk, compression, in_pool, parser, regular_file, Error, Options};
+
+/// What [`validate_bytes`] found
+#[derive(Debug, Default)]
+pub struct Validation {
+    /// Lines checked, including a last one with no `\n`
+    pub lines: u64,
+    /// How many of them are invalid
+    pub invalid: u64,
+    /// How many were invalid for each reason
+    pub reasons: BTreeMap<String, u64>,
+    /// The first invalid lines, in order, as the [`Error::Parse`]
+    /// aggregating would have stopped at
+    pub violations: Vec<Error>,
+}
+
+impl Validation {
+    fn add(&mut self, mut chunk: Validation, limit: usize) {
+        for violation in &mut chunk.violations {
+            if let Error::Parse { line: Some(line), .. } = violation {
+                *line += self.lines;
+            }
+        }
+        let room = limit - self.violations.len();
+        self.violations.extend(chunk.violations.into_iter().take(room));
+
+        self.lines += chunk.lines;
+        self.invalid += chunk.invalid;
+        for (reason, count) in chunk.reasons {
+            *self.reasons.entry(reason).or_default() += count;
+        }
+    }
+}
+
+/// Checks every line in bytes: that it has the delimiter, a temperature
+/// the options' precision (and strictness) allows, a name no longer
+/// than `max_name_len` (and not empty, strictly) and a name that's
+/// valid UTF-8. Keeps up to `limit` of the bad lines
+pub fn validate_bytes(bytes: &[u8], options: &Options, limit: usize) -> Result<Validation, Error> {
+    let chunks = in_pool(options, || {
+        Ok(chunk::chunks_in(bytes, options)
+            .into_par_iter()
+            .map(|(start, end)| validate_chunk(bytes, start, end, options, limit))
+            .collect::<Vec<_>>())
+    })?;
+
+    let mut validation = Validation::default();
+    for chunk in chunks {
+        validation.add(chunk, limit);
+    }
+    Ok(validation)
+}
+
+/// [`validate_bytes`] for a file, which can't be compressed
+pub fn validate_file(path: &Path, options: &Options, limit: usize) -> Result<Validation, Error> {
+    let file = std::fs::File::open(path).map_err(|e| Error::from(e).in_file(path))?;
+    regular_file(path, &file)?;
+    if compression::detect_file(&file)? != compression::Compression::None {
+        return Err(format!("{}: compressed files can't be validated", path.display()).into());
+    }
+    // Mapping an empty file fails on some platforms
+    if file.metadata()?.len() == 0 {
+        return Ok(Validation::default());
+    }
+
+    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+    let mut validation = validate_bytes(&mmap, options, limit)?;
+    for violation in &mut validation.violations {
+        if let Error::Parse { path: file, .. } = violation {
+            *file = Some(path.to_path_buf());
+        }
+    }
+    Ok(validation)
+}
+
+// The lines of bytes[start..end], numbered from 1 at start
+fn validate_chunk(bytes: &[u8], start: usize, end: usize, options: &Options, limit: usize) -> Validation {
+    let chunk = &bytes[start..end];
+    let mut validation = Validation::default();
+    let mut scratch = Vec::new();
+    let bad_line = |validation: &mut Validation, at: usize, invalid: Invalid| {
+        let mut violation = Error::bad_line(invalid, bytes, start + at, 0);
+        if let Error::Parse { line, .. } = &mut violation {
+            *line = Some(validation.lines);
+        }
+        if validation.violations.len() < limit {
+            validation.violations.push(violation);
+        }
+        validation.invalid += 1;
+        *validation.reasons.entry(invalid.to_string()).or_default() += 1;
+    };
+
+    let mut lines = parser::Lines::new(chunk, options.delimiter);
+    for line in lines.by_ref() {
+        validation.lines += 1;
+        let checked = line.and_then(|line| check(line.name, line.temperature, options, &mut scratch).map_err(|e| parser::BadLine(line.start, e)));
+        if let Err(parser::BadLine(at, e)) = checked {
+            bad_line(&mut validation, at, e);
+        }
+    }
+
+    let consumed = lines.consumed();
+    if consumed < chunk.len() {
+        validation.lines += 1;
+        let last = &chunk[consumed..];
+        let checked = parser::split_line(last, options.delimiter).and_then(|(name, temperature)| check(name, temperature, options, &mut scratch));
+        if let Err(e) = checked {
+            bad_line(&mut validation, consumed, e);
+        }
+    }
+
+    validation
+}
+
+// Whether a line cut into its name and temperature is valid
+fn check(name: &[u8], temperature: &[u8], options: &Options, scratch: &mut Vec<u8>) -> Result<(), Invalid> {
+    if options.strict && name.is_empty() {
+        return Err(Invalid::EmptyName);
+    }
+    if let Some(max) = options.max_name_len.filter(|&max| name.len() > max) {
+        return Err(Invalid::LongName(max));
+    }
+    crate::check_range(parser::parse_temperature(temperature, options.precision, options.strict, scratch)?, options)?;
+    if std::str::from_utf8(name).is_err() {
+        return Err(Invalid::NotUtf8);
+    }
+    Ok(())
+}
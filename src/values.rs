@@ -0,0 +1,146 @@
+// `--values`: the results of lines with several values after the name,
+// one row per station per value, in order of station and then of the
+// values as they were named.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::Ordering;
+
+use indicatif::HumanCount;
+use one_billion_row_challenge::{aggregate_metrics, aggregate_metrics_bytes, merge_metrics, Error, MetricMap, Record, StationKey};
+use serde::Serialize;
+
+use crate::numbers::NumberArgs;
+use crate::unit::{degrees, Unit};
+use crate::{csv_field, input_paths, options, station_name, write_output, Format, OutputArgs, RunArgs, MIN_MEAN_MAX};
+
+pub fn run(args: &RunArgs, names: &[String]) -> Result<(), Error> {
+    let output = &args.output;
+    if output.histogram.is_some() || output.aggregates != MIN_MEAN_MAX || !output.stats.is_empty() || output.top.is_some() || output.sort.is_some() || output.group_by.is_some() || output.metadata.is_some() || output.numbers != NumberArgs::default() {
+        return Err("--values results can't have --histogram, --aggregates, --stats, --top, --sort, --group-by, --metadata or the flags for how numbers are written".into());
+    }
+    // Not every value is a temperature
+    if output.unit != Unit::C {
+        return Err("--values results can't be converted with --unit".into());
+    }
+
+    for (i, name) in names.iter().enumerate() {
+        if name.is_empty() || name == "station" || names[..i].contains(name) {
+            return Err(format!("--values can't have a value named {:?}", name).into());
+        }
+    }
+
+    let options = options(&args.input, false)?;
+    let (paths, stdin) = input_paths(&args.input)?;
+    let mut data = MetricMap::default();
+    for path in &paths {
+        data = merge_metrics(data, aggregate_metrics(path, names.len(), &options)?);
+    }
+    // Read in whole, as it can't be mapped
+    if stdin {
+        let mut bytes = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::stdin().lock(), &mut bytes)?;
+        data = merge_metrics(data, aggregate_metrics_bytes(&bytes, names.len(), &options)?);
+    }
+
+    if let Some(bad_lines) = &options.bad_lines {
+        log::warn!("Skipped {} bad lines", HumanCount(bad_lines.load(Ordering::Relaxed)));
+    }
+
+    let mut data = data.into_iter().collect::<Vec<_>>();
+    data.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let results = match output.format {
+        Format::Lines => format_lines(&data, names, output)?,
+        Format::Csv => format_csv(&data, names, output)?,
+        Format::Json => format_json(&data, names)?,
+        _ => return Err("--values results can only be printed as lines, csv or json".into()),
+    };
+    write_output(results.as_bytes(), args.output_path.as_deref(), false)?;
+    Ok(())
+}
+
+type Metrics = [(StationKey, Vec<Option<Record>>)];
+
+// Each station's values that were ever given, with their names
+fn named<'a>(records: &'a [Option<Record>], names: &'a [String]) -> impl Iterator<Item = (&'a str, &'a Record)> {
+    names.iter().zip(records).filter_map(|(name, record)| Some((name.as_str(), record.as_ref()?)))
+}
+
+fn format_lines(data: &Metrics, names: &[String], output: &OutputArgs) -> Result<String, Error> {
+    let mut to_print = String::with_capacity(data.len() * names.len() * 40);
+    for (key, records) in data {
+        let station = station_name(key)?;
+        for (name, value) in named(records, names) {
+            let (min, mean, max) = (degrees(Unit::C.min(value)), degrees(Unit::C.mean(value)), degrees(Unit::C.max(value)));
+            write!(to_print, "{};{};{};{};{}", station, name, min, mean, max).unwrap();
+            if output.with_count {
+                write!(to_print, ";{}", value.count).unwrap();
+            }
+            to_print.push('\n');
+        }
+    }
+    Ok(to_print)
+}
+
+fn format_csv(data: &Metrics, names: &[String], output: &OutputArgs) -> Result<String, Error> {
+    let delimiter = output.csv_delimiter;
+    let mut header = ["station", "value", "min", "mean", "max"].map(String::from).to_vec();
+    if output.with_count {
+        header.push("count".to_string());
+    }
+    let mut to_print = header.join(&delimiter.to_string()) + "\n";
+
+    for (key, records) in data {
+        let station = csv_field(station_name(key)?, delimiter);
+        for (name, value) in named(records, names) {
+            write!(
+                to_print,
+                "{}{d}{}{d}{}{d}{}{d}{}",
+                station,
+                csv_field(name, delimiter),
+                degrees(Unit::C.min(value)),
+                degrees(Unit::C.mean(value)),
+                degrees(Unit::C.max(value)),
+                d = delimiter,
+            ).unwrap();
+            if output.with_count {
+                write!(to_print, "{}{}", delimiter, value.count).unwrap();
+            }
+            to_print.push('\n');
+        }
+    }
+    Ok(to_print)
+}
+
+#[derive(Serialize)]
+struct StationValues<'a> {
+    station: &'a str,
+    #[serde(flatten)]
+    values: BTreeMap<&'a str, ValueSummary>,
+}
+
+#[derive(Serialize)]
+struct ValueSummary {
+    min: f64,
+    mean: f64,
+    max: f64,
+    count: u64,
+}
+
+fn format_json(data: &Metrics, names: &[String]) -> Result<String, Error> {
+    let stations = data.iter()
+        .map(|(key, records)| Ok(StationValues {
+            station: station_name(key)?,
+            values: named(records, names)
+                .map(|(name, value)| (name, ValueSummary {
+                    min: degrees(Unit::C.min(value)),
+                    mean: degrees(Unit::C.mean(value)),
+                    max: degrees(Unit::C.max(value)),
+                    count: value.count,
+                }))
+                .collect(),
+        }))
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(serde_json::to_string_pretty(&stations).unwrap() + "\n")
+}
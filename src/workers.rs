@@ -0,0 +1,122 @@
+// What each of the pool's threads did in an aggregation, for telling
+// an uneven split of the work from a slow machine.
+//
+// The workers add to their own counters by their rayon thread index as
+// they finish each chunk and each block of lines, so nothing is counted
+// per line. Work done off the pool (say, by the io_uring engine's
+// reader) isn't counted.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+#[cfg(feature = "native")]
+use std::time::Instant;
+
+#[cfg(feature = "native")]
+use crate::Options;
+
+/// One thread's share of the work, from [`Workers::stats`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkerStats {
+    /// Input bytes it parsed
+    pub bytes: u64,
+    /// Lines it added to its maps
+    pub rows: u64,
+    /// Chunks it took
+    pub chunks: u64,
+    /// The most stations any one of its maps had. A thread can fold
+    /// into more than one map, so this can be short of all it saw
+    pub stations: u64,
+    /// Time spent on its chunks
+    pub busy: Duration,
+    /// The rest of the time the chunks were being aggregated
+    pub idle: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    bytes: AtomicU64,
+    rows: AtomicU64,
+    chunks: AtomicU64,
+    stations: AtomicU64,
+    busy_nanos: AtomicU64,
+}
+
+/// Per-thread counters for [`Options::workers`] to fill in
+#[derive(Debug)]
+pub struct Workers {
+    threads: Vec<Counters>,
+    // How long the threads had chunks to work on, summed over the inputs
+    wall_nanos: AtomicU64,
+}
+
+impl Workers {
+    /// For a pool of `threads` workers
+    pub fn new(threads: usize) -> Self {
+        Workers { threads: (0..threads).map(|_| Counters::default()).collect(), wall_nanos: AtomicU64::new(0) }
+    }
+
+    /// What each thread has done so far, by index
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        let wall = Duration::from_nanos(self.wall_nanos.load(Ordering::Relaxed));
+        self.threads.iter().map(|counters| {
+            let busy = Duration::from_nanos(counters.busy_nanos.load(Ordering::Relaxed));
+            WorkerStats {
+                bytes: counters.bytes.load(Ordering::Relaxed),
+                rows: counters.rows.load(Ordering::Relaxed),
+                chunks: counters.chunks.load(Ordering::Relaxed),
+                stations: counters.stations.load(Ordering::Relaxed),
+                busy,
+                idle: wall.saturating_sub(busy),
+            }
+        }).collect()
+    }
+
+    // The calling thread's counters, if it's one of the pool's
+    fn current(&self) -> Option<&Counters> {
+        #[cfg(feature = "native")]
+        let index = crate::par::current_thread_index()?;
+        #[cfg(not(feature = "native"))]
+        let index = 0;
+        self.threads.get(index)
+    }
+
+    pub(crate) fn add_bytes(&self, bytes: usize) {
+        if let Some(counters) = self.current() {
+            counters.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    // A block of lines has been parsed into a map with stations in it
+    pub(crate) fn add_rows(&self, rows: u64, stations: usize) {
+        if let Some(counters) = self.current() {
+            counters.rows.fetch_add(rows, Ordering::Relaxed);
+            counters.stations.fetch_max(stations as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+// Runs f on a chunk, counting its time towards the thread's busy time
+#[cfg(feature = "native")]
+pub(crate) fn chunk<T>(options: &Options, f: impl FnOnce() -> T) -> T {
+    let Some(counters) = options.workers.as_ref().and_then(|workers| workers.current()) else {
+        return f();
+    };
+    let start = Instant::now();
+    let result = f();
+    counters.busy_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    counters.chunks.fetch_add(1, Ordering::Relaxed);
+    result
+}
+
+// Runs f, which hands an input's chunks out to the threads, counting its
+// time as time they could have been busy
+#[cfg(feature = "native")]
+pub(crate) fn spread<T>(options: &Options, f: impl FnOnce() -> T) -> T {
+    let Some(workers) = &options.workers else {
+        return f();
+    };
+    let start = Instant::now();
+    let result = f();
+    workers.wall_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
@@ -0,0 +1,52 @@
+#![cfg(feature = "native")]
+
+use one_billion_row_challenge::{aggregate_bytes, aggregate_bytes_by, Aggregator, Options, Record};
+
+// How many measurements were below freezing, and of how many
+struct Frosts {
+    below: u64,
+    count: u64,
+}
+
+impl Aggregator for Frosts {
+    type Output = f64;
+
+    fn new(value: i32, decimals: u8) -> Self {
+        let mut frosts = Frosts { below: 0, count: 0 };
+        assert_eq!(decimals, 1);
+        frosts.add(value);
+        frosts
+    }
+
+    fn add(&mut self, value: i32) {
+        self.below += (value < 0) as u64;
+        self.count += 1;
+    }
+
+    fn combine(&mut self, other: &Self) {
+        self.below += other.below;
+        self.count += other.count;
+    }
+
+    fn finish(&self) -> f64 {
+        self.below as f64 / self.count as f64
+    }
+}
+
+#[test]
+fn custom_aggregators() {
+    let bytes = (0..100_000).map(|i| format!("Station{};{}.{}\n", i % 7, i % 40 - 10, i % 10)).collect::<String>();
+    let options = Options::default();
+
+    let frosts = aggregate_bytes_by::<Frosts>(bytes.as_bytes(), &options).unwrap();
+    assert_eq!(frosts.len(), 7);
+    let station = &frosts[&b"Station0"[..]];
+    assert_eq!(station.count, 100_000 / 7 + 1);
+    assert!((station.finish() - 0.25).abs() < 0.01, "{}", station.finish());
+
+    // Records are aggregators too, and come out the same either way
+    let records = aggregate_bytes_by::<Record>(bytes.as_bytes(), &options).unwrap();
+    assert_eq!(records, aggregate_bytes(bytes.as_bytes(), &options).unwrap());
+
+    assert!(aggregate_bytes_by::<Frosts>(b"Station0;bad\n", &options).is_err());
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use common::{TempFile, ENGINES};
+use one_billion_row_challenge::chunk::ChunkSize;
+use one_billion_row_challenge::{aggregate_stations, OnProgress, Options};
+
+// Options that append `line` to the file at path the first time a block is done
+fn appending(path: &std::path::Path, line: &'static str, options: Options) -> Options {
+    let path = path.to_path_buf();
+    let done = AtomicBool::new(false);
+    let on_progress = OnProgress::new(move |_, _| {
+        if !done.swap(true, Ordering::Relaxed) {
+            std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(line.as_bytes()).unwrap();
+        }
+    });
+    Options { threads: Some(1), chunk_size: ChunkSize::Bytes(4096), read_size: Some(1024), on_progress: Some(on_progress), ..options }
+}
+
+#[test]
+fn leaves_out_lines_appended_while_read() {
+    let rows = common::rows(5000, 20);
+    let file = TempFile::new("appended", &rows);
+
+    let streamed = [
+        Options { max_memory: Some(1), ..Default::default() },
+        #[cfg(feature = "tokio")]
+        Options { engine: one_billion_row_challenge::Engine::Async, ..Default::default() },
+    ];
+    for options in ENGINES.map(|engine| Options { engine, ..Default::default() }).into_iter().chain(streamed) {
+        file.write(&rows);
+        let expected = aggregate_stations(&file, &options).unwrap();
+
+        let appended = Arc::new(AtomicU64::new(0));
+        let options = Options { appended: Some(appended.clone()), ..appending(&file, "Late;1.0\n", options) };
+        assert_eq!(aggregate_stations(&file, &options).unwrap(), expected);
+        assert_eq!(appended.load(Ordering::Relaxed), 9);
+    }
+
+    // The last line may have been cut short mid-write
+    file.write(rows.trim_end());
+    let error = aggregate_stations(&file, &appending(&file, "5\n", Options::default())).unwrap_err();
+    assert!(error.to_string().contains("partway through its last line"), "{}", error);
+}
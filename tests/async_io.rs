@@ -0,0 +1,33 @@
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use std::path::Path;
+
+use common::TempFile;
+use one_billion_row_challenge::{aggregate_async, aggregate_stations, Error, Options, StationMap};
+
+fn block_on(path: &Path, options: &Options) -> Result<StationMap, Error> {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(aggregate_async(path, options))
+}
+
+#[test]
+fn same_results_as_the_blocking_aggregator() {
+    // Small blocks, so the file goes through several of them
+    let options = Options { max_memory: Some(1), threads: Some(2), ..Default::default() };
+    for path in ["tests/data/crlf.txt", "tests/data/no_trailing_newline.txt"] {
+        let path = path.as_ref();
+        assert_eq!(block_on(path, &options).unwrap(), aggregate_stations(path, &Options::default()).unwrap());
+    }
+}
+
+#[test]
+fn bad_lines_by_line_number() {
+    let file = TempFile::new("async", common::rows(100_000, 413) + "oops\n");
+
+    match block_on(&file, &Options { max_memory: Some(1), ..Default::default() }) {
+        Err(Error::Parse { line, path: Some(_), .. }) => assert_eq!(line, Some(100_001)),
+        result => panic!("expected a parse error, got {:?}", result.map(|_| ())),
+    }
+}
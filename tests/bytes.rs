@@ -0,0 +1,16 @@
+use one_billion_row_challenge::{aggregate_bytes, Error, Options};
+
+#[test]
+fn aggregates_a_buffer() {
+    let stations = aggregate_bytes(b"Hamburg;12.0\nBulawayo;8.9\nHamburg;34.2", &Options::default()).unwrap();
+    assert_eq!(stations[&b"Hamburg"[..]].count, 2);
+    assert_eq!(stations[&b"Bulawayo"[..]].max, 89);
+}
+
+#[test]
+fn bad_lines_by_line_number() {
+    match aggregate_bytes(b"Hamburg;12.0\nBulawayo\n", &Options::default()) {
+        Err(Error::Parse { line, offset, .. }) => assert_eq!((line, offset), (Some(2), 13)),
+        result => panic!("expected a parse error, got {:?}", result.map(|_| ())),
+    }
+}
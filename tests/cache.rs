@@ -0,0 +1,58 @@
+#![cfg(feature = "native")]
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use one_billion_row_challenge::{aggregate_stations, Error, OnError, Options, StationFilter};
+
+#[test]
+fn reuses_results_until_the_file_changes() {
+    let dir = std::env::temp_dir().join(format!("brc-cache-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("measurements.txt");
+    std::fs::write(&path, "Hamburg;12.0\nBulawayo;8.9\n").unwrap();
+
+    let options = Options { cache: Some(dir.join("cache")), ..Default::default() };
+    let first = aggregate_stations(&path, &options).unwrap();
+    assert_eq!(std::fs::read_dir(dir.join("cache")).unwrap().count(), 1);
+    assert_eq!(aggregate_stations(&path, &options).unwrap(), first);
+
+    // A fixed hash (the contents' CRC-32), so whatever built the next run
+    // finds the entry
+    let entry = std::fs::read_dir(dir.join("cache")).unwrap().next().unwrap().unwrap().path();
+    assert!(std::fs::read(entry).unwrap().windows(21).any(|key| key == b"fingerprint=cf671536\n"));
+
+    // Rewritten in place, same size and modification time
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+    std::fs::write(&path, "Hamburg;13.0\nBulawayo;8.9\n").unwrap();
+    std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+    assert_eq!(aggregate_stations(&path, &options).unwrap()[&b"Hamburg"[..]].max, 130);
+
+    // Other options, other results
+    std::fs::File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now()).unwrap();
+    let filtered = Options { stations: Some(StationFilter::new(&["Hamburg"]).unwrap()), ..options.clone() };
+    assert_eq!(aggregate_stations(&path, &filtered).unwrap().len(), 1);
+    assert_eq!(aggregate_stations(&path, &options).unwrap().len(), 2);
+    let skipping = Options { on_error: OnError::Skip, ..options.clone() };
+    assert_eq!(aggregate_stations(&path, &Options { range: Some((-100, 100)), ..skipping.clone() }).unwrap().len(), 1);
+    assert_eq!(aggregate_stations(&path, &skipping).unwrap().len(), 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keeps_cancelled_runs_out() {
+    let dir = std::env::temp_dir().join(format!("brc-cache-cancelled-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("measurements.txt");
+    std::fs::write(&path, "Hamburg;12.0\nBulawayo;8.9\n").unwrap();
+
+    let options = Options { cache: Some(dir.join("cache")), ..Default::default() };
+    let cancelled = Options { cancel: Some(Arc::new(AtomicBool::new(true))), ..options.clone() };
+    assert!(matches!(aggregate_stations(&path, &cancelled), Err(Error::Cancelled { .. })));
+    assert!(std::fs::read_dir(dir.join("cache")).map_or(true, |mut entries| entries.next().is_none()));
+    assert_eq!(aggregate_stations(&path, &options).unwrap().len(), 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use common::{TempFile, ENGINES};
+use one_billion_row_challenge::chunk::ChunkSize;
+use one_billion_row_challenge::{aggregate_reader, aggregate_stations, Error, OnProgress, Options};
+
+const ROWS: u64 = 200_000;
+
+fn measurements(name: &str) -> TempFile {
+    TempFile::new(name, common::rows(ROWS, 50))
+}
+
+fn rows(stations: &one_billion_row_challenge::StationMap) -> u64 {
+    stations.values().map(|record| record.count).sum()
+}
+
+#[test]
+fn reports_progress() {
+    let path = measurements("progress");
+    let size = std::fs::metadata(&path).unwrap().len();
+
+    for engine in ENGINES {
+        let last = Arc::new(Mutex::new((0, 0)));
+        let seen = last.clone();
+        let on_progress = OnProgress::new(move |done, total| *seen.lock().unwrap() = (done, total));
+        let options = Options { engine, on_progress: Some(on_progress), chunk_size: ChunkSize::Bytes(64 * 1024), ..Default::default() };
+
+        // Twice, as every call counts from 0
+        for _ in 0..2 {
+            assert_eq!(rows(&aggregate_stations(&path, &options).unwrap()), ROWS);
+            assert_eq!(*last.lock().unwrap(), (size, size));
+        }
+    }
+}
+
+#[test]
+fn cancels_with_partials() {
+    let path = measurements("cancel");
+
+    for engine in ENGINES {
+        // Cancelled a quarter of the way through, which leaves the
+        // chunks from there on
+        let cancel = Arc::new(AtomicBool::new(false));
+        let token = cancel.clone();
+        let on_progress = OnProgress::new(move |done, total| {
+            if done * 4 >= total {
+                token.store(true, Ordering::Relaxed);
+            }
+        });
+        let options = Options {
+            engine,
+            threads: Some(1),
+            read_size: Some(4096),
+            chunk_size: ChunkSize::Bytes(64 * 1024),
+            on_progress: Some(on_progress),
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+
+        match aggregate_stations(&path, &options) {
+            Err(e @ Error::Cancelled { .. }) => {
+                assert_eq!(e.exit_code(), 130);
+                let Error::Cancelled { partial } = e else { unreachable!() };
+                assert!(rows(&partial) > 0 && rows(&partial) < ROWS, "{:?}: {} rows", engine, rows(&partial));
+            }
+            result => panic!("{:?}: not cancelled: {:?}", engine, result.map(|stations| rows(&stations))),
+        }
+    }
+}
+
+#[test]
+fn cancels_streams() {
+    let path = measurements("cancel-stream");
+    let bytes = Arc::new(AtomicU64::new(0));
+    let counted = bytes.clone();
+    let options = Options {
+        cancel: Some(Arc::new(AtomicBool::new(true))),
+        on_progress: Some(OnProgress::new(move |done, total| {
+            assert_eq!(total, 0);
+            counted.store(done, Ordering::Relaxed);
+        })),
+        ..Default::default()
+    };
+
+    let file = std::fs::File::open(&path).unwrap();
+    assert!(matches!(aggregate_reader(file, &options), Err(Error::Cancelled { partial }) if partial.is_empty()));
+    assert_eq!(bytes.load(Ordering::Relaxed), 0);
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "native")]
+
+use one_billion_row_challenge::{aggregate_stations, NormalizeKey, OnError, Options};
+
+#[test]
+fn resumes_from_partials() {
+    let dir = std::env::temp_dir().join(format!("1brc-checkpoint-{}", std::process::id()));
+    let path = "tests/data/crlf.txt".as_ref();
+    let options = Options { threads: Some(2), checkpoint: Some(dir.clone()), ..Default::default() };
+
+    // A chunk per thread, if there are threads
+    let chunks = if cfg!(feature = "parallel") { 2 } else { 1 };
+
+    let whole = aggregate_stations(path, &Options::default()).unwrap();
+    assert_eq!(aggregate_stations(path, &options).unwrap(), whole);
+
+    let partials = || std::fs::read_dir(&dir).unwrap()
+        .flat_map(|entry| std::fs::read_dir(entry.unwrap().path()).unwrap())
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "partial"))
+        .collect::<Vec<_>>();
+    assert_eq!(partials().len(), chunks);
+
+    // A run killed after one chunk leaves only that chunk's partial
+    std::fs::remove_file(&partials()[0]).unwrap();
+    assert_eq!(aggregate_stations(path, &options).unwrap(), whole);
+    assert_eq!(partials().len(), chunks);
+
+    // Partials made with other options aren't used
+    let skipping = Options { on_error: OnError::Skip, ..options.clone() };
+    assert_ne!(aggregate_stations(path, &Options { range: Some((-100, 100)), ..skipping.clone() }).unwrap(), whole);
+    assert_eq!(aggregate_stations(path, &skipping).unwrap(), whole);
+    assert_ne!(aggregate_stations(path, &Options { normalize_keys: vec![NormalizeKey::Lowercase], ..options.clone() }).unwrap(), whole);
+    assert_eq!(aggregate_stations(path, &options).unwrap(), whole);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
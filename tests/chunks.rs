@@ -0,0 +1,47 @@
+#![cfg(feature = "native")]
+
+use one_billion_row_challenge::{aggregate_chunks, aggregate_stations, merge_maps, Options};
+
+#[test]
+fn chunks_cover_the_file() {
+    let path = "tests/data/crlf.txt".as_ref();
+    let options = Options { threads: Some(3), ..Default::default() };
+    let chunks = aggregate_chunks(path, &options).unwrap();
+
+    // A chunk per thread, if there are threads
+    assert_eq!(chunks.len(), if cfg!(feature = "parallel") { 3 } else { 1 });
+    assert_eq!(chunks[0].0.0, 0);
+    assert_eq!(chunks.last().unwrap().0.1, std::fs::metadata(path).unwrap().len());
+    assert!(chunks.windows(2).all(|pair| pair[0].0.1 == pair[1].0.0));
+
+    let merged = chunks.into_iter().map(|(_, map)| map).reduce(merge_maps).unwrap();
+    let whole = aggregate_stations(path, &Options::default()).unwrap();
+    assert_eq!(merged.len(), whole.len());
+    for (station, record) in &whole {
+        let chunked = &merged[station];
+        assert_eq!((chunked.min, chunked.max, chunked.total, chunked.count), (record.min, record.max, record.total, record.count));
+    }
+}
+
+#[test]
+fn checks_chunks() {
+    use one_billion_row_challenge::chunk::{check, chunk_specs, chunk_specs_in, split_in, Problem};
+
+    let path = "tests/data/crlf.txt";
+    let bytes = std::fs::read(path).unwrap();
+    let size = bytes.len() as u64;
+    let as_u64 = |chunks: Vec<(usize, usize)>| chunks.into_iter().map(|(start, end)| (start as u64, end as u64)).collect::<Vec<_>>();
+
+    assert_eq!(check(&bytes, &chunk_specs(path.as_ref(), size).unwrap()), []);
+    assert_eq!(check(&bytes, &as_u64(chunk_specs_in(&bytes))), []);
+    for count in 1..=bytes.len() + 1 {
+        assert_eq!(check(&bytes, &as_u64(split_in(&bytes, count))), [], "{} chunks", count);
+    }
+
+    assert_eq!(check(&bytes, &[(0, 5), (3, size + 1)]), [
+        Problem::Overlap { chunk: 1, from: 3, to: 5 },
+        Problem::MidLine { chunk: 1, start: 3 },
+        Problem::PastEnd { chunk: 1, end: size + 1 },
+    ]);
+    assert_eq!(check(&bytes, &[(2, 2)]), [Problem::Empty(0), Problem::Gap { from: 0, to: 2 }, Problem::MidLine { chunk: 0, start: 2 }, Problem::Gap { from: 2, to: size }]);
+}
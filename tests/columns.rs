@@ -0,0 +1,28 @@
+#![cfg(feature = "native")]
+
+use one_billion_row_challenge::{aggregate_bytes, aggregate_reader, Columns, OnError, Options};
+
+const CSV: &[u8] = b"id,city,when,reading\n1,Hamburg,x,12\n2,Oslo,y,-3.5\n3,Hamburg,z,7.25\n4,Oslo,w,1e3\n5,Oslo\n6,Hamburg,v,4.5";
+
+#[test]
+fn key_and_value_columns() {
+    let options = Options {
+        delimiter: b',',
+        columns: Some(Columns { key: 1, value: 3 }),
+        precision: 2,
+        on_error: OnError::Skip,
+        ..Default::default()
+    };
+    for stations in [aggregate_bytes(CSV, &options).unwrap(), aggregate_reader(CSV, &options).unwrap()] {
+        assert_eq!(stations.len(), 2);
+        let hamburg = &stations[&b"Hamburg"[..]];
+        assert_eq!((hamburg.min, hamburg.max, hamburg.count), (450, 1200, 3));
+        let oslo = &stations[&b"Oslo"[..]];
+        assert_eq!((oslo.min, oslo.max, oslo.count), (-350, -350, 1));
+    }
+
+    // The header line, and the line missing its value
+    let options = Options { on_error: OnError::Abort, ..options };
+    assert!(aggregate_bytes(CSV, &options).is_err());
+    assert!(aggregate_bytes(&CSV[21..34], &options).is_ok());
+}
@@ -0,0 +1,92 @@
+// Fixtures for the integration tests: generated measurements, and files
+// for them that go away however the test ends. Each test binary only
+// uses some of them
+#![allow(dead_code)]
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use one_billion_row_challenge::Engine;
+
+/// The engines that read a file by byte ranges
+pub const ENGINES: [Engine; 2] = [Engine::Buffered, Engine::Mmap];
+
+/// The `i`th generated row, for `station`
+pub fn row(i: u64, station: u64) -> String {
+    format!("Station {};{}.{}\n", station, i as i64 % 100 - 50, i % 10)
+}
+
+/// `count` rows, going round `stations` stations in turn
+pub fn rows(count: u64, stations: u64) -> String {
+    (0..count).map(|i| row(i, i % stations)).collect()
+}
+
+/// `count` rows over `stations` stations in no particular order, so every
+/// part of the file has most of them
+pub fn scattered_rows(count: u64, stations: u64) -> String {
+    (0..count).map(|i| row(i, i * 7919 % stations)).collect()
+}
+
+/// A file in the temp directory, removed when it's dropped, so a test
+/// that panics doesn't leave it behind
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    /// A file named for `name` and this process, holding `contents`
+    pub fn new(name: &str, contents: impl AsRef<[u8]>) -> Self {
+        let file = TempFile(std::env::temp_dir().join(format!("brc-{}-{}.txt", name, std::process::id())));
+        file.write(contents);
+        file
+    }
+
+    /// Replaces what's in the file with `contents`
+    pub fn write(&self, contents: impl AsRef<[u8]>) {
+        std::fs::write(&self.0, contents).unwrap();
+    }
+}
+
+impl Deref for TempFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// A directory in the temp directory, removed with everything in it when
+/// it's dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// A directory named for `name` and this process, for the library to
+    /// make (and fill) itself
+    pub fn new(name: &str) -> Self {
+        TempDir(std::env::temp_dir().join(format!("brc-{}-{}", name, std::process::id())))
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "native")]
+
+// tests/data/edge_cases is `1brc generate --edge-cases`
+
+use one_billion_row_challenge::{aggregate, aggregate_reader, Engine, Options};
+
+#[test]
+fn edge_cases() {
+    let expected = std::fs::read_to_string("tests/data/edge_cases/expected.txt").unwrap();
+    let fixed = |tenths: i64| format!("{}{}.{}", if tenths < 0 { "-" } else { "" }, tenths.abs() / 10, tenths.abs() % 10);
+
+    for file in ["measurements.txt", "no-trailing-newline.txt"] {
+        for engine in [Engine::Buffered, Engine::Mmap] {
+            let options = Options { engine, strict: true, ..Default::default() };
+            let stations = aggregate(format!("tests/data/edge_cases/{}", file).as_ref(), &options).unwrap();
+
+            assert!(stations.keys().any(|name| name.len() == 100 && name.is_ascii()));
+            let results = stations.iter()
+                .map(|(name, record)| format!("{}={}/{}/{}", name, fixed(record.min as i64), fixed(record.mean_scaled()), fixed(record.max as i64)))
+                .collect::<Vec<_>>();
+            assert_eq!(format!("{{{}}}\n", results.join(", ")), expected, "{} with {:?}", file, engine);
+        }
+    }
+}
+
+#[test]
+fn strict() {
+    let strict = Options { strict: true, ..Default::default() };
+    // Parsed as best they can be otherwise
+    for line in ["A;100.0", "A;-100.0"] {
+        assert!(aggregate_reader(line.as_bytes(), &Options::default()).is_ok(), "{}", line);
+        assert!(aggregate_reader(line.as_bytes(), &strict).is_err(), "{}", line);
+    }
+    // But never with other than one decimal
+    for line in ["A;1.23", "A;1"] {
+        assert!(aggregate_reader(line.as_bytes(), &Options::default()).is_err(), "{}", line);
+    }
+    assert!(aggregate_reader(&b";1.0"[..], &strict).is_err());
+}
@@ -0,0 +1,36 @@
+#![cfg(all(feature = "native", unix))]
+
+use std::io::Write;
+use std::path::Path;
+
+use one_billion_row_challenge::{aggregate_reader, aggregate_stations, validate_file, Options};
+
+// Aggregates what a writer thread puts through a new FIFO at path
+fn through_fifo<T>(path: &Path, input: &[u8], read: impl FnOnce(&Path) -> T) -> T {
+    let _ = std::fs::remove_file(path);
+    assert!(std::process::Command::new("mkfifo").arg(path).status().unwrap().success());
+
+    let result = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            // The reader may give up before taking everything
+            let _ = std::fs::OpenOptions::new().write(true).open(path).unwrap().write_all(input);
+        });
+        read(path)
+    });
+    std::fs::remove_file(path).unwrap();
+    result
+}
+
+#[test]
+fn streamed_like_stdin() {
+    let input = (0..50_000).map(|i| format!("Station {};{}.{}\n", i % 97, i % 80 - 40, i % 10)).collect::<String>().into_bytes();
+    let expected = aggregate_reader(&input[..], &Options::default()).unwrap();
+    let path = std::env::temp_dir().join(format!("1brc-fifo-{}", std::process::id()));
+
+    let data = through_fifo(&path, &input, |path| aggregate_stations(path, &Options::default()));
+    assert_eq!(data.unwrap(), expected);
+
+    // Not silently empty where the file would have to be mapped
+    let validation = through_fifo(&path, &input, |path| validate_file(path, &Options::default(), 10));
+    assert!(validation.unwrap_err().to_string().contains("not a regular file"));
+}
@@ -0,0 +1,32 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use std::io::Write;
+
+use common::TempFile;
+use one_billion_row_challenge::{Follower, Options};
+
+#[test]
+fn follows_appended_lines() {
+    let path = TempFile::new("follow", "Hamburg;12.0\nAbha;5.0\nHamb");
+
+    // The unfinished last line waits for the rest of it
+    let mut follower = Follower::new(&path, &Options::default()).unwrap();
+    assert_eq!(follower.offset(), 22);
+    assert_eq!(follower.stations().len(), 2);
+    assert!(!follower.poll().unwrap());
+
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"urg;20.0\nAbha;-1").unwrap();
+    assert!(follower.poll().unwrap());
+    let hamburg = &follower.stations()[&b"Hamburg"[..]];
+    assert_eq!((hamburg.min, hamburg.max, hamburg.count), (120, 200, 2));
+    assert_eq!(follower.stations()[&b"Abha"[..]].count, 1);
+
+    // Shrinking starts again from scratch
+    path.write("Abha;1.0\n");
+    assert!(follower.poll().unwrap());
+    assert_eq!(follower.stations().len(), 1);
+    assert_eq!(follower.offset(), 9);
+}
@@ -0,0 +1,19 @@
+#![cfg(feature = "native")]
+
+use std::sync::Arc;
+
+use one_billion_row_challenge::{aggregate_reader, KnownStations, Options, StationFilter};
+
+const INPUT: &[u8] = b"Abha;12.0\nBulawayo;8.9\nAbha;-3.5\nUnlisted;1.0\nBulawayo;9.1\nUnlisted;2.0\n";
+
+#[test]
+fn same_results_as_the_map() {
+    let known = Options { known_stations: Some(Arc::new(KnownStations::new(["Abha", "Bulawayo", "Cairo"]))), ..Default::default() };
+    let expected = aggregate_reader(INPUT, &Options::default()).unwrap();
+    assert_eq!(aggregate_reader(INPUT, &known).unwrap(), expected);
+
+    // Pattern filters still see every station
+    let filtered = |options: Options| aggregate_reader(INPUT, &Options { stations: Some(StationFilter::new(&["*a*"]).unwrap()), ..options }).unwrap();
+    assert_eq!(filtered(known.clone()), filtered(Options::default()));
+    assert!(!filtered(known).contains_key(&b"Unlisted"[..]));
+}
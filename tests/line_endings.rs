@@ -0,0 +1,38 @@
+#![cfg(feature = "native")]
+
+use one_billion_row_challenge::{aggregate, aggregate_reader, Engine, Options};
+
+#[test]
+fn crlf_line_endings() {
+    let engines = [Engine::Buffered, Engine::Mmap].map(|engine| Options { engine, ..Default::default() });
+    // Unbuffered reads fall back to the buffered engine where they
+    // aren't supported
+    let direct = Options { direct: true, ..Default::default() };
+
+    for options in engines.into_iter().chain([direct]) {
+        let stations = aggregate("tests/data/crlf.txt".as_ref(), &options).unwrap();
+
+        let names = stations.keys().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(names, ["Bulawayo", "Hamburg", "Palembang"]);
+
+        let hamburg = &stations["Hamburg"];
+        assert_eq!((hamburg.min, hamburg.max, hamburg.total, hamburg.count), (120, 342, 462, 2));
+
+        let bulawayo = &stations["Bulawayo"];
+        assert_eq!((bulawayo.min, bulawayo.max, bulawayo.count), (-35, 89, 2));
+    }
+}
+
+#[test]
+fn no_trailing_newline() {
+    for engine in [Engine::Buffered, Engine::Mmap] {
+        let options = Options { engine, ..Default::default() };
+        let stations = aggregate("tests/data/no_trailing_newline.txt".as_ref(), &options).unwrap();
+
+        let bulawayo = &stations["Bulawayo"];
+        assert_eq!((bulawayo.min, bulawayo.max, bulawayo.count), (-35, 89, 2));
+    }
+
+    let stations = aggregate_reader(&b"Hamburg;12.0\nHamburg;-1.5"[..], &Options::default()).unwrap();
+    assert_eq!(stations[&b"Hamburg"[..]].count, 2);
+}
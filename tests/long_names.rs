@@ -0,0 +1,20 @@
+#![cfg(feature = "native")]
+
+use one_billion_row_challenge::{aggregate_reader, Error, Options};
+
+#[test]
+fn max_name_len() {
+    let input = "Abha;1.0\nZürich;2.0\nAbha;3.0\n".as_bytes();
+
+    let options = Options { max_name_len: Some(4), ..Default::default() };
+    match aggregate_reader(input, &options) {
+        Err(Error::Parse { offset, .. }) => assert_eq!(offset, 9),
+        result => panic!("expected a parse error, got {:?}", result.map(|_| ())),
+    }
+
+    // Two bytes of "Zürich" would split the ü
+    let options = Options { max_name_len: Some(2), truncate_names: true, ..Default::default() };
+    let stations = aggregate_reader(input, &options).unwrap();
+    assert_eq!(stations[&b"Ab"[..]].count, 2);
+    assert_eq!(stations[&b"Z"[..]].count, 1);
+}
@@ -0,0 +1,55 @@
+#![cfg(feature = "native")]
+
+// Whatever the bytes, the parser returns stations or an error, and
+// never panics
+
+use one_billion_row_challenge::{aggregate_bytes, aggregate_reader, Options};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Mostly the bytes lines are made of, so the soup is close enough to
+// valid to get past the first check
+const ALPHABET: &[u8] = b";;;;\n\n\n\r-...0123456789aZ, \xc3\xa9\xff\x00";
+
+fn soup(rng: &mut StdRng) -> Vec<u8> {
+    let len = rng.gen_range(0..200);
+    (0..len).map(|_| if rng.gen_bool(0.9) { ALPHABET[rng.gen_range(0..ALPHABET.len())] } else { rng.gen() }).collect()
+}
+
+fn options(rng: &mut StdRng) -> Options {
+    let precision = rng.gen_range(1..=3);
+    Options {
+        delimiter: if rng.gen_bool(0.8) { b';' } else { b',' },
+        threads: Some(rng.gen_range(1..=4)),
+        histograms: precision == 1 && rng.gen(),
+        validate_utf8: rng.gen(),
+        max_name_len: rng.gen_bool(0.3).then(|| rng.gen_range(0..8)),
+        truncate_names: rng.gen(),
+        precision,
+        strict: precision == 1 && rng.gen_bool(0.3),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn no_panics() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..2_000 {
+        let (bytes, options) = (soup(&mut rng), options(&mut rng));
+        let _ = aggregate_bytes(&bytes, &options);
+        let _ = aggregate_reader(&bytes[..], &Options { max_memory: Some(1 << 20), ..options });
+    }
+}
+
+#[test]
+fn extreme_temperatures() {
+    for precision in 1..=3 {
+        let options = Options { precision, ..Default::default() };
+        // Their squares would overflow the records' sums in a few lines
+        let bytes = "a;2147483.647\n".repeat(1000);
+        assert!(aggregate_bytes(bytes.as_bytes(), &options).is_err());
+        let bytes = "a;-3276.7\na;3276.7\n".repeat(1000);
+        assert!(aggregate_bytes(bytes.as_bytes(), &options).is_ok(), "precision {}", precision);
+    }
+}
+
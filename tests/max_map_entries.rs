@@ -0,0 +1,22 @@
+#![cfg(feature = "native")]
+
+use one_billion_row_challenge::{aggregate_bytes, aggregate_reader, map_memory, Error, Options};
+
+#[test]
+fn stops_at_too_many_stations() {
+    let bytes = (0..1000).map(|i| format!("2024-01-01T{:04};1.5\n", i)).collect::<String>();
+    let options = Options { max_map_entries: Some(100), ..Default::default() };
+    for result in [aggregate_bytes(bytes.as_bytes(), &options), aggregate_reader(bytes.as_bytes(), &options)] {
+        match result {
+            Err(Error::Parse { reason, text, .. }) => {
+                assert_eq!(reason, "more than 100 distinct stations");
+                assert_eq!(text, "2024-01-01T0100;1.5");
+            }
+            result => panic!("not stopped: {:?}", result.map(|stations| stations.len())),
+        }
+    }
+
+    let stations = aggregate_bytes(bytes.as_bytes(), &Options { max_map_entries: Some(1000), ..Default::default() }).unwrap();
+    assert_eq!(stations.len(), 1000);
+    assert!(map_memory(&stations) >= 1000 * std::mem::size_of::<one_billion_row_challenge::Record>());
+}
@@ -0,0 +1,40 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use one_billion_row_challenge::{aggregate_reader, aggregate_stations, Error, Options};
+
+// About 1.5 MB, so the smallest blocks go round the pool several times
+fn input() -> Vec<u8> {
+    common::rows(100_000, 413).into_bytes()
+}
+
+#[test]
+fn same_results_in_little_memory() {
+    let input = input();
+    let expected = aggregate_reader(&input[..], &Options::default()).unwrap();
+
+    for max_memory in [1, 200_000, 1 << 20] {
+        let options = Options { max_memory: Some(max_memory), threads: Some(2), ..Default::default() };
+        assert_eq!(aggregate_reader(&input[..], &options).unwrap(), expected);
+    }
+
+    // Files are streamed too, rather than mapped
+    let path = "tests/data/crlf.txt".as_ref();
+    let options = Options { max_memory: Some(1), ..Default::default() };
+    assert_eq!(aggregate_stations(path, &options).unwrap(), aggregate_stations(path, &Options::default()).unwrap());
+}
+
+#[test]
+fn bad_lines_by_their_offset() {
+    let mut input = input();
+    // At the start of the last line
+    let offset = input.len() - 1 - input.iter().rev().skip(1).position(|&byte| byte == b'\n').unwrap();
+    input.splice(offset..offset, *b"oops\n");
+
+    let options = Options { max_memory: Some(1), ..Default::default() };
+    match aggregate_reader(&input[..], &options) {
+        Err(Error::Parse { offset: at, .. }) => assert_eq!(at, offset as u64),
+        result => panic!("expected a parse error, got {:?}", result.map(|_| ())),
+    }
+}
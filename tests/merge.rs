@@ -0,0 +1,20 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::{scattered_rows, TempFile, ENGINES};
+use one_billion_row_challenge::{aggregate_stations, Merge, Options};
+
+#[test]
+fn sharded_merge_matches_tree() {
+    // Enough for each of several threads to see most stations
+    let file = TempFile::new("merge", scattered_rows(200_000, 5000));
+
+    for engine in ENGINES {
+        let tree = Options { engine, threads: Some(4), ..Default::default() };
+        let sharded = Options { merge: Merge::Sharded, ..tree.clone() };
+        let expected = aggregate_stations(&file, &tree).unwrap();
+        assert_eq!(expected.len(), 5000);
+        assert_eq!(aggregate_stations(&file, &sharded).unwrap(), expected);
+    }
+}
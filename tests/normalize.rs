@@ -0,0 +1,26 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::TempFile;
+use one_billion_row_challenge::{aggregate_bytes, aggregate_reader, aggregate_stations, NormalizeKey, Options};
+
+#[test]
+fn normalized_names() {
+    let bytes = "Hamburg;1.0\nhamburg;3.0\nHamburg ;-2.0\nZu\u{308}rich;4.0\nZürich;6.0\n\"Hamburg\";7.0\n".as_bytes();
+    let options = Options { normalize_keys: vec![NormalizeKey::Trim, NormalizeKey::Lowercase, NormalizeKey::Nfc], quoted: true, ..Default::default() };
+    for stations in [aggregate_bytes(bytes, &options).unwrap(), aggregate_reader(bytes, &options).unwrap()] {
+        assert_eq!(stations.len(), 2);
+        let hamburg = &stations[&b"hamburg"[..]];
+        assert_eq!((hamburg.min, hamburg.max, hamburg.count), (-20, 70, 4));
+        assert_eq!(stations["zürich".as_bytes()].count, 2);
+    }
+
+    // And for a file, whose chunks each have both names
+    let path = TempFile::new("normalize", "Abha;1.0\n ABHA;2.0\n".repeat(10_000));
+    let stations = aggregate_stations(&path, &Options { normalize_keys: vec![NormalizeKey::Trim, NormalizeKey::Lowercase], ..Default::default() }).unwrap();
+    assert_eq!(stations.len(), 1);
+    assert_eq!(stations[&b"abha"[..]].count, 20_000);
+
+    assert_eq!(aggregate_bytes(bytes, &Options::default()).unwrap().len(), 6);
+}
@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use one_billion_row_challenge::{aggregate_bytes, Error, OnError, Options};
+
+const INPUT: &[u8] = b"Hamburg;12.0\nBulawayo\nHamburg;x\nCracow;-1.5\n;1.0\nHamburg;3.0";
+
+#[test]
+fn abort() {
+    match aggregate_bytes(INPUT, &Options::default()) {
+        Err(Error::Parse { line, offset, .. }) => assert_eq!((line, offset), (Some(2), 13)),
+        result => panic!("expected a parse error, got {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn skip_and_count() {
+    let bad_lines = Arc::new(AtomicU64::new(0));
+    for on_error in [OnError::Skip, OnError::Count] {
+        let options = Options { on_error, bad_lines: Some(bad_lines.clone()), strict: true, ..Default::default() };
+        let stations = aggregate_bytes(INPUT, &options).unwrap();
+
+        assert_eq!(stations.len(), 2);
+        assert_eq!(stations[&b"Hamburg"[..]].count, 2);
+        assert_eq!(stations[&b"Cracow"[..]].min, -15);
+    }
+    // Only counted the second time round
+    assert_eq!(bad_lines.load(Ordering::Relaxed), 3);
+}
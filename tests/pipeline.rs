@@ -0,0 +1,51 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use std::sync::Arc;
+
+use common::{rows, TempFile};
+use one_billion_row_challenge::chunk::ChunkSize;
+use one_billion_row_challenge::{aggregate_bytes, aggregate_stations, Error, Options, Phase, Timings};
+
+// Small blocks and chunks, so lines are carried between blocks and the
+// queue fills up
+fn pipelined(io_threads: usize, parse_threads: usize) -> Options {
+    Options {
+        io_threads: Some(io_threads),
+        parse_threads: Some(parse_threads),
+        read_size: Some(1000),
+        chunk_size: ChunkSize::Bytes(64 * 1024),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn same_results_pipelined() {
+    let rows = rows(50_000, 413);
+    let file = TempFile::new("pipeline", &rows);
+    // And without a newline at the end
+    for input in [&rows[..], rows.trim_end()] {
+        file.write(input);
+        let expected = aggregate_bytes(input.as_bytes(), &Options::default()).unwrap();
+        for (io_threads, parse_threads) in [(1, 1), (1, 3), (3, 1), (2, 2)] {
+            assert_eq!(aggregate_stations(&file, &pipelined(io_threads, parse_threads)).unwrap(), expected, "{} readers, {} parsers", io_threads, parse_threads);
+        }
+    }
+
+    let timings = Arc::new(Timings::default());
+    aggregate_stations(&file, &Options { timings: Some(timings.clone()), ..pipelined(1, 2) }).unwrap();
+    assert!(timings.get(Phase::Io) > std::time::Duration::ZERO && timings.get(Phase::Parsing) > std::time::Duration::ZERO);
+
+    // The first bad line, by where it is in the file
+    let mut input = rows;
+    let offset = input.len() / 2 + input[input.len() / 2..].find('\n').unwrap() + 1;
+    input.insert_str(offset, "oops\n");
+    input.push_str("worse\n");
+    file.write(&input);
+    let line = input[..offset].matches('\n').count() as u64 + 1;
+    match aggregate_stations(&file, &pipelined(2, 2)) {
+        Err(Error::Parse { offset: at, line: Some(at_line), .. }) => assert_eq!((at, at_line), (offset as u64, line)),
+        result => panic!("expected a parse error, got {:?}", result.map(|_| ())),
+    }
+}
@@ -0,0 +1,30 @@
+#![cfg(feature = "native")]
+
+use one_billion_row_challenge::{aggregate_reader, OnError, Options};
+
+#[test]
+fn two_decimals() {
+    let options = Options { precision: 2, ..Default::default() };
+    let stations = aggregate_reader(&b"Abha;12.34\nAbha;12.5\nAbha;-3\n"[..], &options).unwrap();
+
+    let abha = &stations[&b"Abha"[..]];
+    assert_eq!((abha.min, abha.max, abha.total, abha.decimals), (-300, 1250, 2184, 2));
+    assert_eq!((abha.min(), abha.max(), abha.mean_rounded()), (-3., 12.5, 7.28));
+
+    // A third decimal can't be represented
+    assert!(aggregate_reader(&b"Abha;12.345\n"[..], &options).is_err());
+}
+
+#[test]
+fn out_of_range() {
+    let input = &b"Abha;12.3\nAbha;512.7\nAbha;-99.9\n"[..];
+    // Otherwise taken, however bogus
+    assert_eq!(aggregate_reader(input, &Options::default()).unwrap()[&b"Abha"[..]].max, 5127);
+
+    let options = Options { range: Some((-999, 999)), ..Default::default() };
+    let e = aggregate_reader(input, &options).unwrap_err();
+    assert!(e.to_string().contains("byte 10") && e.to_string().contains("outside -99.9..99.9"), "{}", e);
+
+    let stations = aggregate_reader(input, &Options { on_error: OnError::Skip, ..options }).unwrap();
+    assert_eq!((stations[&b"Abha"[..]].count, stations[&b"Abha"[..]].min), (2, -999));
+}
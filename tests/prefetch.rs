@@ -0,0 +1,26 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::{row, TempFile, ENGINES};
+use one_billion_row_challenge::{aggregate_stations, OnError, Options};
+
+#[test]
+fn batches_match_line_by_line() {
+    // Enough stations for the table to grow partway through a batch, and
+    // a bad line now and then between the good ones
+    let rows = (0..100_000).map(|i| match i % 997 {
+        0 => "no temperature\n".to_string(),
+        _ => row(i, i * 7919 % 20_000),
+    }).collect::<String>();
+    let file = TempFile::new("prefetch", rows);
+
+    for engine in ENGINES {
+        let options = Options { engine, threads: Some(2), on_error: OnError::Skip, ..Default::default() };
+        let expected = aggregate_stations(&file, &options).unwrap();
+        assert_eq!(expected.len(), 20_000);
+        assert_eq!(aggregate_stations(&file, &Options { prefetch: true, ..options.clone() }).unwrap(), expected);
+        assert_eq!(aggregate_stations(&file, &Options { interned: true, ..options.clone() }).unwrap(), expected);
+        assert_eq!(aggregate_stations(&file, &Options { prefetch: true, interned: true, ..options }).unwrap(), expected);
+    }
+}
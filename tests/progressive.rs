@@ -0,0 +1,25 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::TempFile;
+use one_billion_row_challenge::{aggregate_progressive, aggregate_stations, Options, Results};
+
+#[test]
+fn snapshots_converge() {
+    let path = TempFile::new("progressive", common::rows(60_000, 50));
+
+    let options = Options { threads: Some(3), ..Default::default() };
+    let mut snapshots = Vec::new();
+    let results = aggregate_progressive(&path, &options, Duration::ZERO, |snapshot| {
+        snapshots.push(snapshot.iter().map(|(_, record)| record.count).sum::<u64>());
+    }).unwrap();
+
+    assert_eq!(results, Results::from(aggregate_stations(&path, &options).unwrap()));
+    // One for every chunk, then the whole file again
+    assert!(snapshots.len() >= 2);
+    assert!(snapshots.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(snapshots.last(), Some(&60_000));
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::{TempDir, TempFile};
+use one_billion_row_challenge::{aggregate_bytes, aggregate_reader, aggregate_stations, Options};
+
+#[test]
+fn quoted_names() {
+    let bytes = b"\"Foo;Bar\";12.3\nPlain;1.0\n\"Say \"\"hi\"\"\";4.0\n\"Foo;Bar\";2.0\n\"Say \"\"hi\"\"\";-4.0";
+    let options = Options { quoted: true, ..Default::default() };
+    for stations in [aggregate_bytes(bytes, &options).unwrap(), aggregate_reader(&bytes[..], &options).unwrap()] {
+        assert_eq!(stations.len(), 3);
+        assert_eq!(stations[&b"Foo;Bar"[..]].count, 2);
+        assert_eq!(stations[&b"Plain"[..]].count, 1);
+        let hi = &stations[&b"Say \"hi\""[..]];
+        assert_eq!((hi.min, hi.max, hi.count), (-40, 40, 2));
+    }
+
+    // Without quoting, the quotes are part of the name
+    let stations = aggregate_bytes(bytes, &Options::default()).unwrap();
+    assert!(stations.contains_key(&b"\"Foo;Bar\""[..]));
+    assert!(aggregate_bytes(b"\"Foo;12.3\n", &options).is_err());
+}
+
+#[test]
+fn unescaped_once() {
+    // Unescaped again, "Say ""hi""" would be Say "hi", and """Hi""" Hi
+    let file = TempFile::new("quoted", "\"Say \"\"hi\"\"\";4.0\n\"\"\"Hi\"\"\";1.0\n\"Hi\";2.0\n");
+    let (cache, checkpoint) = (TempDir::new("quoted-cache"), TempDir::new("quoted-checkpoint"));
+    let options = Options { quoted: true, ..Default::default() };
+    let expected = aggregate_stations(&file, &options).unwrap();
+    assert_eq!(expected.len(), 3);
+    assert!(expected.contains_key(&b"\"Hi\""[..]));
+
+    // Twice each, as a run that saves its results and one that finds them
+    for options in [
+        Options { cache: Some(cache.to_path_buf()), ..options.clone() },
+        Options { checkpoint: Some(checkpoint.to_path_buf()), ..options.clone() },
+        Options { max_memory: Some(1), ..options.clone() },
+        #[cfg(feature = "tokio")]
+        Options { engine: one_billion_row_challenge::Engine::Async, ..options.clone() },
+    ] {
+        for _ in 0..2 {
+            assert_eq!(aggregate_stations(&file, &options).unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use one_billion_row_challenge::{aggregate_bytes, Options, Results, Style, TopBy};
+
+fn results(bytes: &[u8]) -> Results {
+    aggregate_bytes(bytes, &Options::default()).unwrap().into()
+}
+
+#[test]
+fn results_api() {
+    let mut results = results(b"Hamburg;12.0\nAbha;-3.5\nHamburg;7.0\nZ\xc3\xbcrich;20.5\n");
+    assert_eq!(results.len(), 3);
+    assert_eq!(results.get("Hamburg").unwrap().count, 2);
+    assert!(results.get("Oslo").is_none());
+    assert_eq!(results.iter().map(|(name, _)| name).collect::<Vec<_>>(), ["Abha", "Hamburg", "Zürich"]);
+    assert_eq!(results.top_n_by(2, TopBy::Max).iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["Zürich", "Hamburg"]);
+    assert_eq!(results.top_n_by(5, TopBy::Count)[0].0, "Hamburg");
+
+    results.merge(self::results(b"Abha;40.0\nOslo;1.0\n"));
+    assert_eq!(results.len(), 4);
+    let abha = results.get("Abha").unwrap();
+    assert_eq!((abha.min, abha.max, abha.count), (-35, 400, 2));
+
+    assert_eq!(results.to_string(), "{Abha=-3.5/18.3/40.0, Hamburg=7.0/9.5/12.0, Oslo=1.0/1.0/1.0, Zürich=20.5/20.5/20.5}");
+    assert_eq!(results.render(Style::Lines, false), "Abha;-3.5;18.3;40\nHamburg;7;9.5;12\nOslo;1;1;1\nZürich;20.5;20.5;20.5\n");
+    assert!(results.render(Style::Csv, true).starts_with("station,min,mean,max,count\nAbha,-3.5,18.3,40,2\n"));
+    let json = serde_json::from_str::<serde_json::Value>(&results.render(Style::Json, false)).unwrap();
+    assert_eq!(json[1]["station"], "Hamburg");
+    assert_eq!(json[1]["mean"], 9.5);
+}
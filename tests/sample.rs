@@ -0,0 +1,24 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use common::TempFile;
+use one_billion_row_challenge::{aggregate_sample, aggregate_stations, Options, Sample};
+
+#[test]
+fn samples() {
+    let lines = common::rows(200_000, 10);
+    let path = TempFile::new("sample", &lines);
+    let options = Options::default();
+
+    let first = aggregate_sample(&path, Sample::Rows(25), &options).unwrap();
+    assert_eq!(first.stations.values().map(|record| record.count).sum::<u64>(), 25);
+    assert_eq!(first.bytes, lines.split_inclusive('\n').take(25).map(str::len).sum::<usize>() as u64);
+    assert_eq!(aggregate_sample(&path, Sample::Rows(1_000_000), &options).unwrap().stations, aggregate_stations(&path, &options).unwrap());
+
+    let tenth = aggregate_sample(&path, Sample::Fraction(0.1), &options).unwrap();
+    assert!((0.05..0.2).contains(&tenth.fraction()), "{}", tenth.fraction());
+    assert_eq!(tenth.stations.len(), 10);
+    assert_eq!(aggregate_sample(&path, Sample::Fraction(1.), &options).unwrap().stations, aggregate_stations(&path, &options).unwrap());
+    assert!(aggregate_sample(&path, Sample::Fraction(0.), &options).is_err());
+}
@@ -0,0 +1,28 @@
+// Station names only key a JSON object when they're InlineKeys
+#![cfg(all(feature = "native", feature = "inline-keys"))]
+
+use one_billion_row_challenge::{aggregate_bytes, Options, Record, StationMap};
+
+const BYTES: &[u8] = b"Hamburg;12.0\nAbha;-3.5\nHamburg;7.5\nCura\xc3\xa7ao;30.1\n";
+
+#[test]
+fn results_round_trip() {
+    let stations = aggregate_bytes(BYTES, &Options::default()).unwrap();
+    let json = serde_json::to_string(&stations).unwrap();
+    assert!(json.contains(r#""Hamburg":{"min":75,"max":120,"total":195,"count":2,"sum_squares":20025,"histogram":null,"decimals":1}"#), "{}", json);
+    assert_eq!(serde_json::from_str::<StationMap>(&json).unwrap(), stations);
+
+    // With histograms
+    let options = Options { histograms: true, ..Default::default() };
+    let stations = aggregate_bytes(BYTES, &options).unwrap();
+    let json = serde_json::to_string(&stations).unwrap();
+    let back = serde_json::from_str::<StationMap>(&json).unwrap();
+    assert_eq!(back, stations);
+    assert_eq!(back[&b"Hamburg"[..]].percentile(50.), Some(75));
+
+    // A name that isn't UTF-8 is bytes, which JSON keys can't be
+    let stations = aggregate_bytes(b"Cura\xe7ao;30.1\n", &Options::default()).unwrap();
+    assert!(serde_json::to_string(&stations).is_err());
+
+    assert!(serde_json::from_str::<Record>(r#"{"min":1,"max":1,"total":1,"count":1,"sum_squares":1,"histogram":[1,2],"decimals":1}"#).is_err());
+}
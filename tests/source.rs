@@ -0,0 +1,52 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::TempFile;
+use one_billion_row_challenge::chunk::ChunkSize;
+use one_billion_row_challenge::{aggregate_bytes, aggregate_source, ChunkSource, Options};
+
+// Bytes that come a range at a time, as from a server, counting the reads
+struct Remote {
+    bytes: Vec<u8>,
+    reads: AtomicUsize,
+}
+
+impl ChunkSource for Remote {
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.bytes.len() as u64)
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> std::io::Result<impl AsRef<[u8]> + '_> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        Ok(self.bytes[start as usize..end as usize].to_vec())
+    }
+}
+
+#[test]
+fn aggregates_any_source() {
+    let bytes = common::rows(20_000, 37).into_bytes();
+    // Small blocks and chunks, so lines are carried over between reads
+    let options = Options { read_size: Some(1000), chunk_size: ChunkSize::Bytes(16 * 1024), ..Default::default() };
+    let expected = aggregate_bytes(&bytes, &options).unwrap();
+
+    assert_eq!(aggregate_source(&bytes, &options).unwrap(), expected);
+    assert_eq!(aggregate_source(&bytes[..], &options).unwrap(), expected);
+
+    let path = TempFile::new("source", &bytes);
+    let file = std::fs::File::open(&path).unwrap();
+    assert_eq!(aggregate_source(&file, &options).unwrap(), expected);
+    let mmap = unsafe { memmap2::Mmap::map(&file).unwrap() };
+    assert_eq!(aggregate_source(&mmap, &options).unwrap(), expected);
+    drop((mmap, file));
+
+    let remote = Remote { bytes: bytes.clone(), reads: AtomicUsize::new(0) };
+    assert_eq!(aggregate_source(&remote, &options).unwrap(), expected);
+    assert!(remote.reads.load(Ordering::Relaxed) >= bytes.len() / 1000);
+
+    // Without a newline at the end too
+    let unterminated = &bytes[..bytes.len() - 1];
+    assert_eq!(aggregate_source(unterminated, &options).unwrap(), aggregate_bytes(unterminated, &options).unwrap());
+}
@@ -0,0 +1,18 @@
+#![cfg(feature = "native")]
+
+use one_billion_row_challenge::{aggregate_reader, Error, Options};
+
+#[test]
+fn validate_utf8() {
+    let input = &b"Abha;1.0\nBa\xffd;2.0\nAbha;3.0\n"[..];
+
+    // Without validation the bad name is only a problem for the output
+    let stations = aggregate_reader(input, &Options::default()).unwrap();
+    assert_eq!(stations[&b"Ba\xffd"[..]].count, 1);
+
+    let options = Options { validate_utf8: true, ..Default::default() };
+    match aggregate_reader(input, &options) {
+        Err(Error::Parse { offset, .. }) => assert_eq!(offset, 9),
+        result => panic!("expected a parse error, got {:?}", result.map(|_| ())),
+    }
+}
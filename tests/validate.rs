@@ -0,0 +1,44 @@
+#![cfg(feature = "native")]
+
+use one_billion_row_challenge::{validate_bytes, Error, Options};
+
+// Where and why each violation is, by line number
+fn violations(bytes: &[u8], options: &Options, limit: usize) -> Vec<(u64, String)> {
+    let validation = validate_bytes(bytes, options, limit).unwrap();
+    validation.violations.into_iter().map(|violation| match violation {
+        Error::Parse { line: Some(line), reason, .. } => (line, reason),
+        violation => panic!("expected a parse error, got {:?}", violation),
+    }).collect()
+}
+
+#[test]
+fn finds_every_kind() {
+    let bytes = b"Hamburg;12.0\nBulawayo\nHamburg;1.23\n;1.0\nZ\xff;1.0\nLongname;1.0\nCracow;-1.5";
+    let options = Options { strict: true, max_name_len: Some(7), ..Default::default() };
+    let validation = validate_bytes(bytes, &options, 10).unwrap();
+
+    assert_eq!((validation.lines, validation.invalid), (7, 5));
+    assert_eq!(violations(bytes, &options, 10), [
+        (2, "no delimiter".to_string()),
+        (3, "bad temperature".to_string()),
+        (4, "station name is empty".to_string()),
+        (5, "station name isn't valid UTF-8".to_string()),
+        (6, "station name is longer than 7 bytes".to_string()),
+    ]);
+}
+
+#[test]
+fn line_numbers_across_chunks() {
+    // Every 1000th line is bad, and the threads split the lines up
+    let bytes = (1..=20_000).map(|i| if i % 1000 == 0 { "bad\n".to_string() } else { format!("Station{};{}.5\n", i % 7, i % 50) }).collect::<String>();
+    for threads in [1, 3, 8] {
+        let options = Options { threads: Some(threads), ..Default::default() };
+        let found = violations(bytes.as_bytes(), &options, 100);
+        assert_eq!(found.iter().map(|(line, _)| *line).collect::<Vec<_>>(), (1..=20).map(|i| i * 1000).collect::<Vec<_>>());
+
+        // Only the first few are kept, but every one is counted
+        let validation = validate_bytes(bytes.as_bytes(), &options, 3).unwrap();
+        assert_eq!((validation.violations.len(), validation.invalid, validation.lines), (3, 20, 20_000));
+        assert_eq!(validation.reasons["no delimiter"], 20);
+    }
+}
@@ -0,0 +1,27 @@
+#![cfg(feature = "native")]
+
+mod common;
+
+use std::sync::Arc;
+
+use common::{rows, TempFile, ENGINES};
+use one_billion_row_challenge::{aggregate_stations, Options, Workers};
+
+#[test]
+fn adds_up_to_the_whole_run() {
+    let rows = rows(50_000, 300);
+    let file = TempFile::new("workers", &rows);
+
+    for engine in ENGINES {
+        let workers = Arc::new(Workers::new(2));
+        let options = Options { engine, threads: Some(2), workers: Some(workers.clone()), ..Default::default() };
+        aggregate_stations(&file, &options).unwrap();
+
+        let stats = workers.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats.iter().map(|worker| worker.bytes).sum::<u64>(), rows.len() as u64);
+        assert_eq!(stats.iter().map(|worker| worker.rows).sum::<u64>(), 50_000);
+        assert!(stats.iter().map(|worker| worker.chunks).sum::<u64>() >= 1);
+        assert_eq!(stats.iter().map(|worker| worker.stations).max(), Some(300));
+    }
+}
@@ -0,0 +1,65 @@
+// The parser in the browser: the `brc` wasm module.
+//
+// Build it with wasm-pack, which leaves the module and its JS glue in
+// wasm/pkg for index.html, the playground, to load:
+//
+//     wasm-pack build --target web wasm
+//
+// The library is built without its native feature, so the bytes are
+// parsed on the page's thread.
+
+use one_billion_row_challenge::{aggregate_bytes, Error, Options};
+use wasm_bindgen::prelude::*;
+
+/// A station's results, with its temperatures in degrees
+#[wasm_bindgen(getter_with_clone)]
+pub struct Station {
+    /// Lossily converted if it isn't valid UTF-8
+    pub name: String,
+    pub min: f64,
+    /// Rounded to the temperatures' decimals, as the challenge's output
+    /// is
+    pub mean: f64,
+    pub max: f64,
+    /// A plain JS number rather than a BigInt, which is exact for any
+    /// file a browser can hold
+    pub count: f64,
+}
+
+/// Aggregates the measurements in `bytes` (one `name;temperature` per
+/// line) into their stations' results, sorted by name
+#[wasm_bindgen]
+pub fn aggregate(bytes: &[u8]) -> Result<Vec<Station>, JsError> {
+    stations(bytes).map_err(|e| JsError::new(&e.to_string()))
+}
+
+fn stations(bytes: &[u8]) -> Result<Vec<Station>, Error> {
+    let data = aggregate_bytes(bytes, &Options::default())?;
+
+    let mut stations: Vec<Station> = data.into_iter().map(|(name, record)| Station {
+        name: String::from_utf8_lossy(&name).into_owned(),
+        min: record.min(),
+        mean: record.mean_rounded(),
+        max: record.max(),
+        count: record.count as f64,
+    }).collect();
+    stations.sort_unstable_by(|station1, station2| station1.name.cmp(&station2.name));
+    Ok(stations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_by_name() {
+        let results = stations(b"Hamburg;12.0\nBulawayo;8.9\nHamburg;34.2").unwrap();
+        let summary: Vec<_> = results.iter().map(|station| (&station.name[..], station.min, station.mean, station.max, station.count)).collect();
+        assert_eq!(summary, [("Bulawayo", 8.9, 8.9, 8.9, 1.), ("Hamburg", 12., 23.1, 34.2, 2.)]);
+
+        match stations(b"Hamburg;12.0\nBulawayo\n") {
+            Err(Error::Parse { line: Some(2), .. }) => {}
+            result => panic!("expected a parse error on line 2, got {:?}", result.map(|_| ())),
+        }
+    }
+}